@@ -0,0 +1,2194 @@
+//! Bluetooth LE, on the chips that have it.
+
+use core::fmt;
+
+use crate::event::Event;
+use crate::tcpip::{parse_mac, parse_octets, Mac};
+use crate::{parser, CommandSet, Error, Escaped, Esp32At, Line};
+
+/// The longest device name that can be set.
+pub const MAX_NAME_LEN: usize = 32;
+
+/// How many BLE connections the module can have at the same time.
+pub const MAX_CONNECTIONS: u8 = 3;
+
+/// The shortest time to wait for a connection to be established.
+pub const MIN_CONNECT_TIMEOUT_SECS: u8 = 3;
+
+/// The longest time to wait for a connection to be established.
+pub const MAX_CONNECT_TIMEOUT_SECS: u8 = 30;
+
+/// The shortest connection interval, 7.5 ms.
+pub const MIN_CONNECTION_INTERVAL: u16 = 0x0006;
+
+/// The longest connection interval, 4 s.
+pub const MAX_CONNECTION_INTERVAL: u16 = 0x0c80;
+
+/// The most connection events that the peripheral may skip.
+pub const MAX_LATENCY: u16 = 0x01f3;
+
+/// The shortest supervision timeout, 100 ms.
+pub const MIN_SUPERVISION_TIMEOUT: u16 = 0x000a;
+
+/// The longest supervision timeout, 32 s.
+pub const MAX_SUPERVISION_TIMEOUT: u16 = 0x0c80;
+
+/// The smallest ATT MTU, which every device supports.
+pub const MIN_MTU: u16 = 23;
+
+/// The largest ATT MTU.
+pub const MAX_MTU: u16 = 517;
+
+/// The smallest payload of a link layer packet.
+pub const MIN_PACKET_DATA_LEN: u16 = 0x1b;
+
+/// The largest payload of a link layer packet, with the data length extension.
+pub const MAX_PACKET_DATA_LEN: u16 = 0xfb;
+
+/// The smallest encryption key, in bytes.
+pub const MIN_KEY_SIZE: u8 = 7;
+
+/// The largest encryption key, in bytes.
+pub const MAX_KEY_SIZE: u8 = 16;
+
+/// The largest passkey, which is entered or displayed as 6 digits.
+pub const MAX_PASSKEY: u32 = 999_999;
+
+/// The shortest scan interval or window, 2.5 ms.
+pub const MIN_SCAN_INTERVAL: u16 = 0x0004;
+
+/// The longest scan interval or window, 10.24 s.
+pub const MAX_SCAN_INTERVAL: u16 = 0x4000;
+
+/// The shortest advertising interval, 20 ms.
+pub const MIN_ADVERTISING_INTERVAL: u16 = 0x0020;
+
+/// The longest advertising interval, 10.24 s.
+pub const MAX_ADVERTISING_INTERVAL: u16 = 0x4000;
+
+/// The longest advertising data or scan response.
+pub const MAX_ADVERTISING_DATA_LEN: usize = 31;
+
+/// Advertising data or a scan response, made up of AD structures.
+pub type AdvertisingData = heapless::Vec<u8, heapless::consts::U31>;
+
+/// The established connections, by connection index and address of the peer.
+pub type Connections = heapless::Vec<(u8, [u8; 6]), heapless::consts::U3>;
+
+/// The bonded devices, by index and address.
+pub type BondedDevices = heapless::Vec<(u8, [u8; 6]), heapless::consts::U15>;
+
+/// The GAP device name.
+pub type DeviceName = heapless::String<heapless::consts::U32>;
+
+/// The GATT role that BLE is initialized in, which decides which commands can be used.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BleRole {
+    /// Scans for and connects to peripherals, and uses their GATT services.
+    Client,
+    /// Advertises and accepts connections, and provides GATT services.
+    Server,
+}
+
+/// Whether scanning asks advertisers for their scan response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScanType {
+    /// Only listens to advertisements, which uses less energy.
+    Passive,
+    /// Also requests the scan response of each advertiser.
+    Active,
+}
+
+/// The address that the module itself uses while scanning, advertising or connecting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OwnAddressType {
+    Public,
+    /// The static random address set with `Esp32At::set_ble_random_address`.
+    Random,
+    /// A resolvable private address, or the public address if no IRK is known.
+    ResolvablePrivateOrPublic,
+    /// A resolvable private address, or the random address if no IRK is known.
+    ResolvablePrivateOrRandom,
+}
+
+/// Which advertisements are reported while scanning.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScanFilterPolicy {
+    AllowAll,
+    /// Only advertisements from devices in the white list.
+    WhiteListOnly,
+    /// All undirected advertisements, and directed ones to a resolvable private address.
+    AllowUndirectedAndResolvableDirected,
+    /// Undirected advertisements from devices in the white list, and directed ones to a
+    /// resolvable private address.
+    WhiteListAndResolvableDirected,
+}
+
+/// How scanning is done, see `Esp32At::set_ble_scan_parameters`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScanParameters {
+    pub scan_type: ScanType,
+    pub own_address_type: OwnAddressType,
+    pub filter_policy: ScanFilterPolicy,
+    /// How often to start listening, in units of 0.625 ms, from `MIN_SCAN_INTERVAL` to
+    /// `MAX_SCAN_INTERVAL`.
+    pub interval: u16,
+    /// How long to listen each time, in units of 0.625 ms, at most `interval`.
+    pub window: u16,
+}
+
+/// How the module advertises in the server role.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AdvertisingType {
+    /// Any device can scan and connect.
+    ConnectableUndirected,
+    /// Only the peer can connect, which is advertised for at most 1.28 s.
+    ConnectableDirectedHighDuty,
+    /// Only the peer can connect, advertised at the regular interval.
+    ConnectableDirectedLowDuty,
+    /// Any device can scan, but not connect.
+    ScannableUndirected,
+    /// Only broadcasts the advertising data, e.g. for beacons.
+    NonConnectableUndirected,
+}
+
+/// An advertising channel, of which any combination can be used.
+#[derive(Debug, enumset::EnumSetType)]
+pub enum AdvertisingChannel {
+    Channel37,
+    Channel38,
+    Channel39,
+}
+
+/// Which devices can scan and connect while advertising.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AdvertisingFilterPolicy {
+    AllowAll,
+    /// Only devices in the white list can scan.
+    WhiteListScan,
+    /// Only devices in the white list can connect.
+    WhiteListConnect,
+    /// Only devices in the white list can scan and connect.
+    WhiteListScanAndConnect,
+}
+
+/// How advertising is done, see `Esp32At::set_ble_advertising_parameters`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AdvertisingParameters {
+    /// The shortest time between advertisements, in units of 0.625 ms, from
+    /// `MIN_ADVERTISING_INTERVAL` to `MAX_ADVERTISING_INTERVAL`.
+    pub min_interval: u16,
+    /// The longest time between advertisements, in units of 0.625 ms, at least `min_interval`.
+    pub max_interval: u16,
+    pub advertising_type: AdvertisingType,
+    pub own_address_type: OwnAddressType,
+    /// The channels to advertise on, at least one.
+    pub channels: enumset::EnumSet<AdvertisingChannel>,
+    pub filter_policy: AdvertisingFilterPolicy,
+    /// The device that directed advertisements are for, which only they have.
+    pub peer: Option<([u8; 6], AddressType)>,
+}
+
+/// What an iBeacon advertises, see `Esp32At::ble_start_ibeacon`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IBeacon {
+    /// Identifies the beacons of e.g. an app, most significant byte first.
+    pub uuid: [u8; 16],
+    /// Identifies a group of beacons, e.g. those in a building.
+    pub major: u16,
+    /// Identifies a beacon within its group.
+    pub minor: u16,
+    /// The RSSI measured 1 m away from the beacon, in dBm, which receivers estimate their
+    /// distance from.
+    pub measured_power: i8,
+}
+
+/// The parameters requested for a connection, see
+/// `Esp32At::ble_update_connection_parameters`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConnectionParameters {
+    /// The shortest acceptable connection interval, in units of 1.25 ms, from
+    /// `MIN_CONNECTION_INTERVAL` to `MAX_CONNECTION_INTERVAL`.
+    pub min_interval: u16,
+    /// The longest acceptable connection interval, in units of 1.25 ms, at least `min_interval`.
+    pub max_interval: u16,
+    /// How many connection events the peripheral may skip when it has nothing to send, at most
+    /// `MAX_LATENCY`.
+    pub latency: u16,
+    /// How long the connection may go without a packet before it is considered lost, in units
+    /// of 10 ms, from `MIN_SUPERVISION_TIMEOUT` to `MAX_SUPERVISION_TIMEOUT`.  It has to be
+    /// longer than twice the time that the peripheral may be silent for, given `max_interval`
+    /// and `latency`.
+    pub timeout: u16,
+}
+
+/// The parameters of an established connection, see
+/// `Esp32At::get_ble_connection_parameters`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CurrentConnectionParameters {
+    /// The parameters that were requested last.
+    pub requested: ConnectionParameters,
+    /// The connection interval in use, in units of 1.25 ms.
+    pub interval: u16,
+}
+
+/// What the module can use to confirm pairing with the user, which decides how pairing
+/// protects against man-in-the-middle attacks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IoCapability {
+    DisplayOnly,
+    /// A display and a way to answer yes or no, for comparing numbers.
+    DisplayYesNo,
+    KeyboardOnly,
+    /// Nothing, so pairing can't protect against man-in-the-middle attacks.
+    NoInputNoOutput,
+    KeyboardDisplay,
+}
+
+/// A key that is distributed while pairing, of which any combination can be requested.
+#[derive(Debug, enumset::EnumSetType)]
+pub enum BondingKey {
+    /// The long term key, for encrypting the connection again without pairing.
+    Encryption,
+    /// The identity resolving key, for recognizing a peer that uses resolvable private
+    /// addresses.
+    Identity,
+    /// The connection signature resolving key, for signing data on an unencrypted connection.
+    Signing,
+    /// The key for deriving the BR/EDR link key.
+    Link,
+}
+
+/// How pairing is done, see `Esp32At::set_ble_security_parameters`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SecurityParameters {
+    /// Whether the keys are stored after pairing, so that the peers can encrypt the connection
+    /// again when they reconnect.
+    pub bonding: bool,
+    /// Whether pairing must protect against man-in-the-middle attacks, which takes an
+    /// `io_capability` to confirm it with.
+    pub mitm_protection: bool,
+    /// Whether only LE Secure Connections pairing is allowed, rather than legacy pairing too.
+    pub secure_connections_only: bool,
+    pub io_capability: IoCapability,
+    /// The size of the encryption key in bytes, from `MIN_KEY_SIZE` to `MAX_KEY_SIZE`.
+    pub key_size: u8,
+    /// The keys that the initiator of pairing distributes.
+    pub initiator_keys: enumset::EnumSet<BondingKey>,
+    /// The keys that the responder distributes.
+    pub responder_keys: enumset::EnumSet<BondingKey>,
+}
+
+/// How a connection is encrypted, see `Esp32At::ble_encrypt`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encryption {
+    /// With whatever protection the security parameters allow.
+    Encrypt,
+    /// Without protection against man-in-the-middle attacks.
+    EncryptNoMitm,
+    /// With protection against man-in-the-middle attacks.
+    EncryptMitm,
+}
+
+/// A Bluetooth UUID, of a service or characteristic.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Uuid {
+    /// A UUID assigned by the Bluetooth SIG, like `0x180f` for the battery service.
+    Uuid16(u16),
+    /// Any other UUID, most significant byte first.
+    Uuid128([u8; 16]),
+}
+
+/// The advertising data that the module builds itself, see
+/// `Esp32At::set_ble_advertising_data_ex`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AdvertisingDataEx<'a> {
+    pub name: &'a str,
+    /// The UUID of the main service of the device.
+    pub service_uuid: Uuid,
+    /// The manufacturer specific data, starting with the little-endian company identifier.
+    pub manufacturer_data: &'a [u8],
+    /// Whether to advertise the transmit power.
+    pub include_tx_power: bool,
+}
+
+/// The kind of address of another device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressType {
+    Public,
+    Random,
+}
+
+/// An advertisement received while scanning.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScanResult {
+    pub address: [u8; 6],
+    pub address_type: AddressType,
+    /// The signal strength in dBm.
+    pub rssi: i8,
+    pub advertising_data: AdvertisingData,
+    /// Only received in active scanning, see `ScanType::Active`.
+    pub scan_response: AdvertisingData,
+}
+
+/// An AD structure in advertising data or a scan response, see `ad_structures`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AdStructure<'a> {
+    Flags(u8),
+    /// 16-bit UUIDs of services, a complete or an incomplete list.
+    ServiceUuids16(Uuids16<'a>),
+    /// 128-bit UUIDs of services, a complete or an incomplete list.
+    ServiceUuids128(Uuids128<'a>),
+    ShortenedName(&'a str),
+    CompleteName(&'a str),
+    /// The transmit power in dBm.
+    TxPowerLevel(i8),
+    ManufacturerData {
+        company_id: u16,
+        data: &'a [u8],
+    },
+    /// Any other AD structure, or one of the above that is malformed.
+    Other {
+        ad_type: u8,
+        data: &'a [u8],
+    },
+}
+
+/// The AD structures in advertising data, see `ad_structures`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AdStructures<'a>(&'a [u8]);
+
+/// A list of little-endian 16-bit UUIDs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Uuids16<'a>(&'a [u8]);
+
+/// A list of little-endian 128-bit UUIDs, which are returned in the order they are written in,
+/// i.e. most significant byte first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Uuids128<'a>(&'a [u8]);
+
+impl BleRole {
+    fn code(self) -> u8 {
+        match self {
+            BleRole::Client => 1,
+            BleRole::Server => 2,
+        }
+    }
+}
+
+impl ScanType {
+    fn code(self) -> u8 {
+        match self {
+            ScanType::Passive => 0,
+            ScanType::Active => 1,
+        }
+    }
+
+    fn from_code(code: &str) -> Option<Self> {
+        match code {
+            "0" => Some(ScanType::Passive),
+            "1" => Some(ScanType::Active),
+            _ => None,
+        }
+    }
+}
+
+impl OwnAddressType {
+    fn code(self) -> u8 {
+        match self {
+            OwnAddressType::Public => 0,
+            OwnAddressType::Random => 1,
+            OwnAddressType::ResolvablePrivateOrPublic => 2,
+            OwnAddressType::ResolvablePrivateOrRandom => 3,
+        }
+    }
+
+    fn from_code(code: &str) -> Option<Self> {
+        match code {
+            "0" => Some(OwnAddressType::Public),
+            "1" => Some(OwnAddressType::Random),
+            "2" => Some(OwnAddressType::ResolvablePrivateOrPublic),
+            "3" => Some(OwnAddressType::ResolvablePrivateOrRandom),
+            _ => None,
+        }
+    }
+}
+
+impl ScanFilterPolicy {
+    fn code(self) -> u8 {
+        match self {
+            ScanFilterPolicy::AllowAll => 0,
+            ScanFilterPolicy::WhiteListOnly => 1,
+            ScanFilterPolicy::AllowUndirectedAndResolvableDirected => 2,
+            ScanFilterPolicy::WhiteListAndResolvableDirected => 3,
+        }
+    }
+
+    fn from_code(code: &str) -> Option<Self> {
+        match code {
+            "0" => Some(ScanFilterPolicy::AllowAll),
+            "1" => Some(ScanFilterPolicy::WhiteListOnly),
+            "2" => Some(ScanFilterPolicy::AllowUndirectedAndResolvableDirected),
+            "3" => Some(ScanFilterPolicy::WhiteListAndResolvableDirected),
+            _ => None,
+        }
+    }
+}
+
+impl ScanParameters {
+    /// Parses `<scan_type>,<own_addr_type>,<filter_policy>,<scan_interval>,<scan_window>`.
+    fn parse(arguments: &str) -> Option<Self> {
+        let mut fields = parser::fields(arguments);
+        Some(ScanParameters {
+            scan_type: ScanType::from_code(fields.next()?)?,
+            own_address_type: OwnAddressType::from_code(fields.next()?)?,
+            filter_policy: ScanFilterPolicy::from_code(fields.next()?)?,
+            interval: fields.next()?.parse().ok()?,
+            window: fields.next()?.parse().ok()?,
+        })
+    }
+}
+
+impl ConnectionParameters {
+    fn is_valid(&self) -> bool {
+        let valid_intervals = MIN_CONNECTION_INTERVAL..=MAX_CONNECTION_INTERVAL;
+        let valid_timeouts = MIN_SUPERVISION_TIMEOUT..=MAX_SUPERVISION_TIMEOUT;
+        valid_intervals.contains(&self.min_interval)
+            && valid_intervals.contains(&self.max_interval)
+            && self.min_interval <= self.max_interval
+            && self.latency <= MAX_LATENCY
+            && valid_timeouts.contains(&self.timeout)
+            // timeout * 10 ms > 2 * (1 + latency) * max_interval * 1.25 ms
+            && u32::from(self.timeout) * 4
+                > (1 + u32::from(self.latency)) * u32::from(self.max_interval)
+    }
+}
+
+impl CurrentConnectionParameters {
+    /// Parses `<min_interval>,<max_interval>,<cur_interval>,<latency>,<timeout>`.
+    fn parse<'a>(mut fields: impl Iterator<Item = &'a str>) -> Option<Self> {
+        let min_interval = fields.next()?.parse().ok()?;
+        let max_interval = fields.next()?.parse().ok()?;
+        let interval = fields.next()?.parse().ok()?;
+        Some(CurrentConnectionParameters {
+            requested: ConnectionParameters {
+                min_interval,
+                max_interval,
+                latency: fields.next()?.parse().ok()?,
+                timeout: fields.next()?.parse().ok()?,
+            },
+            interval,
+        })
+    }
+}
+
+impl IoCapability {
+    fn code(self) -> u8 {
+        match self {
+            IoCapability::DisplayOnly => 0,
+            IoCapability::DisplayYesNo => 1,
+            IoCapability::KeyboardOnly => 2,
+            IoCapability::NoInputNoOutput => 3,
+            IoCapability::KeyboardDisplay => 4,
+        }
+    }
+
+    fn from_code(code: &str) -> Option<Self> {
+        match code {
+            "0" => Some(IoCapability::DisplayOnly),
+            "1" => Some(IoCapability::DisplayYesNo),
+            "2" => Some(IoCapability::KeyboardOnly),
+            "3" => Some(IoCapability::NoInputNoOutput),
+            "4" => Some(IoCapability::KeyboardDisplay),
+            _ => None,
+        }
+    }
+}
+
+impl SecurityParameters {
+    /// The authentication requirements, as flags for bonding, MITM protection and Secure
+    /// Connections.
+    fn auth_req(&self) -> u8 {
+        u8::from(self.bonding)
+            | u8::from(self.mitm_protection) << 2
+            | u8::from(self.secure_connections_only) << 3
+    }
+
+    /// Parses `<auth_req>,<iocap>,<enc_key_size>,<init_key>,<rsp_key>`, which some firmware
+    /// versions follow with `<auth_option>`.
+    fn parse(arguments: &str) -> Option<Self> {
+        let mut fields = parser::fields(arguments);
+        let auth_req: u8 = fields.next()?.parse().ok()?;
+        Some(SecurityParameters {
+            bonding: auth_req & 1 != 0,
+            mitm_protection: auth_req & 1 << 2 != 0,
+            secure_connections_only: auth_req & 1 << 3 != 0,
+            io_capability: IoCapability::from_code(fields.next()?)?,
+            key_size: fields.next()?.parse().ok()?,
+            initiator_keys: parse_bonding_keys(fields.next()?)?,
+            responder_keys: parse_bonding_keys(fields.next()?)?,
+        })
+    }
+}
+
+impl Encryption {
+    fn code(self) -> u8 {
+        match self {
+            Encryption::Encrypt => 1,
+            Encryption::EncryptNoMitm => 2,
+            Encryption::EncryptMitm => 3,
+        }
+    }
+}
+
+impl AdvertisingType {
+    fn code(self) -> u8 {
+        match self {
+            AdvertisingType::ConnectableUndirected => 0,
+            AdvertisingType::ConnectableDirectedHighDuty => 1,
+            AdvertisingType::ScannableUndirected => 2,
+            AdvertisingType::NonConnectableUndirected => 3,
+            AdvertisingType::ConnectableDirectedLowDuty => 4,
+        }
+    }
+
+    fn from_code(code: &str) -> Option<Self> {
+        match code {
+            "0" => Some(AdvertisingType::ConnectableUndirected),
+            "1" => Some(AdvertisingType::ConnectableDirectedHighDuty),
+            "2" => Some(AdvertisingType::ScannableUndirected),
+            "3" => Some(AdvertisingType::NonConnectableUndirected),
+            "4" => Some(AdvertisingType::ConnectableDirectedLowDuty),
+            _ => None,
+        }
+    }
+
+    fn is_directed(self) -> bool {
+        match self {
+            AdvertisingType::ConnectableDirectedHighDuty
+            | AdvertisingType::ConnectableDirectedLowDuty => true,
+            AdvertisingType::ConnectableUndirected
+            | AdvertisingType::ScannableUndirected
+            | AdvertisingType::NonConnectableUndirected => false,
+        }
+    }
+}
+
+impl AdvertisingFilterPolicy {
+    fn code(self) -> u8 {
+        match self {
+            AdvertisingFilterPolicy::AllowAll => 0,
+            AdvertisingFilterPolicy::WhiteListScan => 1,
+            AdvertisingFilterPolicy::WhiteListConnect => 2,
+            AdvertisingFilterPolicy::WhiteListScanAndConnect => 3,
+        }
+    }
+
+    fn from_code(code: &str) -> Option<Self> {
+        match code {
+            "0" => Some(AdvertisingFilterPolicy::AllowAll),
+            "1" => Some(AdvertisingFilterPolicy::WhiteListScan),
+            "2" => Some(AdvertisingFilterPolicy::WhiteListConnect),
+            "3" => Some(AdvertisingFilterPolicy::WhiteListScanAndConnect),
+            _ => None,
+        }
+    }
+}
+
+impl AdvertisingParameters {
+    /// Parses `<adv_int_min>,<adv_int_max>,<adv_type>,<own_addr_type>,<channel_map>,
+    /// <adv_filter_policy>,<peer_addr_type>,"<peer_addr>"`.
+    fn parse(arguments: &str) -> Option<Self> {
+        let mut fields = parser::fields(arguments);
+        let min_interval = fields.next()?.parse().ok()?;
+        let max_interval = fields.next()?.parse().ok()?;
+        let advertising_type = AdvertisingType::from_code(fields.next()?)?;
+        let own_address_type = OwnAddressType::from_code(fields.next()?)?;
+        let channel_map: u8 = fields.next()?.parse().ok()?;
+        let all_channels = enumset::EnumSet::<AdvertisingChannel>::all().to_bits();
+        if u128::from(channel_map) & !all_channels != 0 {
+            return None;
+        }
+        let filter_policy = match fields.next() {
+            Some(code) => AdvertisingFilterPolicy::from_code(code)?,
+            None => AdvertisingFilterPolicy::AllowAll,
+        };
+        let peer = match (fields.next(), fields.next()) {
+            (Some(address_type), Some(address)) if advertising_type.is_directed() => Some((
+                parse_octets(parser::unquote(address))?,
+                AddressType::from_code(address_type)?,
+            )),
+            _ => None,
+        };
+        Some(AdvertisingParameters {
+            min_interval,
+            max_interval,
+            advertising_type,
+            own_address_type,
+            channels: enumset::EnumSet::from_bits(channel_map.into()),
+            filter_policy,
+            peer,
+        })
+    }
+}
+
+impl AddressType {
+    fn code(self) -> u8 {
+        match self {
+            AddressType::Public => 0,
+            AddressType::Random => 1,
+        }
+    }
+
+    fn from_code(code: &str) -> Option<Self> {
+        match code {
+            "0" => Some(AddressType::Public),
+            "1" => Some(AddressType::Random),
+            _ => None,
+        }
+    }
+}
+
+impl IBeacon {
+    /// Encodes the advertising data of the beacon: the flags, followed by the manufacturer
+    /// specific data of Apple in the iBeacon format.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use esp_at::ble::{ad_structures, AdStructure, IBeacon};
+    /// let beacon = IBeacon {
+    ///     uuid: [
+    ///         0xfd, 0xa5, 0x06, 0x93, 0xa4, 0xe2, 0x4f, 0xb1, 0xaf, 0xcf, 0xc6, 0xeb, 0x07, 0x64,
+    ///         0x78, 0x25,
+    ///     ],
+    ///     major: 10001,
+    ///     minor: 19641,
+    ///     measured_power: -59,
+    /// };
+    /// let data = beacon.advertising_data();
+    /// assert_eq!(30, data.len());
+    /// match ad_structures(&data).nth(1) {
+    ///     Some(AdStructure::ManufacturerData { company_id, data }) => {
+    ///         assert_eq!(0x004c, company_id);
+    ///         assert_eq!(&[0x27, 0x11, 0x4c, 0xb9, 0xc5], &data[18..]);
+    ///     }
+    ///     other => panic!("unexpected AD structure: {:?}", other),
+    /// }
+    /// ```
+    pub fn advertising_data(&self) -> AdvertisingData {
+        let mut data = AdvertisingData::new();
+        // LE General Discoverable Mode, BR/EDR Not Supported
+        let _ = data.extend_from_slice(&[0x02, 0x01, 0x06]);
+        // The length and type of the AD structure, the company id of Apple, and the iBeacon type
+        // and length
+        let _ = data.extend_from_slice(&[0x1a, 0xff, 0x4c, 0x00, 0x02, 0x15]);
+        let _ = data.extend_from_slice(&self.uuid);
+        let _ = data.extend_from_slice(&self.major.to_be_bytes());
+        let _ = data.extend_from_slice(&self.minor.to_be_bytes());
+        let _ = data.push(self.measured_power as u8);
+        data
+    }
+}
+
+impl ScanResult {
+    /// Parses `"<addr>",<rssi>,<adv_data>,<scan_rsp_data>,<addr_type>`.
+    fn parse(arguments: &str) -> Option<Self> {
+        let mut fields = parser::fields(arguments);
+        let address = parse_octets(parser::unquote(fields.next()?))?;
+        Some(ScanResult {
+            address,
+            rssi: fields.next()?.parse().ok()?,
+            advertising_data: parse_hex(fields.next()?)?,
+            scan_response: parse_hex(fields.next()?)?,
+            address_type: AddressType::from_code(fields.next()?)?,
+        })
+    }
+}
+
+/// Decodes the AD structures in `data`, e.g. `ScanResult::advertising_data`, ending at the
+/// first one that is truncated.
+///
+/// # Examples
+///
+/// ```
+/// use esp_at::ble::{ad_structures, AdStructure};
+/// let data = b"\x02\x01\x06\x03\x03\x0f\x18\x05\x09Lamp\x05\xff\x59\x00\x01\x02";
+/// let mut structures = ad_structures(data);
+/// assert_eq!(Some(AdStructure::Flags(0x06)), structures.next());
+/// match structures.next() {
+///     Some(AdStructure::ServiceUuids16(uuids)) => assert!(uuids.eq([0x180f].iter().copied())),
+///     other => panic!("unexpected AD structure: {:?}", other),
+/// }
+/// assert_eq!(Some(AdStructure::CompleteName("Lamp")), structures.next());
+/// assert_eq!(
+///     Some(AdStructure::ManufacturerData {
+///         company_id: 0x0059,
+///         data: &[0x01, 0x02]
+///     }),
+///     structures.next()
+/// );
+/// assert_eq!(None, structures.next());
+/// ```
+pub fn ad_structures(data: &[u8]) -> AdStructures<'_> {
+    AdStructures(data)
+}
+
+impl<'a> Iterator for AdStructures<'a> {
+    type Item = AdStructure<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // A length of 0 ends the significant part of the data
+        let (&len, rest) = self.0.split_first().filter(|(&len, _)| len > 0)?;
+        let len = len as usize;
+        if rest.len() < len {
+            self.0 = &[];
+            return None;
+        }
+        let (structure, rest) = rest.split_at(len);
+        self.0 = rest;
+        let (&ad_type, data) = structure.split_first()?;
+        Some(AdStructure::parse(ad_type, data))
+    }
+}
+
+impl<'a> AdStructure<'a> {
+    fn parse(ad_type: u8, data: &'a [u8]) -> Self {
+        let structure = match ad_type {
+            0x01 if data.len() == 1 => Some(AdStructure::Flags(data[0])),
+            0x02 | 0x03 if data.len() % 2 == 0 => Some(AdStructure::ServiceUuids16(Uuids16(data))),
+            0x06 | 0x07 if data.len() % 16 == 0 => {
+                Some(AdStructure::ServiceUuids128(Uuids128(data)))
+            }
+            0x08 => core::str::from_utf8(data)
+                .ok()
+                .map(AdStructure::ShortenedName),
+            0x09 => core::str::from_utf8(data)
+                .ok()
+                .map(AdStructure::CompleteName),
+            0x0a if data.len() == 1 => Some(AdStructure::TxPowerLevel(data[0] as i8)),
+            0xff if data.len() >= 2 => Some(AdStructure::ManufacturerData {
+                company_id: u16::from_le_bytes([data[0], data[1]]),
+                data: &data[2..],
+            }),
+            _ => None,
+        };
+        structure.unwrap_or(AdStructure::Other { ad_type, data })
+    }
+}
+
+impl<'a> Iterator for Uuids16<'a> {
+    type Item = u16;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (uuid, rest) = (self.0.get(..2)?, &self.0[2..]);
+        self.0 = rest;
+        Some(u16::from_le_bytes([uuid[0], uuid[1]]))
+    }
+}
+
+impl<'a> Iterator for Uuids128<'a> {
+    type Item = [u8; 16];
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (uuid, rest) = (self.0.get(..16)?, &self.0[16..]);
+        self.0 = rest;
+        let mut bytes = [0; 16];
+        for (byte, &le) in bytes.iter_mut().zip(uuid.iter().rev()) {
+            *byte = le;
+        }
+        Some(bytes)
+    }
+}
+
+impl<RX, TX> Esp32At<RX, TX>
+where
+    RX: embedded_hal::serial::Read<u8>,
+    TX: embedded_hal::serial::Write<u8>,
+{
+    /// Initializes BLE in `role`, which must be done before any other BLE command.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// use esp_at::ble::BleRole;
+    /// let script = [Exchange::new(b"AT+BLEINIT=2\r\n", b"\r\nOK\r\n")];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Ble.into());
+    ///
+    /// nb::block!(esp32_at.ble_init(BleRole::Server)).unwrap();
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn ble_init(&mut self, role: BleRole) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::Ble)?;
+        write_command!(self, "AT+BLEINIT={}", role.code())?;
+        self.expect_ok_response()
+    }
+
+    /// Shuts BLE down, e.g. to initialize it again in another role.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// let script = [Exchange::new(b"AT+BLEINIT=0\r\n", b"\r\nOK\r\n")];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Ble.into());
+    ///
+    /// nb::block!(esp32_at.ble_deinit()).unwrap();
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn ble_deinit(&mut self) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::Ble)?;
+        write_command!(self, "AT+BLEINIT=0")?;
+        self.expect_ok_response()
+    }
+
+    /// Returns the role that BLE has been initialized in, or `None` if it hasn't been.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// use esp_at::ble::BleRole;
+    /// let script = [
+    ///     Exchange::new(b"AT+BLEINIT?\r\n", b"+BLEINIT:0\r\n\r\nOK\r\n"),
+    ///     Exchange::new(b"AT+BLEINIT?\r\n", b"+BLEINIT:1\r\n\r\nOK\r\n"),
+    /// ];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Ble.into());
+    ///
+    /// assert_eq!(None, nb::block!(esp32_at.get_ble_role()).unwrap());
+    /// assert_eq!(Some(BleRole::Client), nb::block!(esp32_at.get_ble_role()).unwrap());
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn get_ble_role(&mut self) -> nb::Result<Option<BleRole>, Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::Ble)?;
+        write_command!(self, "AT+BLEINIT?")?;
+        let role = self.read_query_response("BLEINIT")?;
+        match role.as_str() {
+            "0" => Ok(None),
+            "1" => Ok(Some(BleRole::Client)),
+            "2" => Ok(Some(BleRole::Server)),
+            _ => Err(nb::Error::Other(Error::UnexpectedResponse)),
+        }
+    }
+
+    /// Returns the address that the module uses, which is the public address unless a random
+    /// one has been set.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// let script = [Exchange::new(
+    ///     b"AT+BLEADDR?\r\n",
+    ///     b"+BLEADDR:\"24:0a:c4:d6:e4:46\"\r\n\r\nOK\r\n",
+    /// )];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Ble.into());
+    ///
+    /// let address = nb::block!(esp32_at.get_ble_address()).unwrap();
+    /// assert_eq!([0x24, 0x0a, 0xc4, 0xd6, 0xe4, 0x46], address);
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn get_ble_address(&mut self) -> nb::Result<[u8; 6], Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::Ble)?;
+        write_command!(self, "AT+BLEADDR?")?;
+        let address = self.read_query_response("BLEADDR")?;
+        Ok(parse_mac(parser::unquote(&address))?)
+    }
+
+    /// Makes the module use the static random `address` instead of its public address, e.g. to
+    /// keep the identity of a product when the module is replaced.
+    ///
+    /// A static random address has the two most significant bits set, and at least one of the
+    /// other bits set and one cleared; other addresses fail with `Error::InvalidArgument`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// let script = [Exchange::new(
+    ///     b"AT+BLEADDR=1,\"c0:00:00:00:12:34\"\r\n",
+    ///     b"\r\nOK\r\n",
+    /// )];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Ble.into());
+    ///
+    /// let address = [0xc0, 0, 0, 0, 0x12, 0x34];
+    /// nb::block!(esp32_at.set_ble_random_address(address)).unwrap();
+    /// assert!(esp32_at.set_ble_random_address([0x40, 0, 0, 0, 0x12, 0x34]).is_err());
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn set_ble_random_address(
+        &mut self,
+        address: [u8; 6],
+    ) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::Ble)?;
+        if !is_static_random(address) {
+            return Err(nb::Error::Other(Error::InvalidArgument));
+        }
+        write_command!(self, "AT+BLEADDR=1,\"{}\"", Mac(address))?;
+        self.expect_ok_response()
+    }
+
+    /// Makes the module use its public address again.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// let script = [Exchange::new(b"AT+BLEADDR=0\r\n", b"\r\nOK\r\n")];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Ble.into());
+    ///
+    /// nb::block!(esp32_at.set_ble_public_address()).unwrap();
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn set_ble_public_address(&mut self) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::Ble)?;
+        write_command!(self, "AT+BLEADDR=0")?;
+        self.expect_ok_response()
+    }
+
+    /// Sets the GAP device name, e.g. to include the serial number of the unit; it is at most
+    /// `MAX_NAME_LEN` bytes long.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// let script = [
+    ///     Exchange::new(b"AT+BLENAME=\"sensor-00142\"\r\n", b"\r\nOK\r\n"),
+    ///     Exchange::new(b"AT+BLENAME?\r\n", b"+BLENAME:sensor-00142\r\n\r\nOK\r\n"),
+    /// ];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Ble.into());
+    ///
+    /// nb::block!(esp32_at.set_ble_name("sensor-00142")).unwrap();
+    /// assert_eq!("sensor-00142", nb::block!(esp32_at.get_ble_name()).unwrap());
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn set_ble_name(&mut self, name: &str) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::Ble)?;
+        if name.is_empty() || name.len() > MAX_NAME_LEN {
+            return Err(nb::Error::Other(Error::InvalidArgument));
+        }
+        write_command!(self, "AT+BLENAME=\"{}\"", Escaped(name))?;
+        self.expect_ok_response()
+    }
+
+    pub fn get_ble_name(&mut self) -> nb::Result<DeviceName, Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::Ble)?;
+        write_command!(self, "AT+BLENAME?")?;
+        let name = self.read_query_response("BLENAME")?;
+        Ok(parser::string(parser::unquote(&name)).ok_or(Error::UnexpectedResponse)?)
+    }
+
+    /// Configures how `ble_scan` scans, trading energy use against how quickly devices are
+    /// found; BLE must have been initialized as a client.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// use esp_at::ble::{OwnAddressType, ScanFilterPolicy, ScanParameters, ScanType};
+    /// let script = [
+    ///     Exchange::new(b"AT+BLESCANPARAM=1,0,0,160,48\r\n", b"\r\nOK\r\n"),
+    ///     Exchange::new(
+    ///         b"AT+BLESCANPARAM?\r\n",
+    ///         b"+BLESCANPARAM:1,0,0,160,48\r\n\r\nOK\r\n",
+    ///     ),
+    /// ];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Ble.into());
+    ///
+    /// // Listen for 30 ms every 100 ms
+    /// let parameters = ScanParameters {
+    ///     scan_type: ScanType::Active,
+    ///     own_address_type: OwnAddressType::Public,
+    ///     filter_policy: ScanFilterPolicy::AllowAll,
+    ///     interval: 160,
+    ///     window: 48,
+    /// };
+    /// nb::block!(esp32_at.set_ble_scan_parameters(&parameters)).unwrap();
+    /// assert_eq!(parameters, nb::block!(esp32_at.get_ble_scan_parameters()).unwrap());
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn set_ble_scan_parameters(
+        &mut self,
+        parameters: &ScanParameters,
+    ) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::Ble)?;
+        let valid_intervals = MIN_SCAN_INTERVAL..=MAX_SCAN_INTERVAL;
+        if !valid_intervals.contains(&parameters.interval)
+            || !valid_intervals.contains(&parameters.window)
+            || parameters.window > parameters.interval
+        {
+            return Err(nb::Error::Other(Error::InvalidArgument));
+        }
+        write_command!(
+            self,
+            "AT+BLESCANPARAM={},{},{},{},{}",
+            parameters.scan_type.code(),
+            parameters.own_address_type.code(),
+            parameters.filter_policy.code(),
+            parameters.interval,
+            parameters.window
+        )?;
+        self.expect_ok_response()
+    }
+
+    pub fn get_ble_scan_parameters(
+        &mut self,
+    ) -> nb::Result<ScanParameters, Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::Ble)?;
+        write_command!(self, "AT+BLESCANPARAM?")?;
+        let parameters = self.read_query_response("BLESCANPARAM")?;
+        Ok(ScanParameters::parse(&parameters).ok_or(Error::UnexpectedResponse)?)
+    }
+
+    /// Scans for `duration_secs` seconds, passing each advertisement received to `on_result`;
+    /// BLE must have been initialized as a client.
+    ///
+    /// A device is reported every time it advertises, so most are reported many times.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// use esp_at::ble::{ad_structures, AdStructure, AddressType};
+    /// let script = [
+    ///     Exchange::new(b"AT+BLESCAN=1,3\r\n", b"\r\nOK\r\n"),
+    ///     Exchange::unsolicited(
+    ///         b"+BLESCAN:\"5b:3b:6c:51:90:49\",-87,0201060509546167,,1\r\n\
+    ///           +BLESCAN:\"24:0a:c4:01:02:03\",-52,020106,05094c616d70,0\r\n\
+    ///           +BLESCANDONE\r\n",
+    ///     ),
+    /// ];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Ble.into());
+    ///
+    /// let mut found = None;
+    /// esp32_at
+    ///     .ble_scan(3, |result| {
+    ///         let named_lamp = ad_structures(&result.scan_response)
+    ///             .any(|structure| structure == AdStructure::CompleteName("Lamp"));
+    ///         if named_lamp {
+    ///             found = Some((result.address, result.address_type, result.rssi));
+    ///         }
+    ///     })
+    ///     .unwrap();
+    /// assert_eq!(
+    ///     Some(([0x24, 0x0a, 0xc4, 1, 2, 3], AddressType::Public, -52)),
+    ///     found
+    /// );
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn ble_scan<F>(
+        &mut self,
+        duration_secs: u8,
+        mut on_result: F,
+    ) -> Result<(), Error<RX::Error, TX::Error>>
+    where
+        F: FnMut(&ScanResult),
+    {
+        self.require_command_set(CommandSet::Ble)?;
+        // A duration of 0 would scan until stopped
+        if duration_secs == 0 {
+            return Err(Error::InvalidArgument);
+        }
+        nb::block!(write_command!(self, "AT+BLESCAN=1,{}", duration_secs))?;
+        nb::block!(self.expect_ok_response())?;
+
+        loop {
+            let line: Line = nb::block!(self.read_line())?;
+            if line == "+BLESCANDONE" {
+                return Ok(());
+            }
+            match parser::response(&line, "BLESCAN").and_then(ScanResult::parse) {
+                Some(result) => on_result(&result),
+                None => self.queue_event(&line),
+            }
+        }
+    }
+
+    /// Configures how the module advertises; BLE must have been initialized as a server.
+    ///
+    /// Directed advertising types need a `peer`, which the others must not have.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// use esp_at::ble::{
+    ///     AdvertisingChannel, AdvertisingFilterPolicy, AdvertisingParameters, AdvertisingType,
+    ///     OwnAddressType,
+    /// };
+    /// let script = [
+    ///     Exchange::new(b"AT+BLEADVPARAM=160,320,0,0,7,0\r\n", b"\r\nOK\r\n"),
+    ///     Exchange::new(
+    ///         b"AT+BLEADVPARAM?\r\n",
+    ///         b"+BLEADVPARAM:160,320,0,0,7,0,0,\"00:00:00:00:00:00\"\r\n\r\nOK\r\n",
+    ///     ),
+    /// ];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Ble.into());
+    ///
+    /// // Advertise every 100 to 200 ms
+    /// let parameters = AdvertisingParameters {
+    ///     min_interval: 160,
+    ///     max_interval: 320,
+    ///     advertising_type: AdvertisingType::ConnectableUndirected,
+    ///     own_address_type: OwnAddressType::Public,
+    ///     channels: enumset::EnumSet::all(),
+    ///     filter_policy: AdvertisingFilterPolicy::AllowAll,
+    ///     peer: None,
+    /// };
+    /// nb::block!(esp32_at.set_ble_advertising_parameters(&parameters)).unwrap();
+    /// assert_eq!(
+    ///     parameters,
+    ///     nb::block!(esp32_at.get_ble_advertising_parameters()).unwrap()
+    /// );
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn set_ble_advertising_parameters(
+        &mut self,
+        parameters: &AdvertisingParameters,
+    ) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::Ble)?;
+        let valid_intervals = MIN_ADVERTISING_INTERVAL..=MAX_ADVERTISING_INTERVAL;
+        if !valid_intervals.contains(&parameters.min_interval)
+            || !valid_intervals.contains(&parameters.max_interval)
+            || parameters.min_interval > parameters.max_interval
+            || parameters.channels.is_empty()
+            || parameters.advertising_type.is_directed() != parameters.peer.is_some()
+        {
+            return Err(nb::Error::Other(Error::InvalidArgument));
+        }
+        write_command!(
+            self,
+            "AT+BLEADVPARAM={},{},{},{},{},{}{}",
+            parameters.min_interval,
+            parameters.max_interval,
+            parameters.advertising_type.code(),
+            parameters.own_address_type.code(),
+            parameters.channels.to_bits(),
+            parameters.filter_policy.code(),
+            Peer(parameters.peer)
+        )?;
+        self.expect_ok_response()
+    }
+
+    pub fn get_ble_advertising_parameters(
+        &mut self,
+    ) -> nb::Result<AdvertisingParameters, Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::Ble)?;
+        write_command!(self, "AT+BLEADVPARAM?")?;
+        let parameters = self.read_query_response("BLEADVPARAM")?;
+        Ok(AdvertisingParameters::parse(&parameters).ok_or(Error::UnexpectedResponse)?)
+    }
+
+    /// Sets the advertising data to `data`, which is made up of AD structures and is at most
+    /// `MAX_ADVERTISING_DATA_LEN` bytes long.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// let script = [Exchange::new(
+    ///     b"AT+BLEADVDATA=\"02010605094C616D70\"\r\n",
+    ///     b"\r\nOK\r\n",
+    /// )];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Ble.into());
+    ///
+    /// // Flags, and the complete name "Lamp"
+    /// let data = b"\x02\x01\x06\x05\x09Lamp";
+    /// nb::block!(esp32_at.set_ble_advertising_data(data)).unwrap();
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn set_ble_advertising_data(
+        &mut self,
+        data: &[u8],
+    ) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::Ble)?;
+        if data.len() > MAX_ADVERTISING_DATA_LEN {
+            return Err(nb::Error::Other(Error::InvalidArgument));
+        }
+        write_command!(self, "AT+BLEADVDATA=\"{}\"", Hex(data))?;
+        self.expect_ok_response()
+    }
+
+    /// Lets the module build the advertising data from the most common AD structures, instead
+    /// of setting it with `set_ble_advertising_data`.
+    ///
+    /// The flags are always included; all of it has to fit in `MAX_ADVERTISING_DATA_LEN`
+    /// bytes, otherwise this fails with `Error::CommandFailed`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// use esp_at::ble::{AdvertisingDataEx, Uuid};
+    /// let script = [Exchange::new(
+    ///     b"AT+BLEADVDATAEX=\"Lamp\",\"180F\",\"590001\",1\r\n",
+    ///     b"\r\nOK\r\n",
+    /// )];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Ble.into());
+    ///
+    /// let data = AdvertisingDataEx {
+    ///     name: "Lamp",
+    ///     service_uuid: Uuid::Uuid16(0x180f),
+    ///     manufacturer_data: &[0x59, 0x00, 0x01],
+    ///     include_tx_power: true,
+    /// };
+    /// nb::block!(esp32_at.set_ble_advertising_data_ex(&data)).unwrap();
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn set_ble_advertising_data_ex(
+        &mut self,
+        data: &AdvertisingDataEx,
+    ) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::Ble)?;
+        if data.name.len() > MAX_NAME_LEN || data.manufacturer_data.len() > MAX_ADVERTISING_DATA_LEN
+        {
+            return Err(nb::Error::Other(Error::InvalidArgument));
+        }
+        write_command!(
+            self,
+            "AT+BLEADVDATAEX=\"{}\",\"{}\",\"{}\",{}",
+            Escaped(data.name),
+            data.service_uuid,
+            Hex(data.manufacturer_data),
+            data.include_tx_power as u8
+        )?;
+        self.expect_ok_response()
+    }
+
+    /// Starts advertising; a central connecting is reported as `Event::BleConnected`, after
+    /// which the module stops advertising.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// use esp_at::event::Event;
+    /// let script = [
+    ///     Exchange::new(b"AT+BLEADVSTART\r\n", b"\r\nOK\r\n"),
+    ///     Exchange::unsolicited(b"+BLECONN:0,\"60:51:42:fe:98:aa\"\r\n"),
+    /// ];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Ble.into());
+    ///
+    /// nb::block!(esp32_at.ble_start_advertising()).unwrap();
+    /// assert_eq!(
+    ///     Event::BleConnected {
+    ///         conn_index: 0,
+    ///         address: [0x60, 0x51, 0x42, 0xfe, 0x98, 0xaa],
+    ///     },
+    ///     nb::block!(esp32_at.poll_event()).unwrap()
+    /// );
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn ble_start_advertising(&mut self) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::Ble)?;
+        write_command!(self, "AT+BLEADVSTART")?;
+        self.expect_ok_response()
+    }
+
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// let script = [Exchange::new(b"AT+BLEADVSTOP\r\n", b"\r\nOK\r\n")];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Ble.into());
+    ///
+    /// nb::block!(esp32_at.ble_stop_advertising()).unwrap();
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn ble_stop_advertising(&mut self) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::Ble)?;
+        write_command!(self, "AT+BLEADVSTOP")?;
+        self.expect_ok_response()
+    }
+
+    /// Starts advertising as the iBeacon `beacon` every `interval`, in units of 0.625 ms, which
+    /// sets the advertising parameters and data; BLE must have been initialized as a server.
+    ///
+    /// Since iBeacons only broadcast, centrals can't connect.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// use esp_at::ble::IBeacon;
+    /// let script = [
+    ///     Exchange::new(b"AT+BLEADVPARAM=160,160,3,0,7,0\r\n", b"\r\nOK\r\n"),
+    ///     Exchange::new(
+    ///         b"AT+BLEADVDATA=\"0201061AFF4C000215FDA50693A4E24FB1AFCFC6EB0764782527114CB9C5\"\r\n",
+    ///         b"\r\nOK\r\n",
+    ///     ),
+    ///     Exchange::new(b"AT+BLEADVSTART\r\n", b"\r\nOK\r\n"),
+    /// ];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Ble.into());
+    ///
+    /// let beacon = IBeacon {
+    ///     uuid: [
+    ///         0xfd, 0xa5, 0x06, 0x93, 0xa4, 0xe2, 0x4f, 0xb1, 0xaf, 0xcf, 0xc6, 0xeb, 0x07, 0x64,
+    ///         0x78, 0x25,
+    ///     ],
+    ///     major: 10001,
+    ///     minor: 19641,
+    ///     measured_power: -59,
+    /// };
+    /// // Advertise every 100 ms
+    /// nb::block!(esp32_at.ble_start_ibeacon(&beacon, 160)).unwrap();
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn ble_start_ibeacon(
+        &mut self,
+        beacon: &IBeacon,
+        interval: u16,
+    ) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        let parameters = AdvertisingParameters {
+            min_interval: interval,
+            max_interval: interval,
+            advertising_type: AdvertisingType::NonConnectableUndirected,
+            own_address_type: OwnAddressType::Public,
+            channels: enumset::EnumSet::all(),
+            filter_policy: AdvertisingFilterPolicy::AllowAll,
+            peer: None,
+        };
+        self.set_ble_advertising_parameters(&parameters)?;
+        self.set_ble_advertising_data(&beacon.advertising_data())?;
+        self.ble_start_advertising()
+    }
+
+    /// Connects to the peripheral at `address` as a central, waiting at most `timeout_secs`,
+    /// and returns the connection index that the connection is referred to by.
+    ///
+    /// The lowest connection index that isn't in use is picked; if they all are, this fails
+    /// with `Error::NoFreeLink`.  A connection that couldn't be established fails with
+    /// `Error::ConnectFailed`, and is also reported as `Event::BleConnectFailed`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// use esp_at::ble::AddressType;
+    /// let script = [
+    ///     Exchange::new(
+    ///         b"AT+BLECONN=0,\"24:0a:c4:01:02:03\",0,10\r\n",
+    ///         b"+BLECONN:0,\"24:0a:c4:01:02:03\"\r\n\r\nOK\r\n",
+    ///     ),
+    ///     Exchange::new(
+    ///         b"AT+BLECONN=1,\"c0:00:00:00:12:34\",1,10\r\n",
+    ///         b"+BLECONN:1,-1\r\n\r\nERROR\r\n",
+    ///     ),
+    /// ];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Ble.into());
+    ///
+    /// let lamp = [0x24, 0x0a, 0xc4, 0x01, 0x02, 0x03];
+    /// let conn_index = nb::block!(esp32_at.ble_connect(lamp, AddressType::Public, 10)).unwrap();
+    /// assert_eq!(0, conn_index);
+    ///
+    /// let sensor = [0xc0, 0, 0, 0, 0x12, 0x34];
+    /// assert!(nb::block!(esp32_at.ble_connect(sensor, AddressType::Random, 10)).is_err());
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn ble_connect(
+        &mut self,
+        address: [u8; 6],
+        address_type: AddressType,
+        timeout_secs: u8,
+    ) -> nb::Result<u8, Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::Ble)?;
+        if !(MIN_CONNECT_TIMEOUT_SECS..=MAX_CONNECT_TIMEOUT_SECS).contains(&timeout_secs) {
+            return Err(nb::Error::Other(Error::InvalidArgument));
+        }
+        let conn_index = (0..MAX_CONNECTIONS)
+            .find(|&index| self.ble_connections & 1 << index == 0)
+            .ok_or(Error::NoFreeLink)?;
+        write_command!(
+            self,
+            "AT+BLECONN={},\"{}\",{},{}",
+            conn_index,
+            Mac(address),
+            address_type.code(),
+            timeout_secs
+        )?;
+        match self.expect_ok_response() {
+            Ok(()) => Ok(conn_index),
+            Err(nb::Error::Other(Error::CommandFailed)) => {
+                Err(nb::Error::Other(Error::ConnectFailed))
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Closes the BLE connection `conn_index`, which is reported as `Event::BleDisconnected`
+    /// once it has been.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// use esp_at::event::Event;
+    /// let script = [
+    ///     Exchange::new(b"AT+BLEDISCONN=0\r\n", b"\r\nOK\r\n"),
+    ///     Exchange::unsolicited(b"+BLEDISCONN:0,\"24:0a:c4:01:02:03\"\r\n"),
+    /// ];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Ble.into());
+    ///
+    /// nb::block!(esp32_at.ble_disconnect(0)).unwrap();
+    /// assert_eq!(
+    ///     Event::BleDisconnected {
+    ///         conn_index: 0,
+    ///         address: [0x24, 0x0a, 0xc4, 0x01, 0x02, 0x03],
+    ///     },
+    ///     nb::block!(esp32_at.poll_event()).unwrap()
+    /// );
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn ble_disconnect(
+        &mut self,
+        conn_index: u8,
+    ) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::Ble)?;
+        if conn_index >= MAX_CONNECTIONS {
+            return Err(nb::Error::Other(Error::InvalidArgument));
+        }
+        write_command!(self, "AT+BLEDISCONN={}", conn_index)?;
+        self.expect_ok_response()
+    }
+
+    /// Returns the established BLE connections, with the address of the peer of each, which also
+    /// brings the connection indexes in use up to date.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// let script = [Exchange::new(
+    ///     b"AT+BLECONN?\r\n",
+    ///     b"+BLECONN:0,\"24:0a:c4:01:02:03\"\r\n+BLECONN:2,\"60:51:42:fe:98:aa\"\r\n\r\nOK\r\n",
+    /// )];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Ble.into());
+    ///
+    /// let connections = nb::block!(esp32_at.get_ble_connections()).unwrap();
+    /// assert_eq!(
+    ///     &[
+    ///         (0, [0x24, 0x0a, 0xc4, 0x01, 0x02, 0x03]),
+    ///         (2, [0x60, 0x51, 0x42, 0xfe, 0x98, 0xaa]),
+    ///     ],
+    ///     &connections[..]
+    /// );
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn get_ble_connections(&mut self) -> nb::Result<Connections, Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::Ble)?;
+        write_command!(self, "AT+BLECONN?")?;
+        let mut connections = heapless::Vec::new();
+        let mut in_use = 0;
+        self.read_ble_query_response("BLECONN", |arguments| {
+            let mut fields = parser::fields(arguments);
+            let conn_index: u8 = fields.next()?.parse().ok()?;
+            let address = parse_octets(parser::unquote(fields.next()?))?;
+            if conn_index < MAX_CONNECTIONS {
+                in_use |= 1 << conn_index;
+            }
+            connections.push((conn_index, address)).ok()
+        })?;
+        self.ble_connections = in_use;
+        Ok(connections)
+    }
+
+    /// Requests the parameters of the BLE connection `conn_index` to be updated, e.g. a short
+    /// interval while transferring data and a long one with some latency while idle.
+    ///
+    /// The outcome is reported as `Event::BleConnectionUpdated` or
+    /// `Event::BleConnectionUpdateFailed` once the peer has responded.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// use esp_at::ble::ConnectionParameters;
+    /// use esp_at::event::Event;
+    /// let script = [
+    ///     Exchange::new(b"AT+BLECONNPARAM=0,400,800,2,1000\r\n", b"\r\nOK\r\n"),
+    ///     Exchange::unsolicited(b"+BLECONNPARAM:0,400,800,800,2,1000\r\n"),
+    /// ];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Ble.into());
+    ///
+    /// // Idle at a 0.5 to 1 s interval, skipping up to 2 connection events
+    /// let parameters = ConnectionParameters {
+    ///     min_interval: 400,
+    ///     max_interval: 800,
+    ///     latency: 2,
+    ///     timeout: 1000,
+    /// };
+    /// nb::block!(esp32_at.ble_update_connection_parameters(0, &parameters)).unwrap();
+    /// assert_eq!(
+    ///     Event::BleConnectionUpdated {
+    ///         conn_index: 0,
+    ///         interval: 800,
+    ///         latency: 2,
+    ///         timeout: 1000,
+    ///     },
+    ///     nb::block!(esp32_at.poll_event()).unwrap()
+    /// );
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn ble_update_connection_parameters(
+        &mut self,
+        conn_index: u8,
+        parameters: &ConnectionParameters,
+    ) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::Ble)?;
+        if conn_index >= MAX_CONNECTIONS || !parameters.is_valid() {
+            return Err(nb::Error::Other(Error::InvalidArgument));
+        }
+        write_command!(
+            self,
+            "AT+BLECONNPARAM={},{},{},{},{}",
+            conn_index,
+            parameters.min_interval,
+            parameters.max_interval,
+            parameters.latency,
+            parameters.timeout
+        )?;
+        self.expect_ok_response()
+    }
+
+    /// Returns the parameters of the BLE connection `conn_index`, or `None` if there is no such
+    /// connection.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// let script = [Exchange::new(
+    ///     b"AT+BLECONNPARAM?\r\n",
+    ///     b"+BLECONNPARAM:0,24,40,40,0,500\r\n+BLECONNPARAM:1,6,6,6,0,100\r\n\r\nOK\r\n",
+    /// )];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Ble.into());
+    ///
+    /// let parameters = nb::block!(esp32_at.get_ble_connection_parameters(1)).unwrap().unwrap();
+    /// assert_eq!(6, parameters.interval);
+    /// assert_eq!(100, parameters.requested.timeout);
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn get_ble_connection_parameters(
+        &mut self,
+        conn_index: u8,
+    ) -> nb::Result<Option<CurrentConnectionParameters>, Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::Ble)?;
+        write_command!(self, "AT+BLECONNPARAM?")?;
+        let mut parameters = None;
+        self.read_ble_query_response("BLECONNPARAM", |arguments| {
+            let mut fields = parser::fields(arguments);
+            let index: u8 = fields.next()?.parse().ok()?;
+            let current = CurrentConnectionParameters::parse(fields)?;
+            if index == conn_index {
+                parameters = Some(current);
+            }
+            Some(())
+        })?;
+        Ok(parameters)
+    }
+
+    /// Requests an ATT MTU of `mtu` for the BLE connection `conn_index`, so that GATT values
+    /// longer than the default of 20 bytes can be transferred; BLE must have been initialized as
+    /// a client.
+    ///
+    /// The MTU that the peer agrees to is reported as `Event::BleMtuChanged`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// use esp_at::event::Event;
+    /// let script = [
+    ///     Exchange::new(b"AT+BLECFGMTU=0,247\r\n", b"\r\nOK\r\n"),
+    ///     Exchange::unsolicited(b"+BLECFGMTU:0,185\r\n"),
+    /// ];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Ble.into());
+    ///
+    /// nb::block!(esp32_at.ble_exchange_mtu(0, 247)).unwrap();
+    /// assert_eq!(
+    ///     Event::BleMtuChanged {
+    ///         conn_index: 0,
+    ///         mtu: 185,
+    ///     },
+    ///     nb::block!(esp32_at.poll_event()).unwrap()
+    /// );
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn ble_exchange_mtu(
+        &mut self,
+        conn_index: u8,
+        mtu: u16,
+    ) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::Ble)?;
+        if conn_index >= MAX_CONNECTIONS || !(MIN_MTU..=MAX_MTU).contains(&mtu) {
+            return Err(nb::Error::Other(Error::InvalidArgument));
+        }
+        write_command!(self, "AT+BLECFGMTU={},{}", conn_index, mtu)?;
+        self.expect_ok_response()
+    }
+
+    /// Returns the ATT MTU of the BLE connection `conn_index`, or `None` if there is no such
+    /// connection.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// let script = [Exchange::new(
+    ///     b"AT+BLECFGMTU?\r\n",
+    ///     b"+BLECFGMTU:0,23\r\n+BLECFGMTU:1,185\r\n\r\nOK\r\n",
+    /// )];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Ble.into());
+    ///
+    /// assert_eq!(Some(185), nb::block!(esp32_at.get_ble_mtu(1)).unwrap());
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn get_ble_mtu(
+        &mut self,
+        conn_index: u8,
+    ) -> nb::Result<Option<u16>, Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::Ble)?;
+        write_command!(self, "AT+BLECFGMTU?")?;
+        let mut mtu = None;
+        self.read_ble_query_response("BLECFGMTU", |arguments| {
+            let mut fields = parser::fields(arguments);
+            let index: u8 = fields.next()?.parse().ok()?;
+            let size = fields.next()?.parse().ok()?;
+            if index == conn_index {
+                mtu = Some(size);
+            }
+            Some(())
+        })?;
+        Ok(mtu)
+    }
+
+    /// Sets the largest payload of the link layer packets of the BLE connection `conn_index`,
+    /// which with the data length extension can be raised to `MAX_PACKET_DATA_LEN` so that
+    /// large GATT values don't have to be fragmented.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// use esp_at::ble::MAX_PACKET_DATA_LEN;
+    /// let script = [Exchange::new(b"AT+BLEDATALEN=0,251\r\n", b"\r\nOK\r\n")];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Ble.into());
+    ///
+    /// nb::block!(esp32_at.set_ble_data_len(0, MAX_PACKET_DATA_LEN)).unwrap();
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn set_ble_data_len(
+        &mut self,
+        conn_index: u8,
+        len: u16,
+    ) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::Ble)?;
+        if conn_index >= MAX_CONNECTIONS
+            || !(MIN_PACKET_DATA_LEN..=MAX_PACKET_DATA_LEN).contains(&len)
+        {
+            return Err(nb::Error::Other(Error::InvalidArgument));
+        }
+        write_command!(self, "AT+BLEDATALEN={},{}", conn_index, len)?;
+        self.expect_ok_response()
+    }
+
+    /// Sets how pairing is done, both when the module starts it with `Esp32At::ble_encrypt` and
+    /// when the peer does.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// use esp_at::ble::{BondingKey, IoCapability, SecurityParameters};
+    /// let script = [
+    ///     Exchange::new(b"AT+BLESECPARAM=13,2,16,3,3\r\n", b"\r\nOK\r\n"),
+    ///     Exchange::new(
+    ///         b"AT+BLESECPARAM?\r\n",
+    ///         b"+BLESECPARAM:13,2,16,3,3,0\r\n\r\nOK\r\n",
+    ///     ),
+    /// ];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Ble.into());
+    ///
+    /// // Bond using a passkey entered on the keyboard of the module
+    /// let keys = BondingKey::Encryption | BondingKey::Identity;
+    /// let parameters = SecurityParameters {
+    ///     bonding: true,
+    ///     mitm_protection: true,
+    ///     secure_connections_only: true,
+    ///     io_capability: IoCapability::KeyboardOnly,
+    ///     key_size: 16,
+    ///     initiator_keys: keys,
+    ///     responder_keys: keys,
+    /// };
+    /// nb::block!(esp32_at.set_ble_security_parameters(&parameters)).unwrap();
+    /// assert_eq!(
+    ///     parameters,
+    ///     nb::block!(esp32_at.get_ble_security_parameters()).unwrap()
+    /// );
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn set_ble_security_parameters(
+        &mut self,
+        parameters: &SecurityParameters,
+    ) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::Ble)?;
+        if !(MIN_KEY_SIZE..=MAX_KEY_SIZE).contains(&parameters.key_size) {
+            return Err(nb::Error::Other(Error::InvalidArgument));
+        }
+        write_command!(
+            self,
+            "AT+BLESECPARAM={},{},{},{},{}",
+            parameters.auth_req(),
+            parameters.io_capability.code(),
+            parameters.key_size,
+            parameters.initiator_keys.to_bits(),
+            parameters.responder_keys.to_bits()
+        )?;
+        self.expect_ok_response()
+    }
+
+    pub fn get_ble_security_parameters(
+        &mut self,
+    ) -> nb::Result<SecurityParameters, Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::Ble)?;
+        write_command!(self, "AT+BLESECPARAM?")?;
+        let parameters = self.read_query_response("BLESECPARAM")?;
+        Ok(SecurityParameters::parse(&parameters).ok_or(Error::UnexpectedResponse)?)
+    }
+
+    /// Starts encrypting the BLE connection `conn_index`, pairing with the peer first unless
+    /// they are bonded.
+    ///
+    /// The outcome is reported as `Event::BleAuthenticated` or `Event::BleAuthenticationFailed`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// use esp_at::ble::Encryption;
+    /// use esp_at::event::Event;
+    /// let script = [
+    ///     Exchange::new(b"AT+BLEENC=0,3\r\n", b"\r\nOK\r\n"),
+    ///     Exchange::unsolicited(b"+BLEAUTHCMPL:0,0\r\n"),
+    /// ];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Ble.into());
+    ///
+    /// nb::block!(esp32_at.ble_encrypt(0, Encryption::EncryptMitm)).unwrap();
+    /// assert_eq!(
+    ///     Event::BleAuthenticated { conn_index: 0 },
+    ///     nb::block!(esp32_at.poll_event()).unwrap()
+    /// );
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn ble_encrypt(
+        &mut self,
+        conn_index: u8,
+        encryption: Encryption,
+    ) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::Ble)?;
+        if conn_index >= MAX_CONNECTIONS {
+            return Err(nb::Error::Other(Error::InvalidArgument));
+        }
+        write_command!(self, "AT+BLEENC={},{}", conn_index, encryption.code())?;
+        self.expect_ok_response()
+    }
+
+    /// Accepts or rejects the request of the peer on the BLE connection `conn_index` to encrypt
+    /// it, see `Event::BleSecurityRequested`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// use esp_at::event::Event;
+    /// let script = [
+    ///     Exchange::unsolicited(b"+BLESECREQ:0\r\n"),
+    ///     Exchange::new(b"AT+BLEENCRSP=0,1\r\n", b"\r\nOK\r\n"),
+    /// ];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Ble.into());
+    ///
+    /// if let Event::BleSecurityRequested { conn_index } = nb::block!(esp32_at.poll_event()).unwrap() {
+    ///     nb::block!(esp32_at.ble_respond_to_security_request(conn_index, true)).unwrap();
+    /// }
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn ble_respond_to_security_request(
+        &mut self,
+        conn_index: u8,
+        accept: bool,
+    ) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::Ble)?;
+        if conn_index >= MAX_CONNECTIONS {
+            return Err(nb::Error::Other(Error::InvalidArgument));
+        }
+        write_command!(self, "AT+BLEENCRSP={},{}", conn_index, u8::from(accept))?;
+        self.expect_ok_response()
+    }
+
+    /// Replies with the passkey that the user entered, see `Event::BlePasskeyRequested`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// use esp_at::event::Event;
+    /// let script = [
+    ///     Exchange::unsolicited(b"+BLESECKEYREQ:1\r\n"),
+    ///     Exchange::new(b"AT+BLEKEYREPLY=1,2468\r\n", b"\r\nOK\r\n"),
+    /// ];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Ble.into());
+    ///
+    /// if let Event::BlePasskeyRequested { conn_index } = nb::block!(esp32_at.poll_event()).unwrap() {
+    ///     // e.g. read from a keypad, where it is entered as 002468
+    ///     let passkey = 2468;
+    ///     nb::block!(esp32_at.ble_reply_passkey(conn_index, passkey)).unwrap();
+    /// }
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn ble_reply_passkey(
+        &mut self,
+        conn_index: u8,
+        passkey: u32,
+    ) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::Ble)?;
+        if conn_index >= MAX_CONNECTIONS || passkey > MAX_PASSKEY {
+            return Err(nb::Error::Other(Error::InvalidArgument));
+        }
+        write_command!(self, "AT+BLEKEYREPLY={},{}", conn_index, passkey)?;
+        self.expect_ok_response()
+    }
+
+    /// Replies whether the user confirmed that the same passkey is displayed on both devices,
+    /// see `Event::BlePasskeyConfirmationRequested`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// use esp_at::event::Event;
+    /// let script = [
+    ///     Exchange::unsolicited(b"+BLECFMREQ:0,135790\r\n"),
+    ///     Exchange::new(b"AT+BLECONFREPLY=0,1\r\n", b"\r\nOK\r\n"),
+    /// ];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Ble.into());
+    ///
+    /// match nb::block!(esp32_at.poll_event()).unwrap() {
+    ///     Event::BlePasskeyConfirmationRequested {
+    ///         conn_index,
+    ///         passkey,
+    ///     } => {
+    ///         // e.g. show the passkey and wait for a button to be pressed
+    ///         assert_eq!(135790, passkey);
+    ///         nb::block!(esp32_at.ble_confirm_passkey(conn_index, true)).unwrap();
+    ///     }
+    ///     event => panic!("unexpected event: {:?}", event),
+    /// }
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn ble_confirm_passkey(
+        &mut self,
+        conn_index: u8,
+        confirmed: bool,
+    ) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::Ble)?;
+        if conn_index >= MAX_CONNECTIONS {
+            return Err(nb::Error::Other(Error::InvalidArgument));
+        }
+        write_command!(
+            self,
+            "AT+BLECONFREPLY={},{}",
+            conn_index,
+            u8::from(confirmed)
+        )?;
+        self.expect_ok_response()
+    }
+
+    /// Returns the devices that the module is bonded with, whose keys it has stored.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// let script = [Exchange::new(
+    ///     b"AT+BLEENCDEV?\r\n",
+    ///     b"+BLEENCDEV:0,\"60:51:42:fe:98:aa\"\r\n+BLEENCDEV:1,\"24:0a:c4:01:02:03\"\r\n\r\nOK\r\n",
+    /// )];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Ble.into());
+    ///
+    /// let bonded = nb::block!(esp32_at.get_ble_bonded_devices()).unwrap();
+    /// assert_eq!((1, [0x24, 0x0a, 0xc4, 0x01, 0x02, 0x03]), bonded[1]);
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn get_ble_bonded_devices(
+        &mut self,
+    ) -> nb::Result<BondedDevices, Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::Ble)?;
+        write_command!(self, "AT+BLEENCDEV?")?;
+        let mut devices = heapless::Vec::new();
+        self.read_response(|line| {
+            if let Some(arguments) = parser::response(line, "BLEENCDEV") {
+                let mut fields = parser::fields(arguments);
+                let device = fields
+                    .next()
+                    .and_then(|index| index.parse().ok())
+                    .zip(fields.next().map(parser::unquote).and_then(parse_octets))
+                    .ok_or(Error::UnexpectedResponse)?;
+                devices.push(device).map_err(|_| Error::BufferOverflow)?;
+            }
+            Ok(())
+        })?;
+        Ok(devices)
+    }
+
+    /// Forgets the bonded device `index`, or all of them if it is `None`, so that they have to
+    /// pair again.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// let script = [
+    ///     Exchange::new(b"AT+BLEENCCLEAR=1\r\n", b"\r\nOK\r\n"),
+    ///     Exchange::new(b"AT+BLEENCCLEAR\r\n", b"\r\nOK\r\n"),
+    /// ];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Ble.into());
+    ///
+    /// nb::block!(esp32_at.ble_clear_bonds(Some(1))).unwrap();
+    /// nb::block!(esp32_at.ble_clear_bonds(None)).unwrap();
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn ble_clear_bonds(
+        &mut self,
+        index: Option<u8>,
+    ) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::Ble)?;
+        match index {
+            Some(index) => write_command!(self, "AT+BLEENCCLEAR={}", index)?,
+            None => write_command!(self, "AT+BLEENCCLEAR")?,
+        }
+        self.expect_ok_response()
+    }
+
+    /// Reads the response to a query whose `+<name>:` lines look like unsolicited messages,
+    /// passing their arguments to `on_arguments` instead of queueing them as events.
+    pub(crate) fn read_ble_query_response<F>(
+        &mut self,
+        name: &str,
+        mut on_arguments: F,
+    ) -> nb::Result<(), Error<RX::Error, TX::Error>>
+    where
+        F: FnMut(&str) -> Option<()>,
+    {
+        loop {
+            let line: Line = self.read_line()?;
+            match line.as_str() {
+                "OK" => return Ok(()),
+                "ERROR" => return Err(nb::Error::Other(Error::CommandFailed)),
+                line => match parser::response(line, name) {
+                    Some(arguments) => on_arguments(arguments).ok_or(Error::UnexpectedResponse)?,
+                    None => self.queue_event(line),
+                },
+            }
+        }
+    }
+
+    /// Keeps track of the BLE connection indexes in use.
+    pub(crate) fn track_ble_connection(&mut self, event: &Event) {
+        match *event {
+            Event::BleConnected { conn_index, .. } if conn_index < MAX_CONNECTIONS => {
+                self.ble_connections |= 1 << conn_index
+            }
+            Event::BleDisconnected { conn_index, .. } if conn_index < MAX_CONNECTIONS => {
+                self.ble_connections &= !(1 << conn_index)
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Formats the optional peer of directed advertising as the trailing arguments of
+/// `AT+BLEADVPARAM`.
+struct Peer(Option<([u8; 6], AddressType)>);
+
+impl fmt::Display for Peer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            Some((address, address_type)) => {
+                write!(f, ",{},\"{}\"", address_type.code(), Mac(address))
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+impl Uuid {
+    /// Parses a UUID as reported by the module, like `0x180F`, or 32 hex digits for 128-bit ones.
+    pub(crate) fn parse(uuid: &str) -> Option<Self> {
+        let digits = uuid.strip_prefix("0x").unwrap_or(uuid);
+        match digits.len() {
+            4 => Some(Uuid::Uuid16(u16::from_str_radix(digits, 16).ok()?)),
+            32 => {
+                let bytes: heapless::Vec<u8, heapless::consts::U16> = parse_hex(digits)?;
+                let mut uuid = [0; 16];
+                uuid.copy_from_slice(&bytes);
+                Some(Uuid::Uuid128(uuid))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Uuid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Uuid::Uuid16(uuid) => write!(f, "{:04X}", uuid),
+            Uuid::Uuid128(uuid) => write!(f, "{}", Hex(uuid)),
+        }
+    }
+}
+
+/// Formats binary data as hex digits, as the module expects e.g. advertising data.
+pub(crate) struct Hex<'a>(pub(crate) &'a [u8]);
+
+impl<'a> fmt::Display for Hex<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for byte in self.0 {
+            write!(f, "{:02X}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+/// Whether `address` is a valid static random address.
+fn is_static_random(address: [u8; 6]) -> bool {
+    // The two most significant bits are part of the address type, not of the random part
+    let random = [
+        address[0] & 0x3f,
+        address[1],
+        address[2],
+        address[3],
+        address[4],
+        address[5],
+    ];
+    address[0] & 0xc0 == 0xc0
+        && random.iter().any(|&byte| byte != 0)
+        && random != [0x3f, 0xff, 0xff, 0xff, 0xff, 0xff]
+}
+
+/// Parses the keys distributed while pairing, as a bitmask.
+fn parse_bonding_keys(keys: &str) -> Option<enumset::EnumSet<BondingKey>> {
+    let bits: u8 = keys.parse().ok()?;
+    let all = enumset::EnumSet::<BondingKey>::all().to_bits();
+    if u128::from(bits) & !all != 0 {
+        return None;
+    }
+    Some(enumset::EnumSet::from_bits(bits.into()))
+}
+
+/// Decodes hex encoded data, such as advertising data.
+pub(crate) fn parse_hex<N>(hex: &str) -> Option<heapless::Vec<u8, N>>
+where
+    N: heapless::ArrayLength<u8>,
+{
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+    let mut data = heapless::Vec::new();
+    for i in (0..hex.len()).step_by(2) {
+        let byte = u8::from_str_radix(hex.get(i..i + 2)?, 16).ok()?;
+        data.push(byte).ok()?;
+    }
+    Some(data)
+}
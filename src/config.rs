@@ -0,0 +1,138 @@
+use crate::sntp::SntpConfig;
+use crate::tcpip::{DnsConfig, ReceiveMode};
+use crate::wifi::WifiMode;
+use crate::{Error, Esp32At};
+
+/// A module setting that can be both changed and queried.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Setting {
+    WifiMode(WifiMode),
+    ReceiveMode(ReceiveMode),
+    MultipleConnections(bool),
+    /// The servers are only set if `manual`; otherwise those provided by DHCP are used.
+    Dns(DnsConfig),
+    /// The offset and servers are only set if `enabled`, and the firmware's default servers are
+    /// used if none are given.
+    Sntp(SntpConfig),
+    ServerMaxConnections(u8),
+    ServerTimeout(u16),
+}
+
+impl Setting {
+    /// Whether `current`, as queried after applying this setting, shows that it has taken effect.
+    fn took_effect(&self, current: &Setting) -> bool {
+        match (self, current) {
+            (Setting::Dns(dns), Setting::Dns(current)) if !dns.manual => !current.manual,
+            (Setting::Sntp(sntp), Setting::Sntp(current)) if !sntp.enabled => !current.enabled,
+            (Setting::Sntp(sntp), Setting::Sntp(current)) if sntp.servers.is_empty() => {
+                current.enabled && current.utc_offset_minutes == sntp.utc_offset_minutes
+            }
+            _ => self == current,
+        }
+    }
+}
+
+impl<RX, TX> Esp32At<RX, TX>
+where
+    RX: embedded_hal::serial::Read<u8>,
+    TX: embedded_hal::serial::Write<u8>,
+{
+    /// Applies `settings` in order, verifying each one by querying it afterwards.
+    ///
+    /// If any setting can't be applied or doesn't take effect, the settings applied so far are
+    /// rolled back to their previous values, and the original error is returned.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// use esp_at::config::Setting;
+    /// use esp_at::wifi::WifiMode;
+    /// let script = [
+    ///     Exchange::new(b"AT+CWMODE?\r\n", b"+CWMODE:1\r\n\r\nOK\r\n"),
+    ///     Exchange::new(b"AT+CWMODE=3\r\n", b"\r\nOK\r\n"),
+    ///     Exchange::new(b"AT+CWMODE?\r\n", b"+CWMODE:3\r\n\r\nOK\r\n"),
+    /// ];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Wifi | CommandSet::TcpIp);
+    ///
+    /// esp32_at
+    ///     .transaction(&[Setting::WifiMode(WifiMode::StationAndSoftAp)])
+    ///     .unwrap();
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn transaction(&mut self, settings: &[Setting]) -> Result<(), Error<RX::Error, TX::Error>> {
+        let mut previous = heapless::Vec::<Setting, heapless::consts::U8>::new();
+        if settings.len() > previous.capacity() {
+            return Err(Error::BufferOverflow);
+        }
+
+        for setting in settings {
+            let result = self.query_setting(setting).and_then(|current| {
+                // Cannot fail since the capacity was checked above
+                let _ = previous.push(current);
+                self.apply_setting(setting)?;
+                if setting.took_effect(&self.query_setting(setting)?) {
+                    Ok(())
+                } else {
+                    Err(Error::VerificationFailed)
+                }
+            });
+
+            if let Err(error) = result {
+                for setting in previous.iter().rev() {
+                    // Keep rolling back as far as possible; the original error is more relevant
+                    let _ = self.apply_setting(setting);
+                }
+                return Err(error);
+            }
+        }
+        Ok(())
+    }
+
+    /// Sets `setting` to the value it holds.
+    fn apply_setting(&mut self, setting: &Setting) -> Result<(), Error<RX::Error, TX::Error>> {
+        match setting {
+            Setting::WifiMode(mode) => nb::block!(self.set_wifi_mode(*mode)),
+            Setting::ReceiveMode(mode) => nb::block!(self.set_receive_mode(*mode)),
+            Setting::MultipleConnections(enabled) => {
+                nb::block!(self.set_multiple_connections(*enabled))
+            }
+            Setting::Dns(dns) if dns.manual => nb::block!(self.set_dns_servers(&dns.servers)),
+            Setting::Dns(_) => nb::block!(self.use_dhcp_dns_servers()),
+            Setting::Sntp(sntp) if sntp.enabled => {
+                let servers: heapless::Vec<&str, heapless::consts::U3> =
+                    sntp.servers.iter().map(|server| server.as_str()).collect();
+                nb::block!(self.enable_sntp(sntp.utc_offset_minutes, &servers))
+            }
+            Setting::Sntp(_) => nb::block!(self.disable_sntp()),
+            Setting::ServerMaxConnections(count) => {
+                nb::block!(self.set_server_max_connections(*count))
+            }
+            Setting::ServerTimeout(timeout_secs) => {
+                nb::block!(self.set_server_timeout(*timeout_secs))
+            }
+        }
+    }
+
+    /// Queries the current value of the same kind of setting as `setting`.
+    fn query_setting(&mut self, setting: &Setting) -> Result<Setting, Error<RX::Error, TX::Error>> {
+        Ok(match setting {
+            Setting::WifiMode(_) => Setting::WifiMode(nb::block!(self.get_wifi_mode())?),
+            Setting::ReceiveMode(_) => Setting::ReceiveMode(nb::block!(self.get_receive_mode())?),
+            Setting::MultipleConnections(_) => {
+                Setting::MultipleConnections(nb::block!(self.get_multiple_connections())?)
+            }
+            Setting::Dns(_) => Setting::Dns(nb::block!(self.get_dns_config())?),
+            Setting::Sntp(_) => Setting::Sntp(nb::block!(self.get_sntp_config())?),
+            Setting::ServerMaxConnections(_) => {
+                Setting::ServerMaxConnections(nb::block!(self.get_server_max_connections())?)
+            }
+            Setting::ServerTimeout(_) => {
+                Setting::ServerTimeout(nb::block!(self.get_server_timeout())?)
+            }
+        })
+    }
+}
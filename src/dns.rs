@@ -0,0 +1,161 @@
+//! A cache of host name lookups, so that connecting to the same hosts over and over doesn't
+//! resolve their names every time.
+
+use core::net::IpAddr;
+
+use crate::{Error, Esp32At};
+
+/// The longest host name that is cached; longer ones are resolved every time.
+pub type MaxHostnameLen = heapless::consts::U64;
+
+/// A cached result of `Esp32At::resolve`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    hostname: heapless::String<MaxHostnameLen>,
+    ip: IpAddr,
+    inserted_at: u32,
+}
+
+/// Remembers the addresses of up to `N` host names for `ttl_ms`, since every `AT+CIPDOMAIN`
+/// takes several UART round trips and a network lookup on the module.
+///
+/// Times are in milliseconds from an arbitrary, wrapping monotonic clock.  When the cache is
+/// full, the entry that expires first is replaced.
+///
+/// # Examples
+///
+/// ```
+/// # use esp_at::mock::{Exchange, MockModule};
+/// # use esp_at::{CommandSet, Esp32At};
+/// use esp_at::dns::DnsCache;
+/// let script = [
+///     Exchange::new(
+///         b"AT+CIPDOMAIN=\"example.com\"\r\n",
+///         b"+CIPDOMAIN:\"93.184.216.34\"\r\n\r\nOK\r\n",
+///     ),
+///     Exchange::new(
+///         b"AT+CIPDOMAIN=\"example.com\"\r\n",
+///         b"+CIPDOMAIN:\"93.184.216.35\"\r\n\r\nOK\r\n",
+///     ),
+/// ];
+/// # let module = MockModule::new(&script);
+/// # let (rx, tx) = module.split();
+/// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::TcpIp.into());
+///
+/// let mut cache = DnsCache::<heapless::consts::U4>::new(60_000);
+/// let ip = cache.resolve(&mut esp32_at, "example.com", 0).unwrap();
+/// assert_eq!(core::net::IpAddr::from([93, 184, 216, 34]), ip);
+/// // Answered from the cache
+/// let ip = cache.resolve(&mut esp32_at, "example.com", 59_999).unwrap();
+/// assert_eq!(core::net::IpAddr::from([93, 184, 216, 34]), ip);
+/// // Expired, so resolved again
+/// let ip = cache.resolve(&mut esp32_at, "example.com", 60_000).unwrap();
+/// assert_eq!(core::net::IpAddr::from([93, 184, 216, 35]), ip);
+/// # assert!(module.is_done());
+/// ```
+#[derive(Debug)]
+pub struct DnsCache<N>
+where
+    N: heapless::ArrayLength<Entry>,
+{
+    ttl_ms: u32,
+    entries: heapless::Vec<Entry, N>,
+}
+
+impl<N> DnsCache<N>
+where
+    N: heapless::ArrayLength<Entry>,
+{
+    /// Creates an empty cache, whose entries expire `ttl_ms` after they were inserted.
+    pub fn new(ttl_ms: u32) -> Self {
+        Self {
+            ttl_ms,
+            entries: heapless::Vec::new(),
+        }
+    }
+
+    /// Returns the cached address of `hostname`, if it hasn't expired yet.
+    pub fn get(&self, hostname: &str, now_ms: u32) -> Option<IpAddr> {
+        self.entries
+            .iter()
+            .find(|entry| entry.hostname == hostname && !entry.is_expired(now_ms, self.ttl_ms))
+            .map(|entry| entry.ip)
+    }
+
+    /// Returns the address of `hostname` from the cache, or looks it up with
+    /// `Esp32At::resolve` and caches it, blocking until the lookup has completed.
+    pub fn resolve<RX, TX>(
+        &mut self,
+        esp: &mut Esp32At<RX, TX>,
+        hostname: &str,
+        now_ms: u32,
+    ) -> Result<IpAddr, Error<RX::Error, TX::Error>>
+    where
+        RX: embedded_hal::serial::Read<u8>,
+        TX: embedded_hal::serial::Write<u8>,
+    {
+        if let Some(ip) = self.get(hostname, now_ms) {
+            return Ok(ip);
+        }
+
+        let ip = nb::block!(esp.resolve(hostname))?;
+        self.insert(hostname, ip, now_ms);
+        Ok(ip)
+    }
+
+    /// Caches the address of `hostname`, e.g. one that was resolved some other way.
+    pub fn insert(&mut self, hostname: &str, ip: IpAddr, now_ms: u32) {
+        let mut name = heapless::String::new();
+        if name.push_str(hostname).is_err() {
+            return;
+        }
+        let entry = Entry {
+            hostname: name,
+            ip,
+            inserted_at: now_ms,
+        };
+
+        let index = self
+            .entries
+            .iter()
+            .position(|slot| slot.hostname == entry.hostname)
+            .or_else(|| {
+                self.entries
+                    .iter()
+                    .position(|slot| slot.is_expired(now_ms, self.ttl_ms))
+            });
+        if let Some(index) = index {
+            self.entries[index] = entry;
+        } else if let Err(entry) = self.entries.push(entry) {
+            // Make room by replacing the entry that would have expired first, i.e. the oldest one
+            if let Some(slot) = self
+                .entries
+                .iter_mut()
+                .max_by_key(|slot| now_ms.wrapping_sub(slot.inserted_at))
+            {
+                *slot = entry;
+            }
+        }
+    }
+
+    /// Forgets the address of `hostname`, e.g. after failing to connect to it.
+    pub fn invalidate(&mut self, hostname: &str) {
+        if let Some(index) = self
+            .entries
+            .iter()
+            .position(|entry| entry.hostname == hostname)
+        {
+            self.entries.swap_remove(index);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.entries = heapless::Vec::new();
+    }
+}
+
+impl Entry {
+    fn is_expired(&self, now_ms: u32, ttl_ms: u32) -> bool {
+        now_ms.wrapping_sub(self.inserted_at) >= ttl_ms
+    }
+}
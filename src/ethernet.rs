@@ -0,0 +1,176 @@
+//! The wired interface of ESP32 boards with an Ethernet PHY.
+
+use core::net::Ipv4Addr;
+
+use crate::tcpip::{parse_address, parse_mac, Mac};
+use crate::{parser, CommandSet, Error, Esp32At};
+
+/// The bit of the `AT+CWDHCP` mask for the Ethernet interface.
+const DHCP_ETHERNET: u8 = 1 << 2;
+
+/// The IP configuration of the Ethernet interface.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EthernetAddresses {
+    pub ip: Ipv4Addr,
+    pub gateway: Ipv4Addr,
+    pub netmask: Ipv4Addr,
+}
+
+impl<RX, TX> Esp32At<RX, TX>
+where
+    RX: embedded_hal::serial::Read<u8>,
+    TX: embedded_hal::serial::Write<u8>,
+{
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// let script = [
+    ///     Exchange::new(b"AT+CIPETHMAC=\"02:00:00:00:00:01\"\r\n", b"\r\nOK\r\n"),
+    ///     Exchange::new(
+    ///         b"AT+CIPETHMAC?\r\n",
+    ///         b"+CIPETHMAC:\"02:00:00:00:00:01\"\r\n\r\nOK\r\n",
+    ///     ),
+    /// ];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Ethernet.into());
+    ///
+    /// let mac = [0x02, 0, 0, 0, 0, 1];
+    /// nb::block!(esp32_at.set_ethernet_mac(mac)).unwrap();
+    /// assert_eq!(mac, nb::block!(esp32_at.get_ethernet_mac()).unwrap());
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn set_ethernet_mac(
+        &mut self,
+        mac: [u8; 6],
+    ) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::Ethernet)?;
+        write_command!(self, "AT+CIPETHMAC=\"{}\"", Mac(mac))?;
+        self.expect_ok_response()
+    }
+
+    pub fn get_ethernet_mac(&mut self) -> nb::Result<[u8; 6], Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::Ethernet)?;
+        write_command!(self, "AT+CIPETHMAC?")?;
+        let mac = self.read_query_response("CIPETHMAC")?;
+        Ok(parse_mac(parser::unquote(&mac))?)
+    }
+
+    /// Gives the Ethernet interface a static IP configuration, which disables DHCP on it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// use esp_at::ethernet::EthernetAddresses;
+    /// let script = [
+    ///     Exchange::new(
+    ///         b"AT+CIPETH=\"10.0.0.2\",\"10.0.0.1\",\"255.255.255.0\"\r\n",
+    ///         b"\r\nOK\r\n",
+    ///     ),
+    ///     Exchange::new(
+    ///         b"AT+CIPETH?\r\n",
+    ///         b"+CIPETH:ip:\"10.0.0.2\"\r\n\
+    ///           +CIPETH:gateway:\"10.0.0.1\"\r\n\
+    ///           +CIPETH:netmask:\"255.255.255.0\"\r\n\
+    ///           \r\nOK\r\n",
+    ///     ),
+    /// ];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Ethernet.into());
+    ///
+    /// let addresses = EthernetAddresses {
+    ///     ip: [10, 0, 0, 2].into(),
+    ///     gateway: [10, 0, 0, 1].into(),
+    ///     netmask: [255, 255, 255, 0].into(),
+    /// };
+    /// nb::block!(esp32_at.set_ethernet_addresses(&addresses)).unwrap();
+    /// assert_eq!(addresses, nb::block!(esp32_at.get_ethernet_addresses()).unwrap());
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn set_ethernet_addresses(
+        &mut self,
+        addresses: &EthernetAddresses,
+    ) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::Ethernet)?;
+        write_command!(
+            self,
+            "AT+CIPETH=\"{}\",\"{}\",\"{}\"",
+            addresses.ip,
+            addresses.gateway,
+            addresses.netmask
+        )?;
+        self.expect_ok_response()
+    }
+
+    pub fn get_ethernet_addresses(
+        &mut self,
+    ) -> nb::Result<EthernetAddresses, Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::Ethernet)?;
+        write_command!(self, "AT+CIPETH?")?;
+        let mut addresses = EthernetAddresses {
+            ip: Ipv4Addr::UNSPECIFIED,
+            gateway: Ipv4Addr::UNSPECIFIED,
+            netmask: Ipv4Addr::UNSPECIFIED,
+        };
+        self.read_response(|line| {
+            let (name, address) = match parser::response(line, "CIPETH")
+                .and_then(|arguments| arguments.split_once(':'))
+            {
+                Some((name, address)) => (name, parser::unquote(address)),
+                None => return Ok(()),
+            };
+            match name {
+                "ip" => addresses.ip = parse_address(address)?,
+                "gateway" => addresses.gateway = parse_address(address)?,
+                "netmask" => addresses.netmask = parse_address(address)?,
+                _ => {}
+            }
+            Ok(())
+        })?;
+        Ok(addresses)
+    }
+
+    /// Enables or disables the DHCP client of the Ethernet interface, leaving that of the other
+    /// interfaces as it is.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// let script = [
+    ///     Exchange::new(b"AT+CWDHCP=1,4\r\n", b"\r\nOK\r\n"),
+    ///     Exchange::new(b"AT+CWDHCP?\r\n", b"+CWDHCP:7\r\n\r\nOK\r\n"),
+    /// ];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Ethernet.into());
+    ///
+    /// nb::block!(esp32_at.set_ethernet_dhcp(true)).unwrap();
+    /// assert!(nb::block!(esp32_at.get_ethernet_dhcp()).unwrap());
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn set_ethernet_dhcp(
+        &mut self,
+        enabled: bool,
+    ) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::Ethernet)?;
+        write_command!(self, "AT+CWDHCP={},{}", enabled as u8, DHCP_ETHERNET)?;
+        self.expect_ok_response()
+    }
+
+    pub fn get_ethernet_dhcp(&mut self) -> nb::Result<bool, Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::Ethernet)?;
+        write_command!(self, "AT+CWDHCP?")?;
+        let state: u8 = self
+            .read_query_response("CWDHCP")?
+            .parse()
+            .map_err(|_| Error::UnexpectedResponse)?;
+        Ok(state & DHCP_ETHERNET != 0)
+    }
+}
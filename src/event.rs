@@ -0,0 +1,329 @@
+use core::net::IpAddr;
+
+use crate::parser;
+use crate::tcpip::parse_octets;
+
+/// An unsolicited message from the module.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    WifiConnected,
+    WifiGotIp,
+    WifiDisconnected,
+    /// The provisioning web server has received Wi-Fi credentials from a browser.
+    WebServerCredentialsReceived,
+    /// The module has joined the network using credentials from the provisioning web server.
+    WebServerProvisioned,
+    /// The application data sent along with ESP-Touch v2 credentials.
+    SmartConfigReservedData(heapless::Vec<u8, heapless::consts::U64>),
+    /// The module has joined the network using credentials received through SmartConfig.
+    SmartConfigConnected,
+    /// The clock has been synchronized using SNTP, so the time can be trusted.
+    TimeUpdated,
+    /// The MQTT client has connected to the broker, either by `Esp32At::mqtt_connect` or by
+    /// reconnecting on its own.
+    MqttConnected,
+    /// The MQTT client has lost the connection to the broker.
+    MqttDisconnected,
+    /// A WebSocket connection has been established by `Esp32At::websocket_open`.
+    WebSocketConnected {
+        link_id: u8,
+    },
+    /// A WebSocket connection has been closed, either locally or by the server, or lost.
+    WebSocketDisconnected {
+        link_id: u8,
+    },
+    /// A BLE connection has been established, either by a central connecting to the module
+    /// while it advertises or by `Esp32At::ble_connect`.
+    BleConnected {
+        conn_index: u8,
+        address: [u8; 6],
+    },
+    /// A BLE connection couldn't be established by `Esp32At::ble_connect`, e.g. because the
+    /// peripheral didn't respond in time.
+    BleConnectFailed {
+        conn_index: u8,
+    },
+    /// A BLE connection has been closed, either locally or by the peer, or lost.
+    BleDisconnected {
+        conn_index: u8,
+        address: [u8; 6],
+    },
+    /// The parameters of a BLE connection have been updated, as requested with
+    /// `Esp32At::ble_update_connection_parameters` or by the peer.  `interval` is in units of
+    /// 1.25 ms and `timeout` in units of 10 ms.
+    BleConnectionUpdated {
+        conn_index: u8,
+        interval: u16,
+        latency: u16,
+        timeout: u16,
+    },
+    /// The peer or the controller refused to update the parameters of a BLE connection.
+    BleConnectionUpdateFailed {
+        conn_index: u8,
+    },
+    /// The ATT MTU of a BLE connection has been negotiated, which bounds the size of GATT
+    /// values, e.g. notifications can carry `mtu - 3` bytes.
+    BleMtuChanged {
+        conn_index: u8,
+        mtu: u16,
+    },
+    /// A BLE connection has been encrypted, after pairing if the peers weren't bonded.
+    BleAuthenticated {
+        conn_index: u8,
+    },
+    /// Pairing or encrypting a BLE connection has failed.
+    BleAuthenticationFailed {
+        conn_index: u8,
+    },
+    /// The peer asks for the BLE connection to be encrypted, which is answered with
+    /// `Esp32At::ble_respond_to_security_request`.
+    BleSecurityRequested {
+        conn_index: u8,
+    },
+    /// The passkey to show the user while pairing, for them to enter on the peer.
+    BlePasskeyDisplayed {
+        conn_index: u8,
+        passkey: u32,
+    },
+    /// The peer displays a passkey for the user to enter, which is replied with
+    /// `Esp32At::ble_reply_passkey`.
+    BlePasskeyRequested {
+        conn_index: u8,
+    },
+    /// The passkey to show the user while pairing, for them to confirm that the peer shows the
+    /// same one, which is replied with `Esp32At::ble_confirm_passkey`.
+    BlePasskeyConfirmationRequested {
+        conn_index: u8,
+        passkey: u32,
+    },
+    /// A client has written `value` to the characteristic `char_index` of the service
+    /// `service_index`, or to its descriptor `desc_index`.  Values longer than
+    /// `gatt::MAX_WRITTEN_VALUE_LEN` aren't reported.
+    GattWritten {
+        conn_index: u8,
+        service_index: u8,
+        char_index: u8,
+        desc_index: Option<u8>,
+        value: crate::gatt::WrittenValue,
+    },
+    /// A client has read a characteristic, whose value the module has already responded with.
+    GattRead {
+        conn_index: u8,
+        address: [u8; 6],
+    },
+    /// A connection has been established on a link, either by connecting or by accepting an
+    /// inbound connection.  The remote end is only reported with `AT+CIPDINFO=1`.
+    ///
+    /// `refused` is set for inbound connections beyond the server's reservation of link ids,
+    /// which should be closed with `Esp32At::close`.
+    LinkConnected {
+        link_id: u8,
+        remote: Option<(IpAddr, u16)>,
+        refused: bool,
+    },
+    /// A link has been closed, either locally or by the remote end; `link_id` is only given in
+    /// multiple connection mode.
+    LinkClosed {
+        link_id: Option<u8>,
+    },
+    /// In passive receive mode, data has been buffered by the module on a link that had none
+    /// buffered before; `link_id` is only given in multiple connection mode.
+    DataAvailable {
+        link_id: Option<u8>,
+        len: usize,
+    },
+}
+
+/// Recognizes unsolicited messages among the lines received from the module.
+pub(crate) fn parse(line: &str) -> Option<Event> {
+    match line {
+        "WIFI CONNECTED" => Some(Event::WifiConnected),
+        "WIFI GOT IP" => Some(Event::WifiGotIp),
+        "WIFI DISCONNECT" => Some(Event::WifiDisconnected),
+        "+WEBSERVERRSP:1" => Some(Event::WebServerCredentialsReceived),
+        "+WEBSERVERRSP:2" => Some(Event::WebServerProvisioned),
+        "smartconfig connected wifi" => Some(Event::SmartConfigConnected),
+        "+TIME_UPDATED" => Some(Event::TimeUpdated),
+        "CLOSED" => Some(Event::LinkClosed { link_id: None }),
+        line => parse_link_connected(line)
+            .or_else(|| parse_link_closed(line))
+            .or_else(|| parse_data_available(line))
+            .or_else(|| parse_mqtt_connection(line))
+            .or_else(|| parse_websocket_connection(line))
+            .or_else(|| parse_ble_connection(line))
+            .or_else(|| parse_ble_connection_update(line))
+            .or_else(|| parse_ble_mtu_changed(line))
+            .or_else(|| parse_ble_authentication(line))
+            .or_else(|| parse_ble_pairing(line))
+            .or_else(|| parse_gatt_read(line)),
+    }
+}
+
+/// Parses `<link_id>,CONNECT`, optionally followed by `,"<remote_ip>",<remote_port>`.
+fn parse_link_connected(line: &str) -> Option<Event> {
+    let mut fields = parser::fields(line);
+    let link_id = fields.next()?.parse().ok()?;
+    if fields.next()? != "CONNECT" {
+        return None;
+    }
+    let remote = match fields.next() {
+        Some(ip) => Some((
+            parser::unquote(ip).parse().ok()?,
+            fields.next()?.parse().ok()?,
+        )),
+        None => None,
+    };
+    Some(Event::LinkConnected {
+        link_id,
+        remote,
+        refused: false,
+    })
+}
+
+/// Parses `<link_id>,CLOSED`.
+fn parse_link_closed(line: &str) -> Option<Event> {
+    let link_id = line.strip_suffix(",CLOSED")?.parse().ok()?;
+    Some(Event::LinkClosed {
+        link_id: Some(link_id),
+    })
+}
+
+/// Parses `+IPD,[<link_id>,]<len>[,"<remote_ip>",<remote_port>]`, which unlike data pushed in
+/// active receive mode isn't followed by a colon and the payload.
+fn parse_data_available(line: &str) -> Option<Event> {
+    let mut fields = parser::fields(line.strip_prefix("+IPD,")?);
+    let first = fields.next()?;
+    let (link_id, len) = match (fields.next(), fields.next(), fields.next()) {
+        (None, _, _) | (Some(_), Some(_), None) => (None, first),
+        (Some(len), None, _) | (Some(len), Some(_), Some(_)) => (Some(first.parse().ok()?), len),
+    };
+    Some(Event::DataAvailable {
+        link_id,
+        len: len.parse().ok()?,
+    })
+}
+
+/// Parses `+MQTTCONNECTED:<link_id>,<state>,"<host>",<port>,"<path>",<reconnect>` and
+/// `+MQTTDISCONNECTED:<link_id>`.
+fn parse_mqtt_connection(line: &str) -> Option<Event> {
+    if parser::response(line, "MQTTCONNECTED").is_some() {
+        Some(Event::MqttConnected)
+    } else if parser::response(line, "MQTTDISCONNECTED").is_some() {
+        Some(Event::MqttDisconnected)
+    } else {
+        None
+    }
+}
+
+/// Parses `+WS_CONNECTED:<link_id>`, `+WS_DISCONNECTED:<link_id>` and `+WS_CLOSED:<link_id>`.
+fn parse_websocket_connection(line: &str) -> Option<Event> {
+    if let Some(link_id) = parser::response(line, "WS_CONNECTED") {
+        Some(Event::WebSocketConnected {
+            link_id: link_id.parse().ok()?,
+        })
+    } else if let Some(link_id) =
+        parser::response(line, "WS_DISCONNECTED").or_else(|| parser::response(line, "WS_CLOSED"))
+    {
+        Some(Event::WebSocketDisconnected {
+            link_id: link_id.parse().ok()?,
+        })
+    } else {
+        None
+    }
+}
+
+/// Parses `+BLECONN:<conn_index>,"<remote_address>"`, or `+BLECONN:<conn_index>,-1` if the
+/// connection failed, and `+BLEDISCONN:<conn_index>,"<remote_address>"`.
+fn parse_ble_connection(line: &str) -> Option<Event> {
+    let (connected, arguments) = match parser::response(line, "BLECONN") {
+        Some(arguments) => (true, arguments),
+        None => (false, parser::response(line, "BLEDISCONN")?),
+    };
+    let mut fields = parser::fields(arguments);
+    let conn_index = fields.next()?.parse().ok()?;
+    let address = fields.next()?;
+    if connected && address == "-1" {
+        return Some(Event::BleConnectFailed { conn_index });
+    }
+    let address = parse_octets(parser::unquote(address))?;
+    Some(if connected {
+        Event::BleConnected {
+            conn_index,
+            address,
+        }
+    } else {
+        Event::BleDisconnected {
+            conn_index,
+            address,
+        }
+    })
+}
+
+/// Parses `+BLECONNPARAM:<conn_index>,<min_interval>,<max_interval>,<cur_interval>,<latency>,
+/// <timeout>`, or `+BLECONNPARAM:<conn_index>,-1` if the update failed.
+fn parse_ble_connection_update(line: &str) -> Option<Event> {
+    let mut fields = parser::fields(parser::response(line, "BLECONNPARAM")?);
+    let conn_index = fields.next()?.parse().ok()?;
+    let mut fields = fields.peekable();
+    if fields.peek() == Some(&"-1") {
+        return Some(Event::BleConnectionUpdateFailed { conn_index });
+    }
+    let mut fields = fields.skip(2);
+    Some(Event::BleConnectionUpdated {
+        conn_index,
+        interval: fields.next()?.parse().ok()?,
+        latency: fields.next()?.parse().ok()?,
+        timeout: fields.next()?.parse().ok()?,
+    })
+}
+
+/// Parses `+BLECFGMTU:<conn_index>,<mtu>`.
+fn parse_ble_mtu_changed(line: &str) -> Option<Event> {
+    let mut fields = parser::fields(parser::response(line, "BLECFGMTU")?);
+    Some(Event::BleMtuChanged {
+        conn_index: fields.next()?.parse().ok()?,
+        mtu: fields.next()?.parse().ok()?,
+    })
+}
+
+/// Parses `+BLEAUTHCMPL:<conn_index>,<enc_result>`, where the result is 0 on success.
+fn parse_ble_authentication(line: &str) -> Option<Event> {
+    let mut fields = parser::fields(parser::response(line, "BLEAUTHCMPL")?);
+    let conn_index = fields.next()?.parse().ok()?;
+    match fields.next()? {
+        "0" => Some(Event::BleAuthenticated { conn_index }),
+        _ => Some(Event::BleAuthenticationFailed { conn_index }),
+    }
+}
+
+/// Parses `+BLESECREQ:<conn_index>`, `+BLESECNTFYKEY:<conn_index>,<key>`,
+/// `+BLESECKEYREQ:<conn_index>` and `+BLECFMREQ:<conn_index>,<key>`.
+fn parse_ble_pairing(line: &str) -> Option<Event> {
+    let (name, arguments) = line.strip_prefix('+')?.split_once(':')?;
+    let mut fields = parser::fields(arguments);
+    let conn_index = fields.next()?.parse().ok()?;
+    match name {
+        "BLESECREQ" => Some(Event::BleSecurityRequested { conn_index }),
+        "BLESECNTFYKEY" => Some(Event::BlePasskeyDisplayed {
+            conn_index,
+            passkey: fields.next()?.parse().ok()?,
+        }),
+        "BLESECKEYREQ" => Some(Event::BlePasskeyRequested { conn_index }),
+        "BLECFMREQ" => Some(Event::BlePasskeyConfirmationRequested {
+            conn_index,
+            passkey: fields.next()?.parse().ok()?,
+        }),
+        _ => None,
+    }
+}
+
+/// Parses `+READ:<conn_index>,<remote_address>`.
+fn parse_gatt_read(line: &str) -> Option<Event> {
+    let mut fields = parser::fields(parser::response(line, "READ")?);
+    let conn_index = fields.next()?.parse().ok()?;
+    let address = parse_octets(parser::unquote(fields.next()?))?;
+    Some(Event::GattRead {
+        conn_index,
+        address,
+    })
+}
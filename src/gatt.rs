@@ -0,0 +1,1092 @@
+//! GATT services, provided by the module as a server or used on a peer as a client.
+//!
+//! The services of the server are defined in the manufacturing partition of the module's flash,
+//! since the firmware has no commands to define them.
+
+use core::fmt;
+
+use crate::ble::{Uuid, MAX_CONNECTIONS};
+use crate::event::Event;
+use crate::payload::PayloadSink;
+use crate::{parser, CommandSet, Error, Esp32At, Line};
+
+/// The longest value of a characteristic.
+pub const MAX_VALUE_LEN: usize = 512;
+
+/// The longest value written by a client that is reported as `Event::GattWritten`.
+pub const MAX_WRITTEN_VALUE_LEN: usize = 64;
+
+/// A value written by a client, see `Event::GattWritten`.
+pub type WrittenValue = heapless::Vec<u8, heapless::consts::U64>;
+
+/// The services of the server, see `Esp32At::get_gatt_server_services`.
+pub type ServerServices = heapless::Vec<ServerService, heapless::consts::U8>;
+
+/// The characteristics of the server, see `Esp32At::get_gatt_server_characteristics`.
+pub type ServerCharacteristics = heapless::Vec<ServerCharacteristic, heapless::consts::U32>;
+
+/// A service provided by the server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ServerService {
+    /// The index that the service is referred to by, starting at 1.
+    pub index: u8,
+    pub uuid: Uuid,
+    /// Whether the service is primary rather than secondary.
+    pub primary: bool,
+    /// Whether the service has been started, so that clients can use it.
+    pub started: bool,
+}
+
+/// A characteristic of a service provided by the server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerCharacteristic {
+    pub service_index: u8,
+    /// The index that the characteristic is referred to by within its service, starting at 1.
+    pub index: u8,
+    pub uuid: Uuid,
+    pub properties: enumset::EnumSet<CharacteristicProperty>,
+    /// The indexes of the descriptors of the characteristic, starting at 1.
+    pub descriptors: heapless::Vec<u8, heapless::consts::U4>,
+}
+
+/// The services of a peer, see `Esp32At::discover_gatt_services`.
+pub type ClientServices = heapless::Vec<ClientService, heapless::consts::U8>;
+
+/// The services included by a service of a peer, see `Esp32At::discover_gatt_included_services`.
+pub type IncludedServices = heapless::Vec<IncludedService, heapless::consts::U4>;
+
+/// The characteristics of a service of a peer, see `Esp32At::discover_gatt_characteristics`.
+pub type ClientCharacteristics = heapless::Vec<ClientCharacteristic, heapless::consts::U16>;
+
+/// Refers to a service of the peer on a BLE connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ServiceHandle {
+    pub conn_index: u8,
+    /// The index of the service among those discovered on the peer, starting at 1.
+    pub service_index: u8,
+}
+
+/// Refers to a characteristic of a service of the peer on a BLE connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CharacteristicHandle {
+    pub service: ServiceHandle,
+    /// The index of the characteristic within its service, starting at 1.
+    pub char_index: u8,
+}
+
+/// Refers to a descriptor of a characteristic of the peer on a BLE connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DescriptorHandle {
+    pub characteristic: CharacteristicHandle,
+    /// The index of the descriptor within its characteristic, starting at 1.
+    pub desc_index: u8,
+}
+
+/// A service of a peer, discovered as a client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClientService {
+    pub handle: ServiceHandle,
+    pub uuid: Uuid,
+    /// Whether the service is primary rather than secondary.
+    pub primary: bool,
+}
+
+/// A service that a service of a peer includes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IncludedService {
+    pub uuid: Uuid,
+    /// Whether the included service is primary rather than secondary.
+    pub primary: bool,
+}
+
+/// A characteristic of a service of a peer, discovered as a client.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientCharacteristic {
+    pub handle: CharacteristicHandle,
+    pub uuid: Uuid,
+    pub properties: enumset::EnumSet<CharacteristicProperty>,
+    pub descriptors: heapless::Vec<ClientDescriptor, heapless::consts::U4>,
+}
+
+/// A descriptor of a characteristic of a peer, such as the client characteristic
+/// configuration `0x2902` that enables notifications.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClientDescriptor {
+    pub handle: DescriptorHandle,
+    pub uuid: Uuid,
+}
+
+/// What can be done with a characteristic.
+#[derive(Debug, enumset::EnumSetType)]
+pub enum CharacteristicProperty {
+    Broadcast,
+    Read,
+    WriteWithoutResponse,
+    Write,
+    Notify,
+    Indicate,
+    AuthenticatedSignedWrites,
+    ExtendedProperties,
+}
+
+/// The value pushed by the module as `+WRITE` that is being received.
+#[derive(Debug)]
+pub(crate) struct WriteBuffer {
+    incoming: Option<IncomingWrite>,
+    value: WrittenValue,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct IncomingWrite {
+    conn_index: u8,
+    service_index: u8,
+    char_index: u8,
+    desc_index: Option<u8>,
+    /// Whether the value is dropped, because it is too long.
+    discard: bool,
+    /// Whether all of the value has been received.
+    complete: bool,
+}
+
+impl ServerService {
+    /// Parses `<srv_index>,<start>,<srv_uuid>,<srv_type>`.
+    fn parse(arguments: &str) -> Option<Self> {
+        let mut fields = parser::fields(arguments);
+        Some(ServerService {
+            index: fields.next()?.parse().ok()?,
+            started: parse_flag(fields.next()?)?,
+            uuid: Uuid::parse(fields.next()?)?,
+            primary: parse_flag(fields.next()?)?,
+        })
+    }
+}
+
+/// Parses characteristic properties like `0x1a`.
+pub(crate) fn parse_properties(
+    properties: &str,
+) -> Option<enumset::EnumSet<CharacteristicProperty>> {
+    let bits = u8::from_str_radix(properties.strip_prefix("0x").unwrap_or(properties), 16).ok()?;
+    Some(enumset::EnumSet::from_bits(bits.into()))
+}
+
+fn parse_flag(flag: &str) -> Option<bool> {
+    match flag {
+        "0" => Some(false),
+        "1" => Some(true),
+        _ => None,
+    }
+}
+
+impl<RX, TX> Esp32At<RX, TX>
+where
+    RX: embedded_hal::serial::Read<u8>,
+    TX: embedded_hal::serial::Write<u8>,
+{
+    /// Creates the services defined in the manufacturing partition; BLE must have been
+    /// initialized as a server.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// let script = [Exchange::new(b"AT+BLEGATTSSRVCRE\r\n", b"\r\nOK\r\n")];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Ble.into());
+    ///
+    /// nb::block!(esp32_at.create_gatt_services()).unwrap();
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn create_gatt_services(&mut self) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::Ble)?;
+        write_command!(self, "AT+BLEGATTSSRVCRE")?;
+        self.expect_ok_response()
+    }
+
+    /// Starts the service `service_index`, or all of them if it is `None`, so that clients can
+    /// use them.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// let script = [
+    ///     Exchange::new(b"AT+BLEGATTSSRVSTART\r\n", b"\r\nOK\r\n"),
+    ///     Exchange::new(b"AT+BLEGATTSSRVSTART=2\r\n", b"\r\nOK\r\n"),
+    /// ];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Ble.into());
+    ///
+    /// nb::block!(esp32_at.start_gatt_services(None)).unwrap();
+    /// nb::block!(esp32_at.start_gatt_services(Some(2))).unwrap();
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn start_gatt_services(
+        &mut self,
+        service_index: Option<u8>,
+    ) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::Ble)?;
+        match service_index {
+            Some(service_index) => write_command!(self, "AT+BLEGATTSSRVSTART={}", service_index)?,
+            None => write_command!(self, "AT+BLEGATTSSRVSTART")?,
+        }
+        self.expect_ok_response()
+    }
+
+    /// Stops the service `service_index`, or all of them if it is `None`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// let script = [Exchange::new(b"AT+BLEGATTSSRVSTOP=1\r\n", b"\r\nOK\r\n")];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Ble.into());
+    ///
+    /// nb::block!(esp32_at.stop_gatt_services(Some(1))).unwrap();
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn stop_gatt_services(
+        &mut self,
+        service_index: Option<u8>,
+    ) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::Ble)?;
+        match service_index {
+            Some(service_index) => write_command!(self, "AT+BLEGATTSSRVSTOP={}", service_index)?,
+            None => write_command!(self, "AT+BLEGATTSSRVSTOP")?,
+        }
+        self.expect_ok_response()
+    }
+
+    /// Returns the services of the server, once they have been created.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// use esp_at::ble::Uuid;
+    /// let script = [Exchange::new(
+    ///     b"AT+BLEGATTSSRV?\r\n",
+    ///     b"+BLEGATTSSRV:1,1,0x180F,1\r\n\
+    ///       +BLEGATTSSRV:2,0,0x6E400001B5A3F393E0A9E50E24DCCA9E,1\r\n\
+    ///       \r\nOK\r\n",
+    /// )];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Ble.into());
+    ///
+    /// let services = nb::block!(esp32_at.get_gatt_server_services()).unwrap();
+    /// assert_eq!(2, services.len());
+    /// assert_eq!(Uuid::Uuid16(0x180f), services[0].uuid);
+    /// assert!(services[0].started);
+    /// assert!(!services[1].started);
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn get_gatt_server_services(
+        &mut self,
+    ) -> nb::Result<ServerServices, Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::Ble)?;
+        write_command!(self, "AT+BLEGATTSSRV?")?;
+        let mut services = heapless::Vec::new();
+        self.read_response(|line| {
+            if let Some(arguments) = parser::response(line, "BLEGATTSSRV") {
+                let service = ServerService::parse(arguments).ok_or(Error::UnexpectedResponse)?;
+                services.push(service).map_err(|_| Error::BufferOverflow)?;
+            }
+            Ok(())
+        })?;
+        Ok(services)
+    }
+
+    /// Returns the characteristics of the server's services, along with their descriptors.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// use esp_at::ble::Uuid;
+    /// use esp_at::gatt::CharacteristicProperty;
+    /// let script = [Exchange::new(
+    ///     b"AT+BLEGATTSCHAR?\r\n",
+    ///     b"+BLEGATTSCHAR:\"char\",1,1,0x2A19,0x12\r\n\
+    ///       +BLEGATTSCHAR:\"desc\",1,1,1\r\n\
+    ///       +BLEGATTSCHAR:\"char\",2,1,0x2A00,0x02\r\n\
+    ///       \r\nOK\r\n",
+    /// )];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Ble.into());
+    ///
+    /// let characteristics = nb::block!(esp32_at.get_gatt_server_characteristics()).unwrap();
+    /// let battery_level = &characteristics[0];
+    /// assert_eq!(Uuid::Uuid16(0x2a19), battery_level.uuid);
+    /// assert_eq!(
+    ///     CharacteristicProperty::Read | CharacteristicProperty::Notify,
+    ///     battery_level.properties
+    /// );
+    /// assert_eq!(&[1], &battery_level.descriptors[..]);
+    /// assert!(characteristics[1].descriptors.is_empty());
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn get_gatt_server_characteristics(
+        &mut self,
+    ) -> nb::Result<ServerCharacteristics, Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::Ble)?;
+        write_command!(self, "AT+BLEGATTSCHAR?")?;
+        let mut characteristics: ServerCharacteristics = heapless::Vec::new();
+        self.read_response(|line| {
+            let arguments = match parser::response(line, "BLEGATTSCHAR") {
+                Some(arguments) => arguments,
+                None => return Ok(()),
+            };
+            let mut fields = parser::fields(arguments);
+            match fields.next().map(parser::unquote) {
+                Some("char") => {
+                    let characteristic =
+                        parse_server_characteristic(fields).ok_or(Error::UnexpectedResponse)?;
+                    characteristics
+                        .push(characteristic)
+                        .map_err(|_| Error::BufferOverflow)?;
+                }
+                Some("desc") => {
+                    let (service_index, index, descriptor) =
+                        parse_indexes(fields).ok_or(Error::UnexpectedResponse)?;
+                    // The descriptors follow the characteristic they belong to
+                    let characteristic = characteristics
+                        .iter_mut()
+                        .rev()
+                        .find(|c| c.service_index == service_index && c.index == index)
+                        .ok_or(Error::UnexpectedResponse)?;
+                    characteristic
+                        .descriptors
+                        .push(descriptor)
+                        .map_err(|_| Error::BufferOverflow)?;
+                }
+                _ => return Err(Error::UnexpectedResponse),
+            }
+            Ok(())
+        })?;
+        Ok(characteristics)
+    }
+
+    /// Notifies the client on the BLE connection `conn_index` of `value`, the new value of the
+    /// characteristic `char_index` of the service `service_index`.
+    ///
+    /// The client must have enabled notifications for the characteristic, which doesn't confirm
+    /// receiving them.  A notification that the module couldn't send fails with
+    /// `Error::SendFailed`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// let script = [
+    ///     Exchange::new(b"AT+BLEGATTSNTFY=0,1,1,1\r\n", b"\r\nOK\r\n\r\n>"),
+    ///     Exchange::new(b"\x57", b"\r\nOK\r\n"),
+    /// ];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Ble.into());
+    ///
+    /// let battery_level = 87;
+    /// nb::block!(esp32_at.notify_gatt_client(0, 1, 1, &[battery_level])).unwrap();
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn notify_gatt_client(
+        &mut self,
+        conn_index: u8,
+        service_index: u8,
+        char_index: u8,
+        value: &[u8],
+    ) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.send_gatt_server_value("BLEGATTSNTFY", conn_index, service_index, char_index, value)
+    }
+
+    /// Like `notify_gatt_client`, but as an indication, which the client confirms receiving.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// use esp_at::Error;
+    /// let script = [
+    ///     Exchange::new(b"AT+BLEGATTSIND=1,2,1,4\r\n", b"\r\nOK\r\n\r\n>"),
+    ///     Exchange::new(b"21.5", b"\r\nOK\r\n"),
+    ///     Exchange::new(b"AT+BLEGATTSIND=1,2,1,4\r\n", b"\r\nOK\r\n\r\n>"),
+    ///     Exchange::new(b"21.6", b"\r\nERROR\r\n"),
+    /// ];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Ble.into());
+    ///
+    /// nb::block!(esp32_at.indicate_gatt_client(1, 2, 1, b"21.5")).unwrap();
+    /// match nb::block!(esp32_at.indicate_gatt_client(1, 2, 1, b"21.6")) {
+    ///     Err(Error::SendFailed { link_id }) => assert_eq!(Some(1), link_id),
+    ///     result => panic!("unexpected result: {:?}", result),
+    /// }
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn indicate_gatt_client(
+        &mut self,
+        conn_index: u8,
+        service_index: u8,
+        char_index: u8,
+        value: &[u8],
+    ) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.send_gatt_server_value("BLEGATTSIND", conn_index, service_index, char_index, value)
+    }
+
+    fn send_gatt_server_value(
+        &mut self,
+        command: &str,
+        conn_index: u8,
+        service_index: u8,
+        char_index: u8,
+        value: &[u8],
+    ) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::Ble)?;
+        if conn_index >= MAX_CONNECTIONS || value.is_empty() || value.len() > MAX_VALUE_LEN {
+            return Err(nb::Error::Other(Error::InvalidArgument));
+        }
+        write_command!(
+            self,
+            "AT+{}={},{},{},{}",
+            command,
+            conn_index,
+            service_index,
+            char_index,
+            value.len()
+        )?;
+        self.expect_prompt()?;
+        self.write_all(value)?;
+        self.read_gatt_send_response(conn_index)
+    }
+}
+
+impl<RX, TX> Esp32At<RX, TX>
+where
+    RX: embedded_hal::serial::Read<u8>,
+    TX: embedded_hal::serial::Write<u8>,
+{
+    /// Discovers the primary services of the peer on the BLE connection `conn_index`, which
+    /// must have been established by `Esp32At::ble_connect`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// use esp_at::ble::Uuid;
+    /// let script = [Exchange::new(
+    ///     b"AT+BLEGATTCPRIMSRV=0\r\n",
+    ///     b"+BLEGATTCPRIMSRV:0,1,0x1801,1\r\n\
+    ///       +BLEGATTCPRIMSRV:0,2,0x1800,1\r\n\
+    ///       +BLEGATTCPRIMSRV:0,3,0x180F,1\r\n\
+    ///       \r\nOK\r\n",
+    /// )];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Ble.into());
+    ///
+    /// let services = nb::block!(esp32_at.discover_gatt_services(0)).unwrap();
+    /// let battery = services
+    ///     .iter()
+    ///     .find(|service| service.uuid == Uuid::Uuid16(0x180f))
+    ///     .unwrap();
+    /// assert_eq!(3, battery.handle.service_index);
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn discover_gatt_services(
+        &mut self,
+        conn_index: u8,
+    ) -> nb::Result<ClientServices, Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::Ble)?;
+        if conn_index >= MAX_CONNECTIONS {
+            return Err(nb::Error::Other(Error::InvalidArgument));
+        }
+        write_command!(self, "AT+BLEGATTCPRIMSRV={}", conn_index)?;
+        let mut services = heapless::Vec::new();
+        self.read_response(|line| {
+            if let Some(arguments) = parser::response(line, "BLEGATTCPRIMSRV") {
+                let service = parse_client_service(arguments).ok_or(Error::UnexpectedResponse)?;
+                services.push(service).map_err(|_| Error::BufferOverflow)?;
+            }
+            Ok(())
+        })?;
+        Ok(services)
+    }
+
+    /// Discovers the services that the service `service` of a peer includes.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// use esp_at::ble::Uuid;
+    /// use esp_at::gatt::ServiceHandle;
+    /// let script = [Exchange::new(
+    ///     b"AT+BLEGATTCINCLSRV=0,3\r\n",
+    ///     b"+BLEGATTCINCLSRV:0,3,0x180F,1,0x1805,0\r\n\r\nOK\r\n",
+    /// )];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Ble.into());
+    ///
+    /// let service = ServiceHandle {
+    ///     conn_index: 0,
+    ///     service_index: 3,
+    /// };
+    /// let included = nb::block!(esp32_at.discover_gatt_included_services(service)).unwrap();
+    /// assert_eq!(Uuid::Uuid16(0x1805), included[0].uuid);
+    /// assert!(!included[0].primary);
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn discover_gatt_included_services(
+        &mut self,
+        service: ServiceHandle,
+    ) -> nb::Result<IncludedServices, Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::Ble)?;
+        if service.conn_index >= MAX_CONNECTIONS {
+            return Err(nb::Error::Other(Error::InvalidArgument));
+        }
+        write_command!(
+            self,
+            "AT+BLEGATTCINCLSRV={},{}",
+            service.conn_index,
+            service.service_index
+        )?;
+        let mut included = heapless::Vec::new();
+        self.read_response(|line| {
+            if let Some(arguments) = parser::response(line, "BLEGATTCINCLSRV") {
+                let service = parse_included_service(arguments).ok_or(Error::UnexpectedResponse)?;
+                included.push(service).map_err(|_| Error::BufferOverflow)?;
+            }
+            Ok(())
+        })?;
+        Ok(included)
+    }
+
+    /// Discovers the characteristics of the service `service` of a peer, along with their
+    /// descriptors.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// use esp_at::ble::Uuid;
+    /// use esp_at::gatt::{CharacteristicProperty, ServiceHandle};
+    /// let script = [Exchange::new(
+    ///     b"AT+BLEGATTCCHAR=0,3\r\n",
+    ///     b"+BLEGATTCCHAR:\"char\",0,3,1,0x2A19,0x12\r\n\
+    ///       +BLEGATTCCHAR:\"desc\",0,3,1,1,0x2902\r\n\
+    ///       \r\nOK\r\n",
+    /// )];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Ble.into());
+    ///
+    /// let service = ServiceHandle {
+    ///     conn_index: 0,
+    ///     service_index: 3,
+    /// };
+    /// let characteristics = nb::block!(esp32_at.discover_gatt_characteristics(service)).unwrap();
+    /// let battery_level = &characteristics[0];
+    /// assert_eq!(Uuid::Uuid16(0x2a19), battery_level.uuid);
+    /// assert!(battery_level
+    ///     .properties
+    ///     .contains(CharacteristicProperty::Notify));
+    /// assert_eq!(Uuid::Uuid16(0x2902), battery_level.descriptors[0].uuid);
+    /// assert_eq!(1, battery_level.descriptors[0].handle.desc_index);
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn discover_gatt_characteristics(
+        &mut self,
+        service: ServiceHandle,
+    ) -> nb::Result<ClientCharacteristics, Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::Ble)?;
+        if service.conn_index >= MAX_CONNECTIONS {
+            return Err(nb::Error::Other(Error::InvalidArgument));
+        }
+        write_command!(
+            self,
+            "AT+BLEGATTCCHAR={},{}",
+            service.conn_index,
+            service.service_index
+        )?;
+        let mut characteristics: ClientCharacteristics = heapless::Vec::new();
+        self.read_response(|line| {
+            let arguments = match parser::response(line, "BLEGATTCCHAR") {
+                Some(arguments) => arguments,
+                None => return Ok(()),
+            };
+            let mut fields = parser::fields(arguments);
+            match fields.next().map(parser::unquote) {
+                Some("char") => {
+                    let characteristic =
+                        parse_client_characteristic(fields).ok_or(Error::UnexpectedResponse)?;
+                    characteristics
+                        .push(characteristic)
+                        .map_err(|_| Error::BufferOverflow)?;
+                }
+                Some("desc") => {
+                    let descriptor =
+                        parse_client_descriptor(fields).ok_or(Error::UnexpectedResponse)?;
+                    // The descriptors follow the characteristic they belong to
+                    let characteristic = characteristics
+                        .iter_mut()
+                        .rev()
+                        .find(|c| c.handle == descriptor.handle.characteristic)
+                        .ok_or(Error::UnexpectedResponse)?;
+                    characteristic
+                        .descriptors
+                        .push(descriptor)
+                        .map_err(|_| Error::BufferOverflow)?;
+                }
+                _ => return Err(Error::UnexpectedResponse),
+            }
+            Ok(())
+        })?;
+        Ok(characteristics)
+    }
+
+    /// Reads the value of the characteristic `characteristic` of a peer into `buf`, returning
+    /// its length.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// use esp_at::gatt::{CharacteristicHandle, ServiceHandle};
+    /// let script = [Exchange::new(
+    ///     b"AT+BLEGATTCRD=0,3,1\r\n",
+    ///     b"+BLEGATTCRD:0,1,\x0a\r\n\r\nOK\r\n",
+    /// )];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Ble.into());
+    ///
+    /// let battery_level = CharacteristicHandle {
+    ///     service: ServiceHandle {
+    ///         conn_index: 0,
+    ///         service_index: 3,
+    ///     },
+    ///     char_index: 1,
+    /// };
+    /// let mut buf = [0; 1];
+    /// let len = nb::block!(esp32_at.read_gatt_characteristic(battery_level, &mut buf)).unwrap();
+    /// assert_eq!(&[10], &buf[..len]);
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn read_gatt_characteristic(
+        &mut self,
+        characteristic: CharacteristicHandle,
+        buf: &mut [u8],
+    ) -> nb::Result<usize, Error<RX::Error, TX::Error>> {
+        self.read_gatt_attribute(
+            Attribute {
+                characteristic,
+                desc_index: None,
+            },
+            buf,
+        )
+    }
+
+    /// Like `read_gatt_characteristic`, but reads the value of a descriptor.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// use esp_at::gatt::{CharacteristicHandle, DescriptorHandle, ServiceHandle};
+    /// let script = [Exchange::new(
+    ///     b"AT+BLEGATTCRD=0,3,1,1\r\n",
+    ///     b"+BLEGATTCRD:0,2,\x00\x00\r\n\r\nOK\r\n",
+    /// )];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Ble.into());
+    ///
+    /// let configuration = DescriptorHandle {
+    ///     characteristic: CharacteristicHandle {
+    ///         service: ServiceHandle {
+    ///             conn_index: 0,
+    ///             service_index: 3,
+    ///         },
+    ///         char_index: 1,
+    ///     },
+    ///     desc_index: 1,
+    /// };
+    /// let mut buf = [0; 8];
+    /// let len = nb::block!(esp32_at.read_gatt_descriptor(configuration, &mut buf)).unwrap();
+    /// assert_eq!(&[0, 0], &buf[..len]);
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn read_gatt_descriptor(
+        &mut self,
+        descriptor: DescriptorHandle,
+        buf: &mut [u8],
+    ) -> nb::Result<usize, Error<RX::Error, TX::Error>> {
+        self.read_gatt_attribute(
+            Attribute {
+                characteristic: descriptor.characteristic,
+                desc_index: Some(descriptor.desc_index),
+            },
+            buf,
+        )
+    }
+
+    /// Writes `value` to the characteristic `characteristic` of a peer.
+    ///
+    /// The module writes with a response, which the peer confirms the write with, unless the
+    /// characteristic only supports `CharacteristicProperty::WriteWithoutResponse`.  A write
+    /// that the peer refused fails with `Error::SendFailed`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// use esp_at::gatt::{CharacteristicHandle, ServiceHandle};
+    /// let script = [
+    ///     Exchange::new(b"AT+BLEGATTCWR=1,4,2,3\r\n", b"\r\nOK\r\n\r\n>"),
+    ///     Exchange::new(b"\x01\xff\x00", b"\r\nOK\r\n"),
+    /// ];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Ble.into());
+    ///
+    /// let color = CharacteristicHandle {
+    ///     service: ServiceHandle {
+    ///         conn_index: 1,
+    ///         service_index: 4,
+    ///     },
+    ///     char_index: 2,
+    /// };
+    /// nb::block!(esp32_at.write_gatt_characteristic(color, &[0x01, 0xff, 0x00])).unwrap();
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn write_gatt_characteristic(
+        &mut self,
+        characteristic: CharacteristicHandle,
+        value: &[u8],
+    ) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.write_gatt_attribute(
+            Attribute {
+                characteristic,
+                desc_index: None,
+            },
+            value,
+        )
+    }
+
+    /// Like `write_gatt_characteristic`, but writes the value of a descriptor, e.g. `[1, 0]` to
+    /// the client characteristic configuration to enable notifications.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// use esp_at::gatt::{CharacteristicHandle, DescriptorHandle, ServiceHandle};
+    /// use esp_at::Error;
+    /// let script = [
+    ///     Exchange::new(b"AT+BLEGATTCWR=0,3,1,1,2\r\n", b"\r\nOK\r\n\r\n>"),
+    ///     Exchange::new(b"\x01\x00", b"\r\nERROR\r\n"),
+    /// ];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Ble.into());
+    ///
+    /// let configuration = DescriptorHandle {
+    ///     characteristic: CharacteristicHandle {
+    ///         service: ServiceHandle {
+    ///             conn_index: 0,
+    ///             service_index: 3,
+    ///         },
+    ///         char_index: 1,
+    ///     },
+    ///     desc_index: 1,
+    /// };
+    /// match nb::block!(esp32_at.write_gatt_descriptor(configuration, &[1, 0])) {
+    ///     Err(Error::SendFailed { link_id }) => assert_eq!(Some(0), link_id),
+    ///     result => panic!("unexpected result: {:?}", result),
+    /// }
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn write_gatt_descriptor(
+        &mut self,
+        descriptor: DescriptorHandle,
+        value: &[u8],
+    ) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.write_gatt_attribute(
+            Attribute {
+                characteristic: descriptor.characteristic,
+                desc_index: Some(descriptor.desc_index),
+            },
+            value,
+        )
+    }
+
+    fn read_gatt_attribute(
+        &mut self,
+        attribute: Attribute,
+        buf: &mut [u8],
+    ) -> nb::Result<usize, Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::Ble)?;
+        if attribute.conn_index() >= MAX_CONNECTIONS {
+            return Err(nb::Error::Other(Error::InvalidArgument));
+        }
+        write_command!(self, "AT+BLEGATTCRD={}", attribute)?;
+        // The value is binary, and follows `+BLEGATTCRD:<conn_index>,<len>,`
+        match self.read_data_response("BLEGATTCRD", 2, 1, buf)? {
+            Some(header) => Ok(header.len),
+            None => Err(nb::Error::Other(Error::UnexpectedResponse)),
+        }
+    }
+
+    fn write_gatt_attribute(
+        &mut self,
+        attribute: Attribute,
+        value: &[u8],
+    ) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::Ble)?;
+        let conn_index = attribute.conn_index();
+        if conn_index >= MAX_CONNECTIONS || value.is_empty() || value.len() > MAX_VALUE_LEN {
+            return Err(nb::Error::Other(Error::InvalidArgument));
+        }
+        write_command!(self, "AT+BLEGATTCWR={},{}", attribute, value.len())?;
+        self.expect_prompt()?;
+        self.write_all(value)?;
+        self.read_gatt_send_response(conn_index)
+    }
+
+    /// Reads the response to a value sent after the prompt, which the peer refused if it is
+    /// `ERROR`.
+    fn read_gatt_send_response(
+        &mut self,
+        conn_index: u8,
+    ) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        loop {
+            let line: Line = self.read_line()?;
+            match line.as_str() {
+                "OK" => return Ok(()),
+                "ERROR" => {
+                    return Err(nb::Error::Other(Error::SendFailed {
+                        link_id: Some(conn_index),
+                    }))
+                }
+                line => self.queue_event(line),
+            }
+        }
+    }
+}
+
+/// Parses `<conn_index>,<srv_index>,<srv_uuid>,<srv_type>`.
+fn parse_client_service(arguments: &str) -> Option<ClientService> {
+    let mut fields = parser::fields(arguments);
+    Some(ClientService {
+        handle: ServiceHandle {
+            conn_index: fields.next()?.parse().ok()?,
+            service_index: fields.next()?.parse().ok()?,
+        },
+        uuid: Uuid::parse(fields.next()?)?,
+        primary: parse_flag(fields.next()?)?,
+    })
+}
+
+/// Parses `<conn_index>,<srv_index>,<srv_uuid>,<srv_type>,<included_srv_uuid>,
+/// <included_srv_type>`.
+fn parse_included_service(arguments: &str) -> Option<IncludedService> {
+    let mut fields = parser::fields(arguments).skip(4);
+    Some(IncludedService {
+        uuid: Uuid::parse(fields.next()?)?,
+        primary: parse_flag(fields.next()?)?,
+    })
+}
+
+/// Parses `<conn_index>,<srv_index>,<char_index>,<char_uuid>,<char_prop>`.
+fn parse_client_characteristic<'a>(
+    mut fields: impl Iterator<Item = &'a str>,
+) -> Option<ClientCharacteristic> {
+    Some(ClientCharacteristic {
+        handle: parse_characteristic_handle(&mut fields)?,
+        uuid: Uuid::parse(fields.next()?)?,
+        properties: parse_properties(fields.next()?)?,
+        descriptors: heapless::Vec::new(),
+    })
+}
+
+/// Parses `<conn_index>,<srv_index>,<char_index>,<desc_index>,<desc_uuid>`.
+fn parse_client_descriptor<'a>(
+    mut fields: impl Iterator<Item = &'a str>,
+) -> Option<ClientDescriptor> {
+    Some(ClientDescriptor {
+        handle: DescriptorHandle {
+            characteristic: parse_characteristic_handle(&mut fields)?,
+            desc_index: fields.next()?.parse().ok()?,
+        },
+        uuid: Uuid::parse(fields.next()?)?,
+    })
+}
+
+fn parse_characteristic_handle<'a>(
+    fields: &mut impl Iterator<Item = &'a str>,
+) -> Option<CharacteristicHandle> {
+    Some(CharacteristicHandle {
+        service: ServiceHandle {
+            conn_index: fields.next()?.parse().ok()?,
+            service_index: fields.next()?.parse().ok()?,
+        },
+        char_index: fields.next()?.parse().ok()?,
+    })
+}
+
+/// Parses `<srv_index>,<char_index>,<char_uuid>,<char_prop>`.
+fn parse_server_characteristic<'a>(
+    mut fields: impl Iterator<Item = &'a str>,
+) -> Option<ServerCharacteristic> {
+    Some(ServerCharacteristic {
+        service_index: fields.next()?.parse().ok()?,
+        index: fields.next()?.parse().ok()?,
+        uuid: Uuid::parse(fields.next()?)?,
+        properties: parse_properties(fields.next()?)?,
+        descriptors: heapless::Vec::new(),
+    })
+}
+
+/// Parses `<srv_index>,<char_index>,<desc_index>`.
+fn parse_indexes<'a>(mut fields: impl Iterator<Item = &'a str>) -> Option<(u8, u8, u8)> {
+    Some((
+        fields.next()?.parse().ok()?,
+        fields.next()?.parse().ok()?,
+        fields.next()?.parse().ok()?,
+    ))
+}
+
+impl WriteBuffer {
+    pub(crate) fn new() -> Self {
+        WriteBuffer {
+            incoming: None,
+            value: heapless::Vec::new(),
+        }
+    }
+
+    /// Takes the event for the value once all of it has been received.
+    pub(crate) fn take(&mut self) -> Option<Event> {
+        match self.incoming {
+            Some(IncomingWrite { complete: true, .. }) => {}
+            _ => return None,
+        }
+        let incoming = self.incoming.take()?;
+        if incoming.discard {
+            return None;
+        }
+        Some(Event::GattWritten {
+            conn_index: incoming.conn_index,
+            service_index: incoming.service_index,
+            char_index: incoming.char_index,
+            desc_index: incoming.desc_index,
+            value: core::mem::replace(&mut self.value, heapless::Vec::new()),
+        })
+    }
+}
+
+impl PayloadSink for WriteBuffer {
+    const HEADER_END: u8 = b',';
+
+    /// Starts receiving the value announced by `header`, if it is a complete `+WRITE` header
+    /// like `+WRITE:<conn_index>,<srv_index>,<char_index>,[<desc_index>],<len>,`.
+    fn start(&mut self, header: &[u8]) -> Option<usize> {
+        let arguments = header
+            .strip_suffix(b",")
+            .and_then(|header| core::str::from_utf8(header).ok())
+            .and_then(|header| parser::response(header, "WRITE"))?;
+        let (incoming, len) = parse_write_header(arguments)?;
+        self.value = heapless::Vec::new();
+        self.incoming = Some(incoming);
+        Some(len)
+    }
+
+    fn push(&mut self, byte: u8) {
+        if let Some(IncomingWrite { discard: false, .. }) = self.incoming {
+            // The length was checked in `start`
+            let _ = self.value.push(byte);
+        }
+    }
+
+    fn finish(&mut self) {
+        if let Some(incoming) = &mut self.incoming {
+            incoming.complete = true;
+        }
+    }
+}
+
+/// Parses `<conn_index>,<srv_index>,<char_index>,[<desc_index>],<len>`.
+fn parse_write_header(arguments: &str) -> Option<(IncomingWrite, usize)> {
+    let mut fields = arguments.split(',');
+    let conn_index = fields.next()?.parse().ok()?;
+    let service_index = fields.next()?.parse().ok()?;
+    let char_index = fields.next()?.parse().ok()?;
+    let desc_index = match fields.next()? {
+        "" => None,
+        desc_index => Some(desc_index.parse().ok()?),
+    };
+    let len = fields.next()?.parse().ok()?;
+    if fields.next().is_some() {
+        return None;
+    }
+    let incoming = IncomingWrite {
+        conn_index,
+        service_index,
+        char_index,
+        desc_index,
+        discard: len > MAX_WRITTEN_VALUE_LEN,
+        complete: false,
+    };
+    Some((incoming, len))
+}
+
+/// Formats a characteristic or one of its descriptors as the leading arguments of
+/// `AT+BLEGATTCRD` and `AT+BLEGATTCWR`.
+#[derive(Debug, Clone, Copy)]
+struct Attribute {
+    characteristic: CharacteristicHandle,
+    desc_index: Option<u8>,
+}
+
+impl Attribute {
+    fn conn_index(self) -> u8 {
+        self.characteristic.service.conn_index
+    }
+}
+
+impl fmt::Display for Attribute {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let CharacteristicHandle {
+            service,
+            char_index,
+        } = self.characteristic;
+        write!(
+            f,
+            "{},{},{}",
+            service.conn_index, service.service_index, char_index
+        )?;
+        if let Some(desc_index) = self.desc_index {
+            write!(f, ",{}", desc_index)?;
+        }
+        Ok(())
+    }
+}
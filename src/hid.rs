@@ -0,0 +1,224 @@
+//! A HID device over BLE, i.e. a keyboard, mouse or remote control that hosts such as phones
+//! and computers pair with.
+//!
+//! The reports use the usage IDs of the USB HID usage tables, e.g. `0x04` for the `A` key or
+//! `0xe9` for volume up.  Keys and buttons stay pressed until a report without them is sent.
+
+use core::fmt;
+
+use crate::ble::MAX_NAME_LEN;
+use crate::{CommandSet, Error, Escaped, Esp32At};
+
+/// The most keys that a keyboard report can have pressed at the same time, besides the
+/// modifier keys.
+pub const MAX_KEYS: usize = 6;
+
+/// A modifier key, of which any combination can be pressed.
+#[derive(Debug, enumset::EnumSetType)]
+pub enum Modifier {
+    LeftControl,
+    LeftShift,
+    LeftAlt,
+    LeftGui,
+    RightControl,
+    RightShift,
+    RightAlt,
+    RightGui,
+}
+
+/// A mouse button, of which any combination can be pressed.
+#[derive(Debug, enumset::EnumSetType)]
+pub enum MouseButton {
+    Left,
+    Right,
+    Middle,
+}
+
+impl<RX, TX> Esp32At<RX, TX>
+where
+    RX: embedded_hal::serial::Read<u8>,
+    TX: embedded_hal::serial::Write<u8>,
+{
+    /// Starts the HID service, which advertises the module as a keyboard, mouse and remote
+    /// control; BLE must have been initialized as a server, without any other services.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// let script = [
+    ///     Exchange::new(b"AT+BLEHIDNAME=\"Remote\"\r\n", b"\r\nOK\r\n"),
+    ///     Exchange::new(b"AT+BLEHIDINIT=1\r\n", b"\r\nOK\r\n"),
+    /// ];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Ble.into());
+    ///
+    /// nb::block!(esp32_at.set_ble_hid_name("Remote")).unwrap();
+    /// nb::block!(esp32_at.ble_hid_init()).unwrap();
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn ble_hid_init(&mut self) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::Ble)?;
+        write_command!(self, "AT+BLEHIDINIT=1")?;
+        self.expect_ok_response()
+    }
+
+    /// Stops the HID service.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// let script = [Exchange::new(b"AT+BLEHIDINIT=0\r\n", b"\r\nOK\r\n")];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Ble.into());
+    ///
+    /// nb::block!(esp32_at.ble_hid_deinit()).unwrap();
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn ble_hid_deinit(&mut self) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::Ble)?;
+        write_command!(self, "AT+BLEHIDINIT=0")?;
+        self.expect_ok_response()
+    }
+
+    /// Sets the name that the HID device is advertised with, which is at most `MAX_NAME_LEN`
+    /// bytes long.
+    pub fn set_ble_hid_name(&mut self, name: &str) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::Ble)?;
+        if name.is_empty() || name.len() > MAX_NAME_LEN {
+            return Err(nb::Error::Other(Error::InvalidArgument));
+        }
+        write_command!(self, "AT+BLEHIDNAME=\"{}\"", Escaped(name))?;
+        self.expect_ok_response()
+    }
+
+    /// Sends a keyboard report, with `modifiers` and at most `MAX_KEYS` of `keys` pressed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// use esp_at::hid::Modifier;
+    /// let script = [
+    ///     Exchange::new(b"AT+BLEHIDKB=2,4,0,0,0,0,0\r\n", b"\r\nOK\r\n"),
+    ///     Exchange::new(b"AT+BLEHIDKB=0,0,0,0,0,0,0\r\n", b"\r\nOK\r\n"),
+    /// ];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Ble.into());
+    ///
+    /// // Type a capital A, and release it
+    /// nb::block!(esp32_at.ble_hid_keyboard(Modifier::LeftShift.into(), &[0x04])).unwrap();
+    /// nb::block!(esp32_at.ble_hid_keyboard(enumset::EnumSet::new(), &[])).unwrap();
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn ble_hid_keyboard(
+        &mut self,
+        modifiers: enumset::EnumSet<Modifier>,
+        keys: &[u8],
+    ) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::Ble)?;
+        if keys.len() > MAX_KEYS {
+            return Err(nb::Error::Other(Error::InvalidArgument));
+        }
+        write_command!(self, "AT+BLEHIDKB={},{}", modifiers.to_bits(), Keys(keys))?;
+        self.expect_ok_response()
+    }
+
+    /// Sends a mouse report, with `buttons` pressed, moving the pointer by `x` and `y` and
+    /// scrolling by `wheel`, each from -127 to 127.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// use esp_at::hid::MouseButton;
+    /// let script = [
+    ///     Exchange::new(b"AT+BLEHIDMUS=0,10,-5,0\r\n", b"\r\nOK\r\n"),
+    ///     Exchange::new(b"AT+BLEHIDMUS=1,0,0,0\r\n", b"\r\nOK\r\n"),
+    ///     Exchange::new(b"AT+BLEHIDMUS=0,0,0,0\r\n", b"\r\nOK\r\n"),
+    /// ];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Ble.into());
+    ///
+    /// // Move the pointer right and up, and click
+    /// nb::block!(esp32_at.ble_hid_mouse(enumset::EnumSet::new(), 10, -5, 0)).unwrap();
+    /// nb::block!(esp32_at.ble_hid_mouse(MouseButton::Left.into(), 0, 0, 0)).unwrap();
+    /// nb::block!(esp32_at.ble_hid_mouse(enumset::EnumSet::new(), 0, 0, 0)).unwrap();
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn ble_hid_mouse(
+        &mut self,
+        buttons: enumset::EnumSet<MouseButton>,
+        x: i8,
+        y: i8,
+        wheel: i8,
+    ) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::Ble)?;
+        if x == i8::MIN || y == i8::MIN || wheel == i8::MIN {
+            return Err(nb::Error::Other(Error::InvalidArgument));
+        }
+        write_command!(
+            self,
+            "AT+BLEHIDMUS={},{},{},{}",
+            buttons.to_bits(),
+            x,
+            y,
+            wheel
+        )?;
+        self.expect_ok_response()
+    }
+
+    /// Sends a consumer control report, with the control `usage_id` pressed, or none if it is 0.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// let script = [
+    ///     Exchange::new(b"AT+BLEHIDCONSUMER=233\r\n", b"\r\nOK\r\n"),
+    ///     Exchange::new(b"AT+BLEHIDCONSUMER=0\r\n", b"\r\nOK\r\n"),
+    /// ];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Ble.into());
+    ///
+    /// // Press volume up, and release it
+    /// nb::block!(esp32_at.ble_hid_consumer(0xe9)).unwrap();
+    /// nb::block!(esp32_at.ble_hid_consumer(0)).unwrap();
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn ble_hid_consumer(
+        &mut self,
+        usage_id: u16,
+    ) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::Ble)?;
+        write_command!(self, "AT+BLEHIDCONSUMER={}", usage_id)?;
+        self.expect_ok_response()
+    }
+}
+
+/// Formats the keys of a keyboard report as the `MAX_KEYS` trailing arguments of
+/// `AT+BLEHIDKB`, leaving the keys that aren't pressed 0.
+struct Keys<'a>(&'a [u8]);
+
+impl fmt::Display for Keys<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for i in 0..MAX_KEYS {
+            if i > 0 {
+                f.write_str(",")?;
+            }
+            write!(f, "{}", self.0.get(i).copied().unwrap_or(0))?;
+        }
+        Ok(())
+    }
+}
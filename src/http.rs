@@ -0,0 +1,400 @@
+//! Requests made by the module's own HTTP client, so that the host needs no HTTP stack.
+
+use crate::{parser, CommandSet, Error, Escaped, Esp32At, Line, QuotedList};
+
+/// The longest URL that is given in the commands themselves; longer ones are configured with
+/// `Esp32At::set_http_url` first, to keep the commands within the length limit of the firmware.
+pub const MAX_INLINE_URL_LEN: usize = 128;
+
+/// The longest URL that can be configured with `Esp32At::set_http_url`.
+pub const MAX_URL_LEN: usize = 8192;
+
+/// How many bytes of the response body are handed over at a time.
+const CHUNK_LEN: usize = 64;
+
+/// The media type of a request body.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HttpContentType {
+    FormUrlEncoded,
+    Json,
+    Multipart,
+    Xml,
+}
+
+impl HttpContentType {
+    fn code(self) -> u8 {
+        match self {
+            HttpContentType::FormUrlEncoded => 0,
+            HttpContentType::Json => 1,
+            HttpContentType::Multipart => 2,
+            HttpContentType::Xml => 3,
+        }
+    }
+}
+
+impl<RX, TX> Esp32At<RX, TX>
+where
+    RX: embedded_hal::serial::Read<u8>,
+    TX: embedded_hal::serial::Write<u8>,
+{
+    /// Posts `body` to `url`, reading the response body into `buf` and returning its length.
+    ///
+    /// `headers` are additional request headers like `Authorization: Bearer <token>`.  A
+    /// response body that doesn't fit in `buf` fails with `Error::BufferOverflow` once the
+    /// request has completed.
+    ///
+    /// The body is part of the command, whose length is limited by the firmware; use
+    /// `http_post_chunks` for larger bodies.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// use esp_at::http::HttpContentType;
+    /// let script = [Exchange::new(
+    ///     b"AT+HTTPCLIENT=3,1,\"https://example.com/telemetry\",,,2,\
+    ///       \"{\\\"temp\\\":21.5\\,\\\"rh\\\":40}\",\"Authorization: Bearer abc\"\r\n",
+    ///     b"+HTTPCLIENT:11,{\"ok\":true}\r\n\r\nOK\r\n",
+    /// )];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Http | CommandSet::Tls);
+    ///
+    /// let mut buf = [0; 64];
+    /// let len = nb::block!(esp32_at.http_post(
+    ///     "https://example.com/telemetry",
+    ///     HttpContentType::Json,
+    ///     r#"{"temp":21.5,"rh":40}"#,
+    ///     &["Authorization: Bearer abc"],
+    ///     &mut buf,
+    /// ))
+    /// .unwrap();
+    /// assert_eq!(br#"{"ok":true}"#, &buf[..len]);
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn http_post(
+        &mut self,
+        url: &str,
+        content_type: HttpContentType,
+        body: &str,
+        headers: &[&str],
+        buf: &mut [u8],
+    ) -> nb::Result<usize, Error<RX::Error, TX::Error>> {
+        let url_argument = self.http_url_argument(url)?;
+        write_command!(
+            self,
+            "AT+HTTPCLIENT=3,{},\"{}\",,,{},\"{}\"{}",
+            content_type.code(),
+            url_argument,
+            transport_type(url),
+            Escaped(body),
+            QuotedList(headers)
+        )?;
+        self.read_http_response_into(buf)
+    }
+
+    /// Gets the resource at `url`, passing its body to `on_data` in pieces as they arrive, so
+    /// that bodies too large to be buffered, e.g. firmware images, can be written straight to
+    /// flash.
+    ///
+    /// Once `on_data` fails it isn't passed any more of the body, and its error is returned; the
+    /// rest of the body is still read, since the module can't be stopped half-way through.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// let script = [
+    ///     Exchange::new(
+    ///         b"AT+HTTPCLIENT=2,0,\"http://example.com/image.bin\",,,1,,\"Range: bytes=0-99\"\r\n",
+    ///         b"+HTTPCLIENT:100,",
+    ///     ),
+    ///     Exchange::unsolicited(&[0xa5; 100]),
+    ///     Exchange::unsolicited(b"\r\n+HTTPCLIENT:3,\x00\x01\x02\r\n\r\nOK\r\n"),
+    /// ];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Http.into());
+    ///
+    /// let mut len = 0;
+    /// esp32_at
+    ///     .http_get("http://example.com/image.bin", &["Range: bytes=0-99"], |data| {
+    ///         // e.g. write `data` to flash at offset `len`
+    ///         len += data.len();
+    ///         Ok::<_, esp_at::Error<_, _>>(())
+    ///     })
+    ///     .unwrap();
+    /// assert_eq!(103, len);
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn http_get<F, E>(&mut self, url: &str, headers: &[&str], mut on_data: F) -> Result<(), E>
+    where
+        F: FnMut(&[u8]) -> Result<(), E>,
+        E: From<Error<RX::Error, TX::Error>>,
+    {
+        let url_argument = nb::block!(self.http_url_argument(url))?;
+        // The headers follow the request body, which is left out
+        let body = if headers.is_empty() { "" } else { "," };
+        nb::block!(write_command!(
+            self,
+            "AT+HTTPCLIENT=2,0,\"{}\",,,{}{}{}",
+            url_argument,
+            transport_type(url),
+            body,
+            QuotedList(headers)
+        ))?;
+        let mut failure = None;
+        nb::block!(self.read_http_response(|data| {
+            if failure.is_none() {
+                failure = on_data(data).err();
+            }
+            Ok(())
+        }))?;
+        match failure {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
+    }
+
+    /// Posts a body of any length to `url`, which unlike `http_post` is sent after the command
+    /// instead of in it; `chunks` make up the body, and are iterated over twice, first to
+    /// determine its length and then to send it.
+    ///
+    /// The content type is given in `headers`, e.g. `Content-Type: application/cbor`.  A body
+    /// that the module couldn't send fails with `Error::SendFailed`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// let script = [
+    ///     Exchange::new(
+    ///         b"AT+HTTPCPOST=\"http://example.com/upload\",9,1,\"Content-Type: text/csv\"\r\n",
+    ///         b"\r\nOK\r\n\r\n>",
+    ///     ),
+    ///     Exchange::new(b"1,2\n3,4\n\n", b"\r\nSEND OK\r\n"),
+    /// ];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Http.into());
+    ///
+    /// let rows: [&[u8]; 3] = [b"1,2\n", b"3,4\n", b"\n"];
+    /// nb::block!(esp32_at.http_post_chunks(
+    ///     "http://example.com/upload",
+    ///     &["Content-Type: text/csv"],
+    ///     rows.iter().copied(),
+    /// ))
+    /// .unwrap();
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn http_post_chunks<'b, I>(
+        &mut self,
+        url: &str,
+        headers: &[&str],
+        chunks: I,
+    ) -> nb::Result<(), Error<RX::Error, TX::Error>>
+    where
+        I: IntoIterator<Item = &'b [u8]> + Clone,
+    {
+        let url_argument = self.http_url_argument(url)?;
+        let len: usize = chunks.clone().into_iter().map(<[u8]>::len).sum();
+        write_command!(
+            self,
+            "AT+HTTPCPOST=\"{}\",{},{}{}",
+            url_argument,
+            len,
+            headers.len(),
+            QuotedList(headers)
+        )?;
+        self.expect_prompt()?;
+        for chunk in chunks {
+            self.write_all(chunk)?;
+        }
+
+        loop {
+            let line: Line = self.read_line()?;
+            match line.as_str() {
+                "SEND OK" => return Ok(()),
+                "SEND FAIL" | "ERROR" => {
+                    return Err(nb::Error::Other(Error::SendFailed { link_id: None }))
+                }
+                line => self.queue_event(line),
+            }
+        }
+    }
+
+    /// Returns the size of the resource at `url` as reported by its `Content-Length`, e.g. to
+    /// make sure that a download fits before starting it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// let script = [Exchange::new(
+    ///     b"AT+HTTPGETSIZE=\"http://example.com/firmware.bin\"\r\n",
+    ///     b"+HTTPGETSIZE:1048576\r\n\r\nOK\r\n",
+    /// )];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Http.into());
+    ///
+    /// let size = nb::block!(esp32_at.http_get_size("http://example.com/firmware.bin")).unwrap();
+    /// assert_eq!(1048576, size);
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn http_get_size(&mut self, url: &str) -> nb::Result<u32, Error<RX::Error, TX::Error>> {
+        let url_argument = self.http_url_argument(url)?;
+        write_command!(self, "AT+HTTPGETSIZE=\"{}\"", url_argument)?;
+        let size = self.read_query_response("HTTPGETSIZE")?;
+        Ok(size.parse().map_err(|_| Error::UnexpectedResponse)?)
+    }
+
+    /// Configures the URL used by the HTTP requests that are given an empty one, which is how
+    /// URLs that are too long for the commands themselves are requested; the other methods do
+    /// this on their own for URLs longer than `MAX_INLINE_URL_LEN`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// let script = [
+    ///     Exchange::new(b"AT+HTTPURLCFG=29\r\n", b"\r\nOK\r\n\r\n>"),
+    ///     Exchange::new(b"http://example.com/a?b=c,d\"ef", b"\r\nSET OK\r\n"),
+    /// ];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Http.into());
+    ///
+    /// nb::block!(esp32_at.set_http_url("http://example.com/a?b=c,d\"ef")).unwrap();
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn set_http_url(&mut self, url: &str) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::Http)?;
+        if url.len() > MAX_URL_LEN {
+            return Err(nb::Error::Other(Error::InvalidArgument));
+        }
+        write_command!(self, "AT+HTTPURLCFG={}", url.len())?;
+        self.expect_prompt()?;
+        // The URL is sent as it is, without escaping
+        self.write_all(url.as_bytes())?;
+
+        loop {
+            let line: Line = self.read_line()?;
+            match line.as_str() {
+                "SET OK" => return Ok(()),
+                "ERROR" | "FAIL" => return Err(nb::Error::Other(Error::CommandFailed)),
+                line => self.queue_event(line),
+            }
+        }
+    }
+
+    /// Checks that the command sets needed to request `url` are supported, and returns the URL
+    /// argument of a request, configuring long URLs with `set_http_url` first.
+    fn http_url_argument<'u>(
+        &mut self,
+        url: &'u str,
+    ) -> nb::Result<Escaped<'u>, Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::Http)?;
+        if transport_type(url) == 2 {
+            self.require_command_set(CommandSet::Tls)?;
+        }
+        if Escaped(url).len() <= MAX_INLINE_URL_LEN {
+            return Ok(Escaped(url));
+        }
+        self.set_http_url(url)?;
+        Ok(Escaped(""))
+    }
+
+    /// Reads a response body made up of `+HTTPCLIENT:<len>,<data>` chunks into `buf`, returning
+    /// its length.
+    fn read_http_response_into(
+        &mut self,
+        buf: &mut [u8],
+    ) -> nb::Result<usize, Error<RX::Error, TX::Error>> {
+        let mut len = 0;
+        let mut overflowed = false;
+        self.read_http_response(|data| {
+            match buf.get_mut(len..len + data.len()) {
+                Some(dest) if !overflowed => {
+                    dest.copy_from_slice(data);
+                    len += data.len();
+                }
+                _ => overflowed = true,
+            }
+            Ok(())
+        })?;
+        if overflowed {
+            return Err(nb::Error::Other(Error::BufferOverflow));
+        }
+        Ok(len)
+    }
+
+    /// Reads the response to an HTTP request, passing the response body to `on_data` in pieces
+    /// of up to `CHUNK_LEN` bytes as they arrive.
+    ///
+    /// The body arrives as `+HTTPCLIENT:<len>,<data>` chunks of binary data, which can't be read
+    /// as lines.
+    fn read_http_response<F>(
+        &mut self,
+        mut on_data: F,
+    ) -> nb::Result<(), Error<RX::Error, TX::Error>>
+    where
+        F: FnMut(&[u8]) -> Result<(), Error<RX::Error, TX::Error>>,
+    {
+        loop {
+            let byte = nb::block!(self.poll_byte())?;
+            if self.line.push(byte).is_err() {
+                self.line = heapless::Vec::new();
+                return Err(nb::Error::Other(Error::BufferOverflow));
+            }
+
+            if byte == b',' && self.line.starts_with(b"+HTTPCLIENT:") {
+                let line = core::mem::replace(&mut self.line, heapless::Vec::new());
+                let mut remaining: usize = core::str::from_utf8(&line[..line.len() - 1])
+                    .ok()
+                    .and_then(|line| parser::response(line, "HTTPCLIENT"))
+                    .and_then(|len| len.parse().ok())
+                    .ok_or(Error::UnexpectedResponse)?;
+                let mut chunk = [0; CHUNK_LEN];
+                while remaining > 0 {
+                    let len = remaining.min(CHUNK_LEN);
+                    for byte in chunk[..len].iter_mut() {
+                        *byte = nb::block!(self.getc())?;
+                    }
+                    on_data(&chunk[..len])?;
+                    remaining -= len;
+                }
+            } else if byte == b'\n' && self.line.ends_with(b"\r\n") {
+                let line = core::mem::replace(&mut self.line, heapless::Vec::new());
+                let line: Line = core::str::from_utf8(&line[..line.len() - 2])
+                    .ok()
+                    .and_then(parser::string)
+                    .ok_or(Error::UnexpectedResponse)?;
+                match line.as_str() {
+                    "OK" => return Ok(()),
+                    "ERROR" | "FAIL" => return Err(nb::Error::Other(Error::CommandFailed)),
+                    "" => {}
+                    echo if parser::is_echo(echo) => {}
+                    line => self.queue_event(line),
+                }
+            } else {
+                self.start_payload();
+            }
+        }
+    }
+}
+
+/// The transport type argument of `AT+HTTPCLIENT` for `url`: TLS for `https` URLs, and TCP
+/// otherwise.
+fn transport_type(url: &str) -> u8 {
+    if url.starts_with("https://") {
+        2
+    } else {
+        1
+    }
+}
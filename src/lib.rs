@@ -1,10 +1,50 @@
 #![no_std]
 
+#[cfg(feature = "testing")]
+extern crate std;
+
 use core::fmt;
 
+macro_rules! write_command {
+    ($this:expr, $template:expr) => {
+        write_command!($this, $template,)
+    };
+    ($this:expr, $template:expr, $($args:tt)*) => {
+        $this.write_command(format_args!(concat!($template, "\r\n"), $($args)*))
+    }
+}
+
+pub mod ble;
+pub mod config;
+pub mod dns;
+pub mod ethernet;
+pub mod event;
+pub mod gatt;
+pub mod hid;
+pub mod http;
+pub mod link;
+#[cfg(feature = "mock")]
+pub mod mock;
+pub mod mqtt;
+pub mod mqtt_client;
+pub mod ota;
 mod parser;
-mod serial;
-mod state;
+pub mod passthrough;
+mod payload;
+pub mod pki;
+pub mod receive;
+pub mod reconnect;
+pub mod serial;
+pub mod signal;
+pub mod sntp;
+pub mod socket;
+pub mod state;
+pub mod tcpip;
+#[cfg(feature = "testing")]
+pub mod testing;
+pub mod webserver;
+pub mod websocket;
+pub mod wifi;
 
 #[derive(Debug)]
 pub struct Esp32At<RX, TX>
@@ -15,6 +55,17 @@ where
     rx: RX,
     tx: TX,
     command_sets: enumset::EnumSet<CommandSet>,
+    line: heapless::Vec<u8, heapless::consts::U256>,
+    events: heapless::spsc::Queue<event::Event, heapless::consts::U8>,
+    /// The link ids in use, if multiple connection mode is enabled.
+    links: Option<link::LinkAllocator>,
+    received: payload::PayloadReceiver<receive::ReceiveBuffers>,
+    mqtt_received: payload::PayloadReceiver<mqtt::ReceiveBuffer>,
+    websocket_received: payload::PayloadReceiver<websocket::ReceiveBuffers>,
+    gatt_written: payload::PayloadReceiver<gatt::WriteBuffer>,
+    reserved_data: payload::PayloadReceiver<wifi::ReservedDataBuffer>,
+    /// The BLE connection indexes in use.
+    ble_connections: u8,
 }
 
 #[derive(Debug, enumset::EnumSetType)]
@@ -23,102 +74,308 @@ pub enum CommandSet {
     Wifi,
     TcpIp,
     Ble,
+    // Taken from https://docs.espressif.com/projects/esp-at/en/latest/esp32/AT_Command_Set/index.html
+    Mqtt,
+    /// The module's own HTTP client.
+    Http,
+    WebServer,
+    /// The module's own WebSocket client, which only some firmware builds include.
+    WebSocket,
+    /// TLS connections, which some firmware builds leave out to save space.
+    Tls,
+    /// The wired interface of boards with an Ethernet PHY, in firmware built with Ethernet support.
+    Ethernet,
     // Taken from https://github.com/particle-iot/argon-ncp-firmware/blob/master/README.md
     ParticleArgonExt,
 }
 
-#[derive(Debug, failure::Fail)]
-pub enum Error<RXE, TXE>
-where
-    RXE: failure::Fail,
-    TXE: failure::Fail,
-{
-    #[fail(display = "command set not supported: {:?}", command_set)]
-    CommandSetNotSupported { command_set: CommandSet },
-    #[fail(display = "unexpected response")]
+#[derive(Debug)]
+pub enum Error<RXE, TXE> {
+    CommandSetNotSupported {
+        command_set: CommandSet,
+    },
     UnexpectedResponse,
-    #[fail(display = "buffer overflow")]
+    CommandFailed,
+    InvalidArgument,
+    VerificationFailed,
+    Timeout,
+    JoinFailed {
+        reason: wifi::JoinFailure,
+    },
+    ConnectFailed,
+    /// An MQTT command failed for the reason reported by the module.
+    MqttFailed {
+        reason: mqtt::MqttFailure,
+    },
+    /// The module couldn't send data on the link, which is only given in multiple connection
+    /// mode.
+    SendFailed {
+        link_id: Option<u8>,
+    },
+    /// The link was closed by the remote end while sending data on it.
+    LinkClosed {
+        link_id: Option<u8>,
+    },
+    NoFreeLink,
+    ResolveFailed,
     BufferOverflow,
-    #[fail(display = "UART read error")]
     UartRead {
-        #[cause]
         cause: RXE,
     },
-    #[fail(display = "UART write error")]
     UartWrite {
-        #[cause]
         cause: TXE,
     },
-    #[fail(display = "UTF-8 decoding error: {}", cause)]
     Utf8 {
-        // can't use #[cause] since the Fail trait is not implemented
         cause: core::str::Utf8Error,
     },
 }
 
+impl<RXE, TXE> fmt::Display for Error<RXE, TXE> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::CommandSetNotSupported { command_set } => {
+                write!(f, "command set not supported: {:?}", command_set)
+            }
+            Error::UnexpectedResponse => write!(f, "unexpected response"),
+            Error::CommandFailed => write!(f, "command failed"),
+            Error::InvalidArgument => write!(f, "invalid argument"),
+            Error::VerificationFailed => write!(f, "setting did not take effect"),
+            Error::Timeout => write!(f, "timed out"),
+            Error::JoinFailed { reason } => write!(f, "failed to join access point: {:?}", reason),
+            Error::ConnectFailed => write!(f, "failed to connect"),
+            Error::MqttFailed { reason } => write!(f, "MQTT command failed: {:?}", reason),
+            Error::SendFailed { .. } => write!(f, "failed to send data"),
+            Error::LinkClosed { .. } => write!(f, "link closed"),
+            Error::NoFreeLink => write!(f, "no free link id"),
+            Error::ResolveFailed => write!(f, "failed to resolve host name"),
+            Error::BufferOverflow => write!(f, "buffer overflow"),
+            Error::UartRead { .. } => write!(f, "UART read error"),
+            Error::UartWrite { .. } => write!(f, "UART write error"),
+            Error::Utf8 { cause } => write!(f, "UTF-8 decoding error: {}", cause),
+        }
+    }
+}
+
+#[cfg(feature = "failure")]
+impl<RXE, TXE> failure::Fail for Error<RXE, TXE>
+where
+    RXE: failure::Fail,
+    TXE: failure::Fail,
+{
+    fn cause(&self) -> Option<&dyn failure::Fail> {
+        match self {
+            Error::UartRead { cause } => Some(cause),
+            Error::UartWrite { cause } => Some(cause),
+            // can't return the UTF-8 error since the Fail trait is not implemented for it
+            _ => None,
+        }
+    }
+}
+
+#[cfg(feature = "core-error")]
+impl<RXE, TXE> core::error::Error for Error<RXE, TXE>
+where
+    RXE: core::error::Error + 'static,
+    TXE: core::error::Error + 'static,
+{
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match self {
+            Error::UartRead { cause } => Some(cause),
+            Error::UartWrite { cause } => Some(cause),
+            Error::Utf8 { cause } => Some(cause),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(feature = "embedded-io")]
+impl<RXE, TXE> embedded_io::Error for Error<RXE, TXE>
+where
+    RXE: fmt::Debug,
+    TXE: fmt::Debug,
+{
+    fn kind(&self) -> embedded_io::ErrorKind {
+        match self {
+            Error::InvalidArgument => embedded_io::ErrorKind::InvalidInput,
+            Error::Timeout => embedded_io::ErrorKind::TimedOut,
+            Error::LinkClosed { .. } => embedded_io::ErrorKind::ConnectionReset,
+            Error::BufferOverflow => embedded_io::ErrorKind::OutOfMemory,
+            _ => embedded_io::ErrorKind::Other,
+        }
+    }
+}
+
+/// A line received from the module.
+type Line = heapless::String<heapless::consts::U256>;
+
+/// The header of a response with binary data, see `Esp32At::read_data_response`.
+struct DataHeader {
+    arguments: Line,
+    /// The length of the data.
+    len: usize,
+}
+
 struct Writer<'a, RX, TX>
 where
     RX: embedded_hal::serial::Read<u8>,
-    RX::Error: failure::Fail,
     TX: embedded_hal::serial::Write<u8>,
-    TX::Error: failure::Fail,
 {
     this: &'a mut Esp32At<RX, TX>,
     error_ref: &'a mut Option<nb::Error<Error<RX::Error, TX::Error>>>,
 }
 
-macro_rules! write_command {
-    ($this:expr, $template:expr) => {
-        write_command!($this, $template,)
-    };
-    ($this:expr, $template:expr, $($args:tt)*) => {
-        $this.write_command(format_args!(concat!($template, "\r\n"), $($args)*))
-    }
-}
-
 impl<RX, TX> Esp32At<RX, TX>
 where
     RX: embedded_hal::serial::Read<u8>,
-    RX::Error: failure::Fail,
     TX: embedded_hal::serial::Write<u8>,
-    TX::Error: failure::Fail,
 {
     pub fn new(rx: RX, tx: TX, command_sets: enumset::EnumSet<CommandSet>) -> Self {
         Self {
             rx,
             tx,
             command_sets,
+            line: heapless::Vec::new(),
+            events: heapless::spsc::Queue::new(),
+            links: None,
+            received: payload::PayloadReceiver::new(receive::ReceiveBuffers::new()),
+            mqtt_received: payload::PayloadReceiver::new(mqtt::ReceiveBuffer::new()),
+            websocket_received: payload::PayloadReceiver::new(websocket::ReceiveBuffers::new()),
+            gatt_written: payload::PayloadReceiver::new(gatt::WriteBuffer::new()),
+            reserved_data: payload::PayloadReceiver::new(wifi::ReservedDataBuffer::new()),
+            ble_connections: 0,
         }
     }
 
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// let script = [Exchange::new(b"AT\r\n", b"AT\r\r\n\r\nOK\r\n")];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Wifi.into());
+    ///
+    /// nb::block!(esp32_at.test_startup()).unwrap();
+    /// # assert!(module.is_done());
+    /// ```
     pub fn test_startup(&mut self) -> nb::Result<(), Error<RX::Error, TX::Error>> {
         write_command!(self, "AT")?;
         self.expect_ok_response()
     }
 
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// let script = [Exchange::new(b"AT+RST\r\n", b"\r\nOK\r\n")];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Wifi.into());
+    ///
+    /// nb::block!(esp32_at.restart()).unwrap();
+    /// # assert!(module.is_done());
+    /// ```
     pub fn restart(&mut self) -> nb::Result<(), Error<RX::Error, TX::Error>> {
         write_command!(self, "AT+RST")?;
-        self.expect_ok_response()
+        self.expect_ok_response()?;
+        self.links = None;
+        self.received.sink.remote_info = false;
+        Ok(())
+    }
+
+    /// Returns the command sets that the module supports, which commands are checked against.
+    pub fn command_sets(&self) -> enumset::EnumSet<CommandSet> {
+        self.command_sets
     }
 
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// let script = [Exchange::new(
+    ///     b"AT+GMR\r\n",
+    ///     b"AT version:2.2.0.0(c6fa6bf - ESP32 - Jul  2 2021 06:44:05)\r\n\
+    ///       SDK version:v4.2.2-76-gefa6eca\r\n\
+    ///       compile time(3e2f9a4):Jul  3 2021 11:52:40\r\n\
+    ///       Bin version:2.2.0(WROOM-32)\r\n\
+    ///       \r\n\
+    ///       OK\r\n",
+    /// )];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Wifi.into());
+    ///
+    /// let revision = nb::block!(esp32_at.get_module_revision()).unwrap();
+    /// assert_eq!("v4.2.2-76-gefa6eca", revision.sdk_version);
+    /// # assert!(module.is_done());
+    /// ```
     pub fn get_module_revision(
         &mut self,
     ) -> nb::Result<state::ModuleRevision, Error<RX::Error, TX::Error>> {
         write_command!(self, "AT+GMR")?;
 
-        let at_version = self.read_line()?;
-        let sdk_version = self.read_line()?;
-        let compile_time = self.read_line()?;
+        let mut revision = state::ModuleRevision::default();
+        self.read_response(|line| {
+            revision.parse_line(line);
+            Ok(())
+        })?;
 
-        self.expect_ok_response()?;
+        Ok(revision)
+    }
 
-        Ok(state::ModuleRevision {
-            at_version,
-            sdk_version,
-            compile_time,
-        })
+    /// Queries the module revision and, if it identifies the chip, enables the command sets that
+    /// the firmware supports on it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// use esp_at::state::Chip;
+    /// let script = [Exchange::new(
+    ///     b"AT+GMR\r\n",
+    ///     b"AT version:2.2.0.0(c6fa6bf - ESP32 - Jul  2 2021 06:44:05)\r\n\
+    ///       SDK version:v4.2.2-76-gefa6eca\r\n\
+    ///       compile time(3e2f9a4):Jul  3 2021 11:52:40\r\n\
+    ///       Bin version:2.2.0(WROOM-32)\r\n\
+    ///       \r\n\
+    ///       OK\r\n",
+    /// )];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, enumset::EnumSet::empty());
+    ///
+    /// let revision = nb::block!(esp32_at.detect_command_sets()).unwrap();
+    /// assert_eq!(Some(Chip::Esp32), revision.chip);
+    /// assert!(esp32_at.command_sets().contains(CommandSet::Ble));
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn detect_command_sets(
+        &mut self,
+    ) -> nb::Result<state::ModuleRevision, Error<RX::Error, TX::Error>> {
+        let revision = self.get_module_revision()?;
+        if let Some(chip) = revision.chip {
+            self.command_sets = chip.command_sets();
+        }
+        Ok(revision)
     }
 
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// let script = [Exchange::new(b"AT+GSLP=1000\r\n", b"+GSLP:1000\r\n\r\nOK\r\n")];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Wifi.into());
+    ///
+    /// nb::block!(esp32_at.enter_deep_sleep(1000)).unwrap();
+    /// # assert!(module.is_done());
+    /// ```
     pub fn enter_deep_sleep(
         &mut self,
         wakeup_delay_ms: u32,
@@ -128,60 +385,401 @@ where
         self.expect_ok_response()
     }
 
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// let script = [Exchange::new(b"AT+RESTORE\r\n", b"\r\nOK\r\n")];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Wifi.into());
+    ///
+    /// nb::block!(esp32_at.factory_reset()).unwrap();
+    /// # assert!(module.is_done());
+    /// ```
     pub fn factory_reset(&mut self) -> nb::Result<(), Error<RX::Error, TX::Error>> {
         write_command!(self, "AT+RESTORE")?;
-        self.expect_ok_response()
+        self.expect_ok_response()?;
+        self.links = None;
+        self.received.sink.remote_info = false;
+        Ok(())
+    }
+
+    /// Returns the next unsolicited message from the module, if one has been received.
+    ///
+    /// Messages that arrive while a command is being executed are queued until they are polled.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// use esp_at::event::Event;
+    /// let script = [Exchange::unsolicited(b"WIFI DISCONNECT\r\n")];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Wifi.into());
+    ///
+    /// let event = nb::block!(esp32_at.poll_event()).unwrap();
+    /// assert_eq!(Event::WifiDisconnected, event);
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn poll_event(&mut self) -> nb::Result<event::Event, Error<RX::Error, TX::Error>> {
+        loop {
+            // Values written by GATT clients are queued while polling for lines
+            if let Some(event) = self.events.dequeue() {
+                return Ok(event);
+            }
+            let line: Line = self.poll_line()?;
+            if let Some(event) = self.parse_event(&line) {
+                return Ok(event);
+            }
+        }
+    }
+
+    fn require_command_set(
+        &self,
+        command_set: CommandSet,
+    ) -> Result<(), Error<RX::Error, TX::Error>> {
+        if self.command_sets.contains(command_set) {
+            Ok(())
+        } else {
+            Err(Error::CommandSetNotSupported { command_set })
+        }
     }
 
     fn expect_ok_response(&mut self) -> nb::Result<(), Error<RX::Error, TX::Error>> {
-        self.expect_response("OK")
+        self.read_response(|_| Ok(()))
     }
 
-    fn expect_response(&mut self, response: &str) -> nb::Result<(), Error<RX::Error, TX::Error>> {
-        self.expect(response.as_bytes())
+    /// Waits for the `>` prompt that follows a command which expects a binary payload.
+    fn expect_prompt(&mut self) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.expect_prompt_with(|_| {})
     }
 
-    fn expect(&mut self, data: &[u8]) -> nb::Result<(), Error<RX::Error, TX::Error>> {
-        for &expected_byte in data {
-            let actual_byte = self.getc()?;
+    /// Like `expect_prompt`, but calls `on_line` with the unsolicited messages that arrive before
+    /// the prompt, such as a link being closed.
+    fn expect_prompt_with<F>(
+        &mut self,
+        mut on_line: F,
+    ) -> nb::Result<(), Error<RX::Error, TX::Error>>
+    where
+        F: FnMut(&str),
+    {
+        self.expect_ok_response()?;
+        loop {
+            match nb::block!(self.poll_byte())? {
+                b'>' => return Ok(()),
+                b'\r' | b'\n' => {}
+                byte => {
+                    if self.line.push(byte).is_err() {
+                        return Err(nb::Error::Other(Error::BufferOverflow));
+                    }
+                    let line: Line = self.read_line()?;
+                    self.queue_event(&line);
+                    on_line(&line);
+                }
+            }
+        }
+    }
 
-            if expected_byte != actual_byte {
-                return Err(nb::Error::Other(Error::UnexpectedResponse));
+    /// Reads the response to a command that returns binary data as `+<name>:<header>,<data>`,
+    /// which can't be read as lines.
+    ///
+    /// The header consists of `header_fields` fields, of which the one at `len_field` is the
+    /// length of the data.  The data is read into `buf`, and the header is returned, or `None` if
+    /// the command succeeded without returning any data.
+    fn read_data_response(
+        &mut self,
+        name: &str,
+        header_fields: usize,
+        len_field: usize,
+        buf: &mut [u8],
+    ) -> nb::Result<Option<DataHeader>, Error<RX::Error, TX::Error>> {
+        loop {
+            let byte = nb::block!(self.poll_byte())?;
+            if self.line.push(byte).is_err() {
+                self.line = heapless::Vec::new();
+                return Err(nb::Error::Other(Error::BufferOverflow));
+            }
+
+            if byte == b','
+                && self.line.iter().filter(|&&byte| byte == b',').count() == header_fields
+            {
+                let line = core::mem::replace(&mut self.line, heapless::Vec::new());
+                let header = core::str::from_utf8(&line[..line.len() - 1])
+                    .ok()
+                    .and_then(|line| parser::response(line, name));
+                if let Some(header) = header {
+                    let len = parser::fields(header)
+                        .nth(len_field)
+                        .and_then(|len| len.parse().ok())
+                        .filter(|&len| len <= buf.len())
+                        .ok_or(Error::UnexpectedResponse)?;
+                    let arguments = parser::string(header).ok_or(Error::BufferOverflow)?;
+                    for byte in buf[..len].iter_mut() {
+                        *byte = nb::block!(self.getc())?;
+                    }
+                    self.expect_ok_response()?;
+                    return Ok(Some(DataHeader { arguments, len }));
+                }
+                // Not the header after all, so keep reading the line
+                self.line = line;
+                self.start_payload();
+            } else if byte == b'\n' && self.line.ends_with(b"\r\n") {
+                let line = core::mem::replace(&mut self.line, heapless::Vec::new());
+                let line: Line = core::str::from_utf8(&line[..line.len() - 2])
+                    .ok()
+                    .and_then(parser::string)
+                    .ok_or(Error::UnexpectedResponse)?;
+                match line.as_str() {
+                    "OK" => return Ok(None),
+                    "ERROR" | "FAIL" => return Err(nb::Error::Other(Error::CommandFailed)),
+                    "" => {}
+                    echo if parser::is_echo(echo) => {}
+                    line => self.queue_event(line),
+                }
+            } else {
+                self.start_payload();
             }
         }
-        Ok(())
     }
 
+    /// Reads the response to a query command, returning the arguments of its `+<name>:` line.
+    fn read_query_response(&mut self, name: &str) -> nb::Result<Line, Error<RX::Error, TX::Error>> {
+        let mut value = None;
+        self.read_response(|line| {
+            if let Some(arguments) = parser::response(line, name) {
+                value = Some(Line::from(arguments));
+            }
+            Ok(())
+        })?;
+        Ok(value.ok_or(Error::UnexpectedResponse)?)
+    }
+
+    /// Changes a setting for the duration of `f`, restoring its previous value afterwards even if
+    /// `f` fails.
+    fn with_setting<S, G, P, F, R, E>(&mut self, get: G, set: P, value: S, f: F) -> Result<R, E>
+    where
+        S: Copy + PartialEq,
+        G: Fn(&mut Self) -> nb::Result<S, Error<RX::Error, TX::Error>>,
+        P: Fn(&mut Self, S) -> nb::Result<(), Error<RX::Error, TX::Error>>,
+        F: FnOnce(&mut Self) -> Result<R, E>,
+        E: From<Error<RX::Error, TX::Error>>,
+    {
+        let previous = nb::block!(get(self))?;
+        if previous == value {
+            return f(self);
+        }
+
+        nb::block!(set(self, value))?;
+        let result = f(self);
+        let restored = nb::block!(set(self, previous));
+
+        let value = result?;
+        restored?;
+        Ok(value)
+    }
+
+    /// Reads lines until the final result code of a command, passing every line that is neither
+    /// blank nor the command echo to `on_line`.
+    fn read_response<F>(&mut self, on_line: F) -> nb::Result<(), Error<RX::Error, TX::Error>>
+    where
+        F: FnMut(&str) -> Result<(), Error<RX::Error, TX::Error>>,
+    {
+        self.read_response_with(Self::read_line, on_line)
+    }
+
+    /// Like `read_response`, but fails with `Error::Timeout` if `timer` fires before the final
+    /// result code has been received.
+    fn read_response_within<T, F>(
+        &mut self,
+        timer: &mut T,
+        on_line: F,
+    ) -> nb::Result<(), Error<RX::Error, TX::Error>>
+    where
+        T: embedded_hal::timer::CountDown,
+        F: FnMut(&str) -> Result<(), Error<RX::Error, TX::Error>>,
+    {
+        self.read_response_with(|this| this.read_line_within(timer), on_line)
+    }
+
+    fn read_response_with<L, F>(
+        &mut self,
+        mut next_line: L,
+        mut on_line: F,
+    ) -> nb::Result<(), Error<RX::Error, TX::Error>>
+    where
+        L: FnMut(&mut Self) -> nb::Result<Line, Error<RX::Error, TX::Error>>,
+        F: FnMut(&str) -> Result<(), Error<RX::Error, TX::Error>>,
+    {
+        loop {
+            let line = next_line(self)?;
+            match line.as_str() {
+                "OK" => return Ok(()),
+                "ERROR" | "FAIL" => return Err(nb::Error::Other(Error::CommandFailed)),
+                "" => {}
+                echo if parser::is_echo(echo) => {}
+                line => {
+                    self.queue_event(line);
+                    on_line(line)?
+                }
+            }
+        }
+    }
+
+    /// Handles the lines that have been received so far, which buffers any data pushed in active
+    /// receive mode and queues any events.
+    fn drain_lines(&mut self) -> Result<(), Error<RX::Error, TX::Error>> {
+        loop {
+            match self.poll_line::<heapless::consts::U256>() {
+                Ok(line) => self.queue_event(&line),
+                Err(nb::Error::WouldBlock) => return Ok(()),
+                Err(nb::Error::Other(error)) => return Err(error),
+            }
+        }
+    }
+
+    fn queue_event(&mut self, line: &str) {
+        if let Some(event) = self.parse_event(line) {
+            self.enqueue_event(event);
+        }
+    }
+
+    /// Recognizes an unsolicited message, and keeps track of the links and BLE connections.
+    fn parse_event(&mut self, line: &str) -> Option<event::Event> {
+        let refused = self.track_link(line);
+        let mut event = event::parse(line)?;
+        if let event::Event::LinkConnected { refused: r, .. } = &mut event {
+            *r = refused;
+        }
+        self.track_ble_connection(&event);
+        Some(event)
+    }
+
+    fn enqueue_event(&mut self, event: event::Event) {
+        if let Err(event) = self.events.enqueue(event) {
+            // Make room by dropping the oldest event
+            self.events.dequeue();
+            let _ = self.events.enqueue(event);
+        }
+    }
+
+    fn take_payload_events(&mut self) {
+        if let Some(event) = self.gatt_written.sink.take() {
+            self.enqueue_event(event);
+        }
+        if let Some(event) = self.reserved_data.sink.take() {
+            self.enqueue_event(event);
+        }
+    }
+
+    /// Reads a line, waiting for as long as it takes for one to arrive.
     fn read_line<N>(&mut self) -> nb::Result<heapless::String<N>, Error<RX::Error, TX::Error>>
     where
         N: heapless::ArrayLength<u8>,
     {
-        let mut last = [0; 2];
-        let mut result = heapless::Vec::new();
+        Ok(nb::block!(self.poll_line())?)
+    }
 
+    /// Reads a line, failing with `Error::Timeout` if `timer` fires before one has arrived.
+    fn read_line_within<N, T>(
+        &mut self,
+        timer: &mut T,
+    ) -> nb::Result<heapless::String<N>, Error<RX::Error, TX::Error>>
+    where
+        N: heapless::ArrayLength<u8>,
+        T: embedded_hal::timer::CountDown,
+    {
         loop {
-            last[0] = last[1];
-            last[1] = self.getc()?;
-            if last == [b'\r', b'\n'] {
+            match self.poll_line() {
+                Err(nb::Error::WouldBlock) => {
+                    if timer.wait().is_ok() {
+                        return Err(nb::Error::Other(Error::Timeout));
+                    }
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Reads the bytes that are currently available, returning a line once a complete one has
+    /// been received.  Partial lines are kept until the next call.
+    fn poll_line<N>(&mut self) -> nb::Result<heapless::String<N>, Error<RX::Error, TX::Error>>
+    where
+        N: heapless::ArrayLength<u8>,
+    {
+        loop {
+            let byte = self.poll_byte()?;
+            if byte == b'\n' && self.line.last() == Some(&b'\r') {
+                self.line.pop();
                 break;
             }
 
-            result.push(last[0]).or(Err(Error::BufferOverflow))?;
+            if self.line.push(byte).is_err() {
+                self.line = heapless::Vec::new();
+                return Err(nb::Error::Other(Error::BufferOverflow));
+            }
+            self.start_payload();
         }
 
+        // Not using `Vec::clear`, which trips debug assertions in heapless 0.5 on newer compilers.
+        let line = core::mem::replace(&mut self.line, heapless::Vec::new());
+        let mut result = heapless::Vec::new();
+        result
+            .extend_from_slice(&line)
+            .or(Err(Error::BufferOverflow))?;
+
         Ok(heapless::String::from_utf8(result).map_err(|cause| Error::Utf8 { cause })?)
     }
 
-    fn ignore_line(&mut self) -> nb::Result<(), Error<RX::Error, TX::Error>> {
-        let mut last = [0; 2];
-        while last != [b'\r', b'\n'] {
-            last[0] = last[1];
-            last[1] = self.getc()?;
+    /// Reads the next byte that isn't part of a payload pushed by the module, passing the bytes
+    /// of the payloads to their receivers instead.
+    fn poll_byte(&mut self) -> nb::Result<u8, Error<RX::Error, TX::Error>> {
+        loop {
+            let byte = self.getc()?;
+            if self.received.push(byte)
+                || self.mqtt_received.push(byte)
+                || self.websocket_received.push(byte)
+                || self.gatt_written.push(byte)
+                || self.reserved_data.push(byte)
+            {
+                self.take_payload_events();
+                continue;
+            }
+            return Ok(byte);
         }
+    }
+
+    /// Starts receiving a payload if the partial line is the header of one, in which case the
+    /// header is taken out of the line.
+    ///
+    /// Data pushed in active receive mode, MQTT messages, WebSocket frames, values written by GATT
+    /// clients and SmartConfig reserved data follow a header and aren't terminated by a line
+    /// break.
+    fn start_payload(&mut self) {
+        if self.received.start(&self.line)
+            || self.mqtt_received.start(&self.line)
+            || self.websocket_received.start(&self.line)
+            || self.gatt_written.start(&self.line)
+            || self.reserved_data.start(&self.line)
+        {
+            self.line = heapless::Vec::new();
+            // Values written by GATT clients and reserved data are turned into events once
+            // complete
+            self.take_payload_events();
+        }
+    }
+
+    fn ignore_line(&mut self) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        let _: Line = self.read_line()?;
         Ok(())
     }
 
-    fn write(&mut self, data: &[u8]) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+    /// Writes binary data such as the payload of `AT+CIPSEND` as it is, since it isn't
+    /// necessarily UTF-8 and mustn't be escaped like the arguments of `write_command`.
+    fn write_all(&mut self, data: &[u8]) -> nb::Result<(), Error<RX::Error, TX::Error>> {
         for &byte in data {
             self.putc(byte)?;
         }
@@ -216,26 +814,64 @@ where
     }
 
     fn putc(&mut self, byte: u8) -> nb::Result<(), Error<RX::Error, TX::Error>> {
-        self.tx
-            .write(byte)
-            .map_err(|nb| nb.map(|cause| Error::UartWrite { cause }))
+        // Block on a full transmit buffer; giving up half-way through a command would corrupt it.
+        nb::block!(self.tx.write(byte))
+            .map_err(|cause| nb::Error::Other(Error::UartWrite { cause }))
+    }
+}
+
+/// Formats a string argument with the characters that are special to the AT command parser
+/// escaped.
+struct Escaped<'a>(&'a str);
+
+impl<'a> Escaped<'a> {
+    /// The length of the escaped string.
+    fn len(&self) -> usize {
+        self.0.len()
+            + self
+                .0
+                .bytes()
+                .filter(|byte| matches!(byte, b'"' | b',' | b'\\'))
+                .count()
+    }
+}
+
+impl<'a> fmt::Display for Escaped<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use core::fmt::Write;
+
+        for c in self.0.chars() {
+            if let '"' | ',' | '\\' = c {
+                f.write_char('\\')?;
+            }
+            f.write_char(c)?;
+        }
+        Ok(())
+    }
+}
+
+/// Formats strings as escaped and quoted trailing arguments of a command, each preceded by a
+/// comma.
+struct QuotedList<'a>(&'a [&'a str]);
+
+impl<'a> fmt::Display for QuotedList<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for item in self.0 {
+            write!(f, ",\"{}\"", Escaped(item))?;
+        }
+        Ok(())
     }
 }
 
 impl<'a, RX, TX> fmt::Write for Writer<'a, RX, TX>
 where
     RX: embedded_hal::serial::Read<u8>,
-    RX::Error: failure::Fail,
     TX: embedded_hal::serial::Write<u8>,
-    TX::Error: failure::Fail,
 {
     fn write_str(&mut self, s: &str) -> fmt::Result {
-        for byte in s.bytes() {
-            if let Err(err) = self.this.putc(byte) {
-                *self.error_ref = Some(err);
-                return Err(fmt::Error);
-            }
-        }
-        Ok(())
+        self.this.write_all(s.as_bytes()).map_err(|err| {
+            *self.error_ref = Some(err);
+            fmt::Error
+        })
     }
 }
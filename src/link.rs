@@ -0,0 +1,130 @@
+/// The number of connections the module supports at the same time.
+pub const MAX_LINKS: u8 = 5;
+
+/// What a link id is used for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LinkState {
+    Free,
+    /// Used by a connection established with e.g. `Esp32At::connect_tcp`.
+    Outbound,
+    /// Used by a connection accepted by the server.
+    Inbound,
+    /// Used by an inbound connection beyond the server's reservation, until it has been closed.
+    Refused,
+}
+
+/// Hands out the link ids used to tell multiple connections apart.
+///
+/// Some of the ids can be reserved for connections accepted by the server, so that a burst of
+/// inbound connections can't use up the ids needed for outbound ones, and vice versa.  Since the
+/// module picks the ids of inbound connections itself, the reservation is a quota: `accept`
+/// refuses inbound connections beyond it, and `allocate` refuses outbound ones once the
+/// remaining ids are taken.  The ids of refused connections stay in use until they are closed.
+///
+/// The module's own allocator is kept up to date with the connections that are reported as
+/// established or closed, and sending on a link that isn't in use fails with
+/// `Error::LinkClosed`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct LinkAllocator {
+    server_quota: u8,
+    in_use: u8,
+    server_links: u8,
+    /// The inbound connections that haven't been taken with `take_accepted` yet.
+    unclaimed: u8,
+    refused: u8,
+}
+
+impl LinkAllocator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reserves `count` of the ids for inbound connections.
+    pub fn reserve_for_server(&mut self, count: u8) {
+        self.server_quota = count.min(MAX_LINKS);
+    }
+
+    pub fn server_quota(&self) -> u8 {
+        self.server_quota
+    }
+
+    /// Hands out an id for an outbound connection, if one is available outside of the server's
+    /// reservation.
+    pub fn allocate(&mut self) -> Option<u8> {
+        let clients = (self.in_use & !self.server_links & !self.refused).count_ones() as u8;
+        if clients >= MAX_LINKS - self.server_quota {
+            return None;
+        }
+
+        // Hand out the highest free id, since the module gives inbound connections the lowest one
+        let link_id = (0..MAX_LINKS).rev().find(|&id| !self.is_in_use(id))?;
+        self.in_use |= 1 << link_id;
+        Some(link_id)
+    }
+
+    /// Records an inbound connection that the module accepted on `link_id`, returning whether it
+    /// fits within the server's reservation.  Connections that don't should be closed.
+    pub fn accept(&mut self, link_id: u8) -> bool {
+        if link_id >= MAX_LINKS
+            || self.is_in_use(link_id)
+            || self.server_links.count_ones() as u8 >= self.server_quota
+        {
+            return false;
+        }
+
+        self.in_use |= 1 << link_id;
+        self.server_links |= 1 << link_id;
+        self.unclaimed |= 1 << link_id;
+        true
+    }
+
+    /// Records an inbound connection on `link_id` that `accept` refused, which keeps the id in use
+    /// until the connection has been closed.
+    pub fn refuse(&mut self, link_id: u8) {
+        if link_id < MAX_LINKS {
+            self.in_use |= 1 << link_id;
+            self.refused |= 1 << link_id;
+        }
+    }
+
+    /// Takes the id of an accepted inbound connection that hasn't been taken yet, lowest id first.
+    pub fn take_accepted(&mut self) -> Option<u8> {
+        let link_id = (0..MAX_LINKS).find(|&id| self.unclaimed & 1 << id != 0)?;
+        self.unclaimed &= !(1 << link_id);
+        Some(link_id)
+    }
+
+    /// Makes the id of a closed connection available again.
+    pub fn release(&mut self, link_id: u8) {
+        if link_id < MAX_LINKS {
+            self.in_use &= !(1 << link_id);
+            self.server_links &= !(1 << link_id);
+            self.unclaimed &= !(1 << link_id);
+            self.refused &= !(1 << link_id);
+        }
+    }
+
+    /// Makes the ids of all inbound connections available again, including refused ones.
+    pub fn release_server_links(&mut self) {
+        self.in_use &= !(self.server_links | self.refused);
+        self.server_links = 0;
+        self.unclaimed = 0;
+        self.refused = 0;
+    }
+
+    pub fn is_in_use(&self, link_id: u8) -> bool {
+        link_id < MAX_LINKS && self.in_use & 1 << link_id != 0
+    }
+
+    pub fn state(&self, link_id: u8) -> LinkState {
+        if !self.is_in_use(link_id) {
+            LinkState::Free
+        } else if self.server_links & 1 << link_id != 0 {
+            LinkState::Inbound
+        } else if self.refused & 1 << link_id != 0 {
+            LinkState::Refused
+        } else {
+            LinkState::Outbound
+        }
+    }
+}
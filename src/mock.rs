@@ -0,0 +1,177 @@
+//! A scripted stand-in for a module running esp-at, for testing code built on this driver without
+//! any hardware.
+
+use core::cell::RefCell;
+use core::fmt;
+
+/// How many times in a row reading may find nothing to read before the mock gives up, so that a
+/// driver waiting for a response the script doesn't contain fails instead of hanging.
+const MAX_STALLS: u32 = 10_000;
+
+/// One step of a script: once `command` has been written, `response` can be read.
+///
+/// An empty `command` makes `response` an unsolicited message, which can be read as soon as the
+/// previous step has completed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Exchange<'a> {
+    pub command: &'a [u8],
+    pub response: &'a [u8],
+}
+
+#[derive(Debug)]
+pub struct MockModule<'a> {
+    state: RefCell<State<'a>>,
+}
+
+#[derive(Debug)]
+struct State<'a> {
+    script: &'a [Exchange<'a>],
+    step: usize,
+    written: usize,
+    read: usize,
+    stalls: u32,
+}
+
+/// The receiving half of a `MockModule`.
+#[derive(Debug)]
+pub struct Rx<'m, 'a> {
+    module: &'m MockModule<'a>,
+}
+
+/// The transmitting half of a `MockModule`.
+#[derive(Debug)]
+pub struct Tx<'m, 'a> {
+    module: &'m MockModule<'a>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MockError {
+    /// A byte was written that the script didn't expect; `step` is the index of the exchange.
+    UnexpectedWrite { step: usize, byte: u8 },
+    /// Nothing was written while waiting for the script to continue.
+    Stalled { step: usize },
+}
+
+impl<'a> Exchange<'a> {
+    pub const fn new(command: &'a [u8], response: &'a [u8]) -> Self {
+        Self { command, response }
+    }
+
+    pub const fn unsolicited(response: &'a [u8]) -> Self {
+        Self {
+            command: &[],
+            response,
+        }
+    }
+}
+
+impl<'a> MockModule<'a> {
+    pub fn new(script: &'a [Exchange<'a>]) -> Self {
+        Self {
+            state: RefCell::new(State {
+                script,
+                step: 0,
+                written: 0,
+                read: 0,
+                stalls: 0,
+            }),
+        }
+    }
+
+    pub fn split(&self) -> (Rx<'_, 'a>, Tx<'_, 'a>) {
+        (Rx { module: self }, Tx { module: self })
+    }
+
+    /// Whether the whole script has been played.
+    pub fn is_done(&self) -> bool {
+        let state = self.state.borrow();
+        state.step >= state.script.len()
+    }
+}
+
+impl<'a> State<'a> {
+    fn advance(&mut self) {
+        self.step += 1;
+        self.written = 0;
+        self.read = 0;
+    }
+
+    fn read(&mut self) -> nb::Result<u8, MockError> {
+        if let Some(exchange) = self.script.get(self.step) {
+            if self.written == exchange.command.len() && self.read < exchange.response.len() {
+                let byte = exchange.response[self.read];
+                self.read += 1;
+                self.stalls = 0;
+                if self.read == exchange.response.len() {
+                    self.advance();
+                }
+                return Ok(byte);
+            }
+        }
+
+        self.stalls += 1;
+        if self.stalls > MAX_STALLS {
+            self.stalls = 0;
+            Err(nb::Error::Other(MockError::Stalled { step: self.step }))
+        } else {
+            Err(nb::Error::WouldBlock)
+        }
+    }
+
+    fn write(&mut self, byte: u8) -> nb::Result<(), MockError> {
+        let step = self.step;
+        let exchange = self
+            .script
+            .get(step)
+            .ok_or(MockError::UnexpectedWrite { step, byte })?;
+        if exchange.command.get(self.written) != Some(&byte) {
+            return Err(nb::Error::Other(MockError::UnexpectedWrite { step, byte }));
+        }
+
+        self.written += 1;
+        self.stalls = 0;
+        if self.written == exchange.command.len() && exchange.response.is_empty() {
+            self.advance();
+        }
+        Ok(())
+    }
+}
+
+impl<'m, 'a> embedded_hal::serial::Read<u8> for Rx<'m, 'a> {
+    type Error = MockError;
+
+    fn read(&mut self) -> nb::Result<u8, Self::Error> {
+        self.module.state.borrow_mut().read()
+    }
+}
+
+impl<'m, 'a> embedded_hal::serial::Write<u8> for Tx<'m, 'a> {
+    type Error = MockError;
+
+    fn write(&mut self, byte: u8) -> nb::Result<(), Self::Error> {
+        self.module.state.borrow_mut().write(byte)
+    }
+
+    fn flush(&mut self) -> nb::Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+impl fmt::Display for MockError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MockError::UnexpectedWrite { step, byte } => write!(
+                f,
+                "unexpected write of {:?} in step {}",
+                *byte as char, step
+            ),
+            MockError::Stalled { step } => write!(f, "stalled in step {}", step),
+        }
+    }
+}
+
+#[cfg(feature = "failure")]
+impl failure::Fail for MockError {}
+
+#[cfg(feature = "core-error")]
+impl core::error::Error for MockError {}
@@ -0,0 +1,1053 @@
+use crate::payload::PayloadSink;
+use crate::tcpip::{TlsAuthMode, MAX_ALPN_PROTOCOLS};
+use crate::{parser, CommandSet, Error, Escaped, Esp32At, Line, QuotedList};
+
+/// The MQTT client link; the firmware currently only supports one.
+const LINK_ID: u8 = 0;
+
+/// The longest client id, user name and password that can be set with e.g.
+/// `Esp32At::set_mqtt_client_id`.
+pub const MAX_CREDENTIAL_LEN: usize = 1024;
+
+/// The longest command line that `AT+MQTTPUB` accepts, which limits the length of the topic and
+/// data of `Esp32At::mqtt_publish` once they have been escaped.
+pub const MAX_PUBLISH_COMMAND_LEN: usize = 255;
+
+/// The most subscriptions that `Esp32At::get_mqtt_subscriptions` returns.
+pub const MAX_SUBSCRIPTIONS: usize = 8;
+
+/// How many bytes of the messages received on subscriptions are buffered until they are taken
+/// with `Esp32At::mqtt_receive`.
+#[cfg(feature = "mqtt-receive")]
+pub const MQTT_RECEIVE_BUFFER_LEN: usize = 1024;
+
+/// The longest keepalive interval that the firmware supports.
+pub const MAX_KEEPALIVE_SECS: u16 = 7200;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QoS {
+    AtMostOnce,
+    AtLeastOnce,
+    ExactlyOnce,
+}
+
+/// Why an MQTT command failed, as reported in the `ERR CODE:` line that the firmware prints
+/// when `AT+SYSLOG=1`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MqttFailure {
+    /// The client hasn't been configured with `Esp32At::mqtt_user_config`, or has been torn
+    /// down with `Esp32At::mqtt_disconnect`.
+    NotConfigured,
+    AlreadyConnected,
+    OutOfMemory,
+    InvalidArgument,
+    /// The TLS configuration of the scheme is incomplete, e.g. its certificates are missing.
+    TlsConfig,
+    ConnectFailed,
+    PublishFailed,
+    SubscribeFailed,
+    UnsubscribeFailed,
+    DisconnectFailed,
+    /// Another code from the MQTT error code space of the firmware.
+    Other(u16),
+}
+
+/// The transport used to connect to the broker, and how TLS connections are authenticated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MqttScheme {
+    Tcp,
+    Tls(TlsAuthMode),
+    WebSocket,
+    WebSocketSecure(TlsAuthMode),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MqttUserConfig<'a> {
+    pub scheme: MqttScheme,
+    pub client_id: &'a str,
+    pub username: &'a str,
+    pub password: &'a str,
+    /// The slot of the client certificate and key, see `pki::PkiKind`.
+    pub pki_index: u8,
+    /// The slot of the CA certificate that the broker certificate is verified against.
+    pub ca_index: u8,
+    /// The resource path of WebSocket connections, e.g. `/mqtt`.
+    pub path: &'a str,
+}
+
+/// An MQTT topic or topic filter.
+pub type Topic = heapless::String<heapless::consts::U128>;
+
+/// A topic filter that the client is subscribed to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Subscription {
+    pub topic: Topic,
+    pub qos: QoS,
+}
+
+/// A message received on a subscription, whose payload has been read into the buffer given to
+/// `Esp32At::mqtt_receive`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    pub topic: Topic,
+    /// The length of the payload.
+    pub len: usize,
+}
+
+/// The messages pushed by the module as `+MQTTSUBRECV` that haven't been taken yet.
+#[cfg(feature = "mqtt-receive")]
+#[derive(Debug)]
+pub(crate) struct ReceiveBuffer {
+    data: heapless::spsc::Queue<u8, heapless::consts::U1024>,
+    messages: heapless::spsc::Queue<Message, heapless::consts::U4>,
+    /// The message whose payload is currently being received.
+    incoming: Option<Incoming>,
+    /// Whether a message has been dropped because there was no room for it.
+    overflowed: bool,
+}
+
+/// Skips the messages pushed by the module as `+MQTTSUBRECV`, which are only buffered with the
+/// `mqtt-receive` feature.
+#[cfg(not(feature = "mqtt-receive"))]
+#[derive(Debug)]
+pub(crate) struct ReceiveBuffer;
+
+#[cfg(feature = "mqtt-receive")]
+#[derive(Debug)]
+struct Incoming {
+    /// The topic of the message, or `None` if it is dropped.
+    topic: Option<Topic>,
+    len: usize,
+}
+
+/// A message that the broker publishes on the client's behalf if the connection is lost.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LastWill<'a> {
+    pub topic: &'a str,
+    pub message: &'a str,
+    pub qos: QoS,
+    pub retain: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MqttConnectionConfig<'a> {
+    /// The longest time between messages before the broker considers the client gone, or 0 for
+    /// the firmware default of 120; at most `MAX_KEEPALIVE_SECS`.
+    pub keepalive_secs: u16,
+    /// Discards the subscriptions and queued messages of a previous session when connecting.
+    pub clean_session: bool,
+    pub last_will: Option<LastWill<'a>>,
+}
+
+/// A retained presence announcement on `topic`: `online` is published once connected, and the
+/// broker publishes `offline` as the last will if the connection is lost.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Presence<'a> {
+    pub topic: &'a str,
+    pub online: &'a str,
+    pub offline: &'a str,
+    pub qos: QoS,
+}
+
+impl QoS {
+    fn code(self) -> u8 {
+        match self {
+            QoS::AtMostOnce => 0,
+            QoS::AtLeastOnce => 1,
+            QoS::ExactlyOnce => 2,
+        }
+    }
+
+    fn from_code(code: &str) -> Option<Self> {
+        match code {
+            "0" => Some(QoS::AtMostOnce),
+            "1" => Some(QoS::AtLeastOnce),
+            "2" => Some(QoS::ExactlyOnce),
+            _ => None,
+        }
+    }
+}
+
+impl Subscription {
+    /// Parses `<link_id>,<state>,"<topic>",<qos>`.
+    fn parse(arguments: &str) -> Option<Self> {
+        let mut fields = parser::fields(arguments).skip(2);
+        Some(Subscription {
+            topic: parser::string(parser::unquote(fields.next()?))?,
+            qos: QoS::from_code(fields.next()?)?,
+        })
+    }
+}
+
+#[cfg(feature = "mqtt-receive")]
+impl ReceiveBuffer {
+    pub(crate) fn new() -> Self {
+        ReceiveBuffer {
+            data: heapless::spsc::Queue::new(),
+            messages: heapless::spsc::Queue::new(),
+            incoming: None,
+            overflowed: false,
+        }
+    }
+}
+
+#[cfg(feature = "mqtt-receive")]
+impl PayloadSink for ReceiveBuffer {
+    const HEADER_END: u8 = b',';
+
+    fn start(&mut self, header: &[u8]) -> Option<usize> {
+        let (topic, len) = parse_message_header(header)?;
+
+        // Only keep messages that fit in their entirety
+        let room = self.data.capacity() - self.data.len();
+        let topic = topic.filter(|_| len <= room && self.messages.len() < self.messages.capacity());
+        if topic.is_none() {
+            self.overflowed = true;
+        }
+        self.incoming = Some(Incoming { topic, len });
+        Some(len)
+    }
+
+    fn push(&mut self, byte: u8) {
+        if let Some(Incoming { topic: Some(_), .. }) = self.incoming {
+            // Room for the whole payload was made sure of in `start`
+            let _ = self.data.enqueue(byte);
+        }
+    }
+
+    fn finish(&mut self) {
+        if let Some(Incoming {
+            topic: Some(topic),
+            len,
+        }) = self.incoming.take()
+        {
+            let _ = self.messages.enqueue(Message { topic, len });
+        }
+    }
+}
+
+#[cfg(not(feature = "mqtt-receive"))]
+impl ReceiveBuffer {
+    pub(crate) fn new() -> Self {
+        ReceiveBuffer
+    }
+}
+
+#[cfg(not(feature = "mqtt-receive"))]
+impl PayloadSink for ReceiveBuffer {
+    const HEADER_END: u8 = b',';
+
+    fn start(&mut self, header: &[u8]) -> Option<usize> {
+        parse_message_header(header).map(|(_, len)| len)
+    }
+
+    fn push(&mut self, _byte: u8) {}
+
+    fn finish(&mut self) {}
+}
+
+/// Parses a complete `+MQTTSUBRECV` header like `+MQTTSUBRECV:<link_id>,"<topic>",<len>,` into
+/// the topic, or `None` if it is too long to be kept, and the length of the payload.
+fn parse_message_header(header: &[u8]) -> Option<(Option<Topic>, usize)> {
+    let arguments = header
+        .strip_prefix(b"+MQTTSUBRECV:")
+        .and_then(|header| header.strip_suffix(b","))
+        // The comma might be part of the topic
+        .filter(|arguments| arguments.iter().filter(|&&byte| byte == b'"').count() % 2 == 0)
+        .and_then(|arguments| core::str::from_utf8(arguments).ok())?;
+    let mut fields = parser::fields(arguments);
+    let _link_id: u8 = fields.next()?.parse().ok()?;
+    let topic = fields.next()?;
+    if !topic.starts_with('"') {
+        return None;
+    }
+    let len = fields.next()?.parse().ok()?;
+    if fields.next().is_some() {
+        return None;
+    }
+    Some((parser::string(parser::unquote(topic)), len))
+}
+
+impl MqttFailure {
+    /// Parses an `ERR CODE:0x<code>` line, whose lower 16 bits are the MQTT error code.
+    fn parse(line: &str) -> Option<Self> {
+        let code = line.strip_prefix("ERR CODE:0x")?;
+        let code = u32::from_str_radix(code, 16).ok()? as u16;
+        Some(match code {
+            0x6001..=0x6003 => MqttFailure::NotConfigured,
+            0x6004 => MqttFailure::AlreadyConnected,
+            0x6005 => MqttFailure::OutOfMemory,
+            0x6006..=0x6008 | 0x600a | 0x6010..=0x6039 => MqttFailure::InvalidArgument,
+            0x6009 => MqttFailure::TlsConfig,
+            0x600b => MqttFailure::ConnectFailed,
+            0x600c => MqttFailure::PublishFailed,
+            0x600d => MqttFailure::SubscribeFailed,
+            0x600e => MqttFailure::UnsubscribeFailed,
+            0x600f => MqttFailure::DisconnectFailed,
+            code if code & 0xf000 == 0x6000 => MqttFailure::Other(code),
+            _ => return None,
+        })
+    }
+}
+
+impl MqttScheme {
+    fn code(self) -> u8 {
+        match self {
+            MqttScheme::Tcp => 1,
+            MqttScheme::Tls(auth_mode) => 2 + Self::auth_mode_offset(auth_mode),
+            MqttScheme::WebSocket => 6,
+            MqttScheme::WebSocketSecure(auth_mode) => 7 + Self::auth_mode_offset(auth_mode),
+        }
+    }
+
+    fn auth_mode_offset(auth_mode: TlsAuthMode) -> u8 {
+        match auth_mode {
+            TlsAuthMode::None => 0,
+            TlsAuthMode::ServerCertificate => 1,
+            TlsAuthMode::ClientCertificate => 2,
+            TlsAuthMode::Mutual => 3,
+        }
+    }
+
+    fn uses_tls(self) -> bool {
+        match self {
+            MqttScheme::Tls(_) | MqttScheme::WebSocketSecure(_) => true,
+            MqttScheme::Tcp | MqttScheme::WebSocket => false,
+        }
+    }
+}
+
+impl<RX, TX> Esp32At<RX, TX>
+where
+    RX: embedded_hal::serial::Read<u8>,
+    TX: embedded_hal::serial::Write<u8>,
+{
+    /// Configures the MQTT client, which is the first step of connecting to a broker.
+    ///
+    /// Schemes that use TLS need the `Tls` command set.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// use esp_at::mqtt::{MqttScheme, MqttUserConfig};
+    /// use esp_at::tcpip::TlsAuthMode;
+    /// let script = [Exchange::new(
+    ///     b"AT+MQTTUSERCFG=0,5,\"sensor-1\",\"user\",\"pass\",1,0,\"\"\r\n",
+    ///     b"\r\nOK\r\n",
+    /// )];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Mqtt | CommandSet::Tls);
+    ///
+    /// let config = MqttUserConfig {
+    ///     scheme: MqttScheme::Tls(TlsAuthMode::Mutual),
+    ///     client_id: "sensor-1",
+    ///     username: "user",
+    ///     password: "pass",
+    ///     pki_index: 1,
+    ///     ca_index: 0,
+    ///     path: "",
+    /// };
+    /// nb::block!(esp32_at.mqtt_user_config(&config)).unwrap();
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn mqtt_user_config(
+        &mut self,
+        config: &MqttUserConfig,
+    ) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::Mqtt)?;
+        if config.scheme.uses_tls() {
+            self.require_command_set(CommandSet::Tls)?;
+        }
+        write_command!(
+            self,
+            "AT+MQTTUSERCFG={},{},\"{}\",\"{}\",\"{}\",{},{},\"{}\"",
+            LINK_ID,
+            config.scheme.code(),
+            Escaped(config.client_id),
+            Escaped(config.username),
+            Escaped(config.password),
+            config.pki_index,
+            config.ca_index,
+            Escaped(config.path)
+        )?;
+        self.expect_mqtt_response()
+    }
+
+    /// Sets the client id, overriding the one given to `mqtt_user_config`, which limits it to
+    /// fewer bytes than the `MAX_CREDENTIAL_LEN` accepted here.
+    ///
+    /// The client id is sent after the command as is, so it needn't be escaped.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// let script = [
+    ///     Exchange::new(b"AT+MQTTLONGCLIENTID=0,8\r\n", b"\r\nOK\r\n\r\n>"),
+    ///     Exchange::new(b"device,1", b"\r\nOK\r\n"),
+    /// ];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Mqtt.into());
+    ///
+    /// nb::block!(esp32_at.set_mqtt_client_id("device,1")).unwrap();
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn set_mqtt_client_id(
+        &mut self,
+        client_id: &str,
+    ) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.set_mqtt_credential("MQTTLONGCLIENTID", client_id)
+    }
+
+    /// Sets the user name, overriding the one given to `mqtt_user_config`, e.g. for brokers
+    /// that expect long tokens in it.
+    pub fn set_mqtt_username(
+        &mut self,
+        username: &str,
+    ) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.set_mqtt_credential("MQTTLONGUSERNAME", username)
+    }
+
+    /// Sets the password, overriding the one given to `mqtt_user_config`, e.g. for the SAS
+    /// tokens used by Azure IoT Hub, which are too long for `AT+MQTTUSERCFG`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// let script = [
+    ///     Exchange::new(b"AT+MQTTLONGPASSWORD=0,38\r\n", b"\r\nOK\r\n\r\n>"),
+    ///     Exchange::new(b"SharedAccessSignature sr=hub,sig=\"abc\"", b"\r\nOK\r\n"),
+    /// ];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Mqtt.into());
+    ///
+    /// nb::block!(esp32_at.set_mqtt_password("SharedAccessSignature sr=hub,sig=\"abc\"")).unwrap();
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn set_mqtt_password(
+        &mut self,
+        password: &str,
+    ) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.set_mqtt_credential("MQTTLONGPASSWORD", password)
+    }
+
+    /// Sets the host name sent with Server Name Indication when connecting to the broker over
+    /// TLS, which brokers behind shared endpoints such as Azure IoT Hub require; must be called
+    /// after `mqtt_user_config`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// let script = [Exchange::new(
+    ///     b"AT+MQTTSNI=0,\"hub.azure-devices.net\"\r\n",
+    ///     b"\r\nOK\r\n",
+    /// )];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Mqtt | CommandSet::Tls);
+    ///
+    /// nb::block!(esp32_at.set_mqtt_server_name("hub.azure-devices.net")).unwrap();
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn set_mqtt_server_name(
+        &mut self,
+        server_name: &str,
+    ) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::Mqtt)?;
+        self.require_command_set(CommandSet::Tls)?;
+        write_command!(self, "AT+MQTTSNI={},\"{}\"", LINK_ID, Escaped(server_name))?;
+        self.expect_mqtt_response()
+    }
+
+    /// Sets the protocols offered with Application-Layer Protocol Negotiation when connecting to
+    /// the broker over TLS; must be called after `mqtt_user_config`.
+    ///
+    /// # Examples
+    ///
+    /// Connecting to AWS IoT on port 443 with the client certificate in slot 0:
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// use esp_at::mqtt::{MqttScheme, MqttUserConfig};
+    /// use esp_at::tcpip::TlsAuthMode;
+    /// let script = [
+    ///     Exchange::new(
+    ///         b"AT+MQTTUSERCFG=0,5,\"thing-1\",\"\",\"\",0,0,\"\"\r\n",
+    ///         b"\r\nOK\r\n",
+    ///     ),
+    ///     Exchange::new(b"AT+MQTTALPN=0,1,\"x-amzn-mqtt-ca\"\r\n", b"\r\nOK\r\n"),
+    ///     Exchange::new(
+    ///         b"AT+MQTTCONN=0,\"abc-ats.iot.eu-west-1.amazonaws.com\",443,1\r\n",
+    ///         b"+MQTTCONNECTED:0,5,\"abc-ats.iot.eu-west-1.amazonaws.com\",\"443\",\"\",1\r\n\r\nOK\r\n",
+    ///     ),
+    /// ];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Mqtt | CommandSet::Tls);
+    ///
+    /// let config = MqttUserConfig {
+    ///     scheme: MqttScheme::Tls(TlsAuthMode::Mutual),
+    ///     client_id: "thing-1",
+    ///     username: "",
+    ///     password: "",
+    ///     pki_index: 0,
+    ///     ca_index: 0,
+    ///     path: "",
+    /// };
+    /// nb::block!(esp32_at.mqtt_user_config(&config)).unwrap();
+    /// nb::block!(esp32_at.set_mqtt_alpn(&["x-amzn-mqtt-ca"])).unwrap();
+    /// let host = "abc-ats.iot.eu-west-1.amazonaws.com";
+    /// nb::block!(esp32_at.mqtt_connect(host, 443, true)).unwrap();
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn set_mqtt_alpn(
+        &mut self,
+        protocols: &[&str],
+    ) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::Mqtt)?;
+        self.require_command_set(CommandSet::Tls)?;
+        if protocols.len() > MAX_ALPN_PROTOCOLS {
+            return Err(nb::Error::Other(Error::InvalidArgument));
+        }
+        write_command!(
+            self,
+            "AT+MQTTALPN={},{}{}",
+            LINK_ID,
+            protocols.len(),
+            QuotedList(protocols)
+        )?;
+        self.expect_mqtt_response()
+    }
+
+    /// Configures the connection; must be called after `mqtt_user_config` and before
+    /// connecting.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// use esp_at::mqtt::{LastWill, MqttConnectionConfig, QoS};
+    /// let script = [
+    ///     Exchange::new(b"AT+MQTTCONNCFG=0,60,0,\"\",\"\",0,0\r\n", b"\r\nOK\r\n"),
+    ///     Exchange::new(
+    ///         b"AT+MQTTCONNCFG=0,300,1,\"fleet/sensor-1\",\"{\\\"state\\\":\\\"lost\\\"}\",1,1\r\n",
+    ///         b"\r\nOK\r\n",
+    ///     ),
+    /// ];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Mqtt.into());
+    ///
+    /// let config = MqttConnectionConfig {
+    ///     keepalive_secs: 60,
+    ///     clean_session: true,
+    ///     last_will: None,
+    /// };
+    /// nb::block!(esp32_at.mqtt_connection_config(&config)).unwrap();
+    ///
+    /// let config = MqttConnectionConfig {
+    ///     keepalive_secs: 300,
+    ///     clean_session: false,
+    ///     last_will: Some(LastWill {
+    ///         topic: "fleet/sensor-1",
+    ///         message: "{\"state\":\"lost\"}",
+    ///         qos: QoS::AtLeastOnce,
+    ///         retain: true,
+    ///     }),
+    /// };
+    /// nb::block!(esp32_at.mqtt_connection_config(&config)).unwrap();
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn mqtt_connection_config(
+        &mut self,
+        config: &MqttConnectionConfig,
+    ) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::Mqtt)?;
+        if config.keepalive_secs > MAX_KEEPALIVE_SECS {
+            return Err(nb::Error::Other(Error::InvalidArgument));
+        }
+        let last_will = config.last_will.unwrap_or(LastWill {
+            topic: "",
+            message: "",
+            qos: QoS::AtMostOnce,
+            retain: false,
+        });
+        write_command!(
+            self,
+            "AT+MQTTCONNCFG={},{},{},\"{}\",\"{}\",{},{}",
+            LINK_ID,
+            config.keepalive_secs,
+            !config.clean_session as u8,
+            Escaped(last_will.topic),
+            Escaped(last_will.message),
+            last_will.qos.code(),
+            last_will.retain as u8
+        )?;
+        self.expect_mqtt_response()
+    }
+
+    /// Connects to the broker; with `reconnect`, the client reconnects on its own whenever the
+    /// connection is lost, which is reported as `Event::MqttDisconnected` and
+    /// `Event::MqttConnected`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// use esp_at::event::Event;
+    /// let script = [Exchange::new(
+    ///     b"AT+MQTTCONN=0,\"broker.local\",1883,1\r\n",
+    ///     b"+MQTTCONNECTED:0,1,\"broker.local\",\"1883\",\"\",1\r\n\r\nOK\r\n",
+    /// )];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Mqtt.into());
+    ///
+    /// nb::block!(esp32_at.mqtt_connect("broker.local", 1883, true)).unwrap();
+    /// assert_eq!(Event::MqttConnected, nb::block!(esp32_at.poll_event()).unwrap());
+    /// # assert!(module.is_done());
+    /// ```
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At, Error};
+    /// use esp_at::mqtt::MqttFailure;
+    /// let script = [Exchange::new(
+    ///     b"AT+MQTTCONN=0,\"broker.local\",1883,0\r\n",
+    ///     b"ERR CODE:0x0101600b\r\n\r\nERROR\r\n",
+    /// )];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Mqtt.into());
+    ///
+    /// match nb::block!(esp32_at.mqtt_connect("broker.local", 1883, false)) {
+    ///     Err(Error::MqttFailed { reason }) => assert_eq!(MqttFailure::ConnectFailed, reason),
+    ///     result => panic!("unexpected result: {:?}", result),
+    /// }
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn mqtt_connect(
+        &mut self,
+        host: &str,
+        port: u16,
+        reconnect: bool,
+    ) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::Mqtt)?;
+        write_command!(
+            self,
+            "AT+MQTTCONN={},\"{}\",{},{}",
+            LINK_ID,
+            Escaped(host),
+            port,
+            reconnect as u8
+        )?;
+        self.expect_mqtt_response()
+    }
+
+    /// Disconnects from the broker and tears down the client, which has to be configured again
+    /// with `mqtt_user_config` before it can reconnect.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// let script = [Exchange::new(b"AT+MQTTCLEAN=0\r\n", b"\r\nOK\r\n")];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Mqtt.into());
+    ///
+    /// nb::block!(esp32_at.mqtt_disconnect()).unwrap();
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn mqtt_disconnect(&mut self) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::Mqtt)?;
+        write_command!(self, "AT+MQTTCLEAN={}", LINK_ID)?;
+        self.expect_mqtt_response()
+    }
+
+    /// Publishes a message, which has to be text; the command line is limited to
+    /// `MAX_PUBLISH_COMMAND_LEN`, so longer messages fail with `Error::InvalidArgument` without
+    /// being sent.
+    ///
+    /// A message that the client couldn't publish, e.g. because it isn't connected, fails with
+    /// `Error::MqttFailed`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// use esp_at::mqtt::QoS;
+    /// let script = [
+    ///     Exchange::new(b"AT+MQTTPUB=0,\"sensors/temp\",\"21.5\",1,0\r\n", b"\r\nOK\r\n"),
+    ///     Exchange::new(
+    ///         b"AT+MQTTPUB=0,\"sensors/temp\",\"{\\\"value\\\":21.5\\,\\\"unit\\\":\\\"C\\\"}\",0,1\r\n",
+    ///         b"\r\nOK\r\n",
+    ///     ),
+    /// ];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Mqtt.into());
+    ///
+    /// nb::block!(esp32_at.mqtt_publish("sensors/temp", "21.5", QoS::AtLeastOnce, false)).unwrap();
+    /// let data = r#"{"value":21.5,"unit":"C"}"#;
+    /// nb::block!(esp32_at.mqtt_publish("sensors/temp", data, QoS::AtMostOnce, true)).unwrap();
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn mqtt_publish(
+        &mut self,
+        topic: &str,
+        data: &str,
+        qos: QoS,
+        retain: bool,
+    ) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::Mqtt)?;
+        // AT+MQTTPUB=0,"<topic>","<data>",<qos>,<retain>
+        let len = "AT+MQTTPUB=0,\"\",\"\",0,0".len() + Escaped(topic).len() + Escaped(data).len();
+        if len > MAX_PUBLISH_COMMAND_LEN {
+            return Err(nb::Error::Other(Error::InvalidArgument));
+        }
+        write_command!(
+            self,
+            "AT+MQTTPUB={},\"{}\",\"{}\",{},{}",
+            LINK_ID,
+            Escaped(topic),
+            Escaped(data),
+            qos.code(),
+            retain as u8
+        )?;
+        self.expect_mqtt_response()
+    }
+
+    /// Publishes a message of arbitrary bytes, e.g. CBOR or protobuf, which unlike
+    /// `mqtt_publish` is sent as it is after the command and isn't limited by the length of the
+    /// command line.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// use esp_at::mqtt::QoS;
+    /// let script = [
+    ///     Exchange::new(b"AT+MQTTPUBRAW=0,\"sensors/temp\",5,1,0\r\n", b"\r\nOK\r\n\r\n>"),
+    ///     Exchange::new(b"\xa1\x00\",\n", b"\r\n+MQTTPUB:OK\r\n"),
+    /// ];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Mqtt.into());
+    ///
+    /// let data = b"\xa1\x00\",\n";
+    /// nb::block!(esp32_at.mqtt_publish_raw("sensors/temp", data, QoS::AtLeastOnce, false)).unwrap();
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn mqtt_publish_raw(
+        &mut self,
+        topic: &str,
+        data: &[u8],
+        qos: QoS,
+        retain: bool,
+    ) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::Mqtt)?;
+        write_command!(
+            self,
+            "AT+MQTTPUBRAW={},\"{}\",{},{},{}",
+            LINK_ID,
+            Escaped(topic),
+            data.len(),
+            qos.code(),
+            retain as u8
+        )?;
+        self.expect_prompt()?;
+        self.write_all(data)?;
+
+        // The outcome is reported instead of a final result code
+        loop {
+            let line: Line = self.read_line()?;
+            match line.as_str() {
+                "+MQTTPUB:OK" => return Ok(()),
+                "+MQTTPUB:FAIL" => {
+                    return Err(nb::Error::Other(Error::MqttFailed {
+                        reason: MqttFailure::PublishFailed,
+                    }))
+                }
+                "ERROR" => return Err(nb::Error::Other(Error::CommandFailed)),
+                line => self.queue_event(line),
+            }
+        }
+    }
+
+    /// Subscribes to messages on the topics matching `topic`, which may contain wildcards.
+    ///
+    /// Subscribing to a topic filter again succeeds without changing its QoS.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// use esp_at::mqtt::QoS;
+    /// let script = [
+    ///     Exchange::new(b"AT+MQTTSUB=0,\"commands/#\",1\r\n", b"\r\nOK\r\n"),
+    ///     Exchange::new(b"AT+MQTTSUB=0,\"commands/#\",1\r\n", b"ALREADY SUBSCRIBE\r\n\r\nOK\r\n"),
+    /// ];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Mqtt.into());
+    ///
+    /// nb::block!(esp32_at.mqtt_subscribe("commands/#", QoS::AtLeastOnce)).unwrap();
+    /// nb::block!(esp32_at.mqtt_subscribe("commands/#", QoS::AtLeastOnce)).unwrap();
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn mqtt_subscribe(
+        &mut self,
+        topic: &str,
+        qos: QoS,
+    ) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::Mqtt)?;
+        write_command!(
+            self,
+            "AT+MQTTSUB={},\"{}\",{}",
+            LINK_ID,
+            Escaped(topic),
+            qos.code()
+        )?;
+        self.expect_mqtt_response()
+    }
+
+    /// Unsubscribes from a topic filter given to `mqtt_subscribe`; unsubscribing from one that
+    /// the client isn't subscribed to succeeds as well.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// let script = [Exchange::new(b"AT+MQTTUNSUB=0,\"commands/#\"\r\n", b"\r\nOK\r\n")];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Mqtt.into());
+    ///
+    /// nb::block!(esp32_at.mqtt_unsubscribe("commands/#")).unwrap();
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn mqtt_unsubscribe(&mut self, topic: &str) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::Mqtt)?;
+        write_command!(self, "AT+MQTTUNSUB={},\"{}\"", LINK_ID, Escaped(topic))?;
+        self.expect_mqtt_response()
+    }
+
+    /// Returns the topic filters that the client is subscribed to.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// use esp_at::mqtt::QoS;
+    /// let script = [Exchange::new(
+    ///     b"AT+MQTTSUB?\r\n",
+    ///     b"+MQTTSUB:0,6,\"commands/#\",1\r\n+MQTTSUB:0,6,\"config\",0\r\n\r\nOK\r\n",
+    /// )];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Mqtt.into());
+    ///
+    /// let subscriptions = nb::block!(esp32_at.get_mqtt_subscriptions()).unwrap();
+    /// assert_eq!(2, subscriptions.len());
+    /// assert_eq!("commands/#", subscriptions[0].topic);
+    /// assert_eq!(QoS::AtLeastOnce, subscriptions[0].qos);
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn get_mqtt_subscriptions(
+        &mut self,
+    ) -> nb::Result<heapless::Vec<Subscription, heapless::consts::U8>, Error<RX::Error, TX::Error>>
+    {
+        self.require_command_set(CommandSet::Mqtt)?;
+        write_command!(self, "AT+MQTTSUB?")?;
+
+        let mut subscriptions = heapless::Vec::new();
+        self.read_response(|line| {
+            if let Some(arguments) = parser::response(line, "MQTTSUB") {
+                let subscription =
+                    Subscription::parse(arguments).ok_or(Error::UnexpectedResponse)?;
+                subscriptions
+                    .push(subscription)
+                    .map_err(|_| Error::BufferOverflow)?;
+            }
+            Ok(())
+        })?;
+        Ok(subscriptions)
+    }
+
+    /// Takes the next message received on one of the subscriptions, reading its payload into
+    /// `buf`.
+    ///
+    /// Messages received while waiting for the responses to other commands are buffered, up to
+    /// `MQTT_RECEIVE_BUFFER_LEN` bytes of payload.  Messages that don't fit, or whose topic is
+    /// too long, are dropped, which is reported once with `Error::BufferOverflow`.  If `buf`
+    /// is too small for the payload, this fails with `Error::InvalidArgument` and the message
+    /// is kept.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// let script = [Exchange::unsolicited(
+    ///     b"+MQTTSUBRECV:0,\"commands/led\",8,on\r\n,\"\r\n\r\n+MQTTSUBRECV:0,\"commands/fan\",3,off\r\n",
+    /// )];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Mqtt.into());
+    ///
+    /// let mut buf = [0; 16];
+    /// let message = nb::block!(esp32_at.mqtt_receive(&mut buf)).unwrap();
+    /// assert_eq!("commands/led", message.topic);
+    /// assert_eq!(b"on\r\n,\"\r\n", &buf[..message.len]);
+    /// let message = nb::block!(esp32_at.mqtt_receive(&mut buf)).unwrap();
+    /// assert_eq!("commands/fan", message.topic);
+    /// assert_eq!(b"off", &buf[..message.len]);
+    /// # assert!(module.is_done());
+    /// ```
+    #[cfg(feature = "mqtt-receive")]
+    pub fn mqtt_receive(
+        &mut self,
+        buf: &mut [u8],
+    ) -> nb::Result<Message, Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::Mqtt)?;
+        self.drain_lines()?;
+
+        let received = &mut self.mqtt_received.sink;
+        if received.overflowed {
+            received.overflowed = false;
+            return Err(nb::Error::Other(Error::BufferOverflow));
+        }
+        match received.messages.peek() {
+            Some(message) if message.len > buf.len() => {
+                return Err(nb::Error::Other(Error::InvalidArgument))
+            }
+            Some(_) => {}
+            None => return Err(nb::Error::WouldBlock),
+        }
+
+        let message = received.messages.dequeue().ok_or(nb::Error::WouldBlock)?;
+        for byte in buf[..message.len].iter_mut() {
+            *byte = received.data.dequeue().ok_or(Error::UnexpectedResponse)?;
+        }
+        Ok(message)
+    }
+
+    /// Configures the client and connects to the broker, announcing the client's presence.
+    ///
+    /// The `presence` last will is configured before connecting, and the birth message is
+    /// published once the connection has been established, both retained.  The module reconnects
+    /// on its own, but the birth message is only published here; `MqttClient::set_presence`
+    /// publishes it again after every reconnection.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// use esp_at::mqtt::{MqttScheme, MqttUserConfig, Presence, QoS};
+    /// let script = [
+    ///     Exchange::new(b"AT+MQTTUSERCFG=0,1,\"sensor-1\",\"user\",\"pass\",0,0,\"\"\r\n", b"\r\nOK\r\n"),
+    ///     Exchange::new(b"AT+MQTTCONNCFG=0,60,0,\"status/sensor-1\",\"offline\",1,1\r\n", b"\r\nOK\r\n"),
+    ///     Exchange::new(b"AT+MQTTCONN=0,\"broker.local\",1883,1\r\n", b"\r\nOK\r\n"),
+    ///     Exchange::new(b"AT+MQTTPUB=0,\"status/sensor-1\",\"online\",1,1\r\n", b"\r\nOK\r\n"),
+    /// ];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Mqtt.into());
+    ///
+    /// let config = MqttUserConfig {
+    ///     scheme: MqttScheme::Tcp,
+    ///     client_id: "sensor-1",
+    ///     username: "user",
+    ///     password: "pass",
+    ///     pki_index: 0,
+    ///     ca_index: 0,
+    ///     path: "",
+    /// };
+    /// let presence = Presence {
+    ///     topic: "status/sensor-1",
+    ///     online: "online",
+    ///     offline: "offline",
+    ///     qos: QoS::AtLeastOnce,
+    /// };
+    /// esp32_at
+    ///     .mqtt_connect_with_presence(&config, 60, "broker.local", 1883, &presence)
+    ///     .unwrap();
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn mqtt_connect_with_presence(
+        &mut self,
+        user_config: &MqttUserConfig,
+        keepalive_secs: u16,
+        host: &str,
+        port: u16,
+        presence: &Presence,
+    ) -> Result<(), Error<RX::Error, TX::Error>> {
+        nb::block!(self.mqtt_user_config(user_config))?;
+        nb::block!(self.mqtt_connection_config(&MqttConnectionConfig {
+            keepalive_secs,
+            clean_session: true,
+            last_will: Some(LastWill {
+                topic: presence.topic,
+                message: presence.offline,
+                qos: presence.qos,
+                retain: true,
+            }),
+        }))?;
+        nb::block!(self.mqtt_connect(host, port, true))?;
+        nb::block!(self.mqtt_publish(presence.topic, presence.online, presence.qos, true))
+    }
+
+    /// Like `expect_ok_response`, but turns the error code that precedes a failure into
+    /// `Error::MqttFailed`.
+    fn expect_mqtt_response(&mut self) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        let mut failure = None;
+        let result = self.read_response(|line| {
+            if let Some(reason) = MqttFailure::parse(line) {
+                failure = Some(reason);
+            }
+            Ok(())
+        });
+        match (result, failure) {
+            (Err(nb::Error::Other(Error::CommandFailed)), Some(reason)) => {
+                Err(nb::Error::Other(Error::MqttFailed { reason }))
+            }
+            (result, _) => result,
+        }
+    }
+
+    fn set_mqtt_credential(
+        &mut self,
+        command: &str,
+        value: &str,
+    ) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::Mqtt)?;
+        if value.is_empty() || value.len() > MAX_CREDENTIAL_LEN {
+            return Err(nb::Error::Other(Error::InvalidArgument));
+        }
+        write_command!(self, "AT+{}={},{}", command, LINK_ID, value.len())?;
+        self.expect_prompt()?;
+        self.write_all(value.as_bytes())?;
+        self.expect_mqtt_response()
+    }
+}
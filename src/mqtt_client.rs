@@ -0,0 +1,259 @@
+use crate::event::Event;
+use crate::mqtt::{LastWill, MqttConnectionConfig, MqttUserConfig, Presence, QoS};
+use crate::{Error, Esp32At};
+
+/// An MQTT session on top of the `Esp32At::mqtt_*` commands, which remembers the subscriptions
+/// and makes them again whenever the connection has been re-established, along with the birth
+/// message of its `Presence`, if it has one.
+///
+/// The module reconnects to the broker on its own when the connection is lost.  Events received
+/// with `Esp32At::poll_event` should be passed to `handle_event`, and `poll` should be called
+/// regularly to renew the subscriptions once it has.
+///
+/// # Examples
+///
+/// ```
+/// # use esp_at::mock::{Exchange, MockModule};
+/// # use esp_at::{CommandSet, Esp32At};
+/// use esp_at::mqtt::{MqttConnectionConfig, MqttScheme, MqttUserConfig, QoS};
+/// use esp_at::mqtt_client::MqttClient;
+/// let script = [
+///     Exchange::new(
+///         b"AT+MQTTUSERCFG=0,1,\"sensor-1\",\"\",\"\",0,0,\"\"\r\n",
+///         b"\r\nOK\r\n",
+///     ),
+///     Exchange::new(b"AT+MQTTCONNCFG=0,60,0,\"\",\"\",0,0\r\n", b"\r\nOK\r\n"),
+///     Exchange::new(b"AT+MQTTCONN=0,\"broker.local\",1883,1\r\n", b"\r\nOK\r\n"),
+///     Exchange::new(b"AT+MQTTSUB=0,\"commands/#\",1\r\n", b"\r\nOK\r\n"),
+///     Exchange::unsolicited(b"+MQTTDISCONNECTED:0\r\n"),
+///     Exchange::unsolicited(b"+MQTTCONNECTED:0,1,\"broker.local\",\"1883\",\"\",1\r\n"),
+///     Exchange::new(b"AT+MQTTSUB=0,\"commands/#\",1\r\n", b"\r\nOK\r\n"),
+///     Exchange::new(b"AT+MQTTPUBRAW=0,\"sensors/temp\",4,0,0\r\n", b"\r\nOK\r\n\r\n>"),
+///     Exchange::new(b"21.5", b"\r\n+MQTTPUB:OK\r\n"),
+/// ];
+/// # let module = MockModule::new(&script);
+/// # let (rx, tx) = module.split();
+/// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Mqtt.into());
+///
+/// let user_config = MqttUserConfig {
+///     scheme: MqttScheme::Tcp,
+///     client_id: "sensor-1",
+///     username: "",
+///     password: "",
+///     pki_index: 0,
+///     ca_index: 0,
+///     path: "",
+/// };
+/// let connection_config = MqttConnectionConfig {
+///     keepalive_secs: 60,
+///     clean_session: true,
+///     last_will: None,
+/// };
+/// let mut client = MqttClient::new(user_config, connection_config, "broker.local", 1883);
+/// client.connect(&mut esp32_at).unwrap();
+/// client.subscribe(&mut esp32_at, "commands/#", QoS::AtLeastOnce).unwrap();
+///
+/// for _ in 0..2 {
+///     let event = nb::block!(esp32_at.poll_event()).unwrap();
+///     client.handle_event(&event);
+/// }
+/// client.poll(&mut esp32_at).unwrap();
+/// assert!(client.is_connected());
+///
+/// client
+///     .publish(&mut esp32_at, "sensors/temp", b"21.5", QoS::AtMostOnce, false)
+///     .unwrap();
+/// # assert!(module.is_done());
+/// ```
+#[derive(Debug)]
+pub struct MqttClient<'a> {
+    user_config: MqttUserConfig<'a>,
+    connection_config: MqttConnectionConfig<'a>,
+    host: &'a str,
+    port: u16,
+    subscriptions: heapless::Vec<(&'a str, QoS), heapless::consts::U8>,
+    presence: Option<Presence<'a>>,
+    state: State,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Disconnected,
+    Connected,
+    /// The module has reconnected on its own, and the subscriptions have to be made and the
+    /// presence announced again.
+    Resubscribing,
+}
+
+impl<'a> MqttClient<'a> {
+    pub fn new(
+        user_config: MqttUserConfig<'a>,
+        connection_config: MqttConnectionConfig<'a>,
+        host: &'a str,
+        port: u16,
+    ) -> Self {
+        Self {
+            user_config,
+            connection_config,
+            host,
+            port,
+            subscriptions: heapless::Vec::new(),
+            presence: None,
+            state: State::Disconnected,
+        }
+    }
+
+    /// Announces the client's presence like `Esp32At::mqtt_connect_with_presence`, replacing the
+    /// last will of the connection config; takes effect on the next `connect`.
+    pub fn set_presence(&mut self, presence: Presence<'a>) {
+        self.connection_config.last_will = Some(LastWill {
+            topic: presence.topic,
+            message: presence.offline,
+            qos: presence.qos,
+            retain: true,
+        });
+        self.presence = Some(presence);
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.state != State::Disconnected
+    }
+
+    /// Configures the client and connects to the broker, making the subscriptions that were
+    /// added before.
+    pub fn connect<RX, TX>(
+        &mut self,
+        esp: &mut Esp32At<RX, TX>,
+    ) -> Result<(), Error<RX::Error, TX::Error>>
+    where
+        RX: embedded_hal::serial::Read<u8>,
+        TX: embedded_hal::serial::Write<u8>,
+    {
+        nb::block!(esp.mqtt_user_config(&self.user_config))?;
+        nb::block!(esp.mqtt_connection_config(&self.connection_config))?;
+        nb::block!(esp.mqtt_connect(self.host, self.port, true))?;
+        self.state = State::Resubscribing;
+        self.poll(esp)
+    }
+
+    /// Disconnects from the broker; the subscriptions are kept for the next `connect`.
+    pub fn disconnect<RX, TX>(
+        &mut self,
+        esp: &mut Esp32At<RX, TX>,
+    ) -> Result<(), Error<RX::Error, TX::Error>>
+    where
+        RX: embedded_hal::serial::Read<u8>,
+        TX: embedded_hal::serial::Write<u8>,
+    {
+        self.state = State::Disconnected;
+        nb::block!(esp.mqtt_disconnect())
+    }
+
+    /// Subscribes to `topic`, right away if connected, and again after every reconnection.
+    ///
+    /// At most `mqtt::MAX_SUBSCRIPTIONS` can be made; more fail with `Error::BufferOverflow`.
+    pub fn subscribe<RX, TX>(
+        &mut self,
+        esp: &mut Esp32At<RX, TX>,
+        topic: &'a str,
+        qos: QoS,
+    ) -> Result<(), Error<RX::Error, TX::Error>>
+    where
+        RX: embedded_hal::serial::Read<u8>,
+        TX: embedded_hal::serial::Write<u8>,
+    {
+        match self.subscriptions.iter_mut().find(|(t, _)| *t == topic) {
+            Some(subscription) => subscription.1 = qos,
+            None => self
+                .subscriptions
+                .push((topic, qos))
+                .map_err(|_| Error::BufferOverflow)?,
+        }
+        if self.state == State::Connected {
+            nb::block!(esp.mqtt_subscribe(topic, qos))?;
+        }
+        Ok(())
+    }
+
+    pub fn unsubscribe<RX, TX>(
+        &mut self,
+        esp: &mut Esp32At<RX, TX>,
+        topic: &str,
+    ) -> Result<(), Error<RX::Error, TX::Error>>
+    where
+        RX: embedded_hal::serial::Read<u8>,
+        TX: embedded_hal::serial::Write<u8>,
+    {
+        if let Some(index) = self.subscriptions.iter().position(|(t, _)| *t == topic) {
+            self.subscriptions.swap_remove(index);
+        }
+        if self.state == State::Connected {
+            nb::block!(esp.mqtt_unsubscribe(topic))?;
+        }
+        Ok(())
+    }
+
+    /// Publishes a message of arbitrary bytes with `Esp32At::mqtt_publish_raw`.
+    pub fn publish<RX, TX>(
+        &mut self,
+        esp: &mut Esp32At<RX, TX>,
+        topic: &str,
+        data: &[u8],
+        qos: QoS,
+        retain: bool,
+    ) -> Result<(), Error<RX::Error, TX::Error>>
+    where
+        RX: embedded_hal::serial::Read<u8>,
+        TX: embedded_hal::serial::Write<u8>,
+    {
+        nb::block!(esp.mqtt_publish_raw(topic, data, qos, retain))
+    }
+
+    /// Takes the next message received on one of the subscriptions, see
+    /// `Esp32At::mqtt_receive`.
+    #[cfg(feature = "mqtt-receive")]
+    pub fn poll_message<RX, TX>(
+        &mut self,
+        esp: &mut Esp32At<RX, TX>,
+        buf: &mut [u8],
+    ) -> nb::Result<crate::mqtt::Message, Error<RX::Error, TX::Error>>
+    where
+        RX: embedded_hal::serial::Read<u8>,
+        TX: embedded_hal::serial::Write<u8>,
+    {
+        esp.mqtt_receive(buf)
+    }
+
+    pub fn handle_event(&mut self, event: &Event) {
+        match event {
+            Event::MqttDisconnected => self.state = State::Disconnected,
+            Event::MqttConnected if self.state == State::Disconnected => {
+                self.state = State::Resubscribing
+            }
+            _ => {}
+        }
+    }
+
+    /// Makes the subscriptions and publishes the presence birth message again if the module has
+    /// reconnected, blocking until they have been made.
+    pub fn poll<RX, TX>(
+        &mut self,
+        esp: &mut Esp32At<RX, TX>,
+    ) -> Result<(), Error<RX::Error, TX::Error>>
+    where
+        RX: embedded_hal::serial::Read<u8>,
+        TX: embedded_hal::serial::Write<u8>,
+    {
+        if self.state != State::Resubscribing {
+            return Ok(());
+        }
+        for &(topic, qos) in self.subscriptions.iter() {
+            nb::block!(esp.mqtt_subscribe(topic, qos))?;
+        }
+        if let Some(presence) = self.presence {
+            nb::block!(esp.mqtt_publish(presence.topic, presence.online, presence.qos, true))?;
+        }
+        self.state = State::Connected;
+        Ok(())
+    }
+}
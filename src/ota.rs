@@ -0,0 +1,129 @@
+//! Over-the-air updates of the module's own firmware, downloaded from Espressif's OTA server.
+
+use crate::{parser, CommandSet, Error, Esp32At, QuotedList};
+
+/// How the firmware is downloaded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum OtaScheme {
+    #[default]
+    Http,
+    Https,
+}
+
+/// Which firmware to download; by default, the latest AT firmware over HTTP.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct OtaOptions<'a> {
+    pub scheme: OtaScheme,
+    /// The firmware version to download, e.g. `v2.2.0.0`.
+    pub version: Option<&'a str>,
+    /// The partition to update instead of the AT firmware, e.g. `mqtt_ca`; requires a `version`.
+    pub partition: Option<&'a str>,
+}
+
+/// A step of an update, as reported by `+CIPUPDATE:`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum OtaProgress {
+    FoundServer,
+    Connected,
+    GotVersion,
+    Completed,
+}
+
+impl<RX, TX> Esp32At<RX, TX>
+where
+    RX: embedded_hal::serial::Read<u8>,
+    TX: embedded_hal::serial::Write<u8>,
+{
+    /// Updates the module's firmware, calling `on_progress` as the update proceeds; the module
+    /// has to be connected to the Internet.
+    ///
+    /// Once the update has completed, the module restarts into the new firmware; wait for it with
+    /// `test_startup`.  All links are closed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// use esp_at::ota::{OtaOptions, OtaProgress, OtaScheme};
+    /// let script = [Exchange::new(
+    ///     b"AT+CIUPDATE=1,\"v2.2.0.0\"\r\n",
+    ///     b"+CIPUPDATE:1\r\n+CIPUPDATE:2\r\n+CIPUPDATE:3\r\n+CIPUPDATE:4\r\n\r\nOK\r\n",
+    /// )];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::TcpIp.into());
+    ///
+    /// let options = OtaOptions {
+    ///     scheme: OtaScheme::Https,
+    ///     version: Some("v2.2.0.0"),
+    ///     ..OtaOptions::default()
+    /// };
+    /// let mut steps = 0;
+    /// esp32_at
+    ///     .update_firmware(&options, |progress| {
+    ///         steps += 1;
+    ///         if steps == 4 {
+    ///             assert_eq!(OtaProgress::Completed, progress);
+    ///         }
+    ///     })
+    ///     .unwrap();
+    /// assert_eq!(4, steps);
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn update_firmware<F>(
+        &mut self,
+        options: &OtaOptions,
+        mut on_progress: F,
+    ) -> Result<(), Error<RX::Error, TX::Error>>
+    where
+        F: FnMut(OtaProgress),
+    {
+        self.require_command_set(CommandSet::TcpIp)?;
+        if options.partition.is_some() && options.version.is_none() {
+            return Err(Error::InvalidArgument);
+        }
+        // The partition is only given after the version, which it requires
+        let target = [
+            options.version.unwrap_or_default(),
+            options.partition.unwrap_or_default(),
+        ];
+        let target_len = options.version.is_some() as usize + options.partition.is_some() as usize;
+        nb::block!(write_command!(
+            self,
+            "AT+CIUPDATE={}{}",
+            options.scheme.code(),
+            QuotedList(&target[..target_len])
+        ))?;
+        nb::block!(self.read_response(|line| {
+            if let Some(progress) = parser::response(line, "CIPUPDATE") {
+                on_progress(OtaProgress::from_code(progress).ok_or(Error::UnexpectedResponse)?);
+            }
+            Ok(())
+        }))?;
+        self.links = None;
+        self.received.sink.remote_info = false;
+        Ok(())
+    }
+}
+
+impl OtaScheme {
+    fn code(self) -> u8 {
+        match self {
+            OtaScheme::Http => 0,
+            OtaScheme::Https => 1,
+        }
+    }
+}
+
+impl OtaProgress {
+    fn from_code(code: &str) -> Option<Self> {
+        match code {
+            "1" => Some(OtaProgress::FoundServer),
+            "2" => Some(OtaProgress::Connected),
+            "3" => Some(OtaProgress::GotVersion),
+            "4" => Some(OtaProgress::Completed),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,65 @@
+/// Returns the arguments of a response line like `+NAME:<arguments>`, if it is the response named
+/// `name`.
+pub fn response<'a>(line: &'a str, name: &str) -> Option<&'a str> {
+    line.strip_prefix('+')?
+        .strip_prefix(name)?
+        .strip_prefix(':')
+}
+
+/// Splits the arguments of a response line at the commas that are not inside a quoted string.
+pub fn fields(arguments: &str) -> Fields<'_> {
+    Fields {
+        remaining: Some(arguments),
+    }
+}
+
+/// Strips the quotes around a string argument, if it has any.
+pub fn unquote(field: &str) -> &str {
+    field
+        .strip_prefix('"')
+        .and_then(|field| field.strip_suffix('"'))
+        .unwrap_or(field)
+}
+
+pub struct Fields<'a> {
+    remaining: Option<&'a str>,
+}
+
+impl<'a> Iterator for Fields<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let remaining = self.remaining?;
+        let mut quoted = false;
+        let mut escaped = false;
+        for (i, c) in remaining.char_indices() {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => quoted = !quoted,
+                ',' if !quoted => {
+                    self.remaining = Some(&remaining[i + 1..]);
+                    return Some(&remaining[..i]);
+                }
+                _ => {}
+            }
+        }
+        self.remaining = None;
+        Some(remaining)
+    }
+}
+
+/// Copies a string into a fixed capacity string, if it fits.
+pub fn string<N>(s: &str) -> Option<heapless::String<N>>
+where
+    N: heapless::ArrayLength<u8>,
+{
+    let mut string = heapless::String::new();
+    string.push_str(s).ok()?;
+    Some(string)
+}
+
+/// Whether a line is the module echoing back a command.
+pub fn is_echo(line: &str) -> bool {
+    line.trim_end() == "AT" || line.starts_with("AT+") || line.starts_with("ATE")
+}
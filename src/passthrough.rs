@@ -0,0 +1,543 @@
+//! Transparent transmission, in which the bytes written to the module go straight to the
+//! connection and vice versa.
+
+use crate::{parser, CommandSet, Error, Escaped, Esp32At};
+
+/// How long the line has to be silent before and after the `+++` escape sequence for the module
+/// to recognize it.
+pub const GUARD_TIME_MS: u16 = 20;
+
+/// How long the module needs after the `+++` escape sequence before it accepts commands again.
+pub const EXIT_TIME_MS: u16 = 1_000;
+
+/// The range of intervals at which a dropped transparent transmission connection is
+/// re-established, in steps of 100 ms.
+pub const MIN_RECONNECT_INTERVAL_MS: u32 = 100;
+pub const MAX_RECONNECT_INTERVAL_MS: u32 = 3_600_000;
+
+/// The connection of a module in transparent transmission, started with
+/// `Esp32At::start_passthrough` or `Esp32At::ble_start_spp`.
+///
+/// No commands can be executed while it lasts.
+#[derive(Debug)]
+pub struct Passthrough<'a, RX, TX>
+where
+    RX: embedded_hal::serial::Read<u8>,
+    TX: embedded_hal::serial::Write<u8>,
+{
+    esp32_at: &'a mut Esp32At<RX, TX>,
+}
+
+/// A connection that the module establishes at boot, entering transparent transmission right
+/// away.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SavedLink<'a> {
+    Tcp {
+        host: &'a str,
+        port: u16,
+        keepalive_secs: u16,
+    },
+    Ssl {
+        host: &'a str,
+        port: u16,
+        keepalive_secs: u16,
+    },
+    Udp {
+        host: &'a str,
+        port: u16,
+        local_port: u16,
+    },
+}
+
+/// The GATT characteristics that BLE SPP sends and receives data on, by service and
+/// characteristic index, see `Esp32At::set_ble_spp_config`.
+///
+/// As a server they are characteristics of its own services, and as a client those of the
+/// peer's.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BleSppConfig {
+    /// The characteristic that data is sent on, which the client is notified of as a server.
+    pub tx_service_index: u8,
+    pub tx_char_index: u8,
+    /// The characteristic that data is received on, which the client writes as a server.
+    pub rx_service_index: u8,
+    pub rx_char_index: u8,
+    /// Whether the module reconnects by itself when the connection is lost.
+    pub auto_reconnect: bool,
+}
+
+impl<RX, TX> Esp32At<RX, TX>
+where
+    RX: embedded_hal::serial::Read<u8>,
+    TX: embedded_hal::serial::Write<u8>,
+{
+    /// Selects whether `start_passthrough` can be used; only possible in single connection mode.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// let script = [Exchange::new(b"AT+CIPMODE=1\r\n", b"\r\nOK\r\n")];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::TcpIp.into());
+    ///
+    /// nb::block!(esp32_at.set_passthrough_mode(true)).unwrap();
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn set_passthrough_mode(
+        &mut self,
+        enabled: bool,
+    ) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::TcpIp)?;
+        write_command!(self, "AT+CIPMODE={}", enabled as u8)?;
+        self.expect_ok_response()
+    }
+
+    pub fn get_passthrough_mode(&mut self) -> nb::Result<bool, Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::TcpIp)?;
+        write_command!(self, "AT+CIPMODE?")?;
+        match self.read_query_response("CIPMODE")?.as_str() {
+            "0" => Ok(false),
+            "1" => Ok(true),
+            _ => Err(nb::Error::Other(Error::UnexpectedResponse)),
+        }
+    }
+
+    /// Starts transparent transmission over the open connection; the module has to be in
+    /// passthrough mode.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// let script = [
+    ///     Exchange::new(b"AT+CIPMODE=1\r\n", b"\r\nOK\r\n"),
+    ///     Exchange::new(b"AT+CIPSEND\r\n", b"\r\nOK\r\n\r\n>"),
+    ///     Exchange::new(b"GET / HTTP/1.0\r\n\r\n", b"HTTP/1.0 200 OK"),
+    /// ];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::TcpIp.into());
+    ///
+    /// nb::block!(esp32_at.set_passthrough_mode(true)).unwrap();
+    /// let mut passthrough = nb::block!(esp32_at.start_passthrough()).unwrap();
+    /// nb::block!(passthrough.write(b"GET / HTTP/1.0\r\n\r\n")).unwrap();
+    /// let mut response = [0; 15];
+    /// for byte in response.iter_mut() {
+    ///     *byte = nb::block!(passthrough.read()).unwrap();
+    /// }
+    /// assert_eq!(b"HTTP/1.0 200 OK", &response);
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn start_passthrough(
+        &mut self,
+    ) -> nb::Result<Passthrough<'_, RX, TX>, Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::TcpIp)?;
+        if self.links.is_some() {
+            return Err(nb::Error::Other(Error::InvalidArgument));
+        }
+        write_command!(self, "AT+CIPSEND")?;
+        self.expect_prompt()?;
+        Ok(Passthrough { esp32_at: self })
+    }
+
+    /// Saves a connection for the module to establish at boot, streaming over it in transparent
+    /// transmission without any commands from the host.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// use esp_at::passthrough::SavedLink;
+    /// let script = [
+    ///     Exchange::new(b"AT+SAVETRANSLINK=1,\"10.0.0.2\",9000,\"TCP\",60\r\n", b"\r\nOK\r\n"),
+    ///     Exchange::new(b"AT+SAVETRANSLINK=0\r\n", b"\r\nOK\r\n"),
+    /// ];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::TcpIp.into());
+    ///
+    /// let link = SavedLink::Tcp {
+    ///     host: "10.0.0.2",
+    ///     port: 9000,
+    ///     keepalive_secs: 60,
+    /// };
+    /// nb::block!(esp32_at.save_passthrough_link(&link)).unwrap();
+    /// nb::block!(esp32_at.clear_passthrough_link()).unwrap();
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn save_passthrough_link(
+        &mut self,
+        link: &SavedLink,
+    ) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::TcpIp)?;
+        match *link {
+            SavedLink::Tcp {
+                host,
+                port,
+                keepalive_secs,
+            } => write_command!(
+                self,
+                "AT+SAVETRANSLINK=1,\"{}\",{},\"TCP\",{}",
+                Escaped(host),
+                port,
+                keepalive_secs
+            )?,
+            SavedLink::Ssl {
+                host,
+                port,
+                keepalive_secs,
+            } => {
+                self.require_command_set(CommandSet::Tls)?;
+                write_command!(
+                    self,
+                    "AT+SAVETRANSLINK=1,\"{}\",{},\"SSL\",{}",
+                    Escaped(host),
+                    port,
+                    keepalive_secs
+                )?
+            }
+            SavedLink::Udp {
+                host,
+                port,
+                local_port,
+            } => write_command!(
+                self,
+                "AT+SAVETRANSLINK=1,\"{}\",{},\"UDP\",{}",
+                Escaped(host),
+                port,
+                local_port
+            )?,
+        }
+        self.expect_ok_response()
+    }
+
+    /// Stops the module from establishing a saved connection at boot.
+    pub fn clear_passthrough_link(&mut self) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::TcpIp)?;
+        write_command!(self, "AT+SAVETRANSLINK=0")?;
+        self.expect_ok_response()
+    }
+
+    /// Sets how often the module tries to re-establish the connection of transparent transmission,
+    /// e.g. the saved connection, after it has been dropped; retrying less often saves power.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// let script = [
+    ///     Exchange::new(b"AT+CIPRECONNINTV=50\r\n", b"\r\nOK\r\n"),
+    ///     Exchange::new(b"AT+CIPRECONNINTV?\r\n", b"+CIPRECONNINTV:50\r\n\r\nOK\r\n"),
+    /// ];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::TcpIp.into());
+    ///
+    /// nb::block!(esp32_at.set_passthrough_reconnect_interval(5000)).unwrap();
+    /// let interval = nb::block!(esp32_at.get_passthrough_reconnect_interval()).unwrap();
+    /// assert_eq!(5000, interval);
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn set_passthrough_reconnect_interval(
+        &mut self,
+        interval_ms: u32,
+    ) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::TcpIp)?;
+        if !(MIN_RECONNECT_INTERVAL_MS..=MAX_RECONNECT_INTERVAL_MS).contains(&interval_ms)
+            || interval_ms % 100 != 0
+        {
+            return Err(nb::Error::Other(Error::InvalidArgument));
+        }
+        write_command!(self, "AT+CIPRECONNINTV={}", interval_ms / 100)?;
+        self.expect_ok_response()
+    }
+
+    pub fn get_passthrough_reconnect_interval(
+        &mut self,
+    ) -> nb::Result<u32, Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::TcpIp)?;
+        write_command!(self, "AT+CIPRECONNINTV?")?;
+        let interval = self.read_query_response("CIPRECONNINTV")?;
+        let interval: u32 = interval.parse().map_err(|_| Error::UnexpectedResponse)?;
+        Ok(interval * 100)
+    }
+
+    /// Sets the characteristics that BLE SPP uses, which has to be done before starting it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// use esp_at::passthrough::BleSppConfig;
+    /// let script = [
+    ///     Exchange::new(b"AT+BLESPPCFG=1,1,5,1,7,1\r\n", b"\r\nOK\r\n"),
+    ///     Exchange::new(b"AT+BLESPPCFG?\r\n", b"+BLESPPCFG:1,5,1,7,1\r\n\r\nOK\r\n"),
+    ///     Exchange::new(b"AT+BLESPPCFG=0\r\n", b"\r\nOK\r\n"),
+    /// ];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Ble.into());
+    ///
+    /// let config = BleSppConfig {
+    ///     tx_service_index: 1,
+    ///     tx_char_index: 5,
+    ///     rx_service_index: 1,
+    ///     rx_char_index: 7,
+    ///     auto_reconnect: true,
+    /// };
+    /// nb::block!(esp32_at.set_ble_spp_config(&config)).unwrap();
+    /// assert_eq!(Some(config), nb::block!(esp32_at.get_ble_spp_config()).unwrap());
+    /// nb::block!(esp32_at.clear_ble_spp_config()).unwrap();
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn set_ble_spp_config(
+        &mut self,
+        config: &BleSppConfig,
+    ) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::Ble)?;
+        write_command!(
+            self,
+            "AT+BLESPPCFG=1,{},{},{},{},{}",
+            config.tx_service_index,
+            config.tx_char_index,
+            config.rx_service_index,
+            config.rx_char_index,
+            config.auto_reconnect as u8
+        )?;
+        self.expect_ok_response()
+    }
+
+    /// Returns the characteristics that BLE SPP uses, if they have been set.
+    pub fn get_ble_spp_config(
+        &mut self,
+    ) -> nb::Result<Option<BleSppConfig>, Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::Ble)?;
+        write_command!(self, "AT+BLESPPCFG?")?;
+        let mut config = None;
+        self.read_response(|line| {
+            match parser::response(line, "BLESPPCFG") {
+                // Some firmware versions report a configuration that hasn't been set as disabled
+                Some("0") | None => {}
+                Some(arguments) => {
+                    config = Some(BleSppConfig::parse(arguments).ok_or(Error::UnexpectedResponse)?)
+                }
+            }
+            Ok(())
+        })?;
+        Ok(config)
+    }
+
+    pub fn clear_ble_spp_config(&mut self) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::Ble)?;
+        write_command!(self, "AT+BLESPPCFG=0")?;
+        self.expect_ok_response()
+    }
+
+    /// Starts BLE SPP, transparent transmission over the established BLE connection using the
+    /// characteristics set with `set_ble_spp_config`, which turns the module into a wireless UART
+    /// bridge.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// # struct Delay;
+    /// # impl embedded_hal::blocking::delay::DelayMs<u16> for Delay {
+    /// #     fn delay_ms(&mut self, _ms: u16) {}
+    /// # }
+    /// let script = [
+    ///     Exchange::new(b"AT+BLESPP\r\n", b"\r\nOK\r\n\r\n>"),
+    ///     Exchange::new(b"ping", b"pong"),
+    ///     Exchange::new(b"+++", b""),
+    ///     Exchange::new(b"AT\r\n", b"\r\nOK\r\n"),
+    /// ];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Ble.into());
+    ///
+    /// let mut spp = nb::block!(esp32_at.ble_start_spp()).unwrap();
+    /// nb::block!(spp.write(b"ping")).unwrap();
+    /// let mut response = [0; 4];
+    /// for byte in response.iter_mut() {
+    ///     *byte = nb::block!(spp.read()).unwrap();
+    /// }
+    /// assert_eq!(b"pong", &response);
+    /// spp.exit(&mut Delay).unwrap();
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn ble_start_spp(
+        &mut self,
+    ) -> nb::Result<Passthrough<'_, RX, TX>, Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::Ble)?;
+        write_command!(self, "AT+BLESPP")?;
+        self.expect_prompt()?;
+        Ok(Passthrough { esp32_at: self })
+    }
+
+    /// Ends transparent transmission, e.g. after the `Passthrough` handle has been dropped, and
+    /// waits until the module accepts commands again.
+    ///
+    /// The escape sequence is preceded by `GUARD_TIME_MS` of silence and followed by
+    /// `EXIT_TIME_MS`, measured with `delay`.  Whatever the connection delivers until the line is
+    /// idle is discarded.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// # struct Delay;
+    /// # impl embedded_hal::blocking::delay::DelayMs<u16> for Delay {
+    /// #     fn delay_ms(&mut self, _ms: u16) {}
+    /// # }
+    /// let script = [
+    ///     Exchange::new(b"AT+CIPSEND\r\n", b"\r\nOK\r\n\r\n>"),
+    ///     // Data that was still on its way is discarded
+    ///     Exchange::new(b"+++", b"late\r\ndata"),
+    ///     Exchange::new(b"AT\r\n", b"\r\nOK\r\n"),
+    /// ];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::TcpIp.into());
+    ///
+    /// let passthrough = nb::block!(esp32_at.start_passthrough()).unwrap();
+    /// passthrough.exit(&mut Delay).unwrap();
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn exit_passthrough<D>(&mut self, delay: &mut D) -> Result<(), Error<RX::Error, TX::Error>>
+    where
+        D: embedded_hal::blocking::delay::DelayMs<u16>,
+    {
+        delay.delay_ms(GUARD_TIME_MS);
+        nb::block!(self.write_all(b"+++"))?;
+        delay.delay_ms(EXIT_TIME_MS);
+
+        // Whatever was received before the module left transparent transmission is not a
+        // response to any command
+        while self.discard_received()? {
+            delay.delay_ms(GUARD_TIME_MS);
+        }
+        self.line = heapless::Vec::new();
+        nb::block!(self.test_startup())
+    }
+
+    /// Discards the bytes that have been received so far, returning whether there were any.
+    fn discard_received(&mut self) -> Result<bool, Error<RX::Error, TX::Error>> {
+        let mut discarded = false;
+        loop {
+            match self.getc() {
+                Ok(_) => discarded = true,
+                Err(nb::Error::WouldBlock) => return Ok(discarded),
+                Err(nb::Error::Other(error)) => return Err(error),
+            }
+        }
+    }
+}
+
+impl BleSppConfig {
+    /// Parses `<tx_srv_index>,<tx_char_index>,<rx_srv_index>,<rx_char_index>,<auto_conn>`.
+    fn parse(arguments: &str) -> Option<Self> {
+        let mut fields = parser::fields(arguments);
+        Some(BleSppConfig {
+            tx_service_index: fields.next()?.parse().ok()?,
+            tx_char_index: fields.next()?.parse().ok()?,
+            rx_service_index: fields.next()?.parse().ok()?,
+            rx_char_index: fields.next()?.parse().ok()?,
+            auto_reconnect: match fields.next()? {
+                "0" => false,
+                "1" => true,
+                _ => return None,
+            },
+        })
+    }
+}
+
+impl<'a, RX, TX> Passthrough<'a, RX, TX>
+where
+    RX: embedded_hal::serial::Read<u8>,
+    TX: embedded_hal::serial::Write<u8>,
+{
+    /// Sends `data` over the connection.
+    pub fn write(&mut self, data: &[u8]) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.esp32_at.write_all(data)
+    }
+
+    /// Reads a byte received over the connection, if one is available.
+    pub fn read(&mut self) -> nb::Result<u8, Error<RX::Error, TX::Error>> {
+        self.esp32_at.getc()
+    }
+
+    /// Ends transparent transmission; see `Esp32At::exit_passthrough`.
+    pub fn exit<D>(self, delay: &mut D) -> Result<(), Error<RX::Error, TX::Error>>
+    where
+        D: embedded_hal::blocking::delay::DelayMs<u16>,
+    {
+        self.esp32_at.exit_passthrough(delay)
+    }
+}
+
+#[cfg(feature = "embedded-io")]
+impl<'a, RX, TX> embedded_io::ErrorType for Passthrough<'a, RX, TX>
+where
+    RX: embedded_hal::serial::Read<u8>,
+    RX::Error: core::fmt::Debug,
+    TX: embedded_hal::serial::Write<u8>,
+    TX::Error: core::fmt::Debug,
+{
+    type Error = Error<RX::Error, TX::Error>;
+}
+
+/// Waits for the first byte received over the connection, and reads the ones that follow it
+/// for as long as they are available; the end of the stream is never reached, since the
+/// connection can only be ended by `Passthrough::exit`.
+#[cfg(feature = "embedded-io")]
+impl<'a, RX, TX> embedded_io::Read for Passthrough<'a, RX, TX>
+where
+    RX: embedded_hal::serial::Read<u8>,
+    RX::Error: core::fmt::Debug,
+    TX: embedded_hal::serial::Write<u8>,
+    TX::Error: core::fmt::Debug,
+{
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        buf[0] = nb::block!(Passthrough::read(self))?;
+        for (len, byte) in buf.iter_mut().enumerate().skip(1) {
+            match Passthrough::read(self) {
+                Ok(received) => *byte = received,
+                Err(nb::Error::WouldBlock) => return Ok(len),
+                Err(nb::Error::Other(error)) => return Err(error),
+            }
+        }
+        Ok(buf.len())
+    }
+}
+
+/// Sends the data as is, all of it in every write.
+#[cfg(feature = "embedded-io")]
+impl<'a, RX, TX> embedded_io::Write for Passthrough<'a, RX, TX>
+where
+    RX: embedded_hal::serial::Read<u8>,
+    RX::Error: core::fmt::Debug,
+    TX: embedded_hal::serial::Write<u8>,
+    TX::Error: core::fmt::Debug,
+{
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        nb::block!(Passthrough::write(self, buf))?;
+        Ok(buf.len())
+    }
+
+    /// Waits for the data to have been sent to the module.
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        nb::block!(self.esp32_at.tx.flush()).map_err(|cause| Error::UartWrite { cause })
+    }
+}
@@ -0,0 +1,69 @@
+//! Binary payloads that the module pushes after a header announcing their length, like
+//! `+IPD,<len>:<data>`.
+//!
+//! They can arrive at any time, even in the middle of the response to a command, and aren't
+//! terminated by a line break, so they have to be taken out of the stream before it is split
+//! into lines.
+
+/// Where the payloads announced by one kind of header go.
+pub(crate) trait PayloadSink {
+    /// The byte that ends the header, which is included in the header passed to `start`.
+    const HEADER_END: u8;
+
+    /// Prepares to receive the payload announced by `header`, if it is a complete header of this
+    /// kind, returning its length.
+    fn start(&mut self, header: &[u8]) -> Option<usize>;
+
+    /// Takes the next byte of the payload.
+    fn push(&mut self, byte: u8);
+
+    /// Called once all of the payload has been pushed.
+    fn finish(&mut self);
+}
+
+/// Passes the payloads announced by the headers that `S` recognizes to it, keeping track of how
+/// much of the current one is left.
+#[derive(Debug)]
+pub(crate) struct PayloadReceiver<S> {
+    pub(crate) sink: S,
+    remaining: usize,
+}
+
+impl<S> PayloadReceiver<S>
+where
+    S: PayloadSink,
+{
+    pub(crate) fn new(sink: S) -> Self {
+        PayloadReceiver { sink, remaining: 0 }
+    }
+
+    /// Starts receiving a payload if the partial `line` is a complete header that announces one.
+    pub(crate) fn start(&mut self, line: &[u8]) -> bool {
+        if line.last() != Some(&S::HEADER_END) {
+            return false;
+        }
+        match self.sink.start(line) {
+            Some(len) => {
+                self.remaining = len;
+                if len == 0 {
+                    self.sink.finish();
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Passes `byte` on if it is part of a payload that is being received.
+    pub(crate) fn push(&mut self, byte: u8) -> bool {
+        if self.remaining == 0 {
+            return false;
+        }
+        self.sink.push(byte);
+        self.remaining -= 1;
+        if self.remaining == 0 {
+            self.sink.finish();
+        }
+        true
+    }
+}
@@ -0,0 +1,360 @@
+use crate::tcpip::{TlsAuthMode, TlsClientConfig};
+use crate::{CommandSet, Error, Esp32At};
+
+/// The size of the chunks in which PKI material is written to partitions and read back.
+pub const PKI_CHUNK_LEN: usize = 256;
+
+/// The most bytes of PKI material that can be stored in a slot, since `AT+SYSMFG` writes all of it
+/// at once.
+pub const MAX_PKI_LEN: usize = 8192;
+
+/// A kind of PKI material stored in the module's manufacturing partition.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PkiKind {
+    ClientCertificate,
+    ClientKey,
+    ClientCa,
+    /// Presented by the SSL server; only slot 0 is used.
+    ServerCertificate,
+    ServerKey,
+    /// Used by the SSL server to verify client certificates; only slot 0 is used.
+    ServerCa,
+}
+
+/// The PKI material used for TLS connections.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PkiBundle<'a> {
+    pub client_certificate: &'a [u8],
+    pub client_key: &'a [u8],
+    pub ca_certificate: &'a [u8],
+}
+
+impl PkiKind {
+    fn namespace(self) -> &'static str {
+        match self {
+            PkiKind::ClientCertificate => "client_cert",
+            PkiKind::ClientKey => "client_key",
+            PkiKind::ClientCa => "client_ca",
+            PkiKind::ServerCertificate => "server_cert",
+            PkiKind::ServerKey => "server_key",
+            PkiKind::ServerCa => "server_ca",
+        }
+    }
+}
+
+impl<RX, TX> Esp32At<RX, TX>
+where
+    RX: embedded_hal::serial::Read<u8>,
+    TX: embedded_hal::serial::Write<u8>,
+{
+    /// Stores PEM or DER encoded PKI material in the given slot, which is at most `MAX_PKI_LEN`
+    /// bytes long.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// use esp_at::pki::PkiKind;
+    /// let script = [
+    ///     Exchange::new(b"AT+SYSMFG=2,\"client_ca\",\"client_ca.1\",8,4\r\n", b"\r\nOK\r\n\r\n>"),
+    ///     Exchange::new(b"cert", b"\r\nOK\r\n"),
+    /// ];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Tls.into());
+    ///
+    /// nb::block!(esp32_at.write_pki(PkiKind::ClientCa, 1, b"cert")).unwrap();
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn write_pki(
+        &mut self,
+        kind: PkiKind,
+        index: u8,
+        data: &[u8],
+    ) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::Tls)?;
+        if data.len() > MAX_PKI_LEN {
+            return Err(nb::Error::Other(Error::InvalidArgument));
+        }
+        let namespace = kind.namespace();
+        write_command!(
+            self,
+            "AT+SYSMFG=2,\"{}\",\"{}.{}\",8,{}",
+            namespace,
+            namespace,
+            index,
+            data.len()
+        )?;
+        self.expect_prompt()?;
+        self.write_all(data)?;
+        self.expect_ok_response()
+    }
+
+    /// Stores PKI material like `write_pki`, then reads it back to make sure that it has been
+    /// stored intact.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// use esp_at::pki::PkiKind;
+    /// let script = [
+    ///     Exchange::new(b"AT+SYSMFG=2,\"client_ca\",\"client_ca.1\",8,4\r\n", b"\r\nOK\r\n\r\n>"),
+    ///     Exchange::new(b"cert", b"\r\nOK\r\n"),
+    ///     Exchange::new(
+    ///         b"AT+SYSMFG=1,\"client_ca\",\"client_ca.1\",0,4\r\n",
+    ///         b"+SYSMFG:\"client_ca\",\"client_ca.1\",8,4,cert\r\nOK\r\n",
+    ///     ),
+    /// ];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Tls.into());
+    ///
+    /// esp32_at.provision_pki(PkiKind::ClientCa, 1, b"cert").unwrap();
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn provision_pki(
+        &mut self,
+        kind: PkiKind,
+        index: u8,
+        data: &[u8],
+    ) -> Result<(), Error<RX::Error, TX::Error>> {
+        nb::block!(self.write_pki(kind, index, data))?;
+        self.verify_pki(kind, index, data)
+    }
+
+    /// Reads back the PKI material in the given slot in chunks of `PKI_CHUNK_LEN` bytes, failing
+    /// with `Error::VerificationFailed` if it differs from `data`.
+    pub fn verify_pki(
+        &mut self,
+        kind: PkiKind,
+        index: u8,
+        data: &[u8],
+    ) -> Result<(), Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::Tls)?;
+        let namespace = kind.namespace();
+        // The namespace, key, type and length precede the data
+        self.verify_chunks(data, "SYSMFG", 4, 3, |this, offset, len| {
+            write_command!(
+                this,
+                "AT+SYSMFG=1,\"{}\",\"{}.{}\",{},{}",
+                namespace,
+                namespace,
+                index,
+                offset,
+                len
+            )
+        })
+    }
+
+    /// Replaces the contents of the PKI partition of `kind` with `data`, for firmware that
+    /// predates `AT+SYSMFG` and stores PKI material in dedicated partitions; `data` is the
+    /// partition image generated by the firmware's PKI tooling.
+    ///
+    /// The partition is written and then read back in chunks of `PKI_CHUNK_LEN` bytes, failing
+    /// with `Error::VerificationFailed` if it doesn't match.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// use esp_at::pki::PkiKind;
+    /// let script = [
+    ///     Exchange::new(b"AT+SYSFLASH=0,\"client_cert\"\r\n", b"\r\nOK\r\n"),
+    ///     Exchange::new(b"AT+SYSFLASH=1,\"client_cert\",0,5\r\n", b"\r\nOK\r\n\r\n>"),
+    ///     Exchange::new(b"image", b"\r\nOK\r\n"),
+    ///     Exchange::new(
+    ///         b"AT+SYSFLASH=2,\"client_cert\",0,5\r\n",
+    ///         b"+SYSFLASH:5,image\r\nOK\r\n",
+    ///     ),
+    /// ];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Tls.into());
+    ///
+    /// esp32_at
+    ///     .write_pki_partition(PkiKind::ClientCertificate, b"image")
+    ///     .unwrap();
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn write_pki_partition(
+        &mut self,
+        kind: PkiKind,
+        data: &[u8],
+    ) -> Result<(), Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::Tls)?;
+        let partition = kind.namespace();
+        nb::block!(write_command!(self, "AT+SYSFLASH=0,\"{}\"", partition))?;
+        nb::block!(self.expect_ok_response())?;
+
+        for (i, chunk) in data.chunks(PKI_CHUNK_LEN).enumerate() {
+            nb::block!(write_command!(
+                self,
+                "AT+SYSFLASH=1,\"{}\",{},{}",
+                partition,
+                i * PKI_CHUNK_LEN,
+                chunk.len()
+            ))?;
+            nb::block!(self.expect_prompt())?;
+            nb::block!(self.write_all(chunk))?;
+            nb::block!(self.expect_ok_response())?;
+        }
+
+        // The length precedes the data
+        self.verify_chunks(data, "SYSFLASH", 1, 0, |this, offset, len| {
+            write_command!(this, "AT+SYSFLASH=2,\"{}\",{},{}", partition, offset, len)
+        })
+    }
+
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// use esp_at::pki::PkiKind;
+    /// let script = [Exchange::new(b"AT+SYSMFG=0,\"client_ca\",\"client_ca.1\"\r\n", b"\r\nOK\r\n")];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Tls.into());
+    ///
+    /// nb::block!(esp32_at.erase_pki(PkiKind::ClientCa, 1)).unwrap();
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn erase_pki(
+        &mut self,
+        kind: PkiKind,
+        index: u8,
+    ) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::Tls)?;
+        let namespace = kind.namespace();
+        write_command!(
+            self,
+            "AT+SYSMFG=0,\"{}\",\"{}.{}\"",
+            namespace,
+            namespace,
+            index
+        )?;
+        self.expect_ok_response()
+    }
+
+    /// Rotates the TLS credentials used for mutually authenticated connections.
+    ///
+    /// The new `bundle` is staged in the `staging_index` slots with `provision_pki`, which reads
+    /// it back, and TLS connections are switched over to them.  `verify` is then called with the new slot index, and should establish a
+    /// test connection (and reconfigure any MQTT or HTTP clients that use the credentials).  If it
+    /// fails, TLS connections are switched back to the `current_index` slots.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// use esp_at::pki::PkiBundle;
+    /// let script = [
+    ///     Exchange::new(b"AT+SYSMFG=2,\"client_cert\",\"client_cert.1\",8,4\r\n", b"\r\nOK\r\n\r\n>"),
+    ///     Exchange::new(b"cert", b"\r\nOK\r\n"),
+    ///     Exchange::new(
+    ///         b"AT+SYSMFG=1,\"client_cert\",\"client_cert.1\",0,4\r\n",
+    ///         b"+SYSMFG:\"client_cert\",\"client_cert.1\",8,4,cert\r\nOK\r\n",
+    ///     ),
+    ///     Exchange::new(b"AT+SYSMFG=2,\"client_key\",\"client_key.1\",8,3\r\n", b"\r\nOK\r\n\r\n>"),
+    ///     Exchange::new(b"key", b"\r\nOK\r\n"),
+    ///     Exchange::new(
+    ///         b"AT+SYSMFG=1,\"client_key\",\"client_key.1\",0,3\r\n",
+    ///         b"+SYSMFG:\"client_key\",\"client_key.1\",8,3,key\r\nOK\r\n",
+    ///     ),
+    ///     Exchange::new(b"AT+SYSMFG=2,\"client_ca\",\"client_ca.1\",8,2\r\n", b"\r\nOK\r\n\r\n>"),
+    ///     Exchange::new(b"ca", b"\r\nOK\r\n"),
+    ///     Exchange::new(
+    ///         b"AT+SYSMFG=1,\"client_ca\",\"client_ca.1\",0,2\r\n",
+    ///         b"+SYSMFG:\"client_ca\",\"client_ca.1\",8,2,ca\r\nOK\r\n",
+    ///     ),
+    ///     Exchange::new(b"AT+CIPSSLCCONF=3,1,1\r\n", b"\r\nOK\r\n"),
+    ///     Exchange::new(b"AT+CIPSTART=\"SSL\",\"example.com\",443,0\r\n", b"CONNECT\r\n\r\nOK\r\n"),
+    /// ];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::TcpIp | CommandSet::Tls);
+    ///
+    /// let bundle = PkiBundle {
+    ///     client_certificate: b"cert",
+    ///     client_key: b"key",
+    ///     ca_certificate: b"ca",
+    /// };
+    /// esp32_at
+    ///     .rotate_certificates(&bundle, 0, 1, |esp32_at, _index| {
+    ///         nb::block!(esp32_at.connect_ssl("example.com", 443, 0)).map(|_| ())
+    ///     })
+    ///     .unwrap();
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn rotate_certificates<F, E>(
+        &mut self,
+        bundle: &PkiBundle,
+        current_index: u8,
+        staging_index: u8,
+        verify: F,
+    ) -> Result<(), E>
+    where
+        F: FnOnce(&mut Self, u8) -> Result<(), E>,
+        E: From<Error<RX::Error, TX::Error>>,
+    {
+        self.require_command_set(CommandSet::Tls)?;
+        self.provision_pki(
+            PkiKind::ClientCertificate,
+            staging_index,
+            bundle.client_certificate,
+        )?;
+        self.provision_pki(PkiKind::ClientKey, staging_index, bundle.client_key)?;
+        self.provision_pki(PkiKind::ClientCa, staging_index, bundle.ca_certificate)?;
+
+        nb::block!(self.set_tls_client_config(None, &mutual_auth(staging_index)))?;
+        if let Err(error) = verify(self, staging_index) {
+            nb::block!(self.set_tls_client_config(None, &mutual_auth(current_index)))?;
+            return Err(error);
+        }
+        Ok(())
+    }
+
+    /// Reads back `data` in chunks with the command written by `read`, which is passed the offset
+    /// and the length of a chunk; see `read_data_response` for the other arguments.
+    fn verify_chunks<F>(
+        &mut self,
+        data: &[u8],
+        name: &str,
+        header_fields: usize,
+        len_field: usize,
+        read: F,
+    ) -> Result<(), Error<RX::Error, TX::Error>>
+    where
+        F: Fn(&mut Self, usize, usize) -> nb::Result<(), Error<RX::Error, TX::Error>>,
+    {
+        let mut buf = [0; PKI_CHUNK_LEN];
+        for (i, chunk) in data.chunks(PKI_CHUNK_LEN).enumerate() {
+            nb::block!(read(self, i * PKI_CHUNK_LEN, chunk.len()))?;
+            let header = nb::block!(self.read_data_response(
+                name,
+                header_fields,
+                len_field,
+                &mut buf[..chunk.len()]
+            ))?;
+            match header {
+                Some(header) if buf[..header.len] == *chunk => {}
+                _ => return Err(Error::VerificationFailed),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Authenticates both ends of TLS connections with the certificates in the slot `index`.
+fn mutual_auth(index: u8) -> TlsClientConfig {
+    TlsClientConfig {
+        auth_mode: TlsAuthMode::Mutual,
+        pki_index: index,
+        ca_index: index,
+    }
+}
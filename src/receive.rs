@@ -0,0 +1,475 @@
+//! Reading the data received on connections.
+
+use core::net::IpAddr;
+
+use crate::payload::PayloadSink;
+use crate::{link, parser, CommandSet, Error, Esp32At};
+
+/// How many bytes received in active receive mode are buffered per link until they are `read`.
+pub const RECEIVE_BUFFER_LEN: usize = 512;
+
+/// The IP address and port of the remote end that sent some data.
+pub type Remote = (IpAddr, u16);
+
+/// How much data was read, and who sent it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Received {
+    pub len: usize,
+    /// Only reported if enabled with `Esp32At::set_remote_info`.
+    pub remote: Option<Remote>,
+}
+
+type ReceiveQueue = heapless::spsc::Queue<u8, heapless::consts::U512>;
+type SegmentQueue = heapless::spsc::Queue<Segment, heapless::consts::U8>;
+
+/// The data pushed by the module in active receive mode, as `+IPD` messages, which hasn't been
+/// read yet.
+///
+/// In single connection mode, data is buffered as if it was received on link 0.
+#[derive(Debug)]
+pub(crate) struct ReceiveBuffers {
+    queues: [ReceiveQueue; link::MAX_LINKS as usize],
+    /// The buffered data of each link, split where the remote end changes.
+    segments: [SegmentQueue; link::MAX_LINKS as usize],
+    /// The payload currently being received.
+    incoming: Option<Incoming>,
+    /// The links on which data has been dropped because their buffer was full.
+    overflowed: u8,
+    /// The links that have been closed, so that no more data will be buffered for them.
+    closed: u8,
+    /// Whether the remote end of received data is reported, with `AT+CIPDINFO=1`.
+    pub(crate) remote_info: bool,
+}
+
+/// Consecutive buffered bytes received from the same remote end.
+#[derive(Debug)]
+struct Segment {
+    remote: Option<Remote>,
+    len: usize,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Incoming {
+    index: usize,
+    /// Whether the payload is dropped, because there's no room to keep track of its remote end.
+    discard: bool,
+}
+
+impl<RX, TX> Esp32At<RX, TX>
+where
+    RX: embedded_hal::serial::Read<u8>,
+    TX: embedded_hal::serial::Write<u8>,
+{
+    /// Reads up to `buf.len()` bytes received in active receive mode, returning how many were
+    /// read; `link_id` is only given in multiple connection mode.
+    ///
+    /// Data received while waiting for the responses to other commands is buffered, up to
+    /// `RECEIVE_BUFFER_LEN` bytes per link.  If more was received, `Error::BufferOverflow` is
+    /// returned once, and the data that didn't fit is lost.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// let script = [Exchange::unsolicited(b"+IPD,7:hello\r\n")];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::TcpIp.into());
+    ///
+    /// let mut buf = [0; 16];
+    /// let len = nb::block!(esp32_at.read(None, &mut buf)).unwrap();
+    /// assert_eq!(b"hello\r\n", &buf[..len]);
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn read(
+        &mut self,
+        link_id: Option<u8>,
+        buf: &mut [u8],
+    ) -> nb::Result<usize, Error<RX::Error, TX::Error>> {
+        Ok(self.read_from(link_id, buf)?.len)
+    }
+
+    /// Like `read`, but also returns the remote end that sent the data if `set_remote_info` has
+    /// been enabled, which e.g. a UDP server needs to reply to it.
+    ///
+    /// The data returned by one call is always from the same remote end.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// let script = [
+    ///     Exchange::new(b"AT+CIPDINFO=1\r\n", b"\r\nOK\r\n"),
+    ///     Exchange::unsolicited(b"+IPD,4,\"192.168.1.5\",5000:ping+IPD,4,\"fe80::1\",5001:pong"),
+    /// ];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::TcpIp.into());
+    ///
+    /// nb::block!(esp32_at.set_remote_info(true)).unwrap();
+    /// let mut buf = [0; 16];
+    /// let received = nb::block!(esp32_at.read_from(None, &mut buf)).unwrap();
+    /// assert_eq!(b"ping", &buf[..received.len]);
+    /// assert_eq!(Some(([192, 168, 1, 5].into(), 5000)), received.remote);
+    /// let received = nb::block!(esp32_at.read_from(None, &mut buf)).unwrap();
+    /// assert_eq!(b"pong", &buf[..received.len]);
+    /// assert_eq!(Some(("fe80::1".parse().unwrap(), 5001)), received.remote);
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn read_from(
+        &mut self,
+        link_id: Option<u8>,
+        buf: &mut [u8],
+    ) -> nb::Result<Received, Error<RX::Error, TX::Error>> {
+        let index = match (&self.links, link_id) {
+            (Some(_), Some(link_id)) if link_id < link::MAX_LINKS => link_id as usize,
+            (None, None) => 0,
+            _ => return Err(nb::Error::Other(Error::InvalidArgument)),
+        };
+
+        self.drain_lines()?;
+
+        match self.received.sink.read(index, buf) {
+            Ok(Received { len: 0, .. }) => Err(nb::Error::WouldBlock),
+            Ok(received) => Ok(received),
+            Err(()) => Err(nb::Error::Other(Error::BufferOverflow)),
+        }
+    }
+
+    /// Selects whether the remote end of received data is reported, for `read_from` and
+    /// `recv_from`, and the remote end of inbound connections in `Event::LinkConnected`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// let script = [
+    ///     Exchange::new(b"AT+CIPDINFO=1\r\n", b"\r\nOK\r\n"),
+    ///     Exchange::new(b"AT+CIPDINFO?\r\n", b"+CIPDINFO:TRUE\r\n\r\nOK\r\n"),
+    /// ];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::TcpIp.into());
+    ///
+    /// nb::block!(esp32_at.set_remote_info(true)).unwrap();
+    /// assert!(nb::block!(esp32_at.get_remote_info()).unwrap());
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn set_remote_info(
+        &mut self,
+        enabled: bool,
+    ) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::TcpIp)?;
+        write_command!(self, "AT+CIPDINFO={}", enabled as u8)?;
+        self.expect_ok_response()?;
+        self.received.sink.remote_info = enabled;
+        Ok(())
+    }
+
+    pub fn get_remote_info(&mut self) -> nb::Result<bool, Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::TcpIp)?;
+        write_command!(self, "AT+CIPDINFO?")?;
+        let enabled = match self.read_query_response("CIPDINFO")?.as_str() {
+            "0" | "FALSE" => false,
+            "1" | "TRUE" => true,
+            _ => return Err(nb::Error::Other(Error::UnexpectedResponse)),
+        };
+        self.received.sink.remote_info = enabled;
+        Ok(enabled)
+    }
+
+    /// Reads up to `buf.len()` bytes buffered by the module in passive receive mode, returning how
+    /// many were read.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// let script = [Exchange::new(
+    ///     b"AT+CIPRECVDATA=16\r\n",
+    ///     b"+CIPRECVDATA:5,hello\r\nOK\r\n",
+    /// )];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::TcpIp.into());
+    ///
+    /// let mut buf = [0; 16];
+    /// let len = nb::block!(esp32_at.recv(None, &mut buf)).unwrap();
+    /// assert_eq!(b"hello", &buf[..len]);
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn recv(
+        &mut self,
+        link_id: Option<u8>,
+        buf: &mut [u8],
+    ) -> nb::Result<usize, Error<RX::Error, TX::Error>> {
+        Ok(self.recv_from(link_id, buf)?.len)
+    }
+
+    /// Like `recv`, but also returns the remote end that sent the data if `set_remote_info` has
+    /// been enabled.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// let script = [
+    ///     Exchange::new(b"AT+CIPDINFO=1\r\n", b"\r\nOK\r\n"),
+    ///     Exchange::new(
+    ///         b"AT+CIPRECVDATA=16\r\n",
+    ///         b"+CIPRECVDATA:4,\"192.168.1.5\",5000,ping\r\nOK\r\n",
+    ///     ),
+    /// ];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::TcpIp.into());
+    ///
+    /// nb::block!(esp32_at.set_remote_info(true)).unwrap();
+    /// let mut buf = [0; 16];
+    /// let received = nb::block!(esp32_at.recv_from(None, &mut buf)).unwrap();
+    /// assert_eq!(b"ping", &buf[..received.len]);
+    /// assert_eq!(Some(([192, 168, 1, 5].into(), 5000)), received.remote);
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn recv_from(
+        &mut self,
+        link_id: Option<u8>,
+        buf: &mut [u8],
+    ) -> nb::Result<Received, Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::TcpIp)?;
+        let link = self.link_prefix(link_id)?;
+        if buf.is_empty() {
+            return Err(nb::Error::Other(Error::InvalidArgument));
+        }
+        write_command!(self, "AT+CIPRECVDATA={}{}", link, buf.len())?;
+        self.read_received_data(buf)
+    }
+
+    /// Returns how many bytes the module has buffered in passive receive mode, indexed by link id
+    /// in multiple connection mode; links that aren't in use have nothing buffered.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// let script = [Exchange::new(
+    ///     b"AT+CIPRECVLEN?\r\n",
+    ///     b"+CIPRECVLEN:0,,,,512\r\n\r\nOK\r\n",
+    /// )];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::TcpIp.into());
+    ///
+    /// let lengths = nb::block!(esp32_at.get_received_lengths()).unwrap();
+    /// assert_eq!(&[0, 0, 0, 0, 512], &lengths[..]);
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn get_received_lengths(
+        &mut self,
+    ) -> nb::Result<heapless::Vec<usize, heapless::consts::U5>, Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::TcpIp)?;
+        write_command!(self, "AT+CIPRECVLEN?")?;
+        let arguments = self.read_query_response("CIPRECVLEN")?;
+        let mut lengths = heapless::Vec::new();
+        for field in parser::fields(&arguments) {
+            let len = match field {
+                "" => 0,
+                len => len.parse().map_err(|_| Error::UnexpectedResponse)?,
+            };
+            lengths.push(len).map_err(|_| Error::UnexpectedResponse)?;
+        }
+        Ok(lengths)
+    }
+
+    fn read_received_data(
+        &mut self,
+        buf: &mut [u8],
+    ) -> nb::Result<Received, Error<RX::Error, TX::Error>> {
+        // The length is followed by the remote end, if it is reported
+        let header_fields = if self.received.sink.remote_info { 3 } else { 1 };
+        match self.read_data_response("CIPRECVDATA", header_fields, 0, buf)? {
+            Some(header) => {
+                let (_, remote) =
+                    parse_data_header(&header.arguments).ok_or(Error::UnexpectedResponse)?;
+                Ok(Received {
+                    len: header.len,
+                    remote,
+                })
+            }
+            // Nothing is buffered
+            None => Ok(Received {
+                len: 0,
+                remote: None,
+            }),
+        }
+    }
+}
+
+impl ReceiveBuffers {
+    pub(crate) fn new() -> Self {
+        ReceiveBuffers {
+            queues: [
+                ReceiveQueue::new(),
+                ReceiveQueue::new(),
+                ReceiveQueue::new(),
+                ReceiveQueue::new(),
+                ReceiveQueue::new(),
+            ],
+            segments: [
+                SegmentQueue::new(),
+                SegmentQueue::new(),
+                SegmentQueue::new(),
+                SegmentQueue::new(),
+                SegmentQueue::new(),
+            ],
+            incoming: None,
+            overflowed: 0,
+            closed: 0,
+            remote_info: false,
+        }
+    }
+
+    /// Records that a link has been connected or closed.
+    pub(crate) fn set_closed(&mut self, index: usize, closed: bool) {
+        if closed {
+            self.closed |= 1 << index;
+        } else {
+            self.closed &= !(1 << index);
+        }
+    }
+
+    pub(crate) fn is_closed(&self, index: usize) -> bool {
+        self.closed & 1 << index != 0
+    }
+
+    /// Whether a link has been closed, and all of its buffered data has been read.
+    #[cfg(feature = "embedded-io")]
+    pub(crate) fn is_finished(&self, index: usize) -> bool {
+        self.is_closed(index) && self.segments[index].iter().all(|segment| segment.len == 0)
+    }
+
+    /// Takes up to `buf.len()` buffered bytes of a link that were received from the same remote
+    /// end, failing once if some have been dropped.
+    fn read(&mut self, index: usize, buf: &mut [u8]) -> Result<Received, ()> {
+        if self.overflowed & 1 << index != 0 {
+            self.overflowed &= !(1 << index);
+            return Err(());
+        }
+
+        let segments = &mut self.segments[index];
+        // Skip the segments whose data has all been dropped
+        while segments.len() > 1 && segments.peek().map(|segment| segment.len) == Some(0) {
+            segments.dequeue();
+        }
+        let segment = match segments.iter_mut().next() {
+            Some(segment) => segment,
+            None => {
+                return Ok(Received {
+                    len: 0,
+                    remote: None,
+                })
+            }
+        };
+
+        let len = buf.len().min(segment.len);
+        for byte in buf[..len].iter_mut() {
+            *byte = self.queues[index].dequeue().ok_or(())?;
+        }
+        segment.len -= len;
+        let remote = segment.remote;
+        if segment.len == 0 && segments.len() > 1 {
+            segments.dequeue();
+        }
+        Ok(Received { len, remote })
+    }
+}
+
+impl PayloadSink for ReceiveBuffers {
+    const HEADER_END: u8 = b':';
+
+    /// Starts receiving the payload announced by `header`, if it is a complete `+IPD` header like
+    /// `+IPD,[<link_id>,]<len>[,"<remote_ip>",<remote_port>]:`.
+    fn start(&mut self, header: &[u8]) -> Option<usize> {
+        let arguments = header
+            .strip_prefix(b"+IPD,")
+            .and_then(|header| header.strip_suffix(b":"))
+            // The colon might be part of an IPv6 address
+            .filter(|arguments| arguments.iter().filter(|&&byte| byte == b'"').count() % 2 == 0)
+            .and_then(|arguments| core::str::from_utf8(arguments).ok())?;
+        let (index, len, remote) = parse_ipd_header(arguments)?;
+        if len == 0 {
+            return Some(0);
+        }
+
+        let segments = &mut self.segments[index];
+        let discard = match segments.iter_mut().last() {
+            Some(segment) if segment.remote == remote => false,
+            _ => segments.enqueue(Segment { remote, len: 0 }).is_err(),
+        };
+        if discard {
+            self.overflowed |= 1 << index;
+        }
+        self.incoming = Some(Incoming { index, discard });
+        Some(len)
+    }
+
+    fn push(&mut self, byte: u8) {
+        let incoming = match self.incoming {
+            Some(incoming) => incoming,
+            None => return,
+        };
+        let index = incoming.index;
+        if !incoming.discard {
+            if self.queues[index].enqueue(byte).is_ok() {
+                if let Some(segment) = self.segments[index].iter_mut().last() {
+                    segment.len += 1;
+                }
+            } else {
+                self.overflowed |= 1 << index;
+            }
+        }
+    }
+
+    fn finish(&mut self) {
+        self.incoming = None;
+    }
+}
+
+/// Parses the arguments of a `+IPD` header into the buffer index, length and remote end.
+fn parse_ipd_header(arguments: &str) -> Option<(usize, usize, Option<Remote>)> {
+    let mut fields = parser::fields(arguments);
+    let first = fields.next()?.parse().ok()?;
+    let (index, len, remote) = match (fields.next(), fields.next(), fields.next()) {
+        (None, _, _) => (0, first, None),
+        (Some(len), None, _) => (first, len.parse().ok()?, None),
+        (Some(ip), Some(port), None) => (0, first, Some(parse_remote(ip, port)?)),
+        (Some(len), Some(ip), Some(port)) => {
+            (first, len.parse().ok()?, Some(parse_remote(ip, port)?))
+        }
+    };
+    if index >= link::MAX_LINKS as usize || fields.next().is_some() {
+        return None;
+    }
+    Some((index, len, remote))
+}
+
+/// Parses the arguments of a `+CIPRECVDATA` header into the length and remote end.
+fn parse_data_header(arguments: &str) -> Option<(usize, Option<Remote>)> {
+    let mut fields = parser::fields(arguments);
+    let len = fields.next()?.parse().ok()?;
+    let remote = match fields.next() {
+        Some(ip) => Some(parse_remote(ip, fields.next()?)?),
+        None => None,
+    };
+    Some((len, remote))
+}
+
+fn parse_remote(ip: &str, port: &str) -> Option<Remote> {
+    Some((parser::unquote(ip).parse().ok()?, port.parse().ok()?))
+}
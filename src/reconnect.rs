@@ -0,0 +1,129 @@
+use crate::event::Event;
+use crate::wifi::JoinFailure;
+use crate::{Error, Esp32At};
+
+/// Re-joins an access point with exponential backoff whenever the connection to it is lost.
+///
+/// Events received with `Esp32At::poll_event` should be passed to `handle_event`, and `poll`
+/// should be called regularly to make the scheduled attempts.  Times are in milliseconds from an
+/// arbitrary, wrapping monotonic clock.
+///
+/// The first attempt is made `min_backoff_ms` after the connection was lost, and the delay doubles
+/// after every failed attempt, up to `max_backoff_ms`.
+#[derive(Debug)]
+pub struct ReconnectSupervisor<'a> {
+    ssid: &'a str,
+    password: &'a str,
+    min_backoff_ms: u32,
+    max_backoff_ms: u32,
+    backoff_ms: u32,
+    attempts: u32,
+    next_attempt_at: Option<u32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReconnectEvent {
+    /// The connection was lost and a reconnection attempt has been scheduled.
+    Disconnected,
+    /// A reconnection attempt failed and another one has been scheduled in `retry_in_ms`.
+    AttemptFailed {
+        attempt: u32,
+        reason: JoinFailure,
+        retry_in_ms: u32,
+    },
+    /// The connection has been restored.
+    Reconnected { attempts: u32 },
+}
+
+impl<'a> ReconnectSupervisor<'a> {
+    pub fn new(ssid: &'a str, password: &'a str) -> Self {
+        Self::with_backoff(ssid, password, 1_000, 60_000)
+    }
+
+    pub fn with_backoff(
+        ssid: &'a str,
+        password: &'a str,
+        min_backoff_ms: u32,
+        max_backoff_ms: u32,
+    ) -> Self {
+        Self {
+            ssid,
+            password,
+            min_backoff_ms,
+            max_backoff_ms,
+            backoff_ms: min_backoff_ms,
+            attempts: 0,
+            next_attempt_at: None,
+        }
+    }
+
+    pub fn is_reconnecting(&self) -> bool {
+        self.next_attempt_at.is_some()
+    }
+
+    pub fn handle_event(&mut self, event: &Event, now_ms: u32) -> Option<ReconnectEvent> {
+        match event {
+            Event::WifiDisconnected if !self.is_reconnecting() => {
+                let delay_ms = self.back_off();
+                self.next_attempt_at = Some(now_ms.wrapping_add(delay_ms));
+                Some(ReconnectEvent::Disconnected)
+            }
+            // The module may well have reconnected on its own.
+            Event::WifiGotIp if self.is_reconnecting() => Some(self.reconnected()),
+            _ => None,
+        }
+    }
+
+    /// Makes a reconnection attempt if one is due, blocking until it has completed or `timeout`
+    /// on `timer` has passed, see `Esp32At::join_access_point_within`.
+    pub fn poll<RX, TX, T>(
+        &mut self,
+        esp: &mut Esp32At<RX, TX>,
+        timer: &mut T,
+        timeout: T::Time,
+        now_ms: u32,
+    ) -> Result<Option<ReconnectEvent>, Error<RX::Error, TX::Error>>
+    where
+        RX: embedded_hal::serial::Read<u8>,
+        TX: embedded_hal::serial::Write<u8>,
+        T: embedded_hal::timer::CountDown,
+        T::Time: Clone,
+    {
+        match self.next_attempt_at {
+            Some(due) if now_ms.wrapping_sub(due) as i32 >= 0 => {}
+            _ => return Ok(None),
+        }
+
+        self.attempts += 1;
+        let reason = match esp.join_access_point_within(self.ssid, self.password, timer, timeout) {
+            Ok(()) => return Ok(Some(self.reconnected())),
+            Err(Error::JoinFailed { reason }) => reason,
+            Err(Error::Timeout) => JoinFailure::Timeout,
+            Err(Error::CommandFailed) => JoinFailure::Unknown,
+            Err(error) => return Err(error),
+        };
+
+        let retry_in_ms = self.back_off();
+        self.next_attempt_at = Some(now_ms.wrapping_add(retry_in_ms));
+        Ok(Some(ReconnectEvent::AttemptFailed {
+            attempt: self.attempts,
+            reason,
+            retry_in_ms,
+        }))
+    }
+
+    /// Returns the delay until the next attempt, doubling the one after it.
+    fn back_off(&mut self) -> u32 {
+        let delay_ms = self.backoff_ms;
+        self.backoff_ms = self.backoff_ms.saturating_mul(2).min(self.max_backoff_ms);
+        delay_ms
+    }
+
+    fn reconnected(&mut self) -> ReconnectEvent {
+        let attempts = self.attempts;
+        self.attempts = 0;
+        self.backoff_ms = self.min_backoff_ms;
+        self.next_attempt_at = None;
+        ReconnectEvent::Reconnected { attempts }
+    }
+}
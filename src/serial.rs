@@ -1,3 +1,6 @@
+// The variant names are kept as they are in serial-core for easier migration.
+#![allow(clippy::enum_variant_names)]
+
 // TODO: use serial-core directly if it becomes available as a no_std crate
 // The types below are taken from the serial-core crate, which is:
 
@@ -71,7 +74,7 @@ pub enum BaudRate {
     /// desired baud rate.
     ///
     /// ```no_run
-    /// serial_core::BaudOther(4_000_000); // 4,000,000 baud
+    /// esp_at::serial::BaudRate::BaudOther(4_000_000); // 4,000,000 baud
     /// ```
     ///
     /// Non-standard baud rates may not be supported on all systems.
@@ -87,7 +90,7 @@ impl BaudRate {
     /// ## Example
     ///
     /// ```
-    /// # use serial_core::BaudRate;
+    /// # use esp_at::serial::BaudRate;
     /// assert_eq!(BaudRate::Baud9600, BaudRate::from_speed(9600));
     /// assert_eq!(BaudRate::Baud115200, BaudRate::from_speed(115200));
     /// assert_eq!(BaudRate::BaudOther(4000000), BaudRate::from_speed(4000000));
@@ -114,7 +117,7 @@ impl BaudRate {
     /// ## Example
     ///
     /// ```
-    /// # use serial_core::BaudRate;
+    /// # use esp_at::serial::BaudRate;
     /// assert_eq!(9600, BaudRate::Baud9600.speed());
     /// assert_eq!(115200, BaudRate::Baud115200.speed());
     /// assert_eq!(4000000, BaudRate::BaudOther(4000000).speed());
@@ -0,0 +1,74 @@
+use crate::{Error, Esp32At};
+
+/// Periodically queries the signal strength of the connected access point, reporting when it
+/// drops below `weak_rssi` and when it recovers above `recovered_rssi`.
+///
+/// `poll` should be called regularly; times are in milliseconds from an arbitrary, wrapping
+/// monotonic clock.
+#[derive(Debug)]
+pub struct SignalMonitor {
+    weak_rssi: i8,
+    recovered_rssi: i8,
+    interval_ms: u32,
+    next_query_at: Option<u32>,
+    weak: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignalEvent {
+    Weak { rssi: i8 },
+    Recovered { rssi: i8 },
+}
+
+impl SignalMonitor {
+    /// Creates a monitor; `recovered_rssi` should be somewhat higher than `weak_rssi` so that a
+    /// signal hovering around the threshold doesn't cause a flood of events.
+    pub fn new(weak_rssi: i8, recovered_rssi: i8, interval_ms: u32) -> Self {
+        Self {
+            weak_rssi,
+            recovered_rssi,
+            interval_ms,
+            next_query_at: None,
+            weak: false,
+        }
+    }
+
+    pub fn is_weak(&self) -> bool {
+        self.weak
+    }
+
+    /// Queries the signal strength if the interval has elapsed since the last query.
+    ///
+    /// Not being connected to an access point is not reported as a weak signal.
+    pub fn poll<RX, TX>(
+        &mut self,
+        esp: &mut Esp32At<RX, TX>,
+        now_ms: u32,
+    ) -> Result<Option<SignalEvent>, Error<RX::Error, TX::Error>>
+    where
+        RX: embedded_hal::serial::Read<u8>,
+        TX: embedded_hal::serial::Write<u8>,
+    {
+        if let Some(due) = self.next_query_at {
+            if (now_ms.wrapping_sub(due) as i32) < 0 {
+                return Ok(None);
+            }
+        }
+        self.next_query_at = Some(now_ms.wrapping_add(self.interval_ms));
+
+        let rssi = match nb::block!(esp.get_access_point())? {
+            Some(access_point) => access_point.rssi,
+            None => return Ok(None),
+        };
+
+        if !self.weak && rssi < self.weak_rssi {
+            self.weak = true;
+            Ok(Some(SignalEvent::Weak { rssi }))
+        } else if self.weak && rssi > self.recovered_rssi {
+            self.weak = false;
+            Ok(Some(SignalEvent::Recovered { rssi }))
+        } else {
+            Ok(None)
+        }
+    }
+}
@@ -0,0 +1,244 @@
+use core::fmt;
+
+use crate::{parser, CommandSet, Error, Esp32At, QuotedList};
+
+pub const MIN_SNTP_INTERVAL_SECS: u32 = 15;
+pub const MAX_SNTP_INTERVAL_SECS: u32 = 4_294_967;
+
+/// How the module keeps its clock in sync.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SntpConfig {
+    pub enabled: bool,
+    /// The offset of local time from UTC.
+    pub utc_offset_minutes: i16,
+    pub servers: heapless::Vec<heapless::String<heapless::consts::U64>, heapless::consts::U3>,
+}
+
+/// A local date and time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DateTime {
+    pub year: u16,
+    /// 1 to 12.
+    pub month: u8,
+    /// 1 to 31.
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl<RX, TX> Esp32At<RX, TX>
+where
+    RX: embedded_hal::serial::Read<u8>,
+    TX: embedded_hal::serial::Write<u8>,
+{
+    /// Keeps the module's clock in sync using up to three SNTP servers, or the firmware's default
+    /// servers if none are given; local time is `utc_offset_minutes` ahead of UTC.
+    ///
+    /// The clock has to be set for the module to validate TLS certificates.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// let script = [
+    ///     Exchange::new(b"AT+CIPSNTPCFG=1,530,\"pool.ntp.org\"\r\n", b"\r\nOK\r\n"),
+    ///     Exchange::new(
+    ///         b"AT+CIPSNTPCFG?\r\n",
+    ///         b"+CIPSNTPCFG:1,530,\"pool.ntp.org\"\r\n\r\nOK\r\n",
+    ///     ),
+    /// ];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::TcpIp.into());
+    ///
+    /// nb::block!(esp32_at.enable_sntp(330, &["pool.ntp.org"])).unwrap();
+    /// let config = nb::block!(esp32_at.get_sntp_config()).unwrap();
+    /// assert_eq!(330, config.utc_offset_minutes);
+    /// assert_eq!("pool.ntp.org", config.servers[0]);
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn enable_sntp(
+        &mut self,
+        utc_offset_minutes: i16,
+        servers: &[&str],
+    ) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::TcpIp)?;
+        if servers.len() > 3 || !(-12 * 60..=14 * 60).contains(&utc_offset_minutes) {
+            return Err(nb::Error::Other(Error::InvalidArgument));
+        }
+        write_command!(
+            self,
+            "AT+CIPSNTPCFG=1,{}{}",
+            Timezone(utc_offset_minutes),
+            QuotedList(servers)
+        )?;
+        self.expect_ok_response()
+    }
+
+    pub fn disable_sntp(&mut self) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::TcpIp)?;
+        write_command!(self, "AT+CIPSNTPCFG=0")?;
+        self.expect_ok_response()
+    }
+
+    pub fn get_sntp_config(&mut self) -> nb::Result<SntpConfig, Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::TcpIp)?;
+        write_command!(self, "AT+CIPSNTPCFG?")?;
+        let arguments = self.read_query_response("CIPSNTPCFG")?;
+        Ok(SntpConfig::parse(&arguments).ok_or(Error::UnexpectedResponse)?)
+    }
+
+    /// Sets how often the clock is synchronized, from 15 seconds up to 4294967 seconds.
+    ///
+    /// Every synchronization is reported as an `Event::TimeUpdated`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// use esp_at::event::Event;
+    /// let script = [
+    ///     Exchange::new(b"AT+CIPSNTPINTV=3600\r\n", b"\r\nOK\r\n"),
+    ///     Exchange::new(b"AT+CIPSNTPINTV?\r\n", b"+CIPSNTPINTV:3600\r\n\r\nOK\r\n"),
+    ///     Exchange::unsolicited(b"+TIME_UPDATED\r\n"),
+    /// ];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::TcpIp.into());
+    ///
+    /// nb::block!(esp32_at.set_sntp_interval(3600)).unwrap();
+    /// assert_eq!(3600, nb::block!(esp32_at.get_sntp_interval()).unwrap());
+    /// assert_eq!(Event::TimeUpdated, nb::block!(esp32_at.poll_event()).unwrap());
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn set_sntp_interval(
+        &mut self,
+        interval_secs: u32,
+    ) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::TcpIp)?;
+        if !(MIN_SNTP_INTERVAL_SECS..=MAX_SNTP_INTERVAL_SECS).contains(&interval_secs) {
+            return Err(nb::Error::Other(Error::InvalidArgument));
+        }
+        write_command!(self, "AT+CIPSNTPINTV={}", interval_secs)?;
+        self.expect_ok_response()
+    }
+
+    pub fn get_sntp_interval(&mut self) -> nb::Result<u32, Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::TcpIp)?;
+        write_command!(self, "AT+CIPSNTPINTV?")?;
+        let interval = self.read_query_response("CIPSNTPINTV")?;
+        Ok(interval.parse().map_err(|_| Error::UnexpectedResponse)?)
+    }
+
+    /// Returns the module's local time; until it has been synchronized, this is in 1970.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// use esp_at::sntp::DateTime;
+    /// let script = [Exchange::new(
+    ///     b"AT+CIPSNTPTIME?\r\n",
+    ///     b"+CIPSNTPTIME:Tue Oct  5 17:47:56 2021\r\n\r\nOK\r\n",
+    /// )];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::TcpIp.into());
+    ///
+    /// let time = nb::block!(esp32_at.get_sntp_time()).unwrap();
+    /// assert_eq!(
+    ///     DateTime {
+    ///         year: 2021,
+    ///         month: 10,
+    ///         day: 5,
+    ///         hour: 17,
+    ///         minute: 47,
+    ///         second: 56,
+    ///     },
+    ///     time
+    /// );
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn get_sntp_time(&mut self) -> nb::Result<DateTime, Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::TcpIp)?;
+        write_command!(self, "AT+CIPSNTPTIME?")?;
+        let time = self.read_query_response("CIPSNTPTIME")?;
+        Ok(DateTime::parse(&time).ok_or(Error::UnexpectedResponse)?)
+    }
+}
+
+impl DateTime {
+    /// Parses the `asctime` format, e.g. `Tue Oct  5 17:47:56 2021`.
+    fn parse(time: &str) -> Option<Self> {
+        let mut parts = time.split_whitespace();
+        let _weekday = parts.next()?;
+        let month = parts.next()?;
+        let month = MONTHS.iter().position(|&name| name == month)? as u8 + 1;
+        let day = parts.next()?.parse().ok()?;
+        let mut clock = parts.next()?.splitn(3, ':');
+        let hour = clock.next()?.parse().ok()?;
+        let minute = clock.next()?.parse().ok()?;
+        let second = clock.next()?.parse().ok()?;
+        let year = parts.next()?.parse().ok()?;
+        Some(DateTime {
+            year,
+            month,
+            day,
+            hour,
+            minute,
+            second,
+        })
+    }
+}
+
+impl SntpConfig {
+    fn parse(arguments: &str) -> Option<Self> {
+        let mut fields = parser::fields(arguments);
+        let enabled = match fields.next()? {
+            "0" => false,
+            "1" => true,
+            _ => return None,
+        };
+        let timezone: i16 = fields.next()?.parse().ok()?;
+        let mut servers = heapless::Vec::new();
+        for field in fields {
+            servers.push(parser::string(parser::unquote(field))?).ok()?;
+        }
+        Some(SntpConfig {
+            enabled,
+            utc_offset_minutes: timezone_minutes(timezone),
+            servers,
+        })
+    }
+}
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// Converts a timezone argument, which is either in hours or in the `±HHMM` format, to minutes.
+fn timezone_minutes(timezone: i16) -> i16 {
+    if (-12..=14).contains(&timezone) {
+        timezone * 60
+    } else {
+        timezone / 100 * 60 + timezone % 100
+    }
+}
+
+/// Formats a UTC offset as a timezone argument, in whole hours if possible since older firmware
+/// doesn't support the `±HHMM` format.
+struct Timezone(i16);
+
+impl fmt::Display for Timezone {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.0 % 60 == 0 {
+            write!(f, "{}", self.0 / 60)
+        } else {
+            write!(f, "{}", self.0 / 60 * 100 + self.0 % 60)
+        }
+    }
+}
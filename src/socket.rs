@@ -0,0 +1,282 @@
+//! Connections that are closed when they go out of scope, and accepting inbound ones.
+
+use crate::tcpip::CloseOutcome;
+use crate::{CommandSet, Error, Esp32At};
+
+/// A connection on one of the module's links, which is closed when dropped so that its link id
+/// can't leak.
+///
+/// Connections that have been closed by the remote end aren't closed again.  Errors while closing
+/// the connection on drop are ignored; use `close` to see them.
+///
+/// A socket borrows the driver mutably for as long as it is open, so only one socket can be open
+/// at a time.  To use several connections at once in multiple connection mode, address them by
+/// link id with e.g. `Esp32At::send` and `Esp32At::read` instead.
+#[derive(Debug)]
+pub struct EspSocket<'a, RX, TX>
+where
+    RX: embedded_hal::serial::Read<u8>,
+    TX: embedded_hal::serial::Write<u8>,
+{
+    esp32_at: &'a mut Esp32At<RX, TX>,
+    link_id: Option<u8>,
+    closed: bool,
+}
+
+impl<RX, TX> Esp32At<RX, TX>
+where
+    RX: embedded_hal::serial::Read<u8>,
+    TX: embedded_hal::serial::Write<u8>,
+{
+    /// Takes ownership of a connection that has been established with e.g. `connect_tcp`, or
+    /// accepted by the server; `link_id` is only given in multiple connection mode.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// let script = [
+    ///     Exchange::new(b"AT+CIPMUX=1\r\n", b"\r\nOK\r\n"),
+    ///     Exchange::new(b"AT+CIPSTART=4,\"TCP\",\"example.com\",80,0\r\n", b"4,CONNECT\r\n\r\nOK\r\n"),
+    ///     Exchange::new(b"AT+CIPSEND=4,4\r\n", b"\r\nOK\r\n\r\n>"),
+    ///     Exchange::new(b"ping", b"\r\nRecv 4 bytes\r\n\r\nSEND OK\r\n"),
+    ///     Exchange::new(b"AT+CIPCLOSE=4\r\n", b"4,CLOSED\r\n\r\nOK\r\n"),
+    /// ];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::TcpIp.into());
+    ///
+    /// nb::block!(esp32_at.set_multiple_connections(true)).unwrap();
+    /// let connection = nb::block!(esp32_at.connect_tcp("example.com", 80, 0)).unwrap();
+    /// {
+    ///     let mut socket = esp32_at.socket(connection.link_id).unwrap();
+    ///     nb::block!(socket.send(b"ping")).unwrap();
+    /// }
+    /// assert!(!esp32_at.link_allocator().unwrap().is_in_use(4));
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn socket(
+        &mut self,
+        link_id: Option<u8>,
+    ) -> Result<EspSocket<'_, RX, TX>, Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::TcpIp)?;
+        self.link_prefix(link_id)?;
+        Ok(self.own_connection(link_id))
+    }
+
+    /// Establishes a TCP connection with `connect_tcp`, and takes ownership of it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// let script = [
+    ///     Exchange::new(b"AT+CIPSTART=\"TCP\",\"example.com\",80,60\r\n", b"CONNECT\r\n\r\nOK\r\n"),
+    ///     Exchange::new(b"AT+CIPSEND=4\r\n", b"\r\nOK\r\n\r\n>"),
+    ///     Exchange::new(b"ping", b"\r\nRecv 4 bytes\r\n\r\nSEND OK\r\n"),
+    ///     Exchange::new(b"AT+CIPCLOSE\r\n", b"CLOSED\r\n\r\nOK\r\n"),
+    /// ];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::TcpIp.into());
+    ///
+    /// let mut socket = nb::block!(esp32_at.connect_tcp_socket("example.com", 80, 60)).unwrap();
+    /// nb::block!(socket.send(b"ping")).unwrap();
+    /// drop(socket);
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn connect_tcp_socket(
+        &mut self,
+        host: &str,
+        port: u16,
+        keepalive_secs: u16,
+    ) -> nb::Result<EspSocket<'_, RX, TX>, Error<RX::Error, TX::Error>> {
+        let connection = self.connect_tcp(host, port, keepalive_secs)?;
+        Ok(self.own_connection(connection.link_id))
+    }
+
+    /// Establishes a TLS connection with `connect_ssl`, and takes ownership of it.
+    pub fn connect_ssl_socket(
+        &mut self,
+        host: &str,
+        port: u16,
+        keepalive_secs: u16,
+    ) -> nb::Result<EspSocket<'_, RX, TX>, Error<RX::Error, TX::Error>> {
+        let connection = self.connect_ssl(host, port, keepalive_secs)?;
+        Ok(self.own_connection(connection.link_id))
+    }
+
+    /// Opens a UDP "connection" with `connect_udp`, and takes ownership of it.
+    pub fn connect_udp_socket(
+        &mut self,
+        host: &str,
+        remote_port: u16,
+        local_port: Option<u16>,
+    ) -> nb::Result<EspSocket<'_, RX, TX>, Error<RX::Error, TX::Error>> {
+        let connection = self.connect_udp(host, remote_port, local_port)?;
+        Ok(self.own_connection(connection.link_id))
+    }
+
+    /// Takes an inbound connection accepted by the server started with `start_server`, if there
+    /// is one.
+    ///
+    /// The inbound connections are also reported as `Event::LinkConnected`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// let script = [
+    ///     Exchange::new(b"AT+CIPMUX=1\r\n", b"\r\nOK\r\n"),
+    ///     Exchange::new(b"AT+CIPSERVERMAXCONN=2\r\n", b"\r\nOK\r\n"),
+    ///     Exchange::new(b"AT+CIPSERVER=1,8080\r\n", b"\r\nOK\r\n"),
+    ///     Exchange::unsolicited(b"0,CONNECT\r\n+IPD,0,4:ping"),
+    ///     Exchange::new(b"AT+CIPSEND=0,4\r\n", b"\r\nOK\r\n\r\n>"),
+    ///     Exchange::new(b"pong", b"\r\nRecv 4 bytes\r\n\r\nSEND OK\r\n"),
+    ///     Exchange::new(b"AT+CIPCLOSE=0\r\n", b"0,CLOSED\r\n\r\nOK\r\n"),
+    /// ];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::TcpIp.into());
+    ///
+    /// nb::block!(esp32_at.set_multiple_connections(true)).unwrap();
+    /// nb::block!(esp32_at.set_server_max_connections(2)).unwrap();
+    /// nb::block!(esp32_at.start_server(8080)).unwrap();
+    ///
+    /// let mut client = nb::block!(esp32_at.accept()).unwrap();
+    /// let mut buf = [0; 4];
+    /// let len = nb::block!(client.read(&mut buf)).unwrap();
+    /// if &buf[..len] == b"ping" {
+    ///     nb::block!(client.send(b"pong")).unwrap();
+    /// }
+    /// client.close().unwrap();
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn accept(&mut self) -> nb::Result<EspSocket<'_, RX, TX>, Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::TcpIp)?;
+        if self.links.is_none() {
+            return Err(nb::Error::Other(Error::InvalidArgument));
+        }
+        self.drain_lines()?;
+        let link_id = match self.links.as_mut().and_then(|links| links.take_accepted()) {
+            Some(link_id) => link_id,
+            None => return Err(nb::Error::WouldBlock),
+        };
+        Ok(self.own_connection(Some(link_id)))
+    }
+
+    fn own_connection(&mut self, link_id: Option<u8>) -> EspSocket<'_, RX, TX> {
+        EspSocket {
+            esp32_at: self,
+            link_id,
+            closed: false,
+        }
+    }
+}
+
+impl<'a, RX, TX> EspSocket<'a, RX, TX>
+where
+    RX: embedded_hal::serial::Read<u8>,
+    TX: embedded_hal::serial::Write<u8>,
+{
+    /// The id of the link the connection uses, in multiple connection mode.
+    pub fn link_id(&self) -> Option<u8> {
+        self.link_id
+    }
+
+    /// See `Esp32At::send`.
+    pub fn send(&mut self, data: &[u8]) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.esp32_at.send(self.link_id, data)
+    }
+
+    /// See `Esp32At::read`.
+    pub fn read(&mut self, buf: &mut [u8]) -> nb::Result<usize, Error<RX::Error, TX::Error>> {
+        self.esp32_at.read(self.link_id, buf)
+    }
+
+    /// Closes the connection, releasing its link id.
+    pub fn close(mut self) -> Result<CloseOutcome, Error<RX::Error, TX::Error>> {
+        self.closed = true;
+        nb::block!(self.esp32_at.close(self.link_id))
+    }
+}
+
+impl<'a, RX, TX> Drop for EspSocket<'a, RX, TX>
+where
+    RX: embedded_hal::serial::Read<u8>,
+    TX: embedded_hal::serial::Write<u8>,
+{
+    fn drop(&mut self) {
+        let index = self.link_id.unwrap_or(0) as usize;
+        if !self.closed && !self.esp32_at.received.sink.is_closed(index) {
+            let _ = nb::block!(self.esp32_at.close(self.link_id));
+        }
+    }
+}
+
+#[cfg(feature = "embedded-io")]
+impl<'a, RX, TX> embedded_io::ErrorType for EspSocket<'a, RX, TX>
+where
+    RX: embedded_hal::serial::Read<u8>,
+    RX::Error: core::fmt::Debug,
+    TX: embedded_hal::serial::Write<u8>,
+    TX::Error: core::fmt::Debug,
+{
+    type Error = Error<RX::Error, TX::Error>;
+}
+
+/// Reads the data received in active receive mode, see `Esp32At::read`; once the connection has
+/// been closed and all of its data has been read, the end of the stream is reached.
+#[cfg(feature = "embedded-io")]
+impl<'a, RX, TX> embedded_io::Read for EspSocket<'a, RX, TX>
+where
+    RX: embedded_hal::serial::Read<u8>,
+    RX::Error: core::fmt::Debug,
+    TX: embedded_hal::serial::Write<u8>,
+    TX::Error: core::fmt::Debug,
+{
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let index = self.link_id.unwrap_or(0) as usize;
+        loop {
+            match EspSocket::read(self, buf) {
+                Ok(len) => return Ok(len),
+                Err(nb::Error::WouldBlock) if self.esp32_at.received.sink.is_finished(index) => {
+                    return Ok(0)
+                }
+                Err(nb::Error::WouldBlock) => {}
+                Err(nb::Error::Other(error)) => return Err(error),
+            }
+        }
+    }
+}
+
+/// Sends the data with `AT+CIPSEND`, at most `MAX_SEND_LEN` bytes per write.
+#[cfg(feature = "embedded-io")]
+impl<'a, RX, TX> embedded_io::Write for EspSocket<'a, RX, TX>
+where
+    RX: embedded_hal::serial::Read<u8>,
+    RX::Error: core::fmt::Debug,
+    TX: embedded_hal::serial::Write<u8>,
+    TX::Error: core::fmt::Debug,
+{
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let len = buf.len().min(crate::tcpip::MAX_SEND_LEN);
+        nb::block!(self.send(&buf[..len]))?;
+        Ok(len)
+    }
+
+    /// Does nothing, since the data has been handed to the module's network stack once `write`
+    /// returns.
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
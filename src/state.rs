@@ -1,24 +1,107 @@
-use crate::serial;
+use crate::{serial, CommandSet};
 
 #[derive(Debug)]
 pub struct State {
-    module_revision: ModuleRevision,
-    current_uart_config: UartConfig,
-    default_uart_config: UartConfig,
+    pub module_revision: ModuleRevision,
+    pub current_uart_config: UartConfig,
+    pub default_uart_config: UartConfig,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Default, Clone)]
 pub struct ModuleRevision {
     pub at_version: heapless::String<heapless::consts::U64>,
     pub sdk_version: heapless::String<heapless::consts::U64>,
     pub compile_time: heapless::String<heapless::consts::U64>,
+    /// The version of the firmware image, which is only reported by newer firmware.
+    pub bin_version: Option<heapless::String<heapless::consts::U64>>,
+    /// The chip the firmware was built for, which is only reported by newer firmware.
+    pub chip: Option<Chip>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Chip {
+    Esp8266,
+    Esp32,
+    Esp32S2,
+    Esp32S3,
+    Esp32C2,
+    Esp32C3,
+    Esp32C6,
 }
 
 #[derive(Debug)]
 pub struct UartConfig {
-    baud_rate: serial::BaudRate,
-    char_size: serial::CharSize,
-    stop_bits: serial::StopBits,
-    parity: serial::Parity,
-    flow_control: serial::FlowControl,
+    pub baud_rate: serial::BaudRate,
+    pub char_size: serial::CharSize,
+    pub stop_bits: serial::StopBits,
+    pub parity: serial::Parity,
+    pub flow_control: serial::FlowControl,
+}
+
+impl ModuleRevision {
+    /// Picks the information out of a line of the response to AT+GMR.
+    pub(crate) fn parse_line(&mut self, line: &str) {
+        if let Some(at_version) = line.strip_prefix("AT version:") {
+            self.at_version = truncated(at_version);
+            // e.g. "2.2.0.0(c6fa6bf - ESP32 - Jul  2 2021 06:44:05)"
+            self.chip = at_version
+                .split(['(', ')'])
+                .nth(1)
+                .and_then(|details| details.split(" - ").find_map(Chip::parse));
+        } else if let Some(sdk_version) = line.strip_prefix("SDK version:") {
+            self.sdk_version = truncated(sdk_version);
+        } else if line.starts_with("compile time") {
+            // e.g. "compile time(3e2f9a4):Jul  3 2021 11:52:40"
+            if let Some(index) = line.find(':') {
+                self.compile_time = truncated(&line[index + 1..]);
+            }
+        } else if let Some(bin_version) = line.strip_prefix("Bin version:") {
+            self.bin_version = Some(truncated(bin_version));
+        }
+    }
+}
+
+impl Chip {
+    fn parse(name: &str) -> Option<Self> {
+        match name.trim() {
+            "ESP8266" => Some(Chip::Esp8266),
+            "ESP32" => Some(Chip::Esp32),
+            "ESP32-S2" => Some(Chip::Esp32S2),
+            "ESP32-S3" => Some(Chip::Esp32S3),
+            "ESP32-C2" => Some(Chip::Esp32C2),
+            "ESP32-C3" => Some(Chip::Esp32C3),
+            "ESP32-C6" => Some(Chip::Esp32C6),
+            _ => None,
+        }
+    }
+
+    /// The command sets that the official firmware supports on this chip.
+    pub fn command_sets(self) -> enumset::EnumSet<CommandSet> {
+        let common = CommandSet::Wifi
+            | CommandSet::TcpIp
+            | CommandSet::Tls
+            | CommandSet::Mqtt
+            | CommandSet::Http
+            | CommandSet::WebServer;
+        match self {
+            Chip::Esp8266 | Chip::Esp32S2 => common,
+            Chip::Esp32 | Chip::Esp32S3 | Chip::Esp32C2 | Chip::Esp32C3 | Chip::Esp32C6 => {
+                common | CommandSet::Ble
+            }
+        }
+    }
+}
+
+/// Copies as much of a string as fits into a fixed capacity string.
+fn truncated<N>(s: &str) -> heapless::String<N>
+where
+    N: heapless::ArrayLength<u8>,
+{
+    let mut string = heapless::String::new();
+    for c in s.chars() {
+        if string.push(c).is_err() {
+            break;
+        }
+    }
+    string
 }
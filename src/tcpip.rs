@@ -0,0 +1,2441 @@
+use core::fmt;
+use core::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use crate::event::{self, Event};
+use crate::{link, parser, CommandSet, Error, Escaped, Esp32At, Line, QuotedList};
+
+/// The largest payload that can be sent with a single `AT+CIPSEND`.
+pub const MAX_SEND_LEN: usize = 8192;
+
+/// The most protocols that can be offered with ALPN.
+pub const MAX_ALPN_PROTOCOLS: usize = 5;
+
+/// The longest PSK and hint that can be used by TLS connections.
+pub const MAX_PSK_LEN: usize = 32;
+
+/// The protocol names offered with ALPN.
+pub type AlpnProtocols =
+    heapless::Vec<heapless::String<heapless::consts::U32>, heapless::consts::U5>;
+
+/// The longest idle time before TCP keepalive probes are sent.
+pub const MAX_KEEPALIVE_SECS: u16 = 7200;
+
+/// The longest idle timeout of inbound connections that the server supports.
+pub const MAX_SERVER_TIMEOUT_SECS: u16 = 7200;
+
+/// How data received on a connection is handed to the host.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReceiveMode {
+    /// Data is pushed to the host as soon as it arrives, as `+IPD` messages.
+    Active,
+    /// Data is buffered by the module until the host asks for it, which keeps hosts that can't
+    /// keep up with the UART from losing data; its arrival is reported as
+    /// `Event::DataAvailable`.
+    Passive,
+}
+
+/// Which side of a TLS connection is authenticated by certificate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TlsAuthMode {
+    None,
+    /// The module presents its client certificate to the server.
+    ClientCertificate,
+    /// The module verifies the server certificate against its CA.
+    ServerCertificate,
+    Mutual,
+}
+
+/// How the TLS connections on a link are authenticated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TlsClientConfig {
+    pub auth_mode: TlsAuthMode,
+    /// The slot of the client certificate and key, see `pki::PkiKind`.
+    pub pki_index: u8,
+    /// The slot of the CA certificate that the server certificate is verified against.
+    pub ca_index: u8,
+}
+
+/// The pre-shared key used by TLS connections with PSK cipher suites.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TlsPsk {
+    pub psk: heapless::String<heapless::consts::U32>,
+    pub hint: heapless::String<heapless::consts::U32>,
+}
+
+/// Socket options of the TCP connections on a link.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct TcpOptions {
+    /// How long closing a connection waits for unsent data to be sent, or `None` to return
+    /// right away and send it in the background.
+    pub linger_secs: Option<u16>,
+    /// Sends small segments right away instead of coalescing them with Nagle's algorithm.
+    pub no_delay: bool,
+    /// How long sending waits for room in the send buffer, or 0 to wait indefinitely.
+    pub send_timeout_ms: u32,
+    /// How long a connection has to be idle before keepalive probes are sent, or 0 to disable
+    /// them; at most 7200.
+    pub keepalive_secs: u16,
+}
+
+/// The IP configuration of the station.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StationAddresses {
+    pub ip: Ipv4Addr,
+    pub gateway: Ipv4Addr,
+    pub netmask: Ipv4Addr,
+    /// Only assigned with IPv6 enabled.
+    pub ipv6_link_local: Option<Ipv6Addr>,
+    /// Only assigned with IPv6 enabled, if the network provides one.
+    pub ipv6_global: Option<Ipv6Addr>,
+}
+
+/// The addresses of the station and soft AP interfaces, which are only given for the interfaces
+/// that are enabled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct LocalAddresses {
+    pub station_ip: Option<Ipv4Addr>,
+    pub station_ipv6_link_local: Option<Ipv6Addr>,
+    pub station_ipv6_global: Option<Ipv6Addr>,
+    pub station_mac: Option<[u8; 6]>,
+    pub soft_ap_ip: Option<Ipv4Addr>,
+    pub soft_ap_ipv6_link_local: Option<Ipv6Addr>,
+    pub soft_ap_ipv6_global: Option<Ipv6Addr>,
+    pub soft_ap_mac: Option<[u8; 6]>,
+}
+
+/// The overall state of the station's network connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NetworkState {
+    NotInitialized,
+    /// Initialized, but not connecting to an access point yet.
+    Idle,
+    /// Connected to an access point and an IP address has been obtained.
+    GotIp,
+    /// At least one connection has been established.
+    Transmitting,
+    /// All connections have been closed.
+    ConnectionsClosed,
+    /// Connecting to an access point, or disconnected from it.
+    NotConnected,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkType {
+    Tcp,
+    Udp,
+    Ssl,
+}
+
+/// Which side established a connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkRole {
+    Client,
+    Server,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LinkStatus {
+    pub link_id: u8,
+    pub link_type: LinkType,
+    pub remote_ip: IpAddr,
+    pub remote_port: u16,
+    pub local_port: u16,
+    pub role: LinkRole,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectionStatus {
+    pub state: NetworkState,
+    pub links: heapless::Vec<LinkStatus, heapless::consts::U5>,
+}
+
+/// How the remote end of a UDP link may change once it has been opened.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UdpMode {
+    /// Datagrams are only exchanged with the remote end given when opening the link.
+    Fixed,
+    /// The remote end changes to the sender of the first datagram received.
+    ChangeOnce,
+    /// The remote end changes to the sender of every datagram received, so that `send` replies to
+    /// the last sender.  `send_to` can address any peer.
+    Change,
+}
+
+/// The successful outcomes of establishing a connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectOutcome {
+    Connected,
+    /// The connection had already been established.
+    AlreadyConnected,
+}
+
+/// A connection that has been established, or that had already been established.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Connection {
+    /// The id of the link the connection uses, in multiple connection mode.
+    pub link_id: Option<u8>,
+    pub outcome: ConnectOutcome,
+}
+
+/// The successful outcomes of closing a connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CloseOutcome {
+    Closed,
+    /// The connection had already been closed, e.g. by the remote end.
+    AlreadyClosed,
+}
+
+/// The DNS servers used by the module.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DnsConfig {
+    /// Whether the servers were configured manually, rather than provided by DHCP.
+    pub manual: bool,
+    pub servers: heapless::Vec<IpAddr, heapless::consts::U3>,
+}
+
+/// The round-trip times of a series of pings, in milliseconds.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PingStats {
+    pub sent: u16,
+    pub received: u16,
+    pub min_ms: u32,
+    pub avg_ms: u32,
+    pub max_ms: u32,
+}
+
+impl PingStats {
+    /// The share of pings that got no reply, in percent.
+    pub fn loss_percent(&self) -> u8 {
+        if self.sent == 0 {
+            return 0;
+        }
+        (u32::from(self.sent - self.received) * 100 / u32::from(self.sent)) as u8
+    }
+}
+
+/// A transmission started with `Esp32At::send_ex`, whose length isn't known up front.
+///
+/// The data is sent once `max_len` bytes have been written, or when `finish` is called.
+#[derive(Debug)]
+pub struct ExtendedSend<'a, RX, TX>
+where
+    RX: embedded_hal::serial::Read<u8>,
+    TX: embedded_hal::serial::Write<u8>,
+{
+    esp32_at: &'a mut Esp32At<RX, TX>,
+    link_id: Option<u8>,
+    /// Whether the link has been closed before the data could be written.
+    closed: bool,
+    remaining: usize,
+}
+
+/// A transmission of a known length started with `Esp32At::send_stream` or
+/// `Esp32At::send_large_stream`, whose data is written piece by piece, e.g. as it is read from
+/// storage.
+#[derive(Debug)]
+pub struct StreamingSend<'a, RX, TX>
+where
+    RX: embedded_hal::serial::Read<u8>,
+    TX: embedded_hal::serial::Write<u8>,
+{
+    esp32_at: &'a mut Esp32At<RX, TX>,
+    link_id: Option<u8>,
+    /// Whether the link has been closed before the data could be written.
+    closed: bool,
+    remaining: usize,
+    /// How many more bytes the current `AT+CIPSEND` or `AT+CIPSENDL` transmission takes.
+    chunk_remaining: usize,
+}
+
+impl UdpMode {
+    fn code(self) -> u8 {
+        match self {
+            UdpMode::Fixed => 0,
+            UdpMode::ChangeOnce => 1,
+            UdpMode::Change => 2,
+        }
+    }
+}
+
+impl NetworkState {
+    fn from_code(code: &str) -> Option<Self> {
+        match code {
+            "0" => Some(NetworkState::NotInitialized),
+            "1" => Some(NetworkState::Idle),
+            "2" => Some(NetworkState::GotIp),
+            "3" => Some(NetworkState::Transmitting),
+            "4" => Some(NetworkState::ConnectionsClosed),
+            "5" => Some(NetworkState::NotConnected),
+            _ => None,
+        }
+    }
+}
+
+impl LinkType {
+    fn parse(name: &str) -> Option<Self> {
+        match name.trim_end_matches("v6") {
+            "TCP" => Some(LinkType::Tcp),
+            "UDP" => Some(LinkType::Udp),
+            "SSL" => Some(LinkType::Ssl),
+            _ => None,
+        }
+    }
+}
+
+impl LinkStatus {
+    fn parse(arguments: &str) -> Option<Self> {
+        let mut fields = parser::fields(arguments);
+        Some(LinkStatus {
+            link_id: fields.next()?.parse().ok()?,
+            link_type: LinkType::parse(parser::unquote(fields.next()?))?,
+            remote_ip: parser::unquote(fields.next()?).parse().ok()?,
+            remote_port: fields.next()?.parse().ok()?,
+            local_port: fields.next()?.parse().ok()?,
+            role: match fields.next()? {
+                "0" => LinkRole::Client,
+                "1" => LinkRole::Server,
+                _ => return None,
+            },
+        })
+    }
+}
+
+impl ReceiveMode {
+    fn code(self) -> u8 {
+        match self {
+            ReceiveMode::Active => 0,
+            ReceiveMode::Passive => 1,
+        }
+    }
+
+    fn from_code(code: &str) -> Option<Self> {
+        match code {
+            "0" => Some(ReceiveMode::Active),
+            "1" => Some(ReceiveMode::Passive),
+            _ => None,
+        }
+    }
+}
+
+impl TlsAuthMode {
+    fn code(self) -> u8 {
+        match self {
+            TlsAuthMode::None => 0,
+            TlsAuthMode::ClientCertificate => 1,
+            TlsAuthMode::ServerCertificate => 2,
+            TlsAuthMode::Mutual => 3,
+        }
+    }
+
+    fn from_code(code: &str) -> Option<Self> {
+        match code {
+            "0" => Some(TlsAuthMode::None),
+            "1" => Some(TlsAuthMode::ClientCertificate),
+            "2" => Some(TlsAuthMode::ServerCertificate),
+            "3" => Some(TlsAuthMode::Mutual),
+            _ => None,
+        }
+    }
+}
+
+impl TlsPsk {
+    fn parse(arguments: &str) -> Option<Self> {
+        let mut fields = parser::fields(arguments);
+        Some(TlsPsk {
+            psk: parser::string(parser::unquote(fields.next()?))?,
+            hint: parser::string(parser::unquote(fields.next()?))?,
+        })
+    }
+}
+
+impl TlsClientConfig {
+    fn parse(arguments: &str) -> Option<Self> {
+        let mut fields = parser::fields(arguments);
+        Some(TlsClientConfig {
+            auth_mode: TlsAuthMode::from_code(fields.next()?)?,
+            pki_index: fields.next()?.parse().ok()?,
+            ca_index: fields.next()?.parse().ok()?,
+        })
+    }
+}
+
+impl<RX, TX> Esp32At<RX, TX>
+where
+    RX: embedded_hal::serial::Read<u8>,
+    TX: embedded_hal::serial::Write<u8>,
+{
+    /// Enables or disables multiple connection mode, in which up to `link::MAX_LINKS`
+    /// connections can be open at the same time; this is only possible while no connection is
+    /// open.
+    ///
+    /// In multiple connection mode, the driver hands out the link ids of new connections, and
+    /// the link id has to be passed to every method that acts on a connection.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// let script = [
+    ///     Exchange::new(b"AT+CIPMUX=1\r\n", b"\r\nOK\r\n"),
+    ///     Exchange::new(b"AT+CIPSTART=4,\"TCP\",\"example.com\",80,0\r\n", b"4,CONNECT\r\n\r\nOK\r\n"),
+    ///     Exchange::new(b"AT+CIPSEND=4,4\r\n", b"\r\nOK\r\n\r\n>"),
+    ///     Exchange::new(b"ping", b"\r\nRecv 4 bytes\r\n\r\nSEND OK\r\n"),
+    ///     Exchange::new(b"AT+CIPCLOSE=4\r\n", b"4,CLOSED\r\n\r\nOK\r\n"),
+    /// ];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::TcpIp.into());
+    ///
+    /// nb::block!(esp32_at.set_multiple_connections(true)).unwrap();
+    /// let connection = nb::block!(esp32_at.connect_tcp("example.com", 80, 0)).unwrap();
+    /// assert_eq!(Some(4), connection.link_id);
+    /// nb::block!(esp32_at.send(connection.link_id, b"ping")).unwrap();
+    /// nb::block!(esp32_at.close(connection.link_id)).unwrap();
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn set_multiple_connections(
+        &mut self,
+        enabled: bool,
+    ) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::TcpIp)?;
+        write_command!(self, "AT+CIPMUX={}", enabled as u8)?;
+        self.expect_ok_response()?;
+        self.sync_multiple_connections(enabled);
+        Ok(())
+    }
+
+    /// Queries whether multiple connection mode is enabled.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// let script = [Exchange::new(b"AT+CIPMUX?\r\n", b"+CIPMUX:1\r\n\r\nOK\r\n")];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::TcpIp.into());
+    ///
+    /// assert!(nb::block!(esp32_at.get_multiple_connections()).unwrap());
+    /// assert!(esp32_at.link_allocator().is_some());
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn get_multiple_connections(&mut self) -> nb::Result<bool, Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::TcpIp)?;
+        write_command!(self, "AT+CIPMUX?")?;
+        let enabled = match self.read_query_response("CIPMUX")?.as_str() {
+            "0" => false,
+            "1" => true,
+            _ => return Err(nb::Error::Other(Error::UnexpectedResponse)),
+        };
+        self.sync_multiple_connections(enabled);
+        Ok(enabled)
+    }
+
+    /// The link ids in use, in multiple connection mode.
+    pub fn link_allocator(&self) -> Option<&link::LinkAllocator> {
+        self.links.as_ref()
+    }
+
+    /// The link ids in use, in multiple connection mode; ids can be reserved for the server here.
+    pub fn link_allocator_mut(&mut self) -> Option<&mut link::LinkAllocator> {
+        self.links.as_mut()
+    }
+
+    fn sync_multiple_connections(&mut self, enabled: bool) {
+        if enabled != self.links.is_some() {
+            self.links = if enabled {
+                Some(link::LinkAllocator::new())
+            } else {
+                None
+            };
+        }
+    }
+
+    /// Sets how data received on a connection is handed to the host.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// use esp_at::event::Event;
+    /// use esp_at::tcpip::ReceiveMode;
+    /// let script = [
+    ///     Exchange::new(b"AT+CIPRECVMODE=1\r\n", b"\r\nOK\r\n"),
+    ///     Exchange::unsolicited(b"+IPD,128\r\n"),
+    /// ];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::TcpIp.into());
+    ///
+    /// nb::block!(esp32_at.set_receive_mode(ReceiveMode::Passive)).unwrap();
+    /// assert_eq!(
+    ///     Event::DataAvailable {
+    ///         link_id: None,
+    ///         len: 128
+    ///     },
+    ///     nb::block!(esp32_at.poll_event()).unwrap()
+    /// );
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn set_receive_mode(
+        &mut self,
+        mode: ReceiveMode,
+    ) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::TcpIp)?;
+        write_command!(self, "AT+CIPRECVMODE={}", mode.code())?;
+        self.expect_ok_response()
+    }
+
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// use esp_at::tcpip::ReceiveMode;
+    /// let script = [Exchange::new(b"AT+CIPRECVMODE?\r\n", b"+CIPRECVMODE:0\r\n\r\nOK\r\n")];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::TcpIp.into());
+    ///
+    /// let mode = nb::block!(esp32_at.get_receive_mode()).unwrap();
+    /// assert_eq!(ReceiveMode::Active, mode);
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn get_receive_mode(&mut self) -> nb::Result<ReceiveMode, Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::TcpIp)?;
+        write_command!(self, "AT+CIPRECVMODE?")?;
+        let mode = self.read_query_response("CIPRECVMODE")?;
+        Ok(ReceiveMode::from_code(&mode).ok_or(Error::UnexpectedResponse)?)
+    }
+
+    /// Runs `f` with the receive mode temporarily set to `mode`, restoring the previous mode
+    /// afterwards.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// use esp_at::tcpip::{NetworkState, ReceiveMode};
+    /// let script = [
+    ///     Exchange::new(b"AT+CIPRECVMODE?\r\n", b"+CIPRECVMODE:0\r\n\r\nOK\r\n"),
+    ///     Exchange::new(b"AT+CIPRECVMODE=1\r\n", b"\r\nOK\r\n"),
+    ///     Exchange::new(b"AT+CIPSTATUS\r\n", b"STATUS:2\r\n\r\nOK\r\n"),
+    ///     Exchange::new(b"AT+CIPRECVMODE=0\r\n", b"\r\nOK\r\n"),
+    /// ];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::TcpIp.into());
+    ///
+    /// let status = esp32_at
+    ///     .with_receive_mode(ReceiveMode::Passive, |esp32_at| {
+    ///         nb::block!(esp32_at.connection_status())
+    ///     })
+    ///     .unwrap();
+    /// assert_eq!(NetworkState::GotIp, status.state);
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn with_receive_mode<F, R, E>(&mut self, mode: ReceiveMode, f: F) -> Result<R, E>
+    where
+        F: FnOnce(&mut Self) -> Result<R, E>,
+        E: From<Error<RX::Error, TX::Error>>,
+    {
+        self.with_setting(Self::get_receive_mode, Self::set_receive_mode, mode, f)
+    }
+
+    /// Selects the authentication mode and the certificate slots used by TLS connections on a
+    /// link, or on all links if `link_id` is `None`.
+    ///
+    /// Since the link id of a connection is only known once it has been established, connections
+    /// in multiple connection mode are usually configured all at once.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// use esp_at::tcpip::{TlsAuthMode, TlsClientConfig};
+    /// let script = [
+    ///     Exchange::new(b"AT+CIPMUX=1\r\n", b"\r\nOK\r\n"),
+    ///     Exchange::new(b"AT+CIPSSLCCONF=5,3,0,0\r\n", b"\r\nOK\r\n"),
+    ///     Exchange::new(
+    ///         b"AT+CIPSSLCCONF?\r\n",
+    ///         b"+CIPSSLCCONF:0,3,0,0\r\n+CIPSSLCCONF:1,3,0,0\r\n\r\nOK\r\n",
+    ///     ),
+    /// ];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::TcpIp | CommandSet::Tls);
+    ///
+    /// let config = TlsClientConfig {
+    ///     auth_mode: TlsAuthMode::Mutual,
+    ///     pki_index: 0,
+    ///     ca_index: 0,
+    /// };
+    /// nb::block!(esp32_at.set_multiple_connections(true)).unwrap();
+    /// nb::block!(esp32_at.set_tls_client_config(None, &config)).unwrap();
+    /// assert_eq!(config, nb::block!(esp32_at.get_tls_client_config(Some(1))).unwrap());
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn set_tls_client_config(
+        &mut self,
+        link_id: Option<u8>,
+        config: &TlsClientConfig,
+    ) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::Tls)?;
+        let link = self.config_link_prefix(link_id)?;
+        write_command!(
+            self,
+            "AT+CIPSSLCCONF={}{},{},{}",
+            link,
+            config.auth_mode.code(),
+            config.pki_index,
+            config.ca_index
+        )?;
+        self.expect_ok_response()
+    }
+
+    /// Returns the TLS configuration of a link; `link_id` identifies it in multiple connection
+    /// mode.
+    pub fn get_tls_client_config(
+        &mut self,
+        link_id: Option<u8>,
+    ) -> nb::Result<TlsClientConfig, Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::Tls)?;
+        if self.links.is_some() != link_id.is_some() {
+            return Err(nb::Error::Other(Error::InvalidArgument));
+        }
+        write_command!(self, "AT+CIPSSLCCONF?")?;
+        let config = self.read_link_query_response("CIPSSLCCONF", link_id)?;
+        Ok(TlsClientConfig::parse(&config).ok_or(Error::UnexpectedResponse)?)
+    }
+
+    /// Sets the host name sent with Server Name Indication by TLS connections on a link, or on
+    /// all links if `link_id` is `None`, so that servers hosting several domains present the
+    /// right certificate.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// let script = [
+    ///     Exchange::new(b"AT+CIPSSLCSNI=\"example.com\"\r\n", b"\r\nOK\r\n"),
+    ///     Exchange::new(
+    ///         b"AT+CIPSSLCSNI?\r\n",
+    ///         b"+CIPSSLCSNI:\"example.com\"\r\n\r\nOK\r\n",
+    ///     ),
+    /// ];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::TcpIp | CommandSet::Tls);
+    ///
+    /// nb::block!(esp32_at.set_tls_server_name(None, "example.com")).unwrap();
+    /// assert_eq!("example.com", nb::block!(esp32_at.get_tls_server_name(None)).unwrap());
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn set_tls_server_name(
+        &mut self,
+        link_id: Option<u8>,
+        server_name: &str,
+    ) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::Tls)?;
+        let link = self.config_link_prefix(link_id)?;
+        write_command!(self, "AT+CIPSSLCSNI={}\"{}\"", link, Escaped(server_name))?;
+        self.expect_ok_response()
+    }
+
+    pub fn get_tls_server_name(
+        &mut self,
+        link_id: Option<u8>,
+    ) -> nb::Result<heapless::String<heapless::consts::U64>, Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::Tls)?;
+        if self.links.is_some() != link_id.is_some() {
+            return Err(nb::Error::Other(Error::InvalidArgument));
+        }
+        write_command!(self, "AT+CIPSSLCSNI?")?;
+        let server_name = self.read_link_query_response("CIPSSLCSNI", link_id)?;
+        Ok(parser::string(parser::unquote(&server_name)).ok_or(Error::UnexpectedResponse)?)
+    }
+
+    /// Sets the protocols offered with Application-Layer Protocol Negotiation by TLS connections on
+    /// a link, or on all links if `link_id` is `None`; some brokers require this, e.g. AWS IoT
+    /// accepts MQTT on port 443 with `x-amzn-mqtt-ca`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// let script = [
+    ///     Exchange::new(b"AT+CIPSSLCALPN=1,\"x-amzn-mqtt-ca\"\r\n", b"\r\nOK\r\n"),
+    ///     Exchange::new(
+    ///         b"AT+CIPSSLCALPN?\r\n",
+    ///         b"+CIPSSLCALPN:\"x-amzn-mqtt-ca\"\r\n\r\nOK\r\n",
+    ///     ),
+    /// ];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::TcpIp | CommandSet::Tls);
+    ///
+    /// nb::block!(esp32_at.set_tls_alpn(None, &["x-amzn-mqtt-ca"])).unwrap();
+    /// let protocols = nb::block!(esp32_at.get_tls_alpn(None)).unwrap();
+    /// assert_eq!("x-amzn-mqtt-ca", protocols[0]);
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn set_tls_alpn(
+        &mut self,
+        link_id: Option<u8>,
+        protocols: &[&str],
+    ) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::Tls)?;
+        let link = self.config_link_prefix(link_id)?;
+        if protocols.len() > MAX_ALPN_PROTOCOLS {
+            return Err(nb::Error::Other(Error::InvalidArgument));
+        }
+        write_command!(
+            self,
+            "AT+CIPSSLCALPN={}{}{}",
+            link,
+            protocols.len(),
+            QuotedList(protocols)
+        )?;
+        self.expect_ok_response()
+    }
+
+    pub fn get_tls_alpn(
+        &mut self,
+        link_id: Option<u8>,
+    ) -> nb::Result<AlpnProtocols, Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::Tls)?;
+        if self.links.is_some() != link_id.is_some() {
+            return Err(nb::Error::Other(Error::InvalidArgument));
+        }
+        write_command!(self, "AT+CIPSSLCALPN?")?;
+        let arguments = self.read_link_query_response("CIPSSLCALPN", link_id)?;
+        let mut protocols = heapless::Vec::new();
+        for protocol in parser::fields(&arguments).filter(|field| !field.is_empty()) {
+            let protocol =
+                parser::string(parser::unquote(protocol)).ok_or(Error::UnexpectedResponse)?;
+            protocols
+                .push(protocol)
+                .map_err(|_| Error::UnexpectedResponse)?;
+        }
+        Ok(protocols)
+    }
+
+    /// Sets the pre-shared key and its hint used by TLS connections on a link, or on all links if
+    /// `link_id` is `None`, which saves managing certificates; each is at most `MAX_PSK_LEN`
+    /// bytes long.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// let script = [
+    ///     Exchange::new(b"AT+CIPSSLCPSK=\"secret\",\"device-1\"\r\n", b"\r\nOK\r\n"),
+    ///     Exchange::new(
+    ///         b"AT+CIPSSLCPSK?\r\n",
+    ///         b"+CIPSSLCPSK:\"secret\",\"device-1\"\r\n\r\nOK\r\n",
+    ///     ),
+    /// ];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::TcpIp | CommandSet::Tls);
+    ///
+    /// nb::block!(esp32_at.set_tls_psk(None, "secret", "device-1")).unwrap();
+    /// let psk = nb::block!(esp32_at.get_tls_psk(None)).unwrap();
+    /// assert_eq!("device-1", psk.hint);
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn set_tls_psk(
+        &mut self,
+        link_id: Option<u8>,
+        psk: &str,
+        hint: &str,
+    ) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::Tls)?;
+        let link = self.config_link_prefix(link_id)?;
+        if psk.len() > MAX_PSK_LEN || hint.len() > MAX_PSK_LEN {
+            return Err(nb::Error::Other(Error::InvalidArgument));
+        }
+        write_command!(
+            self,
+            "AT+CIPSSLCPSK={}\"{}\",\"{}\"",
+            link,
+            Escaped(psk),
+            Escaped(hint)
+        )?;
+        self.expect_ok_response()
+    }
+
+    pub fn get_tls_psk(
+        &mut self,
+        link_id: Option<u8>,
+    ) -> nb::Result<TlsPsk, Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::Tls)?;
+        if self.links.is_some() != link_id.is_some() {
+            return Err(nb::Error::Other(Error::InvalidArgument));
+        }
+        write_command!(self, "AT+CIPSSLCPSK?")?;
+        let arguments = self.read_link_query_response("CIPSSLCPSK", link_id)?;
+        Ok(TlsPsk::parse(&arguments).ok_or(Error::UnexpectedResponse)?)
+    }
+
+    /// Sets the socket options of TCP connections on a link, or on all links if `link_id` is
+    /// `None`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// use esp_at::tcpip::TcpOptions;
+    /// let script = [Exchange::new(b"AT+CIPTCPOPT=-1,1,0,60\r\n", b"\r\nOK\r\n")];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::TcpIp.into());
+    ///
+    /// let options = TcpOptions {
+    ///     no_delay: true,
+    ///     keepalive_secs: 60,
+    ///     ..TcpOptions::default()
+    /// };
+    /// nb::block!(esp32_at.set_tcp_options(None, &options)).unwrap();
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn set_tcp_options(
+        &mut self,
+        link_id: Option<u8>,
+        options: &TcpOptions,
+    ) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::TcpIp)?;
+        let link = self.config_link_prefix(link_id)?;
+        if options.keepalive_secs > MAX_KEEPALIVE_SECS {
+            return Err(nb::Error::Other(Error::InvalidArgument));
+        }
+        let linger = options.linger_secs.map_or(-1, i32::from);
+        write_command!(
+            self,
+            "AT+CIPTCPOPT={}{},{},{},{}",
+            link,
+            linger,
+            options.no_delay as u8,
+            options.send_timeout_ms,
+            options.keepalive_secs
+        )?;
+        self.expect_ok_response()
+    }
+
+    /// Establishes a TCP connection; `keepalive_secs` is the TCP keepalive interval, or 0 to
+    /// disable it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// use esp_at::tcpip::ConnectOutcome;
+    /// let script = [Exchange::new(b"AT+CIPSTART=\"TCP\",\"example.com\",80,60\r\n", b"CONNECT\r\n\r\nOK\r\n")];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::TcpIp.into());
+    ///
+    /// let connection = nb::block!(esp32_at.connect_tcp("example.com", 80, 60)).unwrap();
+    /// assert_eq!(ConnectOutcome::Connected, connection.outcome);
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn connect_tcp(
+        &mut self,
+        host: &str,
+        port: u16,
+        keepalive_secs: u16,
+    ) -> nb::Result<Connection, Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::TcpIp)?;
+        self.start_connection(|this, link| {
+            write_command!(
+                this,
+                "AT+CIPSTART={}\"{}\",\"{}\",{},{}",
+                link,
+                Protocol::for_host("TCP", host),
+                Escaped(host),
+                port,
+                keepalive_secs
+            )
+        })
+    }
+
+    /// Establishes a TLS connection, authenticated according to `set_tls_client_config`;
+    /// `keepalive_secs` is the TCP keepalive interval, or 0 to disable it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// use esp_at::tcpip::ConnectOutcome;
+    /// let script = [Exchange::new(b"AT+CIPSTART=\"SSL\",\"example.com\",443,60\r\n", b"CONNECT\r\n\r\nOK\r\n")];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::TcpIp | CommandSet::Tls);
+    ///
+    /// let connection = nb::block!(esp32_at.connect_ssl("example.com", 443, 60)).unwrap();
+    /// assert_eq!(ConnectOutcome::Connected, connection.outcome);
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn connect_ssl(
+        &mut self,
+        host: &str,
+        port: u16,
+        keepalive_secs: u16,
+    ) -> nb::Result<Connection, Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::TcpIp)?;
+        self.require_command_set(CommandSet::Tls)?;
+        self.start_connection(|this, link| {
+            write_command!(
+                this,
+                "AT+CIPSTART={}\"{}\",\"{}\",{},{}",
+                link,
+                Protocol::for_host("SSL", host),
+                Escaped(host),
+                port,
+                keepalive_secs
+            )
+        })
+    }
+
+    /// Opens a UDP "connection" to `host`, which fixes the remote end that `send` transmits
+    /// datagrams to.  Unless a `local_port` is given, the module picks one.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// let script = [
+    ///     Exchange::new(b"AT+CIPSTART=\"UDP\",\"10.0.0.2\",5683,5683,0\r\n", b"CONNECT\r\n\r\nOK\r\n"),
+    ///     Exchange::new(b"AT+CIPSEND=5\r\n", b"\r\nOK\r\n\r\n>"),
+    ///     Exchange::new(b"hello", b"\r\nRecv 5 bytes\r\n\r\nSEND OK\r\n"),
+    /// ];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::TcpIp.into());
+    ///
+    /// nb::block!(esp32_at.connect_udp("10.0.0.2", 5683, Some(5683))).unwrap();
+    /// nb::block!(esp32_at.send(None, b"hello")).unwrap();
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn connect_udp(
+        &mut self,
+        host: &str,
+        remote_port: u16,
+        local_port: Option<u16>,
+    ) -> nb::Result<Connection, Error<RX::Error, TX::Error>> {
+        match local_port {
+            Some(local_port) => self.open_udp(host, remote_port, local_port, UdpMode::Fixed),
+            None => {
+                self.require_command_set(CommandSet::TcpIp)?;
+                self.start_connection(|this, link| {
+                    write_command!(
+                        this,
+                        "AT+CIPSTART={}\"{}\",\"{}\",{}",
+                        link,
+                        Protocol::for_host("UDP", host),
+                        Escaped(host),
+                        remote_port
+                    )
+                })
+            }
+        }
+    }
+
+    /// Opens a UDP link on `local_port` whose remote end changes according to `mode`; with
+    /// `UdpMode::Change`, this acts as a simple UDP server.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// use esp_at::tcpip::UdpMode;
+    /// let script = [
+    ///     Exchange::new(b"AT+CIPSTART=\"UDP\",\"0.0.0.0\",1,4210,2\r\n", b"CONNECT\r\n\r\nOK\r\n"),
+    ///     Exchange::new(b"AT+CIPSEND=4,\"10.0.0.7\",4210\r\n", b"\r\nOK\r\n\r\n>"),
+    ///     Exchange::new(b"here", b"\r\nRecv 4 bytes\r\n\r\nSEND OK\r\n"),
+    /// ];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::TcpIp.into());
+    ///
+    /// nb::block!(esp32_at.open_udp("0.0.0.0", 1, 4210, UdpMode::Change)).unwrap();
+    /// nb::block!(esp32_at.send_to(None, b"here", "10.0.0.7", 4210)).unwrap();
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn open_udp(
+        &mut self,
+        host: &str,
+        remote_port: u16,
+        local_port: u16,
+        mode: UdpMode,
+    ) -> nb::Result<Connection, Error<RX::Error, TX::Error>> {
+        let protocol = Protocol::for_host("UDP", host);
+        self.open_udp_link(protocol, Escaped(host), remote_port, local_port, mode)
+    }
+
+    /// Opens a UDP link that joins the multicast `group` on `port`.
+    ///
+    /// Datagrams sent to the group are received like on any other link, and `send_multicast`
+    /// sends to the group; `send` replies to the sender of the last datagram received.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// use core::net::Ipv4Addr;
+    /// let script = [
+    ///     Exchange::new(b"AT+CIPSTART=\"UDP\",\"239.255.255.250\",1900,1900,2\r\n", b"CONNECT\r\n\r\nOK\r\n"),
+    ///     Exchange::new(b"AT+CIPSEND=5,\"239.255.255.250\",1900\r\n", b"\r\nOK\r\n\r\n>"),
+    ///     Exchange::new(b"hello", b"\r\nRecv 5 bytes\r\n\r\nSEND OK\r\n"),
+    /// ];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::TcpIp.into());
+    ///
+    /// let group = Ipv4Addr::new(239, 255, 255, 250);
+    /// nb::block!(esp32_at.open_multicast(group, 1900)).unwrap();
+    /// nb::block!(esp32_at.send_multicast(None, b"hello", group, 1900)).unwrap();
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn open_multicast(
+        &mut self,
+        group: Ipv4Addr,
+        port: u16,
+    ) -> nb::Result<Connection, Error<RX::Error, TX::Error>> {
+        if !group.is_multicast() {
+            return Err(nb::Error::Other(Error::InvalidArgument));
+        }
+        // The remote end must be allowed to change, or datagrams from group members are dropped
+        let protocol = Protocol::for_host("UDP", "");
+        self.open_udp_link(protocol, group, port, port, UdpMode::Change)
+    }
+
+    /// Sends `data` over the open connection, returning once the module has handed it to the
+    /// network stack.
+    ///
+    /// `link_id` identifies the connection in multiple connection mode.  At most `MAX_SEND_LEN`
+    /// bytes can be sent at a time; over UDP, `data` is sent as a single datagram.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// let script = [
+    ///     Exchange::new(b"AT+CIPSEND=4\r\n", b"\r\nOK\r\n\r\n>"),
+    ///     Exchange::new(b"ping", b"\r\nRecv 4 bytes\r\n\r\nSEND OK\r\n"),
+    /// ];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::TcpIp.into());
+    ///
+    /// nb::block!(esp32_at.send(None, b"ping")).unwrap();
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn send(
+        &mut self,
+        link_id: Option<u8>,
+        data: &[u8],
+    ) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::TcpIp)?;
+        let link = self.link_prefix(link_id)?;
+        if data.is_empty() || data.len() > MAX_SEND_LEN {
+            return Err(nb::Error::Other(Error::InvalidArgument));
+        }
+        write_command!(self, "AT+CIPSEND={}{}", link, data.len())?;
+        self.write_payload(link_id, data)
+    }
+
+    /// Sends `data` as a datagram to the given peer over a UDP link opened with
+    /// `UdpMode::Change`.
+    pub fn send_to(
+        &mut self,
+        link_id: Option<u8>,
+        data: &[u8],
+        host: &str,
+        port: u16,
+    ) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.send_datagram(link_id, data, Escaped(host), port)
+    }
+
+    /// Sends `data` to the multicast `group` over a link opened with `open_multicast`.
+    pub fn send_multicast(
+        &mut self,
+        link_id: Option<u8>,
+        data: &[u8],
+        group: Ipv4Addr,
+        port: u16,
+    ) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.send_datagram(link_id, data, group, port)
+    }
+
+    /// Sends `data` of any length over the open connection, split into as many `AT+CIPSEND`
+    /// transmissions as needed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// use esp_at::tcpip::MAX_SEND_LEN;
+    /// let data = [b'x'; MAX_SEND_LEN + 2];
+    /// let script = [
+    ///     Exchange::new(b"AT+CIPSEND=8192\r\n", b"\r\nOK\r\n\r\n>"),
+    ///     Exchange::new(&data[..MAX_SEND_LEN], b"\r\nRecv 8192 bytes\r\n\r\nSEND OK\r\n"),
+    ///     Exchange::new(b"AT+CIPSEND=2\r\n", b"\r\nOK\r\n\r\n>"),
+    ///     Exchange::new(b"xx", b"\r\nRecv 2 bytes\r\n\r\nSEND OK\r\n"),
+    /// ];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::TcpIp.into());
+    ///
+    /// esp32_at.send_all(None, &data).unwrap();
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn send_all(
+        &mut self,
+        link_id: Option<u8>,
+        data: &[u8],
+    ) -> Result<(), Error<RX::Error, TX::Error>> {
+        if data.is_empty() {
+            return Ok(());
+        }
+        let mut send = self.send_stream(link_id, data.len())?;
+        send.write(data)?;
+        send.finish()
+    }
+
+    /// Starts a transmission of `len` bytes of any length over the open connection, split into
+    /// as many `AT+CIPSEND` transmissions as needed, for data that isn't in memory all at once.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// use esp_at::tcpip::MAX_SEND_LEN;
+    /// let data = [b'x'; MAX_SEND_LEN + 2];
+    /// let script = [
+    ///     Exchange::new(b"AT+CIPSEND=8192\r\n", b"\r\nOK\r\n\r\n>"),
+    ///     Exchange::new(&data[..MAX_SEND_LEN], b"\r\nRecv 8192 bytes\r\n\r\nSEND OK\r\n"),
+    ///     Exchange::new(b"AT+CIPSEND=2\r\n", b"\r\nOK\r\n\r\n>"),
+    ///     Exchange::new(b"xx", b"\r\nRecv 2 bytes\r\n\r\nSEND OK\r\n"),
+    /// ];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::TcpIp.into());
+    ///
+    /// let mut send = esp32_at.send_stream(None, data.len()).unwrap();
+    /// for block in data.chunks(1000) {
+    ///     send.write(block).unwrap();
+    /// }
+    /// send.finish().unwrap();
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn send_stream(
+        &mut self,
+        link_id: Option<u8>,
+        len: usize,
+    ) -> Result<StreamingSend<'_, RX, TX>, Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::TcpIp)?;
+        self.link_prefix(link_id)?;
+        if len == 0 {
+            return Err(Error::InvalidArgument);
+        }
+        Ok(StreamingSend {
+            esp32_at: self,
+            link_id,
+            closed: false,
+            remaining: len,
+            chunk_remaining: 0,
+        })
+    }
+
+    /// Sends `data` of any length over the open connection in a single `AT+CIPSENDL`
+    /// transmission; requires esp-at 2.4 or later.
+    ///
+    /// The module reports its progress as configured with `set_large_send_config`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// let script = [
+    ///     Exchange::new(b"AT+CIPSENDL=5\r\n", b"\r\nOK\r\n\r\n>"),
+    ///     Exchange::new(b"hello", b"\r\n+CIPSENDL:5,5\r\n\r\nSEND OK\r\n"),
+    /// ];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::TcpIp.into());
+    ///
+    /// nb::block!(esp32_at.send_large(None, b"hello")).unwrap();
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn send_large(
+        &mut self,
+        link_id: Option<u8>,
+        data: &[u8],
+    ) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::TcpIp)?;
+        let link = self.link_prefix(link_id)?;
+        if data.is_empty() {
+            return Err(nb::Error::Other(Error::InvalidArgument));
+        }
+        write_command!(self, "AT+CIPSENDL={}{}", link, data.len())?;
+        self.write_payload(link_id, data)
+    }
+
+    /// Starts a single `AT+CIPSENDL` transmission of `len` bytes of any length over the open
+    /// connection, for data that isn't in memory all at once; requires esp-at 2.4 or later.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// let script = [
+    ///     Exchange::new(b"AT+CIPSENDL=10\r\n", b"\r\nOK\r\n\r\n>"),
+    ///     Exchange::new(b"hello", b""),
+    ///     Exchange::new(b"world", b"\r\n+CIPSENDL:10,10\r\n\r\nSEND OK\r\n"),
+    /// ];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::TcpIp.into());
+    ///
+    /// let mut send = esp32_at.send_large_stream(None, 10).unwrap();
+    /// send.write(b"hello").unwrap();
+    /// send.write(b"world").unwrap();
+    /// send.finish().unwrap();
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn send_large_stream(
+        &mut self,
+        link_id: Option<u8>,
+        len: usize,
+    ) -> Result<StreamingSend<'_, RX, TX>, Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::TcpIp)?;
+        let link = self.link_prefix(link_id)?;
+        if len == 0 {
+            return Err(Error::InvalidArgument);
+        }
+        nb::block!(write_command!(self, "AT+CIPSENDL={}{}", link, len))?;
+        let mut closed = false;
+        nb::block!(self.expect_prompt_with(|line| closed |= is_closed(line, link_id)))?;
+        Ok(StreamingSend {
+            esp32_at: self,
+            link_id,
+            closed,
+            remaining: len,
+            chunk_remaining: len,
+        })
+    }
+
+    /// Configures `AT+CIPSENDL`: a progress report is sent every `report_size` bytes, and data is
+    /// passed on to the network stack in segments of `transmit_size` bytes.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// let script = [Exchange::new(b"AT+CIPSENDLCFG=4096,1460\r\n", b"\r\nOK\r\n")];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::TcpIp.into());
+    ///
+    /// nb::block!(esp32_at.set_large_send_config(4096, 1460)).unwrap();
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn set_large_send_config(
+        &mut self,
+        report_size: u32,
+        transmit_size: u16,
+    ) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::TcpIp)?;
+        write_command!(self, "AT+CIPSENDLCFG={},{}", report_size, transmit_size)?;
+        self.expect_ok_response()
+    }
+
+    /// Starts a transmission of at most `max_len` bytes over the open connection, for when the
+    /// length of the data isn't known up front, e.g. in line-oriented protocols.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// let script = [
+    ///     Exchange::new(b"AT+CIPSENDEX=64\r\n", b"\r\nOK\r\n\r\n>"),
+    ///     Exchange::new(b"a\\\\b\r\n\\0", b"\r\nRecv 5 bytes\r\n\r\nSEND OK\r\n"),
+    /// ];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::TcpIp.into());
+    ///
+    /// let mut send = nb::block!(esp32_at.send_ex(None, 64)).unwrap();
+    /// nb::block!(send.write(b"a\\b")).unwrap();
+    /// nb::block!(send.write(b"\r\n")).unwrap();
+    /// send.finish().unwrap();
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn send_ex(
+        &mut self,
+        link_id: Option<u8>,
+        max_len: usize,
+    ) -> nb::Result<ExtendedSend<'_, RX, TX>, Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::TcpIp)?;
+        let link = self.link_prefix(link_id)?;
+        if max_len == 0 || max_len > MAX_SEND_LEN {
+            return Err(nb::Error::Other(Error::InvalidArgument));
+        }
+        write_command!(self, "AT+CIPSENDEX={}{}", link, max_len)?;
+        let mut closed = false;
+        self.expect_prompt_with(|line| closed |= is_closed(line, link_id))?;
+        Ok(ExtendedSend {
+            esp32_at: self,
+            link_id,
+            closed,
+            remaining: max_len,
+        })
+    }
+
+    /// Closes a connection; `link_id` identifies it in multiple connection mode.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// use esp_at::tcpip::CloseOutcome;
+    /// let script = [
+    ///     Exchange::new(b"AT+CIPCLOSE\r\n", b"CLOSED\r\n\r\nOK\r\n"),
+    ///     Exchange::new(b"AT+CIPCLOSE\r\n", b"UNLINK\r\n\r\nERROR\r\n"),
+    /// ];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::TcpIp.into());
+    ///
+    /// assert_eq!(CloseOutcome::Closed, nb::block!(esp32_at.close(None)).unwrap());
+    /// assert_eq!(CloseOutcome::AlreadyClosed, nb::block!(esp32_at.close(None)).unwrap());
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn close(
+        &mut self,
+        link_id: Option<u8>,
+    ) -> nb::Result<CloseOutcome, Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::TcpIp)?;
+        match (self.links.is_some(), link_id) {
+            (true, Some(link_id)) if link_id < link::MAX_LINKS => {
+                write_command!(self, "AT+CIPCLOSE={}", link_id)?
+            }
+            (false, None) => write_command!(self, "AT+CIPCLOSE")?,
+            _ => return Err(nb::Error::Other(Error::InvalidArgument)),
+        }
+        let outcome = self.read_close_response()?;
+        if let (Some(links), Some(link_id)) = (&mut self.links, link_id) {
+            links.release(link_id);
+        }
+        Ok(outcome)
+    }
+
+    /// Closes every open connection.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// let script = [
+    ///     Exchange::new(b"AT+CIPMUX=1\r\n", b"\r\nOK\r\n"),
+    ///     Exchange::new(b"AT+CIPCLOSE=5\r\n", b"0,CLOSED\r\n2,CLOSED\r\n\r\nOK\r\n"),
+    /// ];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::TcpIp.into());
+    ///
+    /// nb::block!(esp32_at.set_multiple_connections(true)).unwrap();
+    /// nb::block!(esp32_at.close_all()).unwrap();
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn close_all(&mut self) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::TcpIp)?;
+        if self.links.is_some() {
+            // The link id one past the last one stands for all of them
+            write_command!(self, "AT+CIPCLOSE={}", link::MAX_LINKS)?;
+        } else {
+            write_command!(self, "AT+CIPCLOSE")?;
+        }
+        self.read_close_response()?;
+        if let Some(links) = &mut self.links {
+            for link_id in 0..link::MAX_LINKS {
+                links.release(link_id);
+            }
+        }
+        Ok(())
+    }
+
+    /// Starts accepting inbound TCP connections on `port`; requires multiple connection mode.
+    ///
+    /// Each inbound connection is reported as an `Event::LinkConnected`, and takes up one of the
+    /// link ids reserved with `set_server_max_connections`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// let script = [
+    ///     Exchange::new(b"AT+CIPMUX=1\r\n", b"\r\nOK\r\n"),
+    ///     Exchange::new(b"AT+CIPSERVER=1,8080\r\n", b"\r\nOK\r\n"),
+    ///     Exchange::new(b"AT+CIPSERVER=0,1\r\n", b"\r\nOK\r\n"),
+    /// ];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::TcpIp.into());
+    ///
+    /// nb::block!(esp32_at.set_multiple_connections(true)).unwrap();
+    /// nb::block!(esp32_at.start_server(8080)).unwrap();
+    /// nb::block!(esp32_at.stop_server(true)).unwrap();
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn start_server(&mut self, port: u16) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::TcpIp)?;
+        if self.links.is_none() {
+            return Err(nb::Error::Other(Error::InvalidArgument));
+        }
+        write_command!(self, "AT+CIPSERVER=1,{}", port)?;
+        self.expect_ok_response()
+    }
+
+    /// Starts accepting inbound TLS connections on `port`; requires multiple connection mode.
+    ///
+    /// The server presents the certificate and key written with `PkiKind::ServerCertificate` and
+    /// `PkiKind::ServerKey`, and if `verify_clients` is set, only accepts clients whose
+    /// certificate is signed by the `PkiKind::ServerCa` certificate.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// let script = [
+    ///     Exchange::new(b"AT+CIPMUX=1\r\n", b"\r\nOK\r\n"),
+    ///     Exchange::new(b"AT+CIPSERVER=1,443,\"SSL\",1\r\n", b"\r\nOK\r\n"),
+    /// ];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::TcpIp | CommandSet::Tls);
+    ///
+    /// nb::block!(esp32_at.set_multiple_connections(true)).unwrap();
+    /// nb::block!(esp32_at.start_ssl_server(443, true)).unwrap();
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn start_ssl_server(
+        &mut self,
+        port: u16,
+        verify_clients: bool,
+    ) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::TcpIp)?;
+        self.require_command_set(CommandSet::Tls)?;
+        if self.links.is_none() {
+            return Err(nb::Error::Other(Error::InvalidArgument));
+        }
+        write_command!(
+            self,
+            "AT+CIPSERVER=1,{},\"SSL\",{}",
+            port,
+            verify_clients as u8
+        )?;
+        self.expect_ok_response()
+    }
+
+    /// Limits how many inbound connections the server accepts at the same time; must be called
+    /// before `start_server`.
+    ///
+    /// In multiple connection mode, this many link ids are also reserved for the server.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// let script = [
+    ///     Exchange::new(b"AT+CIPMUX=1\r\n", b"\r\nOK\r\n"),
+    ///     Exchange::new(b"AT+CIPSERVERMAXCONN=2\r\n", b"\r\nOK\r\n"),
+    ///     Exchange::new(b"AT+CIPSERVERMAXCONN?\r\n", b"+CIPSERVERMAXCONN:2\r\n\r\nOK\r\n"),
+    /// ];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::TcpIp.into());
+    ///
+    /// nb::block!(esp32_at.set_multiple_connections(true)).unwrap();
+    /// nb::block!(esp32_at.set_server_max_connections(2)).unwrap();
+    /// assert_eq!(2, esp32_at.link_allocator().unwrap().server_quota());
+    /// assert_eq!(2, nb::block!(esp32_at.get_server_max_connections()).unwrap());
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn set_server_max_connections(
+        &mut self,
+        count: u8,
+    ) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::TcpIp)?;
+        if count == 0 || count > link::MAX_LINKS {
+            return Err(nb::Error::Other(Error::InvalidArgument));
+        }
+        write_command!(self, "AT+CIPSERVERMAXCONN={}", count)?;
+        self.expect_ok_response()?;
+        if let Some(links) = &mut self.links {
+            links.reserve_for_server(count);
+        }
+        Ok(())
+    }
+
+    pub fn get_server_max_connections(&mut self) -> nb::Result<u8, Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::TcpIp)?;
+        write_command!(self, "AT+CIPSERVERMAXCONN?")?;
+        let count = self.read_query_response("CIPSERVERMAXCONN")?;
+        Ok(count.parse().map_err(|_| Error::UnexpectedResponse)?)
+    }
+
+    /// Sets how long inbound connections may be idle before the server closes them, up to 7200
+    /// seconds; 0 disables the timeout.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// let script = [
+    ///     Exchange::new(b"AT+CIPSTO=300\r\n", b"\r\nOK\r\n"),
+    ///     Exchange::new(b"AT+CIPSTO?\r\n", b"+CIPSTO:300\r\n\r\nOK\r\n"),
+    /// ];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::TcpIp.into());
+    ///
+    /// nb::block!(esp32_at.set_server_timeout(300)).unwrap();
+    /// assert_eq!(300, nb::block!(esp32_at.get_server_timeout()).unwrap());
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn set_server_timeout(
+        &mut self,
+        timeout_secs: u16,
+    ) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::TcpIp)?;
+        if timeout_secs > MAX_SERVER_TIMEOUT_SECS {
+            return Err(nb::Error::Other(Error::InvalidArgument));
+        }
+        write_command!(self, "AT+CIPSTO={}", timeout_secs)?;
+        self.expect_ok_response()
+    }
+
+    pub fn get_server_timeout(&mut self) -> nb::Result<u16, Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::TcpIp)?;
+        write_command!(self, "AT+CIPSTO?")?;
+        let timeout = self.read_query_response("CIPSTO")?;
+        Ok(timeout.parse().map_err(|_| Error::UnexpectedResponse)?)
+    }
+
+    /// Stops accepting inbound connections, and optionally closes the ones that were accepted.
+    pub fn stop_server(
+        &mut self,
+        close_connections: bool,
+    ) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::TcpIp)?;
+        write_command!(self, "AT+CIPSERVER=0,{}", close_connections as u8)?;
+        self.expect_ok_response()?;
+        if let (Some(links), true) = (&mut self.links, close_connections) {
+            links.release_server_links();
+        }
+        Ok(())
+    }
+
+    /// Looks up the IP address of `hostname`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// let script = [Exchange::new(
+    ///     b"AT+CIPDOMAIN=\"example.com\"\r\n",
+    ///     b"+CIPDOMAIN:\"93.184.216.34\"\r\n\r\nOK\r\n",
+    /// )];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::TcpIp.into());
+    ///
+    /// let ip = nb::block!(esp32_at.resolve("example.com")).unwrap();
+    /// assert_eq!(core::net::IpAddr::from([93, 184, 216, 34]), ip);
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn resolve(&mut self, hostname: &str) -> nb::Result<IpAddr, Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::TcpIp)?;
+        write_command!(self, "AT+CIPDOMAIN=\"{}\"", Escaped(hostname))?;
+        self.read_resolve_response()
+    }
+
+    /// Enables or disables IPv6, which has to be enabled before connecting to the access point for
+    /// the station to be assigned IPv6 addresses.
+    ///
+    /// Connecting to an IPv6 address literal uses IPv6, e.g. with `connect_tcp`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// let script = [
+    ///     Exchange::new(b"AT+CIPV6=1\r\n", b"\r\nOK\r\n"),
+    ///     Exchange::new(
+    ///         b"AT+CIPSTART=\"TCPv6\",\"2001:db8::1\",80,0\r\n",
+    ///         b"CONNECT\r\n\r\nOK\r\n",
+    ///     ),
+    /// ];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::TcpIp.into());
+    ///
+    /// nb::block!(esp32_at.set_ipv6(true)).unwrap();
+    /// nb::block!(esp32_at.connect_tcp("2001:db8::1", 80, 0)).unwrap();
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn set_ipv6(&mut self, enabled: bool) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::TcpIp)?;
+        write_command!(self, "AT+CIPV6={}", enabled as u8)?;
+        self.expect_ok_response()
+    }
+
+    pub fn get_ipv6(&mut self) -> nb::Result<bool, Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::TcpIp)?;
+        write_command!(self, "AT+CIPV6?")?;
+        match self.read_query_response("CIPV6")?.as_str() {
+            "0" => Ok(false),
+            "1" => Ok(true),
+            _ => Err(nb::Error::Other(Error::UnexpectedResponse)),
+        }
+    }
+
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// let script = [Exchange::new(
+    ///     b"AT+CIPSTA?\r\n",
+    ///     b"+CIPSTA:ip:\"192.168.1.20\"\r\n\
+    ///       +CIPSTA:gateway:\"192.168.1.1\"\r\n\
+    ///       +CIPSTA:netmask:\"255.255.255.0\"\r\n\
+    ///       +CIPSTA:ip6ll:\"fe80::260a:c4ff:fe00:1\"\r\n\
+    ///       \r\nOK\r\n",
+    /// )];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::TcpIp.into());
+    ///
+    /// let addresses = nb::block!(esp32_at.get_station_addresses()).unwrap();
+    /// assert_eq!([192, 168, 1, 20], addresses.ip.octets());
+    /// assert!(addresses.ipv6_link_local.is_some());
+    /// assert_eq!(None, addresses.ipv6_global);
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn get_station_addresses(
+        &mut self,
+    ) -> nb::Result<StationAddresses, Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::TcpIp)?;
+        write_command!(self, "AT+CIPSTA?")?;
+        let mut addresses = StationAddresses {
+            ip: Ipv4Addr::UNSPECIFIED,
+            gateway: Ipv4Addr::UNSPECIFIED,
+            netmask: Ipv4Addr::UNSPECIFIED,
+            ipv6_link_local: None,
+            ipv6_global: None,
+        };
+        self.read_response(|line| {
+            let (name, address) = match parser::response(line, "CIPSTA")
+                .and_then(|arguments| arguments.split_once(':'))
+            {
+                Some((name, address)) => (name, parser::unquote(address)),
+                None => return Ok(()),
+            };
+            match name {
+                "ip" => addresses.ip = parse_address(address)?,
+                "gateway" => addresses.gateway = parse_address(address)?,
+                "netmask" => addresses.netmask = parse_address(address)?,
+                "ip6ll" => addresses.ipv6_link_local = Some(parse_address(address)?),
+                "ip6gl" => addresses.ipv6_global = Some(parse_address(address)?),
+                _ => {}
+            }
+            Ok(())
+        })?;
+        Ok(addresses)
+    }
+
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// let script = [Exchange::new(
+    ///     b"AT+CIFSR\r\n",
+    ///     b"+CIFSR:STAIP,\"192.168.1.20\"\r\n\
+    ///       +CIFSR:STAIP6GL,\"2001:db8::20\"\r\n\
+    ///       +CIFSR:STAMAC,\"24:0a:c4:00:00:01\"\r\n\
+    ///       \r\nOK\r\n",
+    /// )];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::TcpIp.into());
+    ///
+    /// let addresses = nb::block!(esp32_at.get_local_addresses()).unwrap();
+    /// assert_eq!(Some([192, 168, 1, 20].into()), addresses.station_ip);
+    /// assert_eq!("2001:db8::20".parse().ok(), addresses.station_ipv6_global);
+    /// assert_eq!(Some([0x24, 0x0a, 0xc4, 0, 0, 1]), addresses.station_mac);
+    /// assert_eq!(None, addresses.soft_ap_ip);
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn get_local_addresses(
+        &mut self,
+    ) -> nb::Result<LocalAddresses, Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::TcpIp)?;
+        write_command!(self, "AT+CIFSR")?;
+        let mut addresses = LocalAddresses::default();
+        self.read_response(|line| {
+            let (name, address) = match parser::response(line, "CIFSR")
+                .and_then(|arguments| arguments.split_once(','))
+            {
+                Some((name, address)) => (name, parser::unquote(address)),
+                None => return Ok(()),
+            };
+            match name {
+                "STAIP" => addresses.station_ip = Some(parse_address(address)?),
+                "STAIP6LL" => addresses.station_ipv6_link_local = Some(parse_address(address)?),
+                "STAIP6GL" => addresses.station_ipv6_global = Some(parse_address(address)?),
+                "STAMAC" => addresses.station_mac = Some(parse_mac(address)?),
+                "APIP" => addresses.soft_ap_ip = Some(parse_address(address)?),
+                "APIP6LL" => addresses.soft_ap_ipv6_link_local = Some(parse_address(address)?),
+                "APIP6GL" => addresses.soft_ap_ipv6_global = Some(parse_address(address)?),
+                "APMAC" => addresses.soft_ap_mac = Some(parse_mac(address)?),
+                _ => {}
+            }
+            Ok(())
+        })?;
+        Ok(addresses)
+    }
+
+    /// Looks up the IPv6 address of a host name; requires IPv6 to be enabled.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// let script = [Exchange::new(
+    ///     b"AT+CIPDOMAIN=\"example.com\",2\r\n",
+    ///     b"+CIPDOMAIN:\"2606:2800:220:1:248:1893:25c8:1946\"\r\n\r\nOK\r\n",
+    /// )];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::TcpIp.into());
+    ///
+    /// let ip = nb::block!(esp32_at.resolve_ipv6("example.com")).unwrap();
+    /// assert_eq!("2606:2800:220:1:248:1893:25c8:1946".parse(), Ok(ip));
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn resolve_ipv6(
+        &mut self,
+        hostname: &str,
+    ) -> nb::Result<Ipv6Addr, Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::TcpIp)?;
+        write_command!(self, "AT+CIPDOMAIN=\"{}\",2", Escaped(hostname))?;
+        match self.read_resolve_response()? {
+            IpAddr::V6(ip) => Ok(ip),
+            IpAddr::V4(_) => Err(nb::Error::Other(Error::UnexpectedResponse)),
+        }
+    }
+
+    /// Uses the given DNS servers instead of the ones provided by DHCP; at most three can be
+    /// given.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// let script = [
+    ///     Exchange::new(b"AT+CIPDNS=1,\"1.1.1.1\",\"9.9.9.9\"\r\n", b"\r\nOK\r\n"),
+    ///     Exchange::new(b"AT+CIPDNS?\r\n", b"+CIPDNS:1,\"1.1.1.1\",\"9.9.9.9\"\r\n\r\nOK\r\n"),
+    ///     Exchange::new(b"AT+CIPDNS=0\r\n", b"\r\nOK\r\n"),
+    /// ];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::TcpIp.into());
+    ///
+    /// let servers = [[1, 1, 1, 1].into(), [9, 9, 9, 9].into()];
+    /// nb::block!(esp32_at.set_dns_servers(&servers)).unwrap();
+    /// let config = nb::block!(esp32_at.get_dns_config()).unwrap();
+    /// assert!(config.manual);
+    /// assert_eq!(&servers[..], &config.servers[..]);
+    /// nb::block!(esp32_at.use_dhcp_dns_servers()).unwrap();
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn set_dns_servers(
+        &mut self,
+        servers: &[IpAddr],
+    ) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::TcpIp)?;
+        if servers.is_empty() || servers.len() > 3 {
+            return Err(nb::Error::Other(Error::InvalidArgument));
+        }
+        write_command!(self, "AT+CIPDNS=1{}", DnsServers(servers))?;
+        self.expect_ok_response()
+    }
+
+    /// Goes back to using the DNS servers provided by DHCP.
+    pub fn use_dhcp_dns_servers(&mut self) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::TcpIp)?;
+        write_command!(self, "AT+CIPDNS=0")?;
+        self.expect_ok_response()
+    }
+
+    pub fn get_dns_config(&mut self) -> nb::Result<DnsConfig, Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::TcpIp)?;
+        write_command!(self, "AT+CIPDNS?")?;
+        let arguments = self.read_query_response("CIPDNS")?;
+
+        let mut fields = parser::fields(&arguments);
+        let manual = match fields.next() {
+            Some("0") => false,
+            Some("1") => true,
+            _ => return Err(nb::Error::Other(Error::UnexpectedResponse)),
+        };
+        let mut servers = heapless::Vec::new();
+        for field in fields {
+            let server = parser::unquote(field)
+                .parse()
+                .map_err(|_| Error::UnexpectedResponse)?;
+            servers.push(server).map_err(|_| Error::BufferOverflow)?;
+        }
+        Ok(DnsConfig { manual, servers })
+    }
+
+    /// Pings `host`, returning the round-trip time in milliseconds, or `None` if no reply was
+    /// received.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// let script = [
+    ///     Exchange::new(b"AT+PING=\"example.com\"\r\n", b"+PING:12\r\n\r\nOK\r\n"),
+    ///     Exchange::new(b"AT+PING=\"example.com\"\r\n", b"+PING:TIMEOUT\r\n\r\nERROR\r\n"),
+    /// ];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::TcpIp.into());
+    ///
+    /// assert_eq!(Some(12), nb::block!(esp32_at.ping("example.com")).unwrap());
+    /// assert_eq!(None, nb::block!(esp32_at.ping("example.com")).unwrap());
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn ping(&mut self, host: &str) -> nb::Result<Option<u32>, Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::TcpIp)?;
+        write_command!(self, "AT+PING=\"{}\"", Escaped(host))?;
+
+        let mut time = None;
+        let mut timed_out = false;
+        let result = self.read_response(|line| {
+            match parser::response(line, "PING") {
+                Some("TIMEOUT") => timed_out = true,
+                Some(ms) => time = Some(ms.parse().map_err(|_| Error::UnexpectedResponse)?),
+                None => {}
+            }
+            Ok(())
+        });
+
+        match result {
+            Ok(()) => Ok(Some(time.ok_or(Error::UnexpectedResponse)?)),
+            Err(nb::Error::Other(Error::CommandFailed)) if timed_out => Ok(None),
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Pings `host` `count` times, summarizing the round-trip times of the replies.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// let script = [
+    ///     Exchange::new(b"AT+PING=\"10.0.0.1\"\r\n", b"+PING:10\r\n\r\nOK\r\n"),
+    ///     Exchange::new(b"AT+PING=\"10.0.0.1\"\r\n", b"+PING:TIMEOUT\r\n\r\nERROR\r\n"),
+    ///     Exchange::new(b"AT+PING=\"10.0.0.1\"\r\n", b"+PING:30\r\n\r\nOK\r\n"),
+    /// ];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::TcpIp.into());
+    ///
+    /// let stats = esp32_at.ping_n("10.0.0.1", 3).unwrap();
+    /// assert_eq!((10, 20, 30), (stats.min_ms, stats.avg_ms, stats.max_ms));
+    /// assert_eq!(33, stats.loss_percent());
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn ping_n(
+        &mut self,
+        host: &str,
+        count: u16,
+    ) -> Result<PingStats, Error<RX::Error, TX::Error>> {
+        let mut stats = PingStats::default();
+        let mut total_ms = 0u64;
+        for _ in 0..count {
+            stats.sent += 1;
+            if let Some(ms) = nb::block!(self.ping(host))? {
+                stats.min_ms = if stats.received == 0 {
+                    ms
+                } else {
+                    stats.min_ms.min(ms)
+                };
+                stats.max_ms = stats.max_ms.max(ms);
+                stats.received += 1;
+                total_ms += u64::from(ms);
+            }
+        }
+        if stats.received > 0 {
+            stats.avg_ms = (total_ms / u64::from(stats.received)) as u32;
+        }
+        Ok(stats)
+    }
+
+    /// Returns the state of the network connection and of every open connection.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// use esp_at::tcpip::NetworkState;
+    /// let script = [Exchange::new(b"AT+CIPSTATUS\r\n", b"STATUS:3\r\n+CIPSTATUS:0,\"TCP\",\"192.168.1.2\",8080,50123,0\r\n\r\nOK\r\n")];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::TcpIp.into());
+    ///
+    /// let status = nb::block!(esp32_at.connection_status()).unwrap();
+    /// assert_eq!(NetworkState::Transmitting, status.state);
+    /// assert_eq!(8080, status.links[0].remote_port);
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn connection_status(
+        &mut self,
+    ) -> nb::Result<ConnectionStatus, Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::TcpIp)?;
+        write_command!(self, "AT+CIPSTATUS")?;
+
+        let mut state = None;
+        let mut links = heapless::Vec::new();
+        self.read_response(|line| {
+            if let Some(code) = line.strip_prefix("STATUS:") {
+                state = NetworkState::from_code(code);
+            } else if let Some(arguments) = parser::response(line, "CIPSTATUS") {
+                push_link_status(&mut links, arguments)?;
+            }
+            Ok(())
+        })?;
+        let state = state.ok_or(Error::UnexpectedResponse)?;
+
+        // Newer firmware only lists the connections in response to AT+CIPSTATE?
+        if links.is_empty() && state == NetworkState::Transmitting {
+            write_command!(self, "AT+CIPSTATE?")?;
+            self.read_response(|line| {
+                if let Some(arguments) = parser::response(line, "CIPSTATE") {
+                    push_link_status(&mut links, arguments)?;
+                }
+                Ok(())
+            })?;
+        }
+
+        Ok(ConnectionStatus { state, links })
+    }
+
+    fn open_udp_link<H>(
+        &mut self,
+        protocol: Protocol,
+        host: H,
+        remote_port: u16,
+        local_port: u16,
+        mode: UdpMode,
+    ) -> nb::Result<Connection, Error<RX::Error, TX::Error>>
+    where
+        H: fmt::Display,
+    {
+        self.require_command_set(CommandSet::TcpIp)?;
+        self.start_connection(|this, link| {
+            write_command!(
+                this,
+                "AT+CIPSTART={}\"{}\",\"{}\",{},{},{}",
+                link,
+                protocol,
+                host,
+                remote_port,
+                local_port,
+                mode.code()
+            )
+        })
+    }
+
+    fn send_datagram<H>(
+        &mut self,
+        link_id: Option<u8>,
+        data: &[u8],
+        host: H,
+        port: u16,
+    ) -> nb::Result<(), Error<RX::Error, TX::Error>>
+    where
+        H: fmt::Display,
+    {
+        self.require_command_set(CommandSet::TcpIp)?;
+        let link = self.link_prefix(link_id)?;
+        if data.is_empty() || data.len() > MAX_SEND_LEN {
+            return Err(nb::Error::Other(Error::InvalidArgument));
+        }
+        write_command!(
+            self,
+            "AT+CIPSEND={}{},\"{}\",{}",
+            link,
+            data.len(),
+            host,
+            port
+        )?;
+        self.write_payload(link_id, data)
+    }
+
+    /// Writes the payload of `AT+CIPSEND` once the module is ready for it.
+    fn write_payload(
+        &mut self,
+        link_id: Option<u8>,
+        data: &[u8],
+    ) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        let mut closed = false;
+        self.expect_prompt_with(|line| closed |= is_closed(line, link_id))?;
+        self.write_all(data)?;
+        self.read_send_response(link_id, closed)
+    }
+
+    /// Waits for the module to report whether the payload of `AT+CIPSEND` has been sent on the
+    /// link, which might have been `closed` already while writing it.
+    fn read_send_response(
+        &mut self,
+        link_id: Option<u8>,
+        mut closed: bool,
+    ) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        loop {
+            let line: Line = self.read_line()?;
+            match line.as_str() {
+                "SEND OK" => return Ok(()),
+                "SEND FAIL" | "ERROR" if closed => {
+                    return Err(nb::Error::Other(Error::LinkClosed { link_id }))
+                }
+                "SEND FAIL" | "ERROR" => {
+                    return Err(nb::Error::Other(Error::SendFailed { link_id }))
+                }
+                line => {
+                    closed |= is_closed(line, link_id);
+                    self.queue_event(line)
+                }
+            }
+        }
+    }
+
+    fn read_resolve_response(&mut self) -> nb::Result<IpAddr, Error<RX::Error, TX::Error>> {
+        let mut ip = None;
+        let result = self.read_response(|line| {
+            if let Some(address) = parser::response(line, "CIPDOMAIN") {
+                // Older firmware doesn't quote the address
+                let address = parser::unquote(address);
+                ip = Some(address.parse().map_err(|_| Error::UnexpectedResponse)?);
+            }
+            Ok(())
+        });
+
+        match result {
+            Ok(()) => Ok(ip.ok_or(Error::UnexpectedResponse)?),
+            Err(nb::Error::Other(Error::CommandFailed)) => {
+                Err(nb::Error::Other(Error::ResolveFailed))
+            }
+            Err(error) => Err(error),
+        }
+    }
+
+    fn read_close_response(&mut self) -> nb::Result<CloseOutcome, Error<RX::Error, TX::Error>> {
+        let mut unlinked = false;
+        let result = self.read_response(|line| {
+            unlinked |= line == "UNLINK";
+            Ok(())
+        });
+
+        match result {
+            Ok(()) => Ok(CloseOutcome::Closed),
+            Err(nb::Error::Other(Error::CommandFailed)) if unlinked => {
+                Ok(CloseOutcome::AlreadyClosed)
+            }
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Picks a link id in multiple connection mode and establishes a connection with the command
+    /// written by `write`, which is passed the link id prefix for its arguments.
+    fn start_connection<F>(
+        &mut self,
+        write: F,
+    ) -> nb::Result<Connection, Error<RX::Error, TX::Error>>
+    where
+        F: FnOnce(&mut Self, LinkPrefix) -> nb::Result<(), Error<RX::Error, TX::Error>>,
+    {
+        let link_id = match &mut self.links {
+            Some(links) => Some(links.allocate().ok_or(Error::NoFreeLink)?),
+            None => None,
+        };
+
+        let result = write(self, LinkPrefix(link_id)).and_then(|()| self.read_connect_response());
+        match (result, &mut self.links, link_id) {
+            (Ok(outcome), _, _) => Ok(Connection { link_id, outcome }),
+            (Err(error), Some(links), Some(link_id)) => {
+                links.release(link_id);
+                Err(error)
+            }
+            (Err(error), _, _) => Err(error),
+        }
+    }
+
+    /// Checks that `link_id` is given exactly in multiple connection mode, and refers to a link
+    /// that is in use.
+    pub(crate) fn link_prefix(
+        &self,
+        link_id: Option<u8>,
+    ) -> Result<LinkPrefix, Error<RX::Error, TX::Error>> {
+        match (&self.links, link_id) {
+            (Some(links), Some(link_id)) if links.is_in_use(link_id) => {
+                Ok(LinkPrefix(Some(link_id)))
+            }
+            (Some(_), Some(link_id)) if link_id < link::MAX_LINKS => Err(Error::LinkClosed {
+                link_id: Some(link_id),
+            }),
+            (None, None) => Ok(LinkPrefix(None)),
+            _ => Err(Error::InvalidArgument),
+        }
+    }
+
+    /// Reads the response to a query command that reports a setting of every link, returning the
+    /// arguments of the `+<name>:` line of the link `link_id`, without the link id itself.
+    fn read_link_query_response(
+        &mut self,
+        name: &str,
+        link_id: Option<u8>,
+    ) -> nb::Result<Line, Error<RX::Error, TX::Error>> {
+        let mut value = None;
+        self.read_response(|line| {
+            let arguments = match (parser::response(line, name), link_id) {
+                (Some(arguments), None) => arguments,
+                (Some(arguments), Some(link_id)) => match arguments.split_once(',') {
+                    Some((id, arguments)) if id.parse() == Ok(link_id) => arguments,
+                    _ => return Ok(()),
+                },
+                (None, _) => return Ok(()),
+            };
+            value = Some(Line::from(arguments));
+            Ok(())
+        })?;
+        Ok(value.ok_or(Error::UnexpectedResponse)?)
+    }
+
+    /// Validates the link id of a command that configures a link, which in multiple connection
+    /// mode configures all of them if `link_id` is `None`.
+    fn config_link_prefix(
+        &self,
+        link_id: Option<u8>,
+    ) -> Result<LinkPrefix, Error<RX::Error, TX::Error>> {
+        match (self.links.is_some(), link_id) {
+            (true, Some(link_id)) if link_id < link::MAX_LINKS => Ok(LinkPrefix(Some(link_id))),
+            // The link id one past the last one stands for all of them
+            (true, None) => Ok(LinkPrefix(Some(link::MAX_LINKS))),
+            (false, None) => Ok(LinkPrefix(None)),
+            _ => Err(Error::InvalidArgument),
+        }
+    }
+
+    /// Keeps track of the links that the module reports as connected or closed, returning
+    /// whether an inbound connection was refused.
+    ///
+    /// Inbound connections are recorded as accepted by the server, unless they exceed its
+    /// reservation; the application is expected to close those when it sees their
+    /// `Event::LinkConnected`.
+    pub(crate) fn track_link(&mut self, line: &str) -> bool {
+        match line {
+            "CONNECT" => {
+                self.received.sink.set_closed(0, false);
+                return false;
+            }
+            "CLOSED" => {
+                self.received.sink.set_closed(0, true);
+                return false;
+            }
+            _ => {}
+        }
+        let links = match &mut self.links {
+            Some(links) => links,
+            None => return false,
+        };
+        let mut parts = line.splitn(3, ',');
+        let link_id: u8 = match parts.next().map(str::parse) {
+            Some(Ok(link_id)) if link_id < link::MAX_LINKS => link_id,
+            _ => return false,
+        };
+        let mut refused = false;
+        match parts.next() {
+            Some("CLOSED") => {
+                links.release(link_id);
+                self.received.sink.set_closed(link_id as usize, true);
+            }
+            Some("CONNECT") => {
+                // Outbound connections have been allocated their link id already
+                if !links.is_in_use(link_id) && !links.accept(link_id) {
+                    links.refuse(link_id);
+                    refused = true;
+                }
+                self.received.sink.set_closed(link_id as usize, false);
+            }
+            _ => {}
+        }
+        refused
+    }
+
+    fn read_connect_response(&mut self) -> nb::Result<ConnectOutcome, Error<RX::Error, TX::Error>> {
+        let mut already_connected = false;
+        let result = self.read_response(|line| {
+            already_connected |= line == "ALREADY CONNECTED";
+            Ok(())
+        });
+
+        match result {
+            Ok(()) => Ok(ConnectOutcome::Connected),
+            Err(nb::Error::Other(Error::CommandFailed)) if already_connected => {
+                Ok(ConnectOutcome::AlreadyConnected)
+            }
+            Err(nb::Error::Other(Error::CommandFailed)) => {
+                Err(nb::Error::Other(Error::ConnectFailed))
+            }
+            Err(error) => Err(error),
+        }
+    }
+}
+
+impl<'a, RX, TX> ExtendedSend<'a, RX, TX>
+where
+    RX: embedded_hal::serial::Read<u8>,
+    TX: embedded_hal::serial::Write<u8>,
+{
+    /// How many more bytes can be written before the data is sent.
+    pub fn remaining(&self) -> usize {
+        self.remaining
+    }
+
+    /// Appends `data` to the transmission; fails if it doesn't fit in the remaining length.
+    pub fn write(&mut self, data: &[u8]) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        if data.len() > self.remaining {
+            return Err(nb::Error::Other(Error::InvalidArgument));
+        }
+        for &byte in data {
+            // A backslash starts an escape sequence, and `\0` would end the transmission
+            if byte == b'\\' {
+                self.esp32_at.putc(b'\\')?;
+            }
+            self.esp32_at.putc(byte)?;
+        }
+        self.remaining -= data.len();
+        Ok(())
+    }
+
+    /// Sends the data written so far, returning once the module has handed it to the network
+    /// stack.
+    pub fn finish(self) -> Result<(), Error<RX::Error, TX::Error>> {
+        if self.remaining > 0 {
+            nb::block!(self.esp32_at.write_all(b"\\0"))?;
+        }
+        nb::block!(self.esp32_at.read_send_response(self.link_id, self.closed))
+    }
+}
+
+impl<'a, RX, TX> StreamingSend<'a, RX, TX>
+where
+    RX: embedded_hal::serial::Read<u8>,
+    TX: embedded_hal::serial::Write<u8>,
+{
+    /// How many more bytes have to be written before the transmission can be finished.
+    pub fn remaining(&self) -> usize {
+        self.remaining
+    }
+
+    /// Appends `data` to the transmission, blocking while the module sends the parts that are
+    /// complete; fails if it doesn't fit in the remaining length.
+    pub fn write(&mut self, mut data: &[u8]) -> Result<(), Error<RX::Error, TX::Error>> {
+        if data.len() > self.remaining {
+            return Err(Error::InvalidArgument);
+        }
+        while !data.is_empty() {
+            if self.chunk_remaining == 0 {
+                self.start_chunk()?;
+            }
+            let (chunk, rest) = data.split_at(data.len().min(self.chunk_remaining));
+            nb::block!(self.esp32_at.write_all(chunk))?;
+            self.chunk_remaining -= chunk.len();
+            self.remaining -= chunk.len();
+            // The response to the last part is read by `finish`
+            if self.chunk_remaining == 0 && self.remaining > 0 {
+                nb::block!(self.esp32_at.read_send_response(self.link_id, self.closed))?;
+            }
+            data = rest;
+        }
+        Ok(())
+    }
+
+    /// Waits for the module to send the rest of the data, returning once it has been handed to
+    /// the network stack; fails if less than the length it was started with has been written.
+    pub fn finish(self) -> Result<(), Error<RX::Error, TX::Error>> {
+        if self.remaining > 0 {
+            return Err(Error::InvalidArgument);
+        }
+        nb::block!(self.esp32_at.read_send_response(self.link_id, self.closed))
+    }
+
+    /// Starts an `AT+CIPSEND` transmission of the next part of the data.
+    fn start_chunk(&mut self) -> Result<(), Error<RX::Error, TX::Error>> {
+        let len = self.remaining.min(MAX_SEND_LEN);
+        let link_id = self.link_id;
+        let link = LinkPrefix(link_id);
+        nb::block!(write_command!(self.esp32_at, "AT+CIPSEND={}{}", link, len))?;
+        let mut closed = false;
+        nb::block!(self
+            .esp32_at
+            .expect_prompt_with(|line| closed |= is_closed(line, link_id)))?;
+        self.closed = closed;
+        self.chunk_remaining = len;
+        Ok(())
+    }
+}
+
+/// Formats DNS servers as quoted arguments, each preceded by a comma.
+struct DnsServers<'a>(&'a [IpAddr]);
+
+impl<'a> fmt::Display for DnsServers<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for server in self.0 {
+            write!(f, ",\"{}\"", server)?;
+        }
+        Ok(())
+    }
+}
+
+/// Formats the protocol argument of `AT+CIPSTART`, which has a `v6` suffix for IPv6 hosts.
+struct Protocol {
+    name: &'static str,
+    ipv6: bool,
+}
+
+impl Protocol {
+    fn for_host(name: &'static str, host: &str) -> Self {
+        Protocol {
+            name,
+            ipv6: host.parse::<Ipv6Addr>().is_ok(),
+        }
+    }
+}
+
+impl fmt::Display for Protocol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name)?;
+        if self.ipv6 {
+            f.write_str("v6")?;
+        }
+        Ok(())
+    }
+}
+
+/// Formats the link id argument that commands take in multiple connection mode.
+pub(crate) struct LinkPrefix(Option<u8>);
+
+impl fmt::Display for LinkPrefix {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            Some(link_id) => write!(f, "{},", link_id),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Whether `line` reports that the link has been closed.
+fn is_closed(line: &str, link_id: Option<u8>) -> bool {
+    event::parse(line) == Some(Event::LinkClosed { link_id })
+}
+
+pub(crate) fn parse_address<A, RXE, TXE>(address: &str) -> Result<A, Error<RXE, TXE>>
+where
+    A: core::str::FromStr,
+{
+    address.parse().map_err(|_| Error::UnexpectedResponse)
+}
+
+pub(crate) fn parse_mac<RXE, TXE>(mac: &str) -> Result<[u8; 6], Error<RXE, TXE>> {
+    parse_octets(mac).ok_or(Error::UnexpectedResponse)
+}
+
+/// Parses colon-separated hex octets, like a MAC or BLE address.
+pub(crate) fn parse_octets(mac: &str) -> Option<[u8; 6]> {
+    let mut octets = [0; 6];
+    let mut parts = mac.split(':');
+    for octet in octets.iter_mut() {
+        *octet = u8::from_str_radix(parts.next()?, 16).ok()?;
+    }
+    if parts.next().is_some() {
+        return None;
+    }
+    Some(octets)
+}
+
+/// Formats a MAC address as colon-separated hex octets.
+pub(crate) struct Mac(pub(crate) [u8; 6]);
+
+impl fmt::Display for Mac {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, octet) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str(":")?;
+            }
+            write!(f, "{:02x}", octet)?;
+        }
+        Ok(())
+    }
+}
+
+fn push_link_status<RXE, TXE>(
+    links: &mut heapless::Vec<LinkStatus, heapless::consts::U5>,
+    arguments: &str,
+) -> Result<(), Error<RXE, TXE>> {
+    let link = LinkStatus::parse(arguments).ok_or(Error::UnexpectedResponse)?;
+    links.push(link).or(Err(Error::BufferOverflow))
+}
@@ -0,0 +1,127 @@
+//! Helpers for testing applications against the kind of misbehavior real UARTs exhibit.
+
+use std::collections::VecDeque;
+use std::thread;
+use std::time::Duration;
+
+/// The faults injected by a `FaultyTransport`.  Probabilities are per byte, except for
+/// `spurious_urc_probability` which is per received line.
+#[derive(Debug, Clone)]
+pub struct Faults {
+    pub latency: Duration,
+    pub drop_probability: f64,
+    pub bit_error_probability: f64,
+    pub spurious_urc_probability: f64,
+    /// The line injected as a spurious unsolicited message, including its line terminator.
+    pub spurious_urc: &'static [u8],
+    /// Seeds the pseudo-random fault injection, so that failures can be reproduced.
+    pub seed: u64,
+}
+
+/// Wraps either half of a serial transport, injecting faults into the bytes passing through it.
+#[derive(Debug)]
+pub struct FaultyTransport<T> {
+    inner: T,
+    faults: Faults,
+    rng: u64,
+    injected: VecDeque<u8>,
+}
+
+impl Default for Faults {
+    fn default() -> Self {
+        Self {
+            latency: Duration::from_millis(0),
+            drop_probability: 0.0,
+            bit_error_probability: 0.0,
+            spurious_urc_probability: 0.0,
+            spurious_urc: b"WIFI DISCONNECT\r\n",
+            seed: 0x2545_f491_4f6c_dd1d,
+        }
+    }
+}
+
+impl<T> FaultyTransport<T> {
+    pub fn new(inner: T, faults: Faults) -> Self {
+        Self {
+            inner,
+            // xorshift gets stuck at zero
+            rng: faults.seed.max(1),
+            faults,
+            injected: VecDeque::new(),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+
+    fn chance(&mut self, probability: f64) -> bool {
+        // xorshift64
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        let sample = (self.rng >> 11) as f64 / (1u64 << 53) as f64;
+        sample < probability
+    }
+
+    fn corrupt(&mut self, byte: u8) -> u8 {
+        if self.chance(self.faults.bit_error_probability) {
+            byte ^ 1 << (self.rng % 8)
+        } else {
+            byte
+        }
+    }
+
+    fn delay(&self) {
+        if self.faults.latency > Duration::from_millis(0) {
+            thread::sleep(self.faults.latency);
+        }
+    }
+}
+
+impl<T> embedded_hal::serial::Read<u8> for FaultyTransport<T>
+where
+    T: embedded_hal::serial::Read<u8>,
+{
+    type Error = T::Error;
+
+    fn read(&mut self) -> nb::Result<u8, Self::Error> {
+        if let Some(byte) = self.injected.pop_front() {
+            return Ok(byte);
+        }
+
+        loop {
+            let byte = self.inner.read()?;
+            self.delay();
+            if self.chance(self.faults.drop_probability) {
+                continue;
+            }
+
+            if byte == b'\n' && self.chance(self.faults.spurious_urc_probability) {
+                self.injected.extend(self.faults.spurious_urc);
+            }
+            return Ok(self.corrupt(byte));
+        }
+    }
+}
+
+impl<T> embedded_hal::serial::Write<u8> for FaultyTransport<T>
+where
+    T: embedded_hal::serial::Write<u8>,
+{
+    type Error = T::Error;
+
+    fn write(&mut self, byte: u8) -> nb::Result<(), Self::Error> {
+        self.delay();
+        if self.chance(self.faults.drop_probability) {
+            return Ok(());
+        }
+
+        let byte = self.corrupt(byte);
+        self.inner.write(byte)
+    }
+
+    fn flush(&mut self) -> nb::Result<(), Self::Error> {
+        self.inner.flush()
+    }
+}
@@ -0,0 +1,55 @@
+use crate::{CommandSet, Error, Esp32At};
+
+impl<RX, TX> Esp32At<RX, TX>
+where
+    RX: embedded_hal::serial::Read<u8>,
+    TX: embedded_hal::serial::Write<u8>,
+{
+    /// Starts the built-in provisioning web server, through which a browser can hand the module
+    /// Wi-Fi credentials.
+    ///
+    /// Progress is reported as `Event::WebServerCredentialsReceived` and
+    /// `Event::WebServerProvisioned` events.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// let script = [Exchange::new(b"AT+WEBSERVER=1,80,50\r\n", b"\r\nOK\r\n")];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::WebServer.into());
+    ///
+    /// nb::block!(esp32_at.start_web_server(80, 50)).unwrap();
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn start_web_server(
+        &mut self,
+        port: u16,
+        connection_timeout_secs: u16,
+    ) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::WebServer)?;
+        write_command!(self, "AT+WEBSERVER=1,{},{}", port, connection_timeout_secs)?;
+        self.expect_ok_response()
+    }
+
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// let script = [Exchange::new(b"AT+WEBSERVER=0\r\n", b"\r\nOK\r\n")];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::WebServer.into());
+    ///
+    /// nb::block!(esp32_at.stop_web_server()).unwrap();
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn stop_web_server(&mut self) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::WebServer)?;
+        write_command!(self, "AT+WEBSERVER=0")?;
+        self.expect_ok_response()
+    }
+}
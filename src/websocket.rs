@@ -0,0 +1,541 @@
+//! WebSocket connections made by the module's own WebSocket client.
+
+use crate::payload::PayloadSink;
+use crate::{parser, CommandSet, Error, Escaped, Esp32At, Line};
+
+/// How many WebSocket connections the module can have open at the same time.
+pub const MAX_LINKS: u8 = 3;
+
+/// The longest interval between pings or time to wait for a pong.
+pub const MAX_PING_SECS: u16 = 7200;
+
+/// The longest time to wait for a connection to be established.
+pub const MAX_OPEN_TIMEOUT_MS: u32 = 180_000;
+
+/// How many bytes of received frames are buffered per connection until they are taken.
+#[cfg(feature = "websocket-receive")]
+pub const RECEIVE_BUFFER_LEN: usize = 512;
+
+/// The kind of a frame that is sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Opcode {
+    /// The next part of a message whose first frame had another opcode.
+    Continuation,
+    Text,
+    Binary,
+    Close,
+    Ping,
+    Pong,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WebSocketConfig {
+    /// How often to ping the server, from 1 to `MAX_PING_SECS`; the firmware default is 10.
+    pub ping_interval_secs: u16,
+    /// How long to wait for a pong before the connection is considered lost, from 1 to
+    /// `MAX_PING_SECS`; the firmware default is 120.
+    pub ping_timeout_secs: u16,
+    /// The size of the buffer of the connection, which limits the size of the frames that can be
+    /// received; the firmware default is 1024.
+    pub buffer_size: u16,
+}
+
+#[cfg(feature = "websocket-receive")]
+type DataQueue = heapless::spsc::Queue<u8, heapless::consts::U512>;
+#[cfg(feature = "websocket-receive")]
+type FrameQueue = heapless::spsc::Queue<usize, heapless::consts::U8>;
+
+/// The frames pushed by the module as `+WS_DATA` that haven't been taken yet.
+#[cfg(feature = "websocket-receive")]
+#[derive(Debug)]
+pub(crate) struct ReceiveBuffers {
+    data: [DataQueue; MAX_LINKS as usize],
+    /// The lengths of the buffered frames of each connection.
+    frames: [FrameQueue; MAX_LINKS as usize],
+    /// The frame whose payload is currently being received.
+    incoming: Option<Incoming>,
+    /// The connections on which a frame has been dropped because there was no room for it.
+    overflowed: u8,
+}
+
+/// Skips the frames pushed by the module as `+WS_DATA`, which are only buffered with the
+/// `websocket-receive` feature.
+#[cfg(not(feature = "websocket-receive"))]
+#[derive(Debug)]
+pub(crate) struct ReceiveBuffers;
+
+#[cfg(feature = "websocket-receive")]
+#[derive(Debug, Clone, Copy)]
+struct Incoming {
+    index: usize,
+    len: usize,
+    /// Whether the frame is dropped, because there's no room for all of it.
+    discard: bool,
+}
+
+impl Opcode {
+    fn code(self) -> u8 {
+        match self {
+            Opcode::Continuation => 0,
+            Opcode::Text => 1,
+            Opcode::Binary => 2,
+            Opcode::Close => 8,
+            Opcode::Ping => 9,
+            Opcode::Pong => 10,
+        }
+    }
+}
+
+impl<RX, TX> Esp32At<RX, TX>
+where
+    RX: embedded_hal::serial::Read<u8>,
+    TX: embedded_hal::serial::Write<u8>,
+{
+    /// Configures the WebSocket connection `link_id`; must be called before opening it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// use esp_at::websocket::WebSocketConfig;
+    /// let script = [Exchange::new(b"AT+WSCFG=0,30,60,4096\r\n", b"\r\nOK\r\n")];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::WebSocket.into());
+    ///
+    /// let config = WebSocketConfig {
+    ///     ping_interval_secs: 30,
+    ///     ping_timeout_secs: 60,
+    ///     buffer_size: 4096,
+    /// };
+    /// nb::block!(esp32_at.websocket_config(0, &config)).unwrap();
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn websocket_config(
+        &mut self,
+        link_id: u8,
+        config: &WebSocketConfig,
+    ) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::WebSocket)?;
+        let valid_secs = 1..=MAX_PING_SECS;
+        if link_id >= MAX_LINKS
+            || !valid_secs.contains(&config.ping_interval_secs)
+            || !valid_secs.contains(&config.ping_timeout_secs)
+            || config.buffer_size == 0
+        {
+            return Err(nb::Error::Other(Error::InvalidArgument));
+        }
+        write_command!(
+            self,
+            "AT+WSCFG={},{},{},{}",
+            link_id,
+            config.ping_interval_secs,
+            config.ping_timeout_secs,
+            config.buffer_size
+        )?;
+        self.expect_ok_response()
+    }
+
+    /// Adds a header like `Authorization: Bearer <token>` to the requests that open WebSocket
+    /// connections, which is sent as it is and so may contain any characters.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// let script = [
+    ///     Exchange::new(b"AT+WSHEAD=25\r\n", b"\r\nOK\r\n\r\n>"),
+    ///     Exchange::new(b"Authorization: Bearer a,b", b"\r\nOK\r\n"),
+    /// ];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::WebSocket.into());
+    ///
+    /// nb::block!(esp32_at.add_websocket_header("Authorization: Bearer a,b")).unwrap();
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn add_websocket_header(
+        &mut self,
+        header: &str,
+    ) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::WebSocket)?;
+        if header.is_empty() {
+            return Err(nb::Error::Other(Error::InvalidArgument));
+        }
+        write_command!(self, "AT+WSHEAD={}", header.len())?;
+        self.expect_prompt()?;
+        self.write_all(header.as_bytes())?;
+        self.expect_ok_response()
+    }
+
+    /// Removes the headers added with `add_websocket_header`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// let script = [Exchange::new(b"AT+WSHEAD=0\r\n", b"\r\nOK\r\n")];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::WebSocket.into());
+    ///
+    /// nb::block!(esp32_at.clear_websocket_headers()).unwrap();
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn clear_websocket_headers(&mut self) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::WebSocket)?;
+        write_command!(self, "AT+WSHEAD=0")?;
+        self.expect_ok_response()
+    }
+
+    /// Opens the WebSocket connection `link_id` to `uri`, which is a `ws://` or `wss://` URI,
+    /// waiting at most `timeout_ms` for it to be established.
+    ///
+    /// `subprotocol` is requested with `Sec-WebSocket-Protocol`, unless it is empty.  A
+    /// connection that couldn't be established fails with `Error::ConnectFailed`; the connection
+    /// being lost later is reported as `Event::WebSocketDisconnected`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// use esp_at::event::Event;
+    /// let script = [Exchange::new(
+    ///     b"AT+WSOPEN=0,\"wss://example.com/live\",\"v1.json\",15000\r\n",
+    ///     b"+WS_CONNECTED:0\r\n\r\nOK\r\n",
+    /// )];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::WebSocket | CommandSet::Tls);
+    ///
+    /// nb::block!(esp32_at.websocket_open(0, "wss://example.com/live", "v1.json", 15000)).unwrap();
+    /// assert_eq!(
+    ///     Event::WebSocketConnected { link_id: 0 },
+    ///     nb::block!(esp32_at.poll_event()).unwrap()
+    /// );
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn websocket_open(
+        &mut self,
+        link_id: u8,
+        uri: &str,
+        subprotocol: &str,
+        timeout_ms: u32,
+    ) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::WebSocket)?;
+        if uri.starts_with("wss://") {
+            self.require_command_set(CommandSet::Tls)?;
+        }
+        if link_id >= MAX_LINKS || timeout_ms > MAX_OPEN_TIMEOUT_MS {
+            return Err(nb::Error::Other(Error::InvalidArgument));
+        }
+        write_command!(
+            self,
+            "AT+WSOPEN={},\"{}\",\"{}\",{}",
+            link_id,
+            Escaped(uri),
+            Escaped(subprotocol),
+            timeout_ms
+        )?;
+        self.expect_ok_response().map_err(|e| match e {
+            nb::Error::Other(Error::CommandFailed) => nb::Error::Other(Error::ConnectFailed),
+            e => e,
+        })
+    }
+
+    /// Closes the WebSocket connection `link_id`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// let script = [Exchange::new(b"AT+WSCLOSE=2\r\n", b"\r\nOK\r\n")];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::WebSocket.into());
+    ///
+    /// nb::block!(esp32_at.websocket_close(2)).unwrap();
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn websocket_close(&mut self, link_id: u8) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::WebSocket)?;
+        if link_id >= MAX_LINKS {
+            return Err(nb::Error::Other(Error::InvalidArgument));
+        }
+        write_command!(self, "AT+WSCLOSE={}", link_id)?;
+        self.expect_ok_response()
+    }
+
+    /// Sends `data` as a single frame on the WebSocket connection `link_id`.
+    ///
+    /// A frame that the module couldn't send fails with `Error::SendFailed`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// use esp_at::websocket::Opcode;
+    /// let script = [
+    ///     Exchange::new(b"AT+WSSEND=1,13,1\r\n", b"\r\nOK\r\n\r\n>"),
+    ///     Exchange::new(b"{\"temp\":21.5}", b"\r\nSEND OK\r\n"),
+    /// ];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::WebSocket.into());
+    ///
+    /// nb::block!(esp32_at.websocket_send(1, br#"{"temp":21.5}"#, Opcode::Text)).unwrap();
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn websocket_send(
+        &mut self,
+        link_id: u8,
+        data: &[u8],
+        opcode: Opcode,
+    ) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::WebSocket)?;
+        if link_id >= MAX_LINKS {
+            return Err(nb::Error::Other(Error::InvalidArgument));
+        }
+        write_command!(
+            self,
+            "AT+WSSEND={},{},{}",
+            link_id,
+            data.len(),
+            opcode.code()
+        )?;
+        self.expect_prompt()?;
+        self.write_all(data)?;
+
+        loop {
+            let line: Line = self.read_line()?;
+            match line.as_str() {
+                "SEND OK" => return Ok(()),
+                "SEND FAIL" | "ERROR" => {
+                    return Err(nb::Error::Other(Error::SendFailed {
+                        link_id: Some(link_id),
+                    }))
+                }
+                line => self.queue_event(line),
+            }
+        }
+    }
+
+    /// Takes the next frame received on the WebSocket connection `link_id`, reading its payload
+    /// into `buf` and returning its length.
+    ///
+    /// Frames received while waiting for the responses to other commands are buffered, up to
+    /// `RECEIVE_BUFFER_LEN` bytes per connection.  Frames that don't fit are dropped, which is
+    /// reported once with `Error::BufferOverflow`.  If `buf` is too small for the payload, this
+    /// fails with `Error::InvalidArgument` and the frame is kept.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// let script = [Exchange::unsolicited(
+    ///     b"+WS_DATA:0,7,hi\r\n,\r\n\r\n+WS_DATA:1,5,other\r\n+WS_DATA:0,3,bye\r\n",
+    /// )];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::WebSocket.into());
+    ///
+    /// let mut buf = [0; 16];
+    /// let len = nb::block!(esp32_at.websocket_receive(0, &mut buf)).unwrap();
+    /// assert_eq!(b"hi\r\n,\r\n", &buf[..len]);
+    /// let len = nb::block!(esp32_at.websocket_receive(0, &mut buf)).unwrap();
+    /// assert_eq!(b"bye", &buf[..len]);
+    /// let len = nb::block!(esp32_at.websocket_receive(1, &mut buf)).unwrap();
+    /// assert_eq!(b"other", &buf[..len]);
+    /// # assert!(module.is_done());
+    /// ```
+    #[cfg(feature = "websocket-receive")]
+    pub fn websocket_receive(
+        &mut self,
+        link_id: u8,
+        buf: &mut [u8],
+    ) -> nb::Result<usize, Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::WebSocket)?;
+        if link_id >= MAX_LINKS {
+            return Err(nb::Error::Other(Error::InvalidArgument));
+        }
+        self.drain_lines()?;
+
+        let index = link_id as usize;
+        let received = &mut self.websocket_received.sink;
+        if received.overflowed & 1 << index != 0 {
+            received.overflowed &= !(1 << index);
+            return Err(nb::Error::Other(Error::BufferOverflow));
+        }
+        match received.frames[index].peek() {
+            Some(&len) if len > buf.len() => return Err(nb::Error::Other(Error::InvalidArgument)),
+            Some(_) => {}
+            None => return Err(nb::Error::WouldBlock),
+        }
+        Ok(received.take(index, buf).ok_or(Error::UnexpectedResponse)?)
+    }
+
+    /// Passes every frame received so far, on any WebSocket connection, to `on_frame` along
+    /// with the connection it was received on, which suits applications that handle all frames
+    /// in the same place.
+    ///
+    /// Like `websocket_receive`, a frame that has been dropped is reported with
+    /// `Error::BufferOverflow`, after the frames that were kept have been handed over.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// let script = [Exchange::unsolicited(b"+WS_DATA:2,4,ping\r\n+WS_DATA:0,4,pong\r\n")];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::WebSocket.into());
+    ///
+    /// let mut frames = 0;
+    /// esp32_at
+    ///     .websocket_dispatch(|link_id, data| {
+    ///         match link_id {
+    ///             0 => assert_eq!(b"pong", data),
+    ///             2 => assert_eq!(b"ping", data),
+    ///             _ => unreachable!(),
+    ///         }
+    ///         frames += 1;
+    ///     })
+    ///     .unwrap();
+    /// assert_eq!(2, frames);
+    /// # assert!(module.is_done());
+    /// ```
+    #[cfg(feature = "websocket-receive")]
+    pub fn websocket_dispatch<F>(
+        &mut self,
+        mut on_frame: F,
+    ) -> Result<(), Error<RX::Error, TX::Error>>
+    where
+        F: FnMut(u8, &[u8]),
+    {
+        self.require_command_set(CommandSet::WebSocket)?;
+        self.drain_lines()?;
+
+        let received = &mut self.websocket_received.sink;
+        let mut buf = [0; RECEIVE_BUFFER_LEN];
+        for link_id in 0..MAX_LINKS {
+            while let Some(len) = received.take(link_id as usize, &mut buf) {
+                on_frame(link_id, &buf[..len]);
+            }
+        }
+        if received.overflowed != 0 {
+            received.overflowed = 0;
+            return Err(Error::BufferOverflow);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "websocket-receive")]
+impl ReceiveBuffers {
+    pub(crate) fn new() -> Self {
+        ReceiveBuffers {
+            data: [DataQueue::new(), DataQueue::new(), DataQueue::new()],
+            frames: [FrameQueue::new(), FrameQueue::new(), FrameQueue::new()],
+            incoming: None,
+            overflowed: 0,
+        }
+    }
+
+    /// Takes the next frame of a connection into `buf`, which must be large enough for it.
+    fn take(&mut self, index: usize, buf: &mut [u8]) -> Option<usize> {
+        let len = self.frames[index].dequeue()?;
+        for byte in buf[..len].iter_mut() {
+            *byte = self.data[index].dequeue()?;
+        }
+        Some(len)
+    }
+}
+
+#[cfg(feature = "websocket-receive")]
+impl PayloadSink for ReceiveBuffers {
+    const HEADER_END: u8 = b',';
+
+    fn start(&mut self, header: &[u8]) -> Option<usize> {
+        let (index, len) = parse_data_header(header)?;
+
+        // Only keep frames that fit in their entirety
+        let room = self.data[index].capacity() - self.data[index].len();
+        let discard = len > room || self.frames[index].len() == self.frames[index].capacity();
+        if discard {
+            self.overflowed |= 1 << index;
+        }
+        self.incoming = Some(Incoming {
+            index,
+            len,
+            discard,
+        });
+        Some(len)
+    }
+
+    fn push(&mut self, byte: u8) {
+        if let Some(Incoming {
+            index,
+            discard: false,
+            ..
+        }) = self.incoming
+        {
+            // Room for the whole payload was made sure of in `start`
+            let _ = self.data[index].enqueue(byte);
+        }
+    }
+
+    fn finish(&mut self) {
+        if let Some(Incoming {
+            index,
+            len,
+            discard: false,
+        }) = self.incoming.take()
+        {
+            let _ = self.frames[index].enqueue(len);
+        }
+    }
+}
+
+#[cfg(not(feature = "websocket-receive"))]
+impl ReceiveBuffers {
+    pub(crate) fn new() -> Self {
+        ReceiveBuffers
+    }
+}
+
+#[cfg(not(feature = "websocket-receive"))]
+impl PayloadSink for ReceiveBuffers {
+    const HEADER_END: u8 = b',';
+
+    fn start(&mut self, header: &[u8]) -> Option<usize> {
+        parse_data_header(header).map(|(_, len)| len)
+    }
+
+    fn push(&mut self, _byte: u8) {}
+
+    fn finish(&mut self) {}
+}
+
+/// Parses a complete `+WS_DATA` header like `+WS_DATA:<link_id>,<len>,` into the index of the
+/// connection and the length of the payload.
+fn parse_data_header(header: &[u8]) -> Option<(usize, usize)> {
+    let arguments = header
+        .strip_suffix(b",")
+        .and_then(|header| core::str::from_utf8(header).ok())
+        .and_then(|header| parser::response(header, "WS_DATA"))?;
+    let mut fields = parser::fields(arguments);
+    let link_id: u8 = fields.next()?.parse().ok()?;
+    let len = fields.next()?.parse().ok()?;
+    if link_id >= MAX_LINKS || fields.next().is_some() {
+        return None;
+    }
+    Some((link_id as usize, len))
+}
@@ -0,0 +1,631 @@
+use crate::event::Event;
+use crate::payload::PayloadSink;
+use crate::{parser, CommandSet, Error, Escaped, Esp32At, Line};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WifiMode {
+    /// Wi-Fi RF disabled.
+    Disabled,
+    Station,
+    SoftAp,
+    StationAndSoftAp,
+}
+
+/// The protocol used to receive Wi-Fi credentials from a phone app.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SmartConfigKind<'a> {
+    EspTouch,
+    AirKiss,
+    EspTouchAndAirKiss,
+    /// ESP-Touch v2, optionally with the 16 character key that the credentials and reserved data
+    /// are AES encrypted with.
+    EspTouchV2 {
+        aes_key: Option<&'a str>,
+    },
+}
+
+/// Why joining an access point failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JoinFailure {
+    Timeout,
+    WrongPassword,
+    AccessPointNotFound,
+    ConnectionFailed,
+    Unknown,
+}
+
+/// The outcome of `Esp32At::join_network`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JoinOutcome {
+    /// The access point was joined and an IP address was obtained.
+    Connected { attempts: u8 },
+    /// All attempts failed; `reason` is why the last one did.
+    Failed { attempts: u8, reason: JoinFailure },
+}
+
+/// The access point that the station is connected to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccessPointInfo {
+    pub ssid: heapless::String<heapless::consts::U32>,
+    pub bssid: heapless::String<heapless::consts::U17>,
+    pub channel: u8,
+    /// Signal strength in dBm.
+    pub rssi: i8,
+}
+
+/// The application data pushed by the module as `+SCRD` that is being received.
+#[derive(Debug)]
+pub(crate) struct ReservedDataBuffer {
+    data: heapless::Vec<u8, heapless::consts::U64>,
+    /// Whether the data is dropped, because it is too long.
+    discard: bool,
+    /// Whether all of the data has been received.
+    complete: bool,
+}
+
+impl WifiMode {
+    fn code(self) -> u8 {
+        match self {
+            WifiMode::Disabled => 0,
+            WifiMode::Station => 1,
+            WifiMode::SoftAp => 2,
+            WifiMode::StationAndSoftAp => 3,
+        }
+    }
+
+    fn from_code(code: &str) -> Option<Self> {
+        match code {
+            "0" => Some(WifiMode::Disabled),
+            "1" => Some(WifiMode::Station),
+            "2" => Some(WifiMode::SoftAp),
+            "3" => Some(WifiMode::StationAndSoftAp),
+            _ => None,
+        }
+    }
+}
+
+impl<'a> SmartConfigKind<'a> {
+    fn code(self) -> u8 {
+        match self {
+            SmartConfigKind::EspTouch => 1,
+            SmartConfigKind::AirKiss => 2,
+            SmartConfigKind::EspTouchAndAirKiss => 3,
+            SmartConfigKind::EspTouchV2 { .. } => 4,
+        }
+    }
+}
+
+impl JoinFailure {
+    fn from_code(code: &str) -> Self {
+        match code {
+            "1" => JoinFailure::Timeout,
+            "2" => JoinFailure::WrongPassword,
+            "3" => JoinFailure::AccessPointNotFound,
+            "4" => JoinFailure::ConnectionFailed,
+            _ => JoinFailure::Unknown,
+        }
+    }
+}
+
+impl AccessPointInfo {
+    fn parse(arguments: &str) -> Option<Self> {
+        let mut fields = parser::fields(arguments);
+        Some(AccessPointInfo {
+            ssid: parser::string(parser::unquote(fields.next()?))?,
+            bssid: parser::string(parser::unquote(fields.next()?))?,
+            channel: fields.next()?.parse().ok()?,
+            rssi: fields.next()?.parse().ok()?,
+        })
+    }
+}
+
+impl<RX, TX> Esp32At<RX, TX>
+where
+    RX: embedded_hal::serial::Read<u8>,
+    TX: embedded_hal::serial::Write<u8>,
+{
+    /// Enables mDNS, so that the module can be discovered as `<service_type>._tcp.local`.
+    ///
+    /// The `service_type` should include the leading underscore, e.g. `"_myservice"`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// let script = [Exchange::new(b"AT+MDNS=1,\"esp32\",\"_http\",80\r\n", b"\r\nOK\r\n")];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Wifi.into());
+    ///
+    /// nb::block!(esp32_at.enable_mdns("esp32", "_http", 80)).unwrap();
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn enable_mdns(
+        &mut self,
+        hostname: &str,
+        service_type: &str,
+        port: u16,
+    ) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::Wifi)?;
+        write_command!(
+            self,
+            "AT+MDNS=1,\"{}\",\"{}\",{}",
+            Escaped(hostname),
+            Escaped(service_type),
+            port
+        )?;
+        self.expect_ok_response()
+    }
+
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// let script = [Exchange::new(b"AT+MDNS=0\r\n", b"\r\nOK\r\n")];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Wifi.into());
+    ///
+    /// nb::block!(esp32_at.disable_mdns()).unwrap();
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn disable_mdns(&mut self) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::Wifi)?;
+        write_command!(self, "AT+MDNS=0")?;
+        self.expect_ok_response()
+    }
+
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// use esp_at::wifi::WifiMode;
+    /// let script = [Exchange::new(b"AT+CWMODE=1\r\n", b"\r\nOK\r\n")];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Wifi.into());
+    ///
+    /// nb::block!(esp32_at.set_wifi_mode(WifiMode::Station)).unwrap();
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn set_wifi_mode(&mut self, mode: WifiMode) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::Wifi)?;
+        write_command!(self, "AT+CWMODE={}", mode.code())?;
+        self.expect_ok_response()
+    }
+
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// use esp_at::wifi::WifiMode;
+    /// let script = [Exchange::new(b"AT+CWMODE?\r\n", b"+CWMODE:3\r\n\r\nOK\r\n")];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Wifi.into());
+    ///
+    /// let mode = nb::block!(esp32_at.get_wifi_mode()).unwrap();
+    /// assert_eq!(WifiMode::StationAndSoftAp, mode);
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn get_wifi_mode(&mut self) -> nb::Result<WifiMode, Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::Wifi)?;
+        write_command!(self, "AT+CWMODE?")?;
+        let mode = self.read_query_response("CWMODE")?;
+        Ok(WifiMode::from_code(&mode).ok_or(Error::UnexpectedResponse)?)
+    }
+
+    /// Runs `f` with the Wi-Fi mode temporarily set to `mode`, restoring the previous mode
+    /// afterwards.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// use esp_at::wifi::WifiMode;
+    /// let script = [
+    ///     Exchange::new(b"AT+CWMODE?\r\n", b"+CWMODE:1\r\n\r\nOK\r\n"),
+    ///     Exchange::new(b"AT+CWMODE=2\r\n", b"\r\nOK\r\n"),
+    ///     Exchange::new(b"AT+MDNS=1,\"esp32\",\"_http\",80\r\n", b"\r\nOK\r\n"),
+    ///     Exchange::new(b"AT+CWMODE=1\r\n", b"\r\nOK\r\n"),
+    /// ];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Wifi.into());
+    ///
+    /// esp32_at
+    ///     .with_wifi_mode(WifiMode::SoftAp, |esp32_at| {
+    ///         nb::block!(esp32_at.enable_mdns("esp32", "_http", 80))
+    ///     })
+    ///     .unwrap();
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn with_wifi_mode<F, R, E>(&mut self, mode: WifiMode, f: F) -> Result<R, E>
+    where
+        F: FnOnce(&mut Self) -> Result<R, E>,
+        E: From<Error<RX::Error, TX::Error>>,
+    {
+        self.with_setting(Self::get_wifi_mode, Self::set_wifi_mode, mode, f)
+    }
+
+    /// Joins an access point, returning once an IP address has been obtained.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// let script = [Exchange::new(b"AT+CWJAP=\"home\",\"secret\"\r\n", b"WIFI CONNECTED\r\nWIFI GOT IP\r\n\r\nOK\r\n")];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Wifi.into());
+    ///
+    /// nb::block!(esp32_at.join_access_point("home", "secret")).unwrap();
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn join_access_point(
+        &mut self,
+        ssid: &str,
+        password: &str,
+    ) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.write_join_command(ssid, password)?;
+
+        let mut failure = None;
+        let result = self.read_response(|line| {
+            record_join_failure(line, &mut failure);
+            Ok(())
+        });
+        with_join_failure(result, failure)
+    }
+
+    /// Returns the access point that the station is connected to, if any.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// let script = [Exchange::new(b"AT+CWJAP?\r\n", b"+CWJAP:\"home\",\"aa:bb:cc:dd:ee:ff\",6,-58\r\n\r\nOK\r\n")];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Wifi.into());
+    ///
+    /// let info = nb::block!(esp32_at.get_access_point()).unwrap().unwrap();
+    /// assert_eq!("home", info.ssid);
+    /// assert_eq!(-58, info.rssi);
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn get_access_point(
+        &mut self,
+    ) -> nb::Result<Option<AccessPointInfo>, Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::Wifi)?;
+        write_command!(self, "AT+CWJAP?")?;
+
+        let mut info = None;
+        self.read_response(|line| {
+            if let Some(arguments) = parser::response(line, "CWJAP") {
+                info = Some(AccessPointInfo::parse(arguments).ok_or(Error::UnexpectedResponse)?);
+            }
+            Ok(())
+        })?;
+        Ok(info)
+    }
+
+    /// Starts listening for Wi-Fi credentials sent by a phone app; the module must be in station
+    /// mode.
+    ///
+    /// Once it has joined the network, `Event::SmartConfigConnected` is reported, and
+    /// `stop_smart_config` should be called.  With ESP-Touch v2, any application data sent along
+    /// with the credentials is reported as `Event::SmartConfigReservedData` first.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// use esp_at::wifi::SmartConfigKind;
+    /// let script = [Exchange::new(b"AT+CWSTARTSMART=1\r\n", b"\r\nOK\r\n")];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Wifi.into());
+    ///
+    /// nb::block!(esp32_at.start_smart_config(SmartConfigKind::EspTouch)).unwrap();
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn start_smart_config(
+        &mut self,
+        kind: SmartConfigKind,
+    ) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::Wifi)?;
+        match kind {
+            SmartConfigKind::EspTouchV2 {
+                aes_key: Some(aes_key),
+            } => {
+                if aes_key.len() != 16 {
+                    return Err(nb::Error::Other(Error::InvalidArgument));
+                }
+                write_command!(
+                    self,
+                    "AT+CWSTARTSMART={},0,\"{}\"",
+                    kind.code(),
+                    Escaped(aes_key)
+                )?
+            }
+            _ => write_command!(self, "AT+CWSTARTSMART={}", kind.code())?,
+        }
+        self.expect_ok_response()
+    }
+
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// let script = [Exchange::new(b"AT+CWSTOPSMART\r\n", b"\r\nOK\r\n")];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Wifi.into());
+    ///
+    /// nb::block!(esp32_at.stop_smart_config()).unwrap();
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn stop_smart_config(&mut self) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::Wifi)?;
+        write_command!(self, "AT+CWSTOPSMART")?;
+        self.expect_ok_response()
+    }
+
+    /// Switches to station mode and joins an access point, making up to `attempts` attempts.
+    ///
+    /// Each attempt is made with `join_access_point_within`, given `timeout` on `timer`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// use esp_at::wifi::JoinOutcome;
+    /// # struct NeverFires;
+    /// # impl embedded_hal::timer::CountDown for NeverFires {
+    /// #     type Time = ();
+    /// #     fn start<T: Into<()>>(&mut self, _count: T) {}
+    /// #     fn wait(&mut self) -> nb::Result<(), void::Void> {
+    /// #         Err(nb::Error::WouldBlock)
+    /// #     }
+    /// # }
+    /// # let mut timer = NeverFires;
+    /// let script = [
+    ///     Exchange::new(b"AT+CWMODE=1\r\n", b"\r\nOK\r\n"),
+    ///     Exchange::new(b"AT+CWJAP=\"home\",\"secret\"\r\n", b"\r\n+CWJAP:2\r\n\r\nFAIL\r\n"),
+    ///     Exchange::new(b"AT+CWJAP=\"home\",\"secret\"\r\n", b"WIFI CONNECTED\r\nWIFI GOT IP\r\n\r\nOK\r\n"),
+    /// ];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Wifi.into());
+    ///
+    /// let outcome = esp32_at
+    ///     .join_network("home", "secret", 3, &mut timer, ())
+    ///     .unwrap();
+    /// assert_eq!(JoinOutcome::Connected { attempts: 2 }, outcome);
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn join_network<T>(
+        &mut self,
+        ssid: &str,
+        password: &str,
+        attempts: u8,
+        timer: &mut T,
+        timeout: T::Time,
+    ) -> Result<JoinOutcome, Error<RX::Error, TX::Error>>
+    where
+        T: embedded_hal::timer::CountDown,
+        T::Time: Clone,
+    {
+        nb::block!(self.set_wifi_mode(WifiMode::Station))?;
+
+        let attempts = attempts.max(1);
+        let mut reason = JoinFailure::Timeout;
+        for attempt in 1..=attempts {
+            match self.join_access_point_within(ssid, password, timer, timeout.clone()) {
+                Ok(()) => return Ok(JoinOutcome::Connected { attempts: attempt }),
+                Err(Error::JoinFailed { reason: failure }) => reason = failure,
+                Err(Error::Timeout) => reason = JoinFailure::Timeout,
+                Err(error) => return Err(error),
+            }
+        }
+
+        Ok(JoinOutcome::Failed { attempts, reason })
+    }
+
+    /// Like `join_access_point`, but fails with `Error::Timeout` if no IP address has been
+    /// obtained within `timeout` on `timer`, abandoning the attempt with `AT+CWQAP`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use esp_at::mock::{Exchange, MockModule};
+    /// # use esp_at::{CommandSet, Esp32At};
+    /// # struct NeverFires;
+    /// # impl embedded_hal::timer::CountDown for NeverFires {
+    /// #     type Time = ();
+    /// #     fn start<T: Into<()>>(&mut self, _count: T) {}
+    /// #     fn wait(&mut self) -> nb::Result<(), void::Void> {
+    /// #         Err(nb::Error::WouldBlock)
+    /// #     }
+    /// # }
+    /// # let mut timer = NeverFires;
+    /// let script = [Exchange::new(
+    ///     b"AT+CWJAP=\"home\",\"secret\"\r\n",
+    ///     b"WIFI CONNECTED\r\n\r\nOK\r\nWIFI GOT IP\r\n",
+    /// )];
+    /// # let module = MockModule::new(&script);
+    /// # let (rx, tx) = module.split();
+    /// # let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Wifi.into());
+    ///
+    /// esp32_at
+    ///     .join_access_point_within("home", "secret", &mut timer, ())
+    ///     .unwrap();
+    /// # assert!(module.is_done());
+    /// ```
+    pub fn join_access_point_within<T>(
+        &mut self,
+        ssid: &str,
+        password: &str,
+        timer: &mut T,
+        timeout: T::Time,
+    ) -> Result<(), Error<RX::Error, TX::Error>>
+    where
+        T: embedded_hal::timer::CountDown,
+        T::Time: Clone,
+    {
+        timer.start(timeout.clone());
+        match nb::block!(self.join_attempt(ssid, password, timer)) {
+            Err(Error::Timeout) => {
+                timer.start(timeout);
+                self.abandon_join_attempt(timer)?;
+                Err(Error::Timeout)
+            }
+            result => result,
+        }
+    }
+
+    fn join_attempt<T>(
+        &mut self,
+        ssid: &str,
+        password: &str,
+        timer: &mut T,
+    ) -> nb::Result<(), Error<RX::Error, TX::Error>>
+    where
+        T: embedded_hal::timer::CountDown,
+    {
+        self.write_join_command(ssid, password)?;
+
+        let mut failure = None;
+        let mut got_ip = false;
+        let result = self.read_response_within(timer, |line| {
+            got_ip |= line == "WIFI GOT IP";
+            record_join_failure(line, &mut failure);
+            Ok(())
+        });
+        with_join_failure(result, failure)?;
+
+        // Some firmware versions acknowledge the command before DHCP has completed.
+        while !got_ip {
+            let line: Line = self.read_line_within(timer)?;
+            self.queue_event(&line);
+            got_ip = line == "WIFI GOT IP";
+        }
+        Ok(())
+    }
+
+    /// Stops a join attempt that is still in progress, and waits for the result code of
+    /// `AT+CWQAP`, skipping the `FAIL` of the abandoned `AT+CWJAP` if it arrives first.
+    fn abandon_join_attempt<T>(&mut self, timer: &mut T) -> Result<(), Error<RX::Error, TX::Error>>
+    where
+        T: embedded_hal::timer::CountDown,
+    {
+        nb::block!(write_command!(self, "AT+CWQAP"))?;
+        loop {
+            let line: Line = nb::block!(self.read_line_within(timer))?;
+            match line.as_str() {
+                "OK" => return Ok(()),
+                "ERROR" => return Err(Error::CommandFailed),
+                "" | "FAIL" => {}
+                echo if parser::is_echo(echo) => {}
+                line => self.queue_event(line),
+            }
+        }
+    }
+
+    fn write_join_command(
+        &mut self,
+        ssid: &str,
+        password: &str,
+    ) -> nb::Result<(), Error<RX::Error, TX::Error>> {
+        self.require_command_set(CommandSet::Wifi)?;
+        write_command!(
+            self,
+            "AT+CWJAP=\"{}\",\"{}\"",
+            Escaped(ssid),
+            Escaped(password)
+        )
+    }
+}
+
+impl ReservedDataBuffer {
+    pub(crate) fn new() -> Self {
+        ReservedDataBuffer {
+            data: heapless::Vec::new(),
+            discard: false,
+            complete: false,
+        }
+    }
+
+    /// Takes the event for the data once all of it has been received.
+    pub(crate) fn take(&mut self) -> Option<Event> {
+        if !self.complete {
+            return None;
+        }
+        self.complete = false;
+        if self.discard {
+            return None;
+        }
+        Some(Event::SmartConfigReservedData(core::mem::replace(
+            &mut self.data,
+            heapless::Vec::new(),
+        )))
+    }
+}
+
+impl PayloadSink for ReservedDataBuffer {
+    const HEADER_END: u8 = b',';
+
+    /// Starts receiving the data announced by `header`, if it is a complete `+SCRD` header like
+    /// `+SCRD:<len>,`.
+    fn start(&mut self, header: &[u8]) -> Option<usize> {
+        let len = header
+            .strip_suffix(b",")
+            .and_then(|header| core::str::from_utf8(header).ok())
+            .and_then(|header| parser::response(header, "SCRD"))?
+            .parse()
+            .ok()?;
+        self.data = heapless::Vec::new();
+        self.discard = len > self.data.capacity();
+        self.complete = false;
+        Some(len)
+    }
+
+    fn push(&mut self, byte: u8) {
+        if !self.discard {
+            // The length was checked in `start`
+            let _ = self.data.push(byte);
+        }
+    }
+
+    fn finish(&mut self) {
+        self.complete = true;
+    }
+}
+
+fn record_join_failure(line: &str, failure: &mut Option<JoinFailure>) {
+    if let Some(code) = line.strip_prefix("+CWJAP:") {
+        *failure = Some(JoinFailure::from_code(code));
+    }
+}
+
+fn with_join_failure<RXE, TXE>(
+    result: nb::Result<(), Error<RXE, TXE>>,
+    failure: Option<JoinFailure>,
+) -> nb::Result<(), Error<RXE, TXE>> {
+    match (result, failure) {
+        (Err(nb::Error::Other(Error::CommandFailed)), Some(reason)) => {
+            Err(nb::Error::Other(Error::JoinFailed { reason }))
+        }
+        (result, _) => result,
+    }
+}
@@ -0,0 +1,44 @@
+#![cfg(feature = "testing")]
+
+use esp_at::mock::{Exchange, MockModule};
+use esp_at::testing::{Faults, FaultyTransport};
+use esp_at::{event, CommandSet, Esp32At};
+
+#[test]
+fn spurious_urcs_are_queued_as_events() -> Result<(), failure::Error> {
+    let script = [Exchange::new(b"AT\r\n", b"\r\nOK\r\n")];
+    let module = MockModule::new(&script);
+    let (rx, tx) = module.split();
+    let rx = FaultyTransport::new(
+        rx,
+        Faults {
+            spurious_urc_probability: 1.0,
+            ..Faults::default()
+        },
+    );
+    let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Wifi.into());
+
+    nb::block!(esp32_at.test_startup())?;
+    assert!(matches!(
+        esp32_at.poll_event(),
+        Ok(event::Event::WifiDisconnected)
+    ));
+    Ok(())
+}
+
+#[test]
+fn dropped_bytes_are_detected() {
+    let script = [Exchange::new(b"AT\r\n", b"\r\nOK\r\n")];
+    let module = MockModule::new(&script);
+    let (rx, tx) = module.split();
+    let tx = FaultyTransport::new(
+        tx,
+        Faults {
+            drop_probability: 1.0,
+            ..Faults::default()
+        },
+    );
+    let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Wifi.into());
+
+    assert!(nb::block!(esp32_at.test_startup()).is_err());
+}
@@ -0,0 +1,52 @@
+use esp_at::link::{LinkAllocator, LinkState};
+
+#[test]
+fn server_reservation_is_enforced() {
+    let mut links = LinkAllocator::new();
+    links.reserve_for_server(2);
+
+    assert_eq!(Some(4), links.allocate());
+    assert_eq!(Some(3), links.allocate());
+    assert_eq!(Some(2), links.allocate());
+    assert_eq!(None, links.allocate());
+
+    assert!(links.accept(0));
+    assert!(links.accept(1));
+
+    links.release(1);
+    assert_eq!(None, links.allocate());
+    links.release(4);
+    assert_eq!(Some(4), links.allocate());
+}
+
+#[test]
+fn inbound_connections_beyond_quota_are_refused() {
+    let mut links = LinkAllocator::new();
+    links.reserve_for_server(1);
+
+    assert!(links.accept(0));
+    assert!(!links.accept(1));
+    assert!(!links.is_in_use(1));
+
+    // The refused connection holds on to its id, but not to a slot of the reservation
+    links.refuse(1);
+    assert_eq!(LinkState::Refused, links.state(1));
+    assert_eq!(Some(4), links.allocate());
+    links.release(1);
+    assert_eq!(LinkState::Free, links.state(1));
+}
+
+#[test]
+fn server_links_are_released_together() {
+    let mut links = LinkAllocator::new();
+    links.reserve_for_server(2);
+
+    assert!(links.accept(0));
+    assert!(links.accept(1));
+    assert_eq!(Some(4), links.allocate());
+
+    links.release_server_links();
+    assert!(!links.is_in_use(0));
+    assert!(!links.is_in_use(1));
+    assert!(links.is_in_use(4));
+}
@@ -1,4 +1,5 @@
 #[test]
+#[ignore = "requires an ESP32 running esp-at on /dev/ttyUSB1"]
 fn module_revision() -> Result<(), failure::Error> {
     let mut esp32_at = setup()?;
     let revision = nb::block!(esp32_at.get_module_revision())?;
@@ -9,7 +10,7 @@ fn module_revision() -> Result<(), failure::Error> {
 }
 
 fn setup(
-) -> Result<esp32_at::Esp32At<serial_embedded_hal::Rx, serial_embedded_hal::Tx>, failure::Error> {
+) -> Result<esp_at::Esp32At<serial_embedded_hal::Rx, serial_embedded_hal::Tx>, failure::Error> {
     let serial = serial_embedded_hal::Serial::new(
         "/dev/ttyUSB1",
         &serial::PortSettings {
@@ -22,9 +23,9 @@ fn setup(
     )?;
     let (tx, rx) = serial.split();
 
-    Ok(esp32_at::Esp32At::new(
+    Ok(esp_at::Esp32At::new(
         rx,
         tx,
-        esp32_at::CommandSet::TcpIp | esp32_at::CommandSet::Wifi,
+        esp_at::CommandSet::TcpIp | esp_at::CommandSet::Wifi,
     ))
 }
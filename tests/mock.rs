@@ -0,0 +1,1450 @@
+use esp_at::mock::{Exchange, MockModule};
+use esp_at::{
+    ble, config, dns, event, http, link, mqtt, mqtt_client, pki, reconnect, signal, sntp, state,
+    tcpip, wifi, CommandSet, Esp32At,
+};
+
+#[test]
+fn test_startup() -> Result<(), failure::Error> {
+    let script = [Exchange::new(b"AT\r\n", b"AT\r\r\n\r\nOK\r\n")];
+    let module = MockModule::new(&script);
+    let (rx, tx) = module.split();
+    let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Wifi.into());
+
+    nb::block!(esp32_at.test_startup())?;
+    assert!(module.is_done());
+    Ok(())
+}
+
+#[test]
+fn join_network_retries() -> Result<(), failure::Error> {
+    let script = [
+        Exchange::new(b"AT+CWMODE=1\r\n", b"\r\nOK\r\n"),
+        Exchange::new(
+            b"AT+CWJAP=\"ssid\",\"pass\\,word\"\r\n",
+            b"\r\n+CWJAP:3\r\n\r\nFAIL\r\n",
+        ),
+        Exchange::new(
+            b"AT+CWJAP=\"ssid\",\"pass\\,word\"\r\n",
+            b"WIFI CONNECTED\r\nWIFI GOT IP\r\n\r\nOK\r\n",
+        ),
+    ];
+    let module = MockModule::new(&script);
+    let (rx, tx) = module.split();
+    let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Wifi.into());
+
+    let outcome = esp32_at.join_network("ssid", "pass,word", 3, &mut NeverFires, ())?;
+    assert_eq!(wifi::JoinOutcome::Connected { attempts: 2 }, outcome);
+    assert!(matches!(
+        esp32_at.poll_event(),
+        Ok(event::Event::WifiConnected)
+    ));
+    assert!(matches!(esp32_at.poll_event(), Ok(event::Event::WifiGotIp)));
+    assert!(module.is_done());
+    Ok(())
+}
+
+#[test]
+fn join_network_abandons_timed_out_attempts() -> Result<(), failure::Error> {
+    let script = [
+        Exchange::new(b"AT+CWMODE=1\r\n", b"\r\nOK\r\n"),
+        Exchange::new(
+            b"AT+CWJAP=\"ssid\",\"password\"\r\n",
+            b"\r\nOK\r\nWIFI CONNECTED\r\n",
+        ),
+        Exchange::new(b"AT+CWQAP\r\n", b"WIFI DISCONNECT\r\n\r\nOK\r\n"),
+        Exchange::new(
+            b"AT+CWJAP=\"ssid\",\"password\"\r\n",
+            b"WIFI CONNECTED\r\nWIFI GOT IP\r\n\r\nOK\r\n",
+        ),
+    ];
+    let module = MockModule::new(&script);
+    let (rx, tx) = module.split();
+    let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Wifi.into());
+
+    let mut timer = FiresAfter::new(3);
+    let outcome = esp32_at.join_network("ssid", "password", 2, &mut timer, ())?;
+    assert_eq!(wifi::JoinOutcome::Connected { attempts: 2 }, outcome);
+    assert!(matches!(
+        esp32_at.poll_event(),
+        Ok(event::Event::WifiConnected)
+    ));
+    assert!(matches!(
+        esp32_at.poll_event(),
+        Ok(event::Event::WifiDisconnected)
+    ));
+    assert!(module.is_done());
+    Ok(())
+}
+
+#[test]
+fn reconnect_backs_off_up_to_the_cap() -> Result<(), failure::Error> {
+    const JOIN: &[u8] = b"AT+CWJAP=\"ssid\",\"password\"\r\n";
+    let script = [
+        Exchange::new(JOIN, b"\r\n+CWJAP:3\r\n\r\nFAIL\r\n"),
+        // Connects, but never obtains an IP address
+        Exchange::new(JOIN, b"WIFI CONNECTED\r\n\r\nOK\r\n"),
+        Exchange::new(b"AT+CWQAP\r\n", b"WIFI DISCONNECT\r\n\r\nOK\r\n"),
+        Exchange::new(JOIN, b"\r\n+CWJAP:3\r\n\r\nFAIL\r\n"),
+        Exchange::new(JOIN, b"\r\n+CWJAP:1\r\n\r\nFAIL\r\n"),
+    ];
+    let module = MockModule::new(&script);
+    let (rx, tx) = module.split();
+    let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Wifi.into());
+    let mut timer = FiresAfter::new(3);
+    let mut supervisor =
+        reconnect::ReconnectSupervisor::with_backoff("ssid", "password", 1_000, 5_000);
+
+    assert_eq!(
+        Some(reconnect::ReconnectEvent::Disconnected),
+        supervisor.handle_event(&event::Event::WifiDisconnected, 0)
+    );
+    assert_eq!(None, supervisor.poll(&mut esp32_at, &mut timer, (), 999)?);
+    // The backoff doubles after every failed attempt, until it reaches the cap
+    let mut now_ms = 1_000;
+    for (attempt, &(reason, retry_in_ms)) in [
+        (wifi::JoinFailure::AccessPointNotFound, 2_000),
+        (wifi::JoinFailure::Timeout, 4_000),
+        (wifi::JoinFailure::AccessPointNotFound, 5_000),
+        (wifi::JoinFailure::Timeout, 5_000),
+    ]
+    .iter()
+    .enumerate()
+    {
+        assert_eq!(
+            Some(reconnect::ReconnectEvent::AttemptFailed {
+                attempt: attempt as u32 + 1,
+                reason,
+                retry_in_ms,
+            }),
+            supervisor.poll(&mut esp32_at, &mut timer, (), now_ms)?
+        );
+        assert_eq!(
+            None,
+            supervisor.poll(&mut esp32_at, &mut timer, (), now_ms + retry_in_ms - 1)?
+        );
+        now_ms += retry_in_ms;
+    }
+    assert!(supervisor.is_reconnecting());
+    assert!(module.is_done());
+    Ok(())
+}
+
+#[test]
+fn reconnect_resets_backoff_on_got_ip() -> Result<(), failure::Error> {
+    const JOIN: &[u8] = b"AT+CWJAP=\"ssid\",\"password\"\r\n";
+    let script = [
+        Exchange::new(JOIN, b"\r\n+CWJAP:3\r\n\r\nFAIL\r\n"),
+        Exchange::new(JOIN, b"\r\n+CWJAP:3\r\n\r\nFAIL\r\n"),
+        Exchange::new(JOIN, b"\r\n+CWJAP:3\r\n\r\nFAIL\r\n"),
+    ];
+    let module = MockModule::new(&script);
+    let (rx, tx) = module.split();
+    let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Wifi.into());
+    let mut supervisor =
+        reconnect::ReconnectSupervisor::with_backoff("ssid", "password", 1_000, 60_000);
+
+    supervisor.handle_event(&event::Event::WifiDisconnected, 0);
+    assert!(matches!(
+        supervisor.poll(&mut esp32_at, &mut NeverFires, (), 1_000)?,
+        Some(reconnect::ReconnectEvent::AttemptFailed {
+            retry_in_ms: 2_000,
+            ..
+        })
+    ));
+    assert!(matches!(
+        supervisor.poll(&mut esp32_at, &mut NeverFires, (), 3_000)?,
+        Some(reconnect::ReconnectEvent::AttemptFailed {
+            retry_in_ms: 4_000,
+            ..
+        })
+    ));
+    // The module reconnected on its own before the next attempt was due
+    assert_eq!(
+        Some(reconnect::ReconnectEvent::Reconnected { attempts: 2 }),
+        supervisor.handle_event(&event::Event::WifiGotIp, 5_000)
+    );
+    assert!(!supervisor.is_reconnecting());
+    assert_eq!(
+        None,
+        supervisor.poll(&mut esp32_at, &mut NeverFires, (), 7_000)?
+    );
+
+    // The next disconnection starts over from the minimum backoff
+    supervisor.handle_event(&event::Event::WifiDisconnected, 10_000);
+    assert_eq!(
+        None,
+        supervisor.poll(&mut esp32_at, &mut NeverFires, (), 10_999)?
+    );
+    assert_eq!(
+        Some(reconnect::ReconnectEvent::AttemptFailed {
+            attempt: 1,
+            reason: wifi::JoinFailure::AccessPointNotFound,
+            retry_in_ms: 2_000,
+        }),
+        supervisor.poll(&mut esp32_at, &mut NeverFires, (), 11_000)?
+    );
+    assert!(module.is_done());
+    Ok(())
+}
+
+#[test]
+fn unsupported_command_set() {
+    let script = [];
+    let module = MockModule::new(&script);
+    let (rx, tx) = module.split();
+    let mut esp32_at = Esp32At::new(rx, tx, CommandSet::TcpIp.into());
+
+    match esp32_at.set_wifi_mode(wifi::WifiMode::Station) {
+        Err(nb::Error::Other(esp_at::Error::CommandSetNotSupported { command_set })) => {
+            assert_eq!(CommandSet::Wifi, command_set)
+        }
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn transaction_rolls_back() {
+    let script = [
+        Exchange::new(b"AT+CWMODE?\r\n", b"+CWMODE:1\r\n\r\nOK\r\n"),
+        Exchange::new(b"AT+CWMODE=3\r\n", b"\r\nOK\r\n"),
+        Exchange::new(b"AT+CWMODE?\r\n", b"+CWMODE:3\r\n\r\nOK\r\n"),
+        Exchange::new(b"AT+CIPRECVMODE?\r\n", b"+CIPRECVMODE:0\r\n\r\nOK\r\n"),
+        Exchange::new(b"AT+CIPRECVMODE=1\r\n", b"\r\nERROR\r\n"),
+        Exchange::new(b"AT+CIPRECVMODE=0\r\n", b"\r\nOK\r\n"),
+        Exchange::new(b"AT+CWMODE=1\r\n", b"\r\nOK\r\n"),
+    ];
+    let module = MockModule::new(&script);
+    let (rx, tx) = module.split();
+    let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Wifi | CommandSet::TcpIp);
+
+    let result = esp32_at.transaction(&[
+        config::Setting::WifiMode(wifi::WifiMode::StationAndSoftAp),
+        config::Setting::ReceiveMode(tcpip::ReceiveMode::Passive),
+    ]);
+    assert!(matches!(result, Err(esp_at::Error::CommandFailed)));
+    assert!(module.is_done());
+}
+
+#[test]
+fn transaction_applies_network_settings() -> Result<(), failure::Error> {
+    let script = [
+        Exchange::new(b"AT+CIPMUX?\r\n", b"+CIPMUX:0\r\n\r\nOK\r\n"),
+        Exchange::new(b"AT+CIPMUX=1\r\n", b"\r\nOK\r\n"),
+        Exchange::new(b"AT+CIPMUX?\r\n", b"+CIPMUX:1\r\n\r\nOK\r\n"),
+        Exchange::new(
+            b"AT+CIPSERVERMAXCONN?\r\n",
+            b"+CIPSERVERMAXCONN:5\r\n\r\nOK\r\n",
+        ),
+        Exchange::new(b"AT+CIPSERVERMAXCONN=2\r\n", b"\r\nOK\r\n"),
+        Exchange::new(
+            b"AT+CIPSERVERMAXCONN?\r\n",
+            b"+CIPSERVERMAXCONN:2\r\n\r\nOK\r\n",
+        ),
+        Exchange::new(b"AT+CIPSTO?\r\n", b"+CIPSTO:180\r\n\r\nOK\r\n"),
+        Exchange::new(b"AT+CIPSTO=30\r\n", b"\r\nOK\r\n"),
+        Exchange::new(b"AT+CIPSTO?\r\n", b"+CIPSTO:30\r\n\r\nOK\r\n"),
+        Exchange::new(b"AT+CIPDNS?\r\n", b"+CIPDNS:1,\"1.1.1.1\"\r\n\r\nOK\r\n"),
+        Exchange::new(b"AT+CIPDNS=0\r\n", b"\r\nOK\r\n"),
+        // The servers provided by DHCP needn't match
+        Exchange::new(
+            b"AT+CIPDNS?\r\n",
+            b"+CIPDNS:0,\"192.168.1.1\"\r\n\r\nOK\r\n",
+        ),
+        Exchange::new(b"AT+CIPSNTPCFG?\r\n", b"+CIPSNTPCFG:0,0\r\n\r\nOK\r\n"),
+        Exchange::new(b"AT+CIPSNTPCFG=1,1\r\n", b"\r\nOK\r\n"),
+        // Nor the firmware's default SNTP servers
+        Exchange::new(
+            b"AT+CIPSNTPCFG?\r\n",
+            b"+CIPSNTPCFG:1,1,\"cn.ntp.org.cn\",\"ntp.sjtu.edu.cn\"\r\n\r\nOK\r\n",
+        ),
+    ];
+    let module = MockModule::new(&script);
+    let (rx, tx) = module.split();
+    let mut esp32_at = Esp32At::new(rx, tx, CommandSet::TcpIp.into());
+
+    esp32_at.transaction(&[
+        config::Setting::MultipleConnections(true),
+        config::Setting::ServerMaxConnections(2),
+        config::Setting::ServerTimeout(30),
+        config::Setting::Dns(tcpip::DnsConfig {
+            manual: false,
+            servers: heapless::Vec::new(),
+        }),
+        config::Setting::Sntp(sntp::SntpConfig {
+            enabled: true,
+            utc_offset_minutes: 60,
+            servers: heapless::Vec::new(),
+        }),
+    ])?;
+    assert_eq!(2, esp32_at.link_allocator().unwrap().server_quota());
+    assert!(module.is_done());
+    Ok(())
+}
+
+#[test]
+fn transaction_rolls_back_network_settings() {
+    let script = [
+        Exchange::new(b"AT+CIPMUX?\r\n", b"+CIPMUX:0\r\n\r\nOK\r\n"),
+        Exchange::new(b"AT+CIPMUX=1\r\n", b"\r\nOK\r\n"),
+        Exchange::new(b"AT+CIPMUX?\r\n", b"+CIPMUX:1\r\n\r\nOK\r\n"),
+        Exchange::new(
+            b"AT+CIPSERVERMAXCONN?\r\n",
+            b"+CIPSERVERMAXCONN:5\r\n\r\nOK\r\n",
+        ),
+        Exchange::new(b"AT+CIPSERVERMAXCONN=2\r\n", b"\r\nOK\r\n"),
+        Exchange::new(
+            b"AT+CIPSERVERMAXCONN?\r\n",
+            b"+CIPSERVERMAXCONN:2\r\n\r\nOK\r\n",
+        ),
+        Exchange::new(b"AT+CIPSTO?\r\n", b"+CIPSTO:180\r\n\r\nOK\r\n"),
+        Exchange::new(b"AT+CIPSTO=30\r\n", b"\r\nOK\r\n"),
+        Exchange::new(b"AT+CIPSTO?\r\n", b"+CIPSTO:30\r\n\r\nOK\r\n"),
+        Exchange::new(
+            b"AT+CIPDNS?\r\n",
+            b"+CIPDNS:0,\"192.168.1.1\"\r\n\r\nOK\r\n",
+        ),
+        Exchange::new(b"AT+CIPDNS=1,\"1.1.1.1\"\r\n", b"\r\nOK\r\n"),
+        Exchange::new(b"AT+CIPDNS?\r\n", b"+CIPDNS:1,\"1.1.1.1\"\r\n\r\nOK\r\n"),
+        Exchange::new(
+            b"AT+CIPSNTPCFG?\r\n",
+            b"+CIPSNTPCFG:1,8,\"cn.ntp.org.cn\"\r\n\r\nOK\r\n",
+        ),
+        Exchange::new(b"AT+CIPSNTPCFG=1,1,\"pool.ntp.org\"\r\n", b"\r\nOK\r\n"),
+        // The server wasn't accepted
+        Exchange::new(b"AT+CIPSNTPCFG?\r\n", b"+CIPSNTPCFG:1,1\r\n\r\nOK\r\n"),
+        Exchange::new(b"AT+CIPSNTPCFG=1,8,\"cn.ntp.org.cn\"\r\n", b"\r\nOK\r\n"),
+        Exchange::new(b"AT+CIPDNS=0\r\n", b"\r\nOK\r\n"),
+        Exchange::new(b"AT+CIPSTO=180\r\n", b"\r\nOK\r\n"),
+        Exchange::new(b"AT+CIPSERVERMAXCONN=5\r\n", b"\r\nOK\r\n"),
+        Exchange::new(b"AT+CIPMUX=0\r\n", b"\r\nOK\r\n"),
+    ];
+    let module = MockModule::new(&script);
+    let (rx, tx) = module.split();
+    let mut esp32_at = Esp32At::new(rx, tx, CommandSet::TcpIp.into());
+
+    let mut servers = heapless::Vec::new();
+    servers.push([1, 1, 1, 1].into()).unwrap();
+    let mut sntp_servers = heapless::Vec::new();
+    sntp_servers.push("pool.ntp.org".into()).unwrap();
+    let result = esp32_at.transaction(&[
+        config::Setting::MultipleConnections(true),
+        config::Setting::ServerMaxConnections(2),
+        config::Setting::ServerTimeout(30),
+        config::Setting::Dns(tcpip::DnsConfig {
+            manual: true,
+            servers,
+        }),
+        config::Setting::Sntp(sntp::SntpConfig {
+            enabled: true,
+            utc_offset_minutes: 60,
+            servers: sntp_servers,
+        }),
+    ]);
+    assert!(matches!(result, Err(esp_at::Error::VerificationFailed)));
+    assert!(esp32_at.link_allocator().is_none());
+    assert!(module.is_done());
+}
+
+#[test]
+fn connection_status() -> Result<(), failure::Error> {
+    let script = [
+        Exchange::new(b"AT+CIPSTATUS\r\n", b"STATUS:3\r\n\r\nOK\r\n"),
+        Exchange::new(
+            b"AT+CIPSTATE?\r\n",
+            b"+CIPSTATE:0,\"TCP\",\"192.168.1.2\",8080,50123,0\r\n\r\nOK\r\n",
+        ),
+    ];
+    let module = MockModule::new(&script);
+    let (rx, tx) = module.split();
+    let mut esp32_at = Esp32At::new(rx, tx, CommandSet::TcpIp.into());
+
+    let status = nb::block!(esp32_at.connection_status())?;
+    assert_eq!(tcpip::NetworkState::Transmitting, status.state);
+    assert_eq!(
+        &[tcpip::LinkStatus {
+            link_id: 0,
+            link_type: tcpip::LinkType::Tcp,
+            remote_ip: [192, 168, 1, 2].into(),
+            remote_port: 8080,
+            local_port: 50123,
+            role: tcpip::LinkRole::Client,
+        }],
+        &status.links[..]
+    );
+    Ok(())
+}
+
+#[test]
+fn detect_command_sets() -> Result<(), failure::Error> {
+    let script = [Exchange::new(
+        b"AT+GMR\r\n",
+        b"AT version:2.2.0.0(c6fa6bf - ESP32 - Jul  2 2021 06:44:05)\r\n\
+          SDK version:v4.2.2-76-gefa6eca\r\n\
+          compile time(3e2f9a4):Jul  3 2021 11:52:40\r\n\
+          Bin version:2.2.0(WROOM-32)\r\n\
+          \r\n\
+          OK\r\n",
+    )];
+    let module = MockModule::new(&script);
+    let (rx, tx) = module.split();
+    let mut esp32_at = Esp32At::new(rx, tx, enumset::EnumSet::empty());
+
+    let revision = nb::block!(esp32_at.detect_command_sets())?;
+    assert_eq!(
+        "2.2.0.0(c6fa6bf - ESP32 - Jul  2 2021 06:44:05)",
+        revision.at_version
+    );
+    assert_eq!("v4.2.2-76-gefa6eca", revision.sdk_version);
+    assert_eq!("Jul  3 2021 11:52:40", revision.compile_time);
+    assert_eq!(Some("2.2.0(WROOM-32)"), revision.bin_version.as_deref());
+    assert_eq!(Some(state::Chip::Esp32), revision.chip);
+    assert!(esp32_at.command_sets().contains(CommandSet::Ble));
+    Ok(())
+}
+
+#[test]
+fn connect_tcp() -> Result<(), failure::Error> {
+    let script = [
+        Exchange::new(
+            b"AT+CIPSTART=\"TCP\",\"example.com\",80,60\r\n",
+            b"CONNECT\r\n\r\nOK\r\n",
+        ),
+        Exchange::new(
+            b"AT+CIPSTART=\"TCP\",\"example.com\",80,60\r\n",
+            b"ALREADY CONNECTED\r\n\r\nERROR\r\n",
+        ),
+    ];
+    let module = MockModule::new(&script);
+    let (rx, tx) = module.split();
+    let mut esp32_at = Esp32At::new(rx, tx, CommandSet::TcpIp.into());
+
+    let outcome = nb::block!(esp32_at.connect_tcp("example.com", 80, 60))?;
+    assert_eq!(tcpip::ConnectOutcome::Connected, outcome.outcome);
+    let outcome = nb::block!(esp32_at.connect_tcp("example.com", 80, 60))?;
+    assert_eq!(tcpip::ConnectOutcome::AlreadyConnected, outcome.outcome);
+    Ok(())
+}
+
+#[test]
+fn send_failure() {
+    let script = [
+        Exchange::new(b"AT+CIPSEND=4\r\n", b"\r\nOK\r\n\r\n>"),
+        Exchange::new(b"ping", b"\r\nRecv 4 bytes\r\n\r\nSEND FAIL\r\n"),
+    ];
+    let module = MockModule::new(&script);
+    let (rx, tx) = module.split();
+    let mut esp32_at = Esp32At::new(rx, tx, CommandSet::TcpIp.into());
+
+    let result = nb::block!(esp32_at.send(None, b"ping"));
+    assert!(matches!(
+        result,
+        Err(esp_at::Error::SendFailed { link_id: None })
+    ));
+    assert!(module.is_done());
+}
+
+#[test]
+fn send_on_closed_link() {
+    let script = [
+        Exchange::new(b"AT+CIPSEND=4\r\n", b"\r\nOK\r\nCLOSED\r\n\r\n>"),
+        Exchange::new(b"ping", b"\r\nSEND FAIL\r\n"),
+    ];
+    let module = MockModule::new(&script);
+    let (rx, tx) = module.split();
+    let mut esp32_at = Esp32At::new(rx, tx, CommandSet::TcpIp.into());
+
+    let result = nb::block!(esp32_at.send(None, b"ping"));
+    assert!(matches!(
+        result,
+        Err(esp_at::Error::LinkClosed { link_id: None })
+    ));
+    assert_eq!(
+        Some(event::Event::LinkClosed { link_id: None }),
+        esp32_at.poll_event().ok()
+    );
+    assert!(module.is_done());
+}
+
+#[test]
+fn links_are_reclaimed() -> Result<(), failure::Error> {
+    let script = [
+        Exchange::new(b"AT+CIPMUX=1\r\n", b"\r\nOK\r\n"),
+        Exchange::new(
+            b"AT+CIPSTART=4,\"TCP\",\"example.com\",80,0\r\n",
+            b"4,CONNECT\r\n\r\nOK\r\n",
+        ),
+        Exchange::new(
+            b"AT+CIPSTART=3,\"TCP\",\"example.com\",81,0\r\n",
+            b"\r\nERROR\r\n",
+        ),
+        Exchange::new(b"AT+CIPRECVMODE=0\r\n", b"4,CLOSED\r\n\r\nOK\r\n"),
+    ];
+    let module = MockModule::new(&script);
+    let (rx, tx) = module.split();
+    let mut esp32_at = Esp32At::new(rx, tx, CommandSet::TcpIp.into());
+
+    nb::block!(esp32_at.set_multiple_connections(true))?;
+    let connection = nb::block!(esp32_at.connect_tcp("example.com", 80, 0))?;
+    assert_eq!(Some(4), connection.link_id);
+    let result = nb::block!(esp32_at.connect_tcp("example.com", 81, 0));
+    assert!(matches!(result, Err(esp_at::Error::ConnectFailed)));
+    let links = esp32_at.link_allocator().unwrap();
+    assert_eq!(link::LinkState::Outbound, links.state(4));
+    assert!(!links.is_in_use(3));
+
+    nb::block!(esp32_at.set_receive_mode(tcpip::ReceiveMode::Active))?;
+    assert_eq!(
+        link::LinkState::Free,
+        esp32_at.link_allocator().unwrap().state(4)
+    );
+    assert!(matches!(
+        esp32_at.send(Some(4), b"ping"),
+        Err(nb::Error::Other(esp_at::Error::LinkClosed {
+            link_id: Some(4)
+        }))
+    ));
+    assert!(module.is_done());
+    Ok(())
+}
+
+#[test]
+fn server_accepts_connections() -> Result<(), failure::Error> {
+    let script = [
+        Exchange::new(b"AT+CIPMUX=1\r\n", b"\r\nOK\r\n"),
+        Exchange::new(b"AT+CIPSERVERMAXCONN=1\r\n", b"\r\nOK\r\n"),
+        Exchange::new(b"AT+CIPSERVER=1,8080\r\n", b"\r\nOK\r\n"),
+        Exchange::unsolicited(b"0,CONNECT,\"192.168.1.5\",50000\r\n"),
+    ];
+    let module = MockModule::new(&script);
+    let (rx, tx) = module.split();
+    let mut esp32_at = Esp32At::new(rx, tx, CommandSet::TcpIp.into());
+
+    nb::block!(esp32_at.set_multiple_connections(true))?;
+    nb::block!(esp32_at.set_server_max_connections(1))?;
+    nb::block!(esp32_at.start_server(8080))?;
+    assert_eq!(
+        event::Event::LinkConnected {
+            link_id: 0,
+            remote: Some(([192, 168, 1, 5].into(), 50000)),
+            refused: false,
+        },
+        nb::block!(esp32_at.poll_event())?
+    );
+    assert_eq!(
+        link::LinkState::Inbound,
+        esp32_at.link_allocator().unwrap().state(0)
+    );
+    assert!(module.is_done());
+    Ok(())
+}
+
+#[test]
+fn server_refuses_connections_beyond_its_reservation() -> Result<(), failure::Error> {
+    let script = [
+        Exchange::new(b"AT+CIPMUX=1\r\n", b"\r\nOK\r\n"),
+        Exchange::new(b"AT+CIPSERVERMAXCONN=1\r\n", b"\r\nOK\r\n"),
+        Exchange::new(b"AT+CIPSERVER=1,8080\r\n", b"\r\nOK\r\n"),
+        Exchange::unsolicited(b"0,CONNECT\r\n1,CONNECT\r\n"),
+        Exchange::new(b"AT+CIPCLOSE=1\r\n", b"1,CLOSED\r\n\r\nOK\r\n"),
+    ];
+    let module = MockModule::new(&script);
+    let (rx, tx) = module.split();
+    let mut esp32_at = Esp32At::new(rx, tx, CommandSet::TcpIp.into());
+
+    nb::block!(esp32_at.set_multiple_connections(true))?;
+    nb::block!(esp32_at.set_server_max_connections(1))?;
+    nb::block!(esp32_at.start_server(8080))?;
+    for &(link_id, refused) in &[(0, false), (1, true)] {
+        assert_eq!(
+            event::Event::LinkConnected {
+                link_id,
+                remote: None,
+                refused,
+            },
+            nb::block!(esp32_at.poll_event())?
+        );
+    }
+    // The id stays in use until the refused connection has been closed
+    assert_eq!(
+        link::LinkState::Refused,
+        esp32_at.link_allocator().unwrap().state(1)
+    );
+    nb::block!(esp32_at.close(Some(1)))?;
+    assert_eq!(
+        link::LinkState::Free,
+        esp32_at.link_allocator().unwrap().state(1)
+    );
+    assert!(module.is_done());
+    Ok(())
+}
+
+#[test]
+fn recv_binary_payload() -> Result<(), failure::Error> {
+    let script = [
+        Exchange::new(b"AT+CIPMUX=1\r\n", b"\r\nOK\r\n"),
+        Exchange::new(
+            b"AT+CIPSTART=4,\"TCP\",\"example.com\",80,0\r\n",
+            b"4,CONNECT\r\n\r\nOK\r\n",
+        ),
+        Exchange::new(
+            b"AT+CIPRECVDATA=4,8\r\n",
+            b"\r\n+CIPRECVDATA:6,\r\nOK\r\n\r\nOK\r\n",
+        ),
+    ];
+    let module = MockModule::new(&script);
+    let (rx, tx) = module.split();
+    let mut esp32_at = Esp32At::new(rx, tx, CommandSet::TcpIp.into());
+
+    nb::block!(esp32_at.set_multiple_connections(true))?;
+    let connection = nb::block!(esp32_at.connect_tcp("example.com", 80, 0))?;
+    let mut buf = [0; 8];
+    let len = nb::block!(esp32_at.recv(connection.link_id, &mut buf))?;
+    assert_eq!(b"\r\nOK\r\n", &buf[..len]);
+    assert!(module.is_done());
+    Ok(())
+}
+
+#[test]
+fn data_arrives_during_command() -> Result<(), failure::Error> {
+    let script = [
+        Exchange::new(b"AT+CIPMUX=1\r\n", b"\r\nOK\r\n"),
+        Exchange::new(
+            b"AT+CIPSTATUS\r\n",
+            b"STATUS:3\r\n+IPD,2,6:\r\nOK\r\n+IPD,0,2:hi\r\nOK\r\n",
+        ),
+        Exchange::new(b"AT+CIPSTATE?\r\n", b"\r\nOK\r\n"),
+    ];
+    let module = MockModule::new(&script);
+    let (rx, tx) = module.split();
+    let mut esp32_at = Esp32At::new(rx, tx, CommandSet::TcpIp.into());
+
+    nb::block!(esp32_at.set_multiple_connections(true))?;
+    let status = nb::block!(esp32_at.connection_status())?;
+    assert_eq!(tcpip::NetworkState::Transmitting, status.state);
+    let mut buf = [0; 8];
+    let len = nb::block!(esp32_at.read(Some(2), &mut buf))?;
+    assert_eq!(b"\r\nOK\r\n", &buf[..len]);
+    let len = nb::block!(esp32_at.read(Some(0), &mut buf))?;
+    assert_eq!(b"hi", &buf[..len]);
+    assert!(matches!(
+        esp32_at.read(Some(1), &mut buf),
+        Err(nb::Error::WouldBlock)
+    ));
+    assert!(module.is_done());
+    Ok(())
+}
+
+struct NeverFires;
+
+impl embedded_hal::timer::CountDown for NeverFires {
+    type Time = ();
+
+    fn start<T>(&mut self, _count: T)
+    where
+        T: Into<()>,
+    {
+    }
+
+    fn wait(&mut self) -> nb::Result<(), void::Void> {
+        Err(nb::Error::WouldBlock)
+    }
+}
+
+/// Fires once it has been waited on `waits` times since it was started.
+struct FiresAfter {
+    waits: u32,
+    remaining: u32,
+}
+
+impl FiresAfter {
+    fn new(waits: u32) -> Self {
+        FiresAfter {
+            waits,
+            remaining: waits,
+        }
+    }
+}
+
+impl embedded_hal::timer::CountDown for FiresAfter {
+    type Time = ();
+
+    fn start<T>(&mut self, _count: T)
+    where
+        T: Into<()>,
+    {
+        self.remaining = self.waits;
+    }
+
+    fn wait(&mut self) -> nb::Result<(), void::Void> {
+        if self.remaining == 0 {
+            return Ok(());
+        }
+        self.remaining -= 1;
+        Err(nb::Error::WouldBlock)
+    }
+}
+
+#[cfg(feature = "embedded-io")]
+#[test]
+fn socket_write_all() -> Result<(), failure::Error> {
+    use embedded_io::Write;
+
+    let data = [b'x'; tcpip::MAX_SEND_LEN + 2];
+    let script = [
+        Exchange::new(b"AT+CIPSEND=8192\r\n", b"\r\nOK\r\n\r\n>"),
+        Exchange::new(&data[..tcpip::MAX_SEND_LEN], b"\r\nSEND OK\r\n"),
+        Exchange::new(b"AT+CIPSEND=2\r\n", b"\r\nOK\r\n\r\n>"),
+        Exchange::new(b"xx", b"\r\nSEND OK\r\n"),
+        Exchange::new(b"AT+CIPCLOSE\r\n", b"CLOSED\r\n\r\nOK\r\n"),
+    ];
+    let module = MockModule::new(&script);
+    let (rx, tx) = module.split();
+    let mut esp32_at = Esp32At::new(rx, tx, CommandSet::TcpIp.into());
+
+    let mut socket = esp32_at.socket(None)?;
+    socket.write_all(&data)?;
+    socket.close()?;
+    assert!(module.is_done());
+    Ok(())
+}
+
+#[cfg(feature = "embedded-io")]
+#[test]
+fn socket_read_to_end() -> Result<(), failure::Error> {
+    use embedded_io::Read;
+
+    let script = [
+        Exchange::new(b"AT+CIPMUX=1\r\n", b"\r\nOK\r\n"),
+        Exchange::new(
+            b"AT+CIPSTART=4,\"TCP\",\"example.com\",80,0\r\n",
+            b"4,CONNECT\r\n\r\nOK\r\n",
+        ),
+        Exchange::unsolicited(b"+IPD,4,5:hello+IPD,4,6: world4,CLOSED\r\n"),
+    ];
+    let module = MockModule::new(&script);
+    let (rx, tx) = module.split();
+    let mut esp32_at = Esp32At::new(rx, tx, CommandSet::TcpIp.into());
+
+    nb::block!(esp32_at.set_multiple_connections(true))?;
+    let mut socket = nb::block!(esp32_at.connect_tcp_socket("example.com", 80, 0))?;
+    let mut buf = [0; 16];
+    let mut len = 0;
+    loop {
+        match Read::read(&mut socket, &mut buf[len..])? {
+            0 => break,
+            n => len += n,
+        }
+    }
+    assert_eq!(b"hello world", &buf[..len]);
+    drop(socket);
+    assert!(module.is_done());
+    Ok(())
+}
+
+#[cfg(feature = "embedded-io")]
+#[test]
+fn passthrough_embedded_io() -> Result<(), failure::Error> {
+    use embedded_io::{Read, Write};
+
+    let script = [
+        Exchange::new(b"AT+CIPMODE=1\r\n", b"\r\nOK\r\n"),
+        Exchange::new(b"AT+CIPSEND\r\n", b"\r\nOK\r\n\r\n>"),
+        Exchange::new(b"GET / HTTP/1.0\r\n\r\n", b"HTTP/1.0 200 OK"),
+    ];
+    let module = MockModule::new(&script);
+    let (rx, tx) = module.split();
+    let mut esp32_at = Esp32At::new(rx, tx, CommandSet::TcpIp.into());
+
+    nb::block!(esp32_at.set_passthrough_mode(true))?;
+    let mut passthrough = nb::block!(esp32_at.start_passthrough())?;
+    passthrough.write_all(b"GET / HTTP/1.0\r\n\r\n")?;
+    passthrough.flush()?;
+    let mut buf = [0; 32];
+    let len = Read::read(&mut passthrough, &mut buf)?;
+    assert_eq!(b"HTTP/1.0 200 OK", &buf[..len]);
+    assert!(module.is_done());
+    Ok(())
+}
+
+#[test]
+fn dns_cache_replaces_first_to_expire() -> Result<(), failure::Error> {
+    let script = [Exchange::new(
+        b"AT+CIPDOMAIN=\"b.example\"\r\n",
+        b"+CIPDOMAIN:\"192.0.2.2\"\r\n\r\nOK\r\n",
+    )];
+    let module = MockModule::new(&script);
+    let (rx, tx) = module.split();
+    let mut esp32_at = Esp32At::new(rx, tx, CommandSet::TcpIp.into());
+
+    let mut cache = dns::DnsCache::<heapless::consts::U2>::new(1_000);
+    cache.insert("a.example", [192, 0, 2, 1].into(), 0);
+    cache.insert("c.example", [192, 0, 2, 3].into(), 500);
+    cache.resolve(&mut esp32_at, "b.example", 600)?;
+    assert_eq!(None, cache.get("a.example", 600));
+    assert_eq!(Some([192, 0, 2, 2].into()), cache.get("b.example", 600));
+    assert_eq!(Some([192, 0, 2, 3].into()), cache.get("c.example", 600));
+
+    cache.invalidate("c.example");
+    assert_eq!(None, cache.get("c.example", 600));
+    assert!(module.is_done());
+    Ok(())
+}
+
+#[test]
+fn dns_cache_expires_after_long_ttls_and_clock_wraparound() {
+    let ip = [192, 0, 2, 1].into();
+    let mut cache = dns::DnsCache::<heapless::consts::U2>::new(3_000_000_000);
+    cache.insert("a.example", ip, 0);
+    assert_eq!(Some(ip), cache.get("a.example", 2_999_999_999));
+    assert_eq!(None, cache.get("a.example", 3_000_000_000));
+
+    let mut cache = dns::DnsCache::<heapless::consts::U2>::new(1_000);
+    cache.insert("a.example", ip, u32::MAX - 499);
+    assert_eq!(Some(ip), cache.get("a.example", 499));
+    assert_eq!(None, cache.get("a.example", 500));
+}
+
+#[test]
+fn mqtt_publish_failures() {
+    let script = [Exchange::new(
+        b"AT+MQTTPUB=0,\"topic\",\"data\",1,0\r\n",
+        b"ERR CODE:0x0101600c\r\n\r\nERROR\r\n",
+    )];
+    let module = MockModule::new(&script);
+    let (rx, tx) = module.split();
+    let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Mqtt.into());
+
+    // Every comma is escaped, which takes the command line over the limit
+    let data = [b','; 120];
+    let data = core::str::from_utf8(&data).unwrap();
+    match esp32_at.mqtt_publish("topic", data, mqtt::QoS::AtLeastOnce, false) {
+        Err(nb::Error::Other(esp_at::Error::InvalidArgument)) => {}
+        other => panic!("unexpected result: {:?}", other),
+    }
+
+    match nb::block!(esp32_at.mqtt_publish("topic", "data", mqtt::QoS::AtLeastOnce, false)) {
+        Err(esp_at::Error::MqttFailed { reason }) => {
+            assert_eq!(mqtt::MqttFailure::PublishFailed, reason)
+        }
+        other => panic!("unexpected result: {:?}", other),
+    }
+    assert!(module.is_done());
+}
+
+#[test]
+#[cfg(feature = "mqtt-receive")]
+fn mqtt_message_arrives_during_command() -> Result<(), failure::Error> {
+    let script = [Exchange::new(
+        b"AT+MQTTPUB=0,\"topic\",\"data\",0,0\r\n",
+        b"+MQTTSUBRECV:0,\"a,b\",6,\r\nOK\r\n\r\n\r\nOK\r\n",
+    )];
+    let module = MockModule::new(&script);
+    let (rx, tx) = module.split();
+    let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Mqtt.into());
+
+    nb::block!(esp32_at.mqtt_publish("topic", "data", mqtt::QoS::AtMostOnce, false))?;
+
+    let mut buf = [0; 4];
+    match esp32_at.mqtt_receive(&mut buf) {
+        Err(nb::Error::Other(esp_at::Error::InvalidArgument)) => {}
+        other => panic!("unexpected result: {:?}", other),
+    }
+    let mut buf = [0; 8];
+    let message = nb::block!(esp32_at.mqtt_receive(&mut buf))?;
+    assert_eq!("a,b", message.topic);
+    assert_eq!(b"\r\nOK\r\n", &buf[..message.len]);
+    assert!(matches!(
+        esp32_at.mqtt_receive(&mut buf),
+        Err(nb::Error::WouldBlock)
+    ));
+    assert!(module.is_done());
+    Ok(())
+}
+
+#[test]
+#[cfg(not(feature = "mqtt-receive"))]
+fn mqtt_message_is_skipped_without_receive_buffer() -> Result<(), failure::Error> {
+    let script = [Exchange::new(
+        b"AT+MQTTPUB=0,\"topic\",\"data\",0,0\r\n",
+        b"+MQTTSUBRECV:0,\"a,b\",6,\r\nOK\r\n\r\n+MQTTDISCONNECTED:0\r\n\r\nOK\r\n",
+    )];
+    let module = MockModule::new(&script);
+    let (rx, tx) = module.split();
+    let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Mqtt.into());
+
+    nb::block!(esp32_at.mqtt_publish("topic", "data", mqtt::QoS::AtMostOnce, false))?;
+    assert!(matches!(
+        esp32_at.poll_event(),
+        Ok(event::Event::MqttDisconnected)
+    ));
+    assert!(module.is_done());
+    Ok(())
+}
+
+#[test]
+fn http_post_reads_chunked_response() -> Result<(), failure::Error> {
+    let script = [Exchange::new(
+        b"AT+HTTPCLIENT=3,0,\"http://example.com/form\",,,1,\"a=1&b=2\"\r\n",
+        b"+HTTPCLIENT:6,\r\nOK\r\n\r\nWIFI GOT IP\r\n+HTTPCLIENT:3,,\r\n\r\n\r\nOK\r\n",
+    )];
+    let module = MockModule::new(&script);
+    let (rx, tx) = module.split();
+    let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Http.into());
+
+    let mut buf = [0; 16];
+    let len = nb::block!(esp32_at.http_post(
+        "http://example.com/form",
+        http::HttpContentType::FormUrlEncoded,
+        "a=1&b=2",
+        &[],
+        &mut buf,
+    ))?;
+    assert_eq!(b"\r\nOK\r\n,\r\n", &buf[..len]);
+    assert!(matches!(esp32_at.poll_event(), Ok(event::Event::WifiGotIp)));
+    assert!(module.is_done());
+    Ok(())
+}
+
+#[test]
+fn http_get_configures_long_url_first() -> Result<(), failure::Error> {
+    let url = "https://bucket.s3.amazonaws.com/firmware.bin?X-Amz-Algorithm=AWS4-HMAC-SHA256\
+               &X-Amz-Credential=AKIAEXAMPLE%2F20260101%2Fus-east-1%2Fs3%2Faws4_request\
+               &X-Amz-Signature=0123456789abcdef";
+    let command = format!("AT+HTTPURLCFG={}\r\n", url.len());
+    let script = [
+        Exchange::new(command.as_bytes(), b"\r\nOK\r\n\r\n>"),
+        Exchange::new(url.as_bytes(), b"\r\nSET OK\r\n"),
+        Exchange::new(
+            b"AT+HTTPCLIENT=2,0,\"\",,,2\r\n",
+            b"+HTTPCLIENT:2,ok\r\n\r\nOK\r\n",
+        ),
+    ];
+    let module = MockModule::new(&script);
+    let (rx, tx) = module.split();
+    let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Http | CommandSet::Tls);
+
+    assert!(url.len() > http::MAX_INLINE_URL_LEN);
+    let mut len = 0;
+    esp32_at.http_get(url, &[], |data| {
+        len += data.len();
+        Ok::<_, failure::Error>(())
+    })?;
+    assert_eq!(2, len);
+    assert!(module.is_done());
+    Ok(())
+}
+
+#[test]
+fn data_received_during_http_response_is_kept() -> Result<(), failure::Error> {
+    let script = [Exchange::new(
+        b"AT+HTTPCLIENT=2,0,\"http://example.com/\",,,1\r\n",
+        b"+HTTPCLIENT:2,ok+IPD,8:\r\nOK\r\n,\xff\r\n+HTTPCLIENT:3,\x00\x01\x02\r\n\r\nOK\r\n",
+    )];
+    let module = MockModule::new(&script);
+    let (rx, tx) = module.split();
+    let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Http | CommandSet::TcpIp);
+
+    let mut body = Vec::new();
+    esp32_at.http_get("http://example.com/", &[], |data| {
+        body.extend_from_slice(data);
+        Ok::<_, failure::Error>(())
+    })?;
+    assert_eq!(b"ok\x00\x01\x02", &body[..]);
+    // The data received on the connection in the middle of the response isn't taken for part of
+    // it, even though it looks like the end of the response
+    let mut buf = [0; 16];
+    let len = nb::block!(esp32_at.read(None, &mut buf))?;
+    assert_eq!(b"\r\nOK\r\n,\xff", &buf[..len]);
+    assert!(module.is_done());
+    Ok(())
+}
+
+#[test]
+fn http_get_stops_passing_on_data_after_an_error() -> Result<(), failure::Error> {
+    let script = [
+        Exchange::new(
+            b"AT+HTTPCLIENT=2,0,\"http://example.com/image.bin\",,,1\r\n",
+            b"+HTTPCLIENT:100,",
+        ),
+        Exchange::unsolicited(&[0xa5; 100]),
+        Exchange::unsolicited(b"\r\n\r\nOK\r\n"),
+    ];
+    let module = MockModule::new(&script);
+    let (rx, tx) = module.split();
+    let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Http.into());
+
+    let mut calls = 0;
+    let result = esp32_at.http_get("http://example.com/image.bin", &[], |_| {
+        calls += 1;
+        Err(esp_at::Error::VerificationFailed)
+    });
+    match result {
+        Err(esp_at::Error::VerificationFailed) => {}
+        other => panic!("unexpected result: {:?}", other),
+    }
+    assert_eq!(1, calls);
+    // The rest of the response has been read, so the next command can be sent
+    assert!(module.is_done());
+    Ok(())
+}
+
+#[test]
+#[cfg(feature = "websocket-receive")]
+fn websocket_frames_that_dont_fit_are_dropped() -> Result<(), failure::Error> {
+    let mut incoming = b"+WS_DATA:0,600,".to_vec();
+    incoming.extend_from_slice(&[b'x'; 600]);
+    incoming.extend_from_slice(b"\r\n+WS_DATA:0,6,\r\nOK\r\n\r\n");
+    let script = [
+        Exchange::new(b"AT+WSSEND=0,4,9\r\n", b"\r\nOK\r\n\r\n>"),
+        Exchange::new(b"ping", &incoming),
+        Exchange::unsolicited(b"SEND OK\r\n"),
+    ];
+    let module = MockModule::new(&script);
+    let (rx, tx) = module.split();
+    let mut esp32_at = Esp32At::new(rx, tx, CommandSet::WebSocket.into());
+
+    nb::block!(esp32_at.websocket_send(0, b"ping", esp_at::websocket::Opcode::Ping))?;
+
+    let mut buf = [0; 16];
+    assert!(matches!(
+        esp32_at.websocket_receive(0, &mut buf),
+        Err(nb::Error::Other(esp_at::Error::BufferOverflow))
+    ));
+    let len = nb::block!(esp32_at.websocket_receive(0, &mut buf))?;
+    assert_eq!(b"\r\nOK\r\n", &buf[..len]);
+    assert!(matches!(
+        esp32_at.websocket_receive(0, &mut buf),
+        Err(nb::Error::WouldBlock)
+    ));
+    assert!(module.is_done());
+    Ok(())
+}
+
+#[test]
+#[cfg(not(feature = "websocket-receive"))]
+fn websocket_frame_is_skipped_without_receive_buffer() -> Result<(), failure::Error> {
+    let script = [
+        Exchange::new(b"AT+WSSEND=0,4,9\r\n", b"\r\nOK\r\n\r\n>"),
+        Exchange::new(
+            b"ping",
+            b"+WS_DATA:0,6,\r\nOK\r\n\r\n+WS_DISCONNECTED:0\r\n",
+        ),
+        Exchange::unsolicited(b"SEND OK\r\n"),
+    ];
+    let module = MockModule::new(&script);
+    let (rx, tx) = module.split();
+    let mut esp32_at = Esp32At::new(rx, tx, CommandSet::WebSocket.into());
+
+    nb::block!(esp32_at.websocket_send(0, b"ping", esp_at::websocket::Opcode::Ping))?;
+    assert!(matches!(
+        esp32_at.poll_event(),
+        Ok(event::Event::WebSocketDisconnected { link_id: 0 })
+    ));
+    assert!(module.is_done());
+    Ok(())
+}
+
+#[test]
+fn ble_directed_advertising() -> Result<(), failure::Error> {
+    let script = [
+        Exchange::new(
+            b"AT+BLEADVPARAM=32,64,4,1,1,0,1,\"c0:11:22:33:44:55\"\r\n",
+            b"\r\nOK\r\n",
+        ),
+        Exchange::new(
+            b"AT+BLEADVPARAM?\r\n",
+            b"+BLEADVPARAM:32,64,4,1,1,0,1,\"c0:11:22:33:44:55\"\r\n\r\nOK\r\n",
+        ),
+    ];
+    let module = MockModule::new(&script);
+    let (rx, tx) = module.split();
+    let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Ble.into());
+
+    let mut parameters = ble::AdvertisingParameters {
+        min_interval: 32,
+        max_interval: 64,
+        advertising_type: ble::AdvertisingType::ConnectableDirectedLowDuty,
+        own_address_type: ble::OwnAddressType::Random,
+        channels: ble::AdvertisingChannel::Channel37.into(),
+        filter_policy: ble::AdvertisingFilterPolicy::AllowAll,
+        peer: None,
+    };
+    assert!(matches!(
+        esp32_at.set_ble_advertising_parameters(&parameters),
+        Err(nb::Error::Other(esp_at::Error::InvalidArgument))
+    ));
+    parameters.peer = Some((
+        [0xc0, 0x11, 0x22, 0x33, 0x44, 0x55],
+        ble::AddressType::Random,
+    ));
+    nb::block!(esp32_at.set_ble_advertising_parameters(&parameters))?;
+    assert_eq!(
+        parameters,
+        nb::block!(esp32_at.get_ble_advertising_parameters())?
+    );
+    assert!(module.is_done());
+    Ok(())
+}
+
+#[test]
+fn ble_connection_indexes_are_reused() -> Result<(), failure::Error> {
+    let script = [
+        Exchange::new(
+            b"AT+BLECONN=0,\"24:0a:c4:01:02:03\",0,5\r\n",
+            b"+BLECONN:0,\"24:0a:c4:01:02:03\"\r\n+BLECONN:1,\"60:51:42:fe:98:aa\"\r\n\r\nOK\r\n",
+        ),
+        Exchange::new(
+            b"AT+BLECONN=2,\"24:0a:c4:01:02:04\",0,5\r\n",
+            b"+BLECONN:2,\"24:0a:c4:01:02:04\"\r\n\r\nOK\r\n",
+        ),
+        Exchange::unsolicited(b"+BLEDISCONN:1,\"60:51:42:fe:98:aa\"\r\n"),
+        Exchange::new(
+            b"AT+BLECONN=1,\"24:0a:c4:01:02:05\",0,5\r\n",
+            b"+BLECONN:1,\"24:0a:c4:01:02:05\"\r\n\r\nOK\r\n",
+        ),
+    ];
+    let module = MockModule::new(&script);
+    let (rx, tx) = module.split();
+    let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Ble.into());
+
+    let public = ble::AddressType::Public;
+    let address = |last| [0x24, 0x0a, 0xc4, 0x01, 0x02, last];
+    assert_eq!(0, nb::block!(esp32_at.ble_connect(address(3), public, 5))?);
+    // A central connected to the module on index 1 in the meantime
+    assert_eq!(2, nb::block!(esp32_at.ble_connect(address(4), public, 5))?);
+    assert!(matches!(
+        esp32_at.ble_connect(address(5), public, 5),
+        Err(nb::Error::Other(esp_at::Error::NoFreeLink))
+    ));
+    while !matches!(
+        nb::block!(esp32_at.poll_event())?,
+        event::Event::BleDisconnected { conn_index: 1, .. }
+    ) {}
+    assert_eq!(1, nb::block!(esp32_at.ble_connect(address(5), public, 5))?);
+    assert!(module.is_done());
+    Ok(())
+}
+
+#[test]
+fn ble_failed_connection_is_reported() -> Result<(), failure::Error> {
+    let script = [
+        Exchange::new(
+            b"AT+BLECONN=0,\"24:0a:c4:01:02:03\",0,5\r\n",
+            b"+BLECONN:0,-1\r\n\r\nERROR\r\n",
+        ),
+        Exchange::new(
+            b"AT+BLECONN=0,\"24:0a:c4:01:02:03\",0,5\r\n",
+            b"+BLECONN:0,\"24:0a:c4:01:02:03\"\r\n\r\nOK\r\n",
+        ),
+    ];
+    let module = MockModule::new(&script);
+    let (rx, tx) = module.split();
+    let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Ble.into());
+
+    let address = [0x24, 0x0a, 0xc4, 0x01, 0x02, 0x03];
+    let public = ble::AddressType::Public;
+    assert!(matches!(
+        nb::block!(esp32_at.ble_connect(address, public, 5)),
+        Err(esp_at::Error::ConnectFailed)
+    ));
+    assert_eq!(
+        event::Event::BleConnectFailed { conn_index: 0 },
+        nb::block!(esp32_at.poll_event())?
+    );
+    // The index of the failed connection is free to use again
+    assert_eq!(0, nb::block!(esp32_at.ble_connect(address, public, 5))?);
+    assert_eq!(
+        event::Event::BleConnected {
+            conn_index: 0,
+            address,
+        },
+        nb::block!(esp32_at.poll_event())?
+    );
+    assert!(module.is_done());
+    Ok(())
+}
+
+#[test]
+fn gatt_writes_arrive_during_notification() -> Result<(), failure::Error> {
+    let script = [
+        Exchange::new(
+            b"AT+BLEGATTSNTFY=0,1,2,2\r\n",
+            b"+WRITE:0,1,3,,4,1,\r\n\r\n\r\nOK\r\n\r\n>",
+        ),
+        Exchange::new(b"\x01\x02", b"+WRITE:0,1,2,1,2,\x01\x00\r\n\r\nOK\r\n"),
+        Exchange::unsolicited(b"+READ:0,\"60:51:42:fe:98:aa\"\r\n"),
+    ];
+    let module = MockModule::new(&script);
+    let (rx, tx) = module.split();
+    let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Ble.into());
+
+    nb::block!(esp32_at.notify_gatt_client(0, 1, 2, &[1, 2]))?;
+    // The value written is binary, and can contain commas and line breaks
+    assert_eq!(
+        event::Event::GattWritten {
+            conn_index: 0,
+            service_index: 1,
+            char_index: 3,
+            desc_index: None,
+            value: heapless::Vec::from_slice(b"1,\r\n").unwrap(),
+        },
+        nb::block!(esp32_at.poll_event())?
+    );
+    // Enabling notifications by writing the client characteristic configuration descriptor
+    assert_eq!(
+        event::Event::GattWritten {
+            conn_index: 0,
+            service_index: 1,
+            char_index: 2,
+            desc_index: Some(1),
+            value: heapless::Vec::from_slice(&[1, 0]).unwrap(),
+        },
+        nb::block!(esp32_at.poll_event())?
+    );
+    assert_eq!(
+        event::Event::GattRead {
+            conn_index: 0,
+            address: [0x60, 0x51, 0x42, 0xfe, 0x98, 0xaa],
+        },
+        nb::block!(esp32_at.poll_event())?
+    );
+    assert!(module.is_done());
+    Ok(())
+}
+
+#[test]
+fn pki_material_is_checked_before_writing() {
+    let module = MockModule::new(&[]);
+    let (rx, tx) = module.split();
+    let mut esp32_at = Esp32At::new(rx, tx, CommandSet::TcpIp.into());
+
+    match esp32_at.write_pki(pki::PkiKind::ClientCa, 0, b"cert") {
+        Err(nb::Error::Other(esp_at::Error::CommandSetNotSupported {
+            command_set: CommandSet::Tls,
+        })) => {}
+        other => panic!("unexpected result: {:?}", other),
+    }
+
+    let (rx, tx) = module.split();
+    let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Tls.into());
+    let data = [b'x'; pki::MAX_PKI_LEN + 1];
+    match esp32_at.write_pki(pki::PkiKind::ClientCa, 0, &data) {
+        Err(nb::Error::Other(esp_at::Error::InvalidArgument)) => {}
+        other => panic!("unexpected result: {:?}", other),
+    }
+    assert!(module.is_done());
+}
+
+#[test]
+fn signal_monitor_reports_threshold_crossings_with_hysteresis() -> Result<(), failure::Error> {
+    let access_point = |rssi: i8| {
+        format!(
+            "+CWJAP:\"home\",\"aa:bb:cc:dd:ee:ff\",6,{}\r\n\r\nOK\r\n",
+            rssi
+        )
+    };
+    let responses = [
+        access_point(-75),
+        access_point(-81),
+        access_point(-90),
+        access_point(-75),
+        access_point(-65),
+        access_point(-79),
+    ];
+    let script = [
+        Exchange::new(b"AT+CWJAP?\r\n", responses[0].as_bytes()),
+        Exchange::new(b"AT+CWJAP?\r\n", responses[1].as_bytes()),
+        Exchange::new(b"AT+CWJAP?\r\n", responses[2].as_bytes()),
+        Exchange::new(b"AT+CWJAP?\r\n", responses[3].as_bytes()),
+        Exchange::new(b"AT+CWJAP?\r\n", b"No AP\r\n\r\nOK\r\n"),
+        Exchange::new(b"AT+CWJAP?\r\n", responses[4].as_bytes()),
+        Exchange::new(b"AT+CWJAP?\r\n", responses[5].as_bytes()),
+    ];
+    let module = MockModule::new(&script);
+    let (rx, tx) = module.split();
+    let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Wifi.into());
+
+    // Start close to the end of the clock's range, so that it wraps around
+    let start = u32::MAX - 1_500;
+    let at = |ms| start.wrapping_add(ms);
+    let mut monitor = signal::SignalMonitor::new(-80, -70, 1_000);
+    assert_eq!(None, monitor.poll(&mut esp32_at, at(0))?);
+    // The interval hasn't elapsed, so the module isn't queried
+    assert_eq!(None, monitor.poll(&mut esp32_at, at(500))?);
+    assert_eq!(
+        Some(signal::SignalEvent::Weak { rssi: -81 }),
+        monitor.poll(&mut esp32_at, at(1_000))?
+    );
+    assert!(monitor.is_weak());
+    // A weak signal is only reported once
+    assert_eq!(None, monitor.poll(&mut esp32_at, at(2_000))?);
+    // Above the weak threshold, but not above the recovered one
+    assert_eq!(None, monitor.poll(&mut esp32_at, at(3_000))?);
+    assert!(monitor.is_weak());
+    // Not being connected neither recovers nor weakens the signal
+    assert_eq!(None, monitor.poll(&mut esp32_at, at(4_000))?);
+    assert!(monitor.is_weak());
+    assert_eq!(
+        Some(signal::SignalEvent::Recovered { rssi: -65 }),
+        monitor.poll(&mut esp32_at, at(5_000))?
+    );
+    assert!(!monitor.is_weak());
+    // Below the recovered threshold, but not below the weak one
+    assert_eq!(None, monitor.poll(&mut esp32_at, at(6_000))?);
+    assert!(!monitor.is_weak());
+    assert!(module.is_done());
+    Ok(())
+}
+
+#[test]
+fn mdns_is_enabled_and_disabled() -> Result<(), failure::Error> {
+    let script = [
+        Exchange::new(
+            b"AT+MDNS=1,\"living\\,room\",\"_http\",80\r\n",
+            b"\r\nERROR\r\n",
+        ),
+        Exchange::new(b"AT+MDNS=1,\"livingroom\",\"_http\",80\r\n", b"\r\nOK\r\n"),
+        Exchange::new(b"AT+MDNS=0\r\n", b"\r\nOK\r\n"),
+    ];
+    let module = MockModule::new(&script);
+    let (rx, tx) = module.split();
+    let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Wifi.into());
+
+    // The host name is escaped, and the module refusing it is reported
+    match nb::block!(esp32_at.enable_mdns("living,room", "_http", 80)) {
+        Err(esp_at::Error::CommandFailed) => {}
+        other => panic!("unexpected result: {:?}", other),
+    }
+    nb::block!(esp32_at.enable_mdns("livingroom", "_http", 80))?;
+    nb::block!(esp32_at.disable_mdns())?;
+    assert!(module.is_done());
+
+    let (rx, tx) = module.split();
+    let mut esp32_at = Esp32At::new(rx, tx, CommandSet::TcpIp.into());
+    match esp32_at.disable_mdns() {
+        Err(nb::Error::Other(esp_at::Error::CommandSetNotSupported {
+            command_set: CommandSet::Wifi,
+        })) => {}
+        other => panic!("unexpected result: {:?}", other),
+    }
+    Ok(())
+}
+
+#[test]
+fn smart_config_reserved_data_is_binary() -> Result<(), failure::Error> {
+    let mut too_long = b"+SCRD:65,".to_vec();
+    too_long.extend_from_slice(&[b'x'; 65]);
+    too_long.extend_from_slice(b"\r\n");
+    let script = [
+        Exchange::new(
+            b"AT+CWSTARTSMART=4\r\n",
+            b"\r\nOK\r\n+SCRD:7,id\r\n,\x00\xff\r\n",
+        ),
+        Exchange::unsolicited(&too_long),
+        Exchange::unsolicited(b"smartconfig connected wifi\r\n"),
+    ];
+    let module = MockModule::new(&script);
+    let (rx, tx) = module.split();
+    let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Wifi.into());
+
+    let kind = wifi::SmartConfigKind::EspTouchV2 { aes_key: None };
+    nb::block!(esp32_at.start_smart_config(kind))?;
+    // The data can contain line breaks and commas, and needn't be text
+    assert_eq!(
+        event::Event::SmartConfigReservedData(
+            heapless::Vec::from_slice(b"id\r\n,\x00\xff").unwrap()
+        ),
+        nb::block!(esp32_at.poll_event())?
+    );
+    // Data that doesn't fit in the event is dropped
+    assert_eq!(
+        event::Event::SmartConfigConnected,
+        nb::block!(esp32_at.poll_event())?
+    );
+    assert!(module.is_done());
+    Ok(())
+}
+
+#[test]
+fn mqtt_client_announces_presence_after_reconnecting() -> Result<(), failure::Error> {
+    const ONLINE: &[u8] = b"AT+MQTTPUB=0,\"status/sensor-1\",\"online\",1,1\r\n";
+    let script = [
+        Exchange::new(
+            b"AT+MQTTUSERCFG=0,1,\"sensor-1\",\"\",\"\",0,0,\"\"\r\n",
+            b"\r\nOK\r\n",
+        ),
+        Exchange::new(
+            b"AT+MQTTCONNCFG=0,60,0,\"status/sensor-1\",\"offline\",1,1\r\n",
+            b"\r\nOK\r\n",
+        ),
+        Exchange::new(b"AT+MQTTCONN=0,\"broker.local\",1883,1\r\n", b"\r\nOK\r\n"),
+        Exchange::new(b"AT+MQTTSUB=0,\"commands/#\",1\r\n", b"\r\nOK\r\n"),
+        Exchange::new(ONLINE, b"\r\nOK\r\n"),
+        Exchange::unsolicited(b"+MQTTDISCONNECTED:0\r\n"),
+        Exchange::unsolicited(b"+MQTTCONNECTED:0,1,\"broker.local\",\"1883\",\"\",1\r\n"),
+        Exchange::new(b"AT+MQTTSUB=0,\"commands/#\",1\r\n", b"\r\nOK\r\n"),
+        Exchange::new(ONLINE, b"\r\nOK\r\n"),
+    ];
+    let module = MockModule::new(&script);
+    let (rx, tx) = module.split();
+    let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Mqtt.into());
+
+    let user_config = mqtt::MqttUserConfig {
+        scheme: mqtt::MqttScheme::Tcp,
+        client_id: "sensor-1",
+        username: "",
+        password: "",
+        pki_index: 0,
+        ca_index: 0,
+        path: "",
+    };
+    let connection_config = mqtt::MqttConnectionConfig {
+        keepalive_secs: 60,
+        clean_session: true,
+        last_will: None,
+    };
+    let mut client =
+        mqtt_client::MqttClient::new(user_config, connection_config, "broker.local", 1883);
+    client.set_presence(mqtt::Presence {
+        topic: "status/sensor-1",
+        online: "online",
+        offline: "offline",
+        qos: mqtt::QoS::AtLeastOnce,
+    });
+    client.subscribe(&mut esp32_at, "commands/#", mqtt::QoS::AtLeastOnce)?;
+    client.connect(&mut esp32_at)?;
+
+    for _ in 0..2 {
+        let event = nb::block!(esp32_at.poll_event())?;
+        client.handle_event(&event);
+    }
+    client.poll(&mut esp32_at)?;
+    assert!(client.is_connected());
+    assert!(module.is_done());
+    Ok(())
+}
+
+#[test]
+fn mqtt_credentials_are_sent_after_the_command() -> Result<(), failure::Error> {
+    // Commas and quotes aren't escaped, and the value can be longer than a command line
+    let password = format!("sig=\"{}\",", "x".repeat(mqtt::MAX_CREDENTIAL_LEN - 7));
+    let command = format!("AT+MQTTLONGPASSWORD=0,{}\r\n", mqtt::MAX_CREDENTIAL_LEN);
+    let script = [
+        Exchange::new(command.as_bytes(), b"\r\nOK\r\n\r\n>"),
+        Exchange::new(password.as_bytes(), b"\r\nOK\r\n"),
+    ];
+    let module = MockModule::new(&script);
+    let (rx, tx) = module.split();
+    let mut esp32_at = Esp32At::new(rx, tx, CommandSet::Mqtt.into());
+
+    for invalid in &["", &format!("{}x", password)] {
+        match esp32_at.set_mqtt_password(invalid) {
+            Err(nb::Error::Other(esp_at::Error::InvalidArgument)) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+    nb::block!(esp32_at.set_mqtt_password(&password))?;
+    assert!(module.is_done());
+    Ok(())
+}